@@ -0,0 +1,217 @@
+//! Finding problems in documents: the errors the parser recovered from and structure that is
+//! likely a mistake, like duplicate ids.
+
+use crate::Document;
+use kuchikiki::NodeRef;
+use kuchikiki::traits::NodeIterator;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An error the parser recovered from, see [`Document::parse_errors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The 1-based line the parser was at, which is where the tag or text causing it ends.
+    pub line: usize,
+    pub message: String,
+}
+
+/// How bad a [`Problem`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Structure that is valid to parse, but likely not what was meant.
+    Warning,
+    /// Markup the parser had to recover from, so the tree might differ from what the input
+    /// suggests.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// Something wrong with a document, see [`check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    /// Where in the input the problem is, if known. The column is only known for elements.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    /// Writes the problem like `3:5: warning: <li> outside of a list`, like compilers do.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "{line}:")?;
+        }
+        if let Some(column) = self.column {
+            write!(f, "{column}:")?;
+        }
+        if self.line.is_some() {
+            f.write_str(" ")?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// All problems in the document, ordered by where they are.
+///
+/// Those are the [parse errors](Document::parse_errors) as well as these warnings:
+///
+/// - `id` values used by more than one element
+/// - `<li>` elements outside of `<ul>`, `<ol>` and `<menu>`
+/// - more than one `<main>` element that isn't `hidden`
+///
+/// Where elements are is only known if the document was parsed with
+/// [`ParseOptions::positions`](crate::ParseOptions::positions).
+pub fn check(document: &Document) -> Vec<Problem> {
+    let mut problems: Vec<_> = document
+        .parse_errors()
+        .iter()
+        .map(|error| Problem {
+            severity: Severity::Error,
+            line: Some(error.line),
+            column: None,
+            message: error.message.clone(),
+        })
+        .collect();
+
+    let warning = |node: &NodeRef, message: String| {
+        let position = document.position(node);
+        Problem {
+            severity: Severity::Warning,
+            line: position.map(|position| position.line),
+            column: position.map(|position| position.column),
+            message,
+        }
+    };
+    let mut ids: HashMap<String, NodeRef> = HashMap::new();
+    let mut main: Option<NodeRef> = None;
+    for elem in document.root().descendants().elements() {
+        let node = elem.as_node();
+        let attributes = elem.attributes.borrow();
+
+        if let Some(id) = attributes.get("id").filter(|id| !id.is_empty()) {
+            if let Some(first) = ids.get(id) {
+                let message = match document.position(first) {
+                    Some(position) => format!("duplicate id `{id}`, first used at {position}"),
+                    None => format!("duplicate id `{id}`"),
+                };
+                problems.push(warning(node, message));
+            } else {
+                ids.insert(id.to_string(), node.clone());
+            }
+        }
+
+        match &*elem.name.local {
+            "li" => {
+                let in_list = node.parent().is_some_and(|parent| {
+                    parent
+                        .as_element()
+                        .is_some_and(|parent| matches!(&*parent.name.local, "ul" | "ol" | "menu"))
+                });
+                if !in_list {
+                    problems.push(warning(node, "<li> outside of a list".to_string()));
+                }
+            }
+            "main" if !attributes.contains("hidden") => match &main {
+                Some(first) => {
+                    let message = match document.position(first) {
+                        Some(position) => {
+                            format!("more than one <main>, the first is at {position}")
+                        }
+                        None => "more than one <main>".to_string(),
+                    };
+                    problems.push(warning(node, message));
+                }
+                None => main = Some(node.clone()),
+            },
+            _ => {}
+        }
+    }
+
+    // stable, so problems on the same line stay in the order they were found in
+    problems.sort_by_key(|problem| problem.line.unwrap_or(usize::MAX));
+    problems
+}
+
+lazy_static! {
+    static ref UNEXPECTED_TAG: Regex =
+        Regex::new(r"^Unexpected token .*?kind: (StartTag|EndTag), name: Atom\(\\?'([^'\\]*)")
+            .unwrap();
+}
+
+/// Makes the exact error messages of html5ever, which include debug output of the tokens,
+/// readable.
+pub(crate) fn describe_parse_error(message: &str) -> String {
+    if let Some(captures) = UNEXPECTED_TAG.captures(message) {
+        let slash = if &captures[1] == "EndTag" { "/" } else { "" };
+        return format!("unexpected <{slash}{}>", &captures[2]);
+    }
+    if message.starts_with("Unexpected token") {
+        return "unexpected token".to_string();
+    }
+    let mut chars = message.chars();
+    chars
+        .next()
+        .map(|first| first.to_lowercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseOptions;
+
+    fn problems(html: &str) -> Vec<String> {
+        let options = ParseOptions {
+            positions: true,
+            parse_errors: true,
+            ..ParseOptions::default()
+        };
+        let document = Document::parse_with(&mut html.as_bytes(), &options).unwrap();
+        check(&document).iter().map(Problem::to_string).collect()
+    }
+
+    #[test]
+    fn parse_errors_are_described() {
+        assert_eq!(
+            problems("<!DOCTYPE html>\n<div><p a=1 a=2>x</div>\n</div>\n<b><i>y</b></i>"),
+            [
+                "2: error: duplicate attribute",
+                "3: error: unexpected </div>",
+                "4: error: formatting element not current node",
+                "4: error: formatting element not open",
+            ]
+        );
+    }
+
+    #[test]
+    fn structure_is_linted() {
+        let html = "<!DOCTYPE html>\n<main id=\"a\"><ul><li>1</li></ul>\n\
+            <div><li id=\"a\">2</li></div></main>\n<main hidden></main><main></main>";
+        assert_eq!(
+            problems(html),
+            [
+                "3:6: warning: duplicate id `a`, first used at 2:1",
+                "3:6: warning: <li> outside of a list",
+                "4:21: warning: more than one <main>, the first is at 2:1",
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_documents_have_no_problems() {
+        let html = "<!DOCTYPE html><html><head><title>x</title></head>\
+            <body><main><ol><li>a</li></ol></main></body></html>";
+        assert_eq!(problems(html), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,437 @@
+//! CSS selectors, extended by the `:contains()` and `:icontains()` pseudo-classes.
+//!
+//! Selectors without them are handed as-is to kuchikiki. Otherwise each compound selector is
+//! compiled on its own and the combinators between them are matched here.
+
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{ElementData, NodeDataRef, NodeRef, Selectors};
+
+/// A compiled list of selectors, any of which can match.
+#[derive(Debug)]
+pub(crate) enum Selector {
+    Plain(Selectors),
+    Extended(Vec<Complex>),
+}
+
+impl Selector {
+    pub(crate) fn compile(source: &str) -> Result<Self, ()> {
+        if !has_extension(source) {
+            return Selectors::compile(source).map(Self::Plain);
+        }
+
+        split_top_level(source, |c| c == ',')
+            .into_iter()
+            .map(Complex::compile)
+            .collect::<Result<_, _>>()
+            .map(Self::Extended)
+    }
+
+    pub(crate) fn matches(&self, element: &NodeDataRef<ElementData>) -> bool {
+        match self {
+            Self::Plain(selectors) => selectors.matches(element),
+            Self::Extended(alternatives) => {
+                alternatives.iter().any(|complex| complex.matches(element))
+            }
+        }
+    }
+}
+
+/// Compound selectors chained by combinators, like `ul > li:contains("x") a`.
+#[derive(Debug)]
+pub(crate) struct Complex {
+    compounds: Vec<Compound>,
+    /// What connects each compound to the next one, so one less than there are compounds.
+    combinators: Vec<Combinator>,
+}
+
+impl Complex {
+    fn compile(source: &str) -> Result<Self, ()> {
+        let mut compounds = Vec::new();
+        let mut combinators = Vec::new();
+        let mut pending = None;
+
+        for part in split_top_level(source, |c| {
+            c.is_whitespace() || matches!(c, '>' | '+' | '~')
+        }) {
+            let part = part.trim();
+            let combinator = match part {
+                "" => continue,
+                ">" => Some(Combinator::Child),
+                "+" => Some(Combinator::NextSibling),
+                "~" => Some(Combinator::SubsequentSibling),
+                _ => None,
+            };
+            if let Some(combinator) = combinator {
+                // two combinators in a row, or one at the start
+                if pending.is_some() || compounds.is_empty() {
+                    return Err(());
+                }
+                pending = Some(combinator);
+                continue;
+            }
+
+            if !compounds.is_empty() {
+                combinators.push(pending.take().unwrap_or(Combinator::Descendant));
+            }
+            compounds.push(Compound::compile(part)?);
+        }
+
+        if compounds.is_empty() || pending.is_some() {
+            return Err(());
+        }
+        Ok(Self {
+            compounds,
+            combinators,
+        })
+    }
+
+    fn matches(&self, element: &NodeDataRef<ElementData>) -> bool {
+        self.matches_from(element, self.compounds.len() - 1)
+    }
+
+    /// If the element matches the compound at `index` and everything before it.
+    fn matches_from(&self, element: &NodeDataRef<ElementData>, index: usize) -> bool {
+        if !self.compounds[index].matches(element) {
+            return false;
+        }
+        let Some(previous) = index.checked_sub(1) else {
+            return true;
+        };
+
+        let node = element.as_node();
+        let matches_previous =
+            |candidate: &NodeDataRef<ElementData>| self.matches_from(candidate, previous);
+        match self.combinators[previous] {
+            Combinator::Descendant => node.ancestors().elements().any(|e| matches_previous(&e)),
+            Combinator::Child => node
+                .parent()
+                .and_then(|parent| parent.into_element_ref())
+                .is_some_and(|parent| matches_previous(&parent)),
+            Combinator::NextSibling => node
+                .preceding_siblings()
+                .elements()
+                .next()
+                .is_some_and(|sibling| matches_previous(&sibling)),
+            Combinator::SubsequentSibling => node
+                .preceding_siblings()
+                .elements()
+                .any(|sibling| matches_previous(&sibling)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+/// A compound selector like `a.download:contains("Download")`.
+#[derive(Debug)]
+struct Compound {
+    /// Everything except the text conditions.
+    selectors: Selectors,
+    conditions: Vec<Contains>,
+}
+
+impl Compound {
+    fn compile(mut source: &str) -> Result<Self, ()> {
+        let mut rest = String::new();
+        let mut conditions = Vec::new();
+
+        while let Some((start, name)) = find_extension(source) {
+            rest.push_str(&source[..start]);
+            let (needle, len) = parse_argument(&source[start + name.len()..])?;
+            conditions.push(Contains {
+                ignore_case: name == ":icontains(",
+                needle,
+            });
+            source = &source[start + name.len() + len..];
+        }
+        rest.push_str(source);
+
+        // `:contains("x")` on its own applies to any element
+        let rest = if rest.is_empty() { "*" } else { &rest };
+        Ok(Self {
+            selectors: Selectors::compile(rest)?,
+            conditions,
+        })
+    }
+
+    fn matches(&self, element: &NodeDataRef<ElementData>) -> bool {
+        if !self.selectors.matches(element) {
+            return false;
+        }
+        if self.conditions.is_empty() {
+            return true;
+        }
+
+        let text = crate::serialize_text(element.as_node(), false);
+        self.conditions
+            .iter()
+            .all(|condition| condition.holds(&text))
+    }
+}
+
+/// The `:contains()` or `:icontains()` pseudo-class.
+#[derive(Debug)]
+struct Contains {
+    needle: String,
+    ignore_case: bool,
+}
+
+impl Contains {
+    fn holds(&self, text: &str) -> bool {
+        if self.ignore_case {
+            text.to_lowercase().contains(&self.needle.to_lowercase())
+        } else {
+            text.contains(&self.needle)
+        }
+    }
+}
+
+const EXTENSIONS: [&str; 2] = [":contains(", ":icontains("];
+
+fn has_extension(source: &str) -> bool {
+    EXTENSIONS.iter().any(|name| source.contains(name))
+}
+
+/// Finds the first extension pseudo-class outside of quotes and parentheses.
+fn find_extension(source: &str) -> Option<(usize, &'static str)> {
+    let mut found = None;
+    scan(source, |idx, depth| {
+        if depth == 0 {
+            found = EXTENSIONS
+                .iter()
+                .find(|name| source[idx..].starts_with(*name))
+                .map(|name| (idx, *name));
+        }
+        found.is_some()
+    });
+    found
+}
+
+/// Parses the argument after the opening parenthesis of an extension, returning it and
+/// how long it was including the closing parenthesis.
+///
+/// It can be quoted with `"` or `'`, in which case quotes can be escaped by a backslash.
+fn parse_argument(source: &str) -> Result<(String, usize), ()> {
+    let trimmed = source.trim_start();
+    let mut len = source.len() - trimmed.len();
+    let mut chars = trimmed.chars();
+
+    let argument = match chars.next() {
+        Some(quote @ ('"' | '\'')) => {
+            len += 1;
+            let mut argument = String::new();
+            loop {
+                let c = chars.next().ok_or(())?;
+                len += c.len_utf8();
+                match c {
+                    '\\' => {
+                        let escaped = chars.next().ok_or(())?;
+                        len += escaped.len_utf8();
+                        argument.push(escaped);
+                    }
+                    c if c == quote => break argument,
+                    c => argument.push(c),
+                }
+            }
+        }
+        _ => {
+            let end = trimmed.find(')').ok_or(())?;
+            return Ok((trimmed[..end].trim_end().to_string(), len + end + 1));
+        }
+    };
+
+    let after = &source[len..];
+    let closing = after.trim_start();
+    if !closing.starts_with(')') {
+        return Err(());
+    }
+    Ok((argument, len + (after.len() - closing.len()) + 1))
+}
+
+/// Splits the source at all characters matching `is_separator` outside of quotes,
+/// parentheses and brackets. Separators other than commas are kept as their own parts.
+fn split_top_level(source: &str, is_separator: impl Fn(char) -> bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    scan(source, |idx, depth| {
+        let c = source[idx..].chars().next().unwrap_or_default();
+        if depth == 0 && is_separator(c) {
+            parts.push(&source[start..idx]);
+            if c != ',' {
+                parts.push(&source[idx..idx + c.len_utf8()]);
+            }
+            start = idx + c.len_utf8();
+        }
+        false
+    });
+    parts.push(&source[start..]);
+    parts
+}
+
+/// Calls `visit` with the byte index and nesting depth of each character outside of strings,
+/// until it returns true.
+fn scan(source: &str, mut visit: impl FnMut(usize, usize) -> bool) {
+    let mut depth = 0_usize;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (idx, c) in source.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (c, quote) {
+            ('\\', _) => escaped = true,
+            (c, Some(open)) if c == open => quote = None,
+            (_, Some(_)) => {}
+            ('"' | '\'', None) => quote = Some(c),
+            _ => {
+                if matches!(c, ')' | ']') {
+                    depth = depth.saturating_sub(1);
+                }
+                if visit(idx, depth) {
+                    return;
+                }
+                if matches!(c, '(' | '[') {
+                    depth += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Builds a selector from the root element down to the element, like
+/// `html > body > div#content > ul > li:nth-child(3)`, which matches only that element.
+///
+/// Returns [`None`] if the node isn't an element.
+pub(crate) fn path_to(node: &NodeRef) -> Option<String> {
+    node.as_element()?;
+    let mut steps: Vec<_> = node
+        .inclusive_ancestors()
+        .elements()
+        .map(|e| step(&e))
+        .collect();
+    steps.reverse();
+    Some(steps.join(" > "))
+}
+
+/// A selector for the element that matches none of its siblings.
+fn step(elem: &NodeDataRef<ElementData>) -> String {
+    let id = elem
+        .attributes
+        .borrow()
+        .get("id")
+        .filter(|id| is_identifier(id))
+        .map(str::to_string);
+    let same_step = |other: &NodeDataRef<ElementData>| {
+        other.name.local == elem.name.local
+            && id
+                .as_deref()
+                .is_none_or(|id| other.attributes.borrow().get("id") == Some(id))
+    };
+
+    let mut step = elem.name.local.to_string();
+    if let Some(id) = &id {
+        step.push('#');
+        step.push_str(id);
+    }
+
+    let node = elem.as_node();
+    let ambiguous = node
+        .preceding_siblings()
+        .elements()
+        .chain(node.following_siblings().elements())
+        .any(|sibling| same_step(&sibling));
+    if ambiguous {
+        let position = node.preceding_siblings().elements().count() + 1;
+        step.push_str(&format!(":nth-child({position})"));
+    }
+    step
+}
+
+/// If the text can be used as-is as identifier in a selector.
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    let valid_start = match chars.next() {
+        Some('-') => chars
+            .clone()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_'),
+        Some(c) => c.is_alphabetic() || c == '_',
+        None => false,
+    };
+    valid_start && chars.all(|c| c.is_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn select(selector: &str, html: &str) -> Vec<String> {
+        let selector = Selector::compile(selector).unwrap();
+        kuchikiki::parse_html()
+            .one(html)
+            .inclusive_descendants()
+            .elements()
+            .filter(|elem| selector.matches(elem))
+            .map(|elem| elem.as_node().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn contains_filters_by_text() {
+        let html = "<a href=\"/1\">Docs</a><a href=\"/2\">Download now</a>";
+        assert_eq!(
+            select("a:contains(\"Download\")", html),
+            ["<a href=\"/2\">Download now</a>"]
+        );
+        assert_eq!(select("a:contains(download)", html), Vec::<String>::new());
+        assert_eq!(select("a:icontains('DOWNLOAD')", html).len(), 1);
+    }
+
+    #[test]
+    fn contains_on_any_compound() {
+        let html = "<dl><dt>Price</dt><dd>5</dd><dt>Size</dt><dd>L</dd></dl>\
+            <ul><li>a <b>x</b></li><li>b <b>y</b></li></ul>";
+        assert_eq!(select("dt:contains(Price) + dd", html), ["<dd>5</dd>"]);
+        assert_eq!(select("dt:contains(Price) ~ dd", html).len(), 2);
+        assert_eq!(select("ul > li:contains('b') > b", html), ["<b>y</b>"]);
+        assert_eq!(
+            select("li:contains(a) b, dd:contains(L)", html),
+            ["<dd>L</dd>", "<b>x</b>"]
+        );
+    }
+
+    #[test]
+    fn contains_argument_can_have_quotes_and_combinators() {
+        let html = "<p>say \"hi\" > bye</p><p>it's (fine)</p>";
+        assert_eq!(select(r#"p:contains("say \"hi\" > bye")"#, html).len(), 1);
+        assert_eq!(select(r"p:contains('it\'s (fine)')", html).len(), 1);
+        assert_eq!(
+            select(":contains('(fine)'):not(body):not(html)", html).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn invalid_extended_selectors_are_rejected() {
+        for selector in [
+            "a:contains(\"open",
+            "a:contains(x",
+            "a:contains('x' y)",
+            "> a:contains(x)",
+            "a:contains(x) >",
+            "a:contains(x) > > b",
+            "a[:contains(x)",
+        ] {
+            assert!(Selector::compile(selector).is_err(), "{selector}");
+        }
+    }
+}
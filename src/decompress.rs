@@ -0,0 +1,84 @@
+use std::io::{self, Read};
+
+/// What compressed input starts with.
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// Wraps the reader in a decoder if its content looks compressed, no matter what it's called.
+///
+/// The first few bytes are read right away to look at them, which blocks until they're there.
+pub fn decompress(mut reader: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut reader)
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let reader = io::Cursor::new(magic.clone()).chain(reader);
+
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(Decompressing {
+            inner: flate2::read::MultiGzDecoder::new(reader),
+            format: "gzip",
+        })
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(Decompressing {
+            inner: zstd::Decoder::new(reader)?,
+            format: "zstd",
+        })
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Makes errors of the decoder say that they happened while decompressing.
+struct Decompressing<R> {
+    inner: R,
+    format: &'static str,
+}
+
+impl<R: Read> Read for Decompressing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("decompressing {} failed: {err}", self.format),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn read_all(input: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        decompress(Box::new(io::Cursor::new(input)))?.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn compressed_input_is_detected() {
+        let html = b"<p>Hello</p>".to_vec();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&html).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(html.as_slice(), 0).unwrap();
+
+        assert_eq!(read_all(gzip).unwrap(), html);
+        assert_eq!(read_all(zstd).unwrap(), html);
+        assert_eq!(read_all(html.clone()).unwrap(), html);
+        // shorter than any magic
+        assert_eq!(read_all(b"\x1f".to_vec()).unwrap(), b"\x1f");
+        assert_eq!(read_all(Vec::new()).unwrap(), b"");
+    }
+
+    #[test]
+    fn corrupted_input_is_an_error() {
+        let err = read_all(b"\x1f\x8bnot really gzip".to_vec()).unwrap_err();
+        assert!(
+            err.to_string().starts_with("decompressing gzip failed: "),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,492 @@
+//! Figuring out which character encoding the input is in, like browsers do.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+pub use encoding_rs::Encoding;
+use encoding_rs::{
+    DecoderResult, Encoder, EncoderResult, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252, X_USER_DEFINED,
+};
+
+/// How much of the input is searched for a `<meta charset>`.
+const PRESCAN_LEN: usize = 2048;
+
+/// Detects the encoding of the input.
+///
+/// A byte order mark wins, then the encoding the input was declared to be in when transferred,
+/// like the `charset` of an HTTP `Content-Type`, and then a `<meta charset>` or
+/// `<meta http-equiv="Content-Type">` near the start of the input. Without any of those,
+/// the input is assumed to be UTF-8.
+pub fn sniff(input: &[u8], transport: Option<&'static Encoding>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(input) {
+        return encoding;
+    }
+    transport
+        .or_else(|| prescan(&input[..input.len().min(PRESCAN_LEN)]))
+        .unwrap_or(UTF_8)
+}
+
+/// Decodes the input, replacing malformed sequences by U+FFFD and removing a byte order mark
+/// matching the encoding.
+pub fn decode<'a>(input: &'a [u8], encoding: &'static Encoding) -> Cow<'a, str> {
+    encoding.decode_with_bom_removal(input).0
+}
+
+/// Like [`decode`], but fails on the first malformed sequence instead of replacing it.
+pub fn decode_strict(input: &[u8], encoding: &'static Encoding) -> Result<String, DecodeError> {
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut output = String::new();
+    let capacity = decoder
+        .max_utf8_buffer_length_without_replacement(input.len())
+        .unwrap_or(input.len());
+    output.reserve(capacity);
+
+    let (result, read) = decoder.decode_to_string_without_replacement(input, &mut output, true);
+    match result {
+        DecoderResult::Malformed(malformed, after) => Err(DecodeError {
+            offset: read - usize::from(after) - usize::from(malformed),
+            encoding,
+        }),
+        DecoderResult::InputEmpty | DecoderResult::OutputFull => Ok(output),
+    }
+}
+
+/// Input that isn't valid in the encoding it's decoded as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    /// Where in the input the first malformed sequence starts, in bytes.
+    pub offset: usize,
+    pub encoding: &'static Encoding,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid {} at byte offset {}",
+            self.encoding.name(),
+            self.offset
+        )
+    }
+}
+
+impl Error for DecodeError {}
+
+/// The encoding named by the `charset` parameter of a `Content-Type`, like
+/// `text/html; charset=Shift_JIS`.
+pub fn from_content_type(value: &str) -> Option<&'static Encoding> {
+    charset_parameter(value.as_bytes())
+}
+
+/// What an [`EncodingWriter`] does with characters its encoding can't represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmappable {
+    /// Write them as numeric character reference like `&#8364;`, which is fine for HTML.
+    CharacterReference,
+    /// Write a `?` instead.
+    QuestionMark,
+    /// Fail with [`io::ErrorKind::InvalidData`].
+    Error,
+}
+
+/// Transcodes the UTF-8 written to it into another encoding before passing it on.
+///
+/// Characters may be split across writes. Flushing ends the escape sequence a stateful encoding
+/// like ISO-2022-JP might be in, so what was written so far is complete after that.
+/// There is no UTF-16 encoder, writers for it write UTF-8 instead.
+pub struct EncodingWriter<W> {
+    inner: W,
+    encoder: Encoder,
+    unmappable: Unmappable,
+    /// The start of a character whose remaining bytes weren't written yet.
+    pending: Vec<u8>,
+}
+
+impl<W: io::Write> EncodingWriter<W> {
+    pub fn new(inner: W, encoding: &'static Encoding, unmappable: Unmappable) -> Self {
+        Self {
+            inner,
+            encoder: encoding.new_encoder(),
+            unmappable,
+            pending: Vec::new(),
+        }
+    }
+
+    fn encode(&mut self, mut text: &str, last: bool) -> io::Result<()> {
+        let mut buffer = [0; 1024];
+        loop {
+            let (result, read, written) =
+                self.encoder
+                    .encode_from_utf8_without_replacement(text, &mut buffer, last);
+            self.inner.write_all(&buffer[..written])?;
+            text = &text[read..];
+            match result {
+                EncoderResult::InputEmpty => return Ok(()),
+                EncoderResult::OutputFull => {}
+                EncoderResult::Unmappable(c) => {
+                    let replacement = match self.unmappable {
+                        Unmappable::CharacterReference => format!("&#{};", u32::from(c)),
+                        Unmappable::QuestionMark => "?".to_string(),
+                        Unmappable::Error => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "{c:?} can't be represented in {}",
+                                    self.encoder.encoding().name()
+                                ),
+                            ));
+                        }
+                    };
+                    self.encode(&replacement, false)?;
+                }
+            }
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&input) {
+            Ok(valid) => valid,
+            Err(err) if err.error_len().is_none() => {
+                let (valid, rest) = input.split_at(err.valid_up_to());
+                self.pending = rest.to_vec();
+                std::str::from_utf8(valid).expect("should be valid up to there")
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        self.encode(valid, false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encode("", true)?;
+        // an encoder can't be used anymore once it's told the input ended
+        self.encoder = self.encoder.encoding().new_encoder();
+        self.inner.flush()
+    }
+}
+
+/// Looks for the first `<meta>` declaring an encoding, skipping comments and other tags.
+///
+/// A simplified version of the prescan in the HTML spec.
+fn prescan(input: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    while pos < input.len() {
+        let rest = &input[pos..];
+        if rest.starts_with(b"<!--") {
+            let end = find(&input[pos + 4..], b"-->")?;
+            pos += 4 + end + 3;
+            continue;
+        } else if starts_with_ignore_case(rest, b"<meta")
+            && rest.get(5).is_some_and(|&b| is_space(b) || b == b'/')
+        {
+            pos += 5;
+            if let Some(encoding) = meta_encoding(input, &mut pos) {
+                return Some(fixup(encoding));
+            }
+        } else if rest.len() > 1
+            && rest[0] == b'<'
+            && (rest[1].is_ascii_alphabetic()
+                || (rest[1] == b'/' && rest.get(2).is_some_and(u8::is_ascii_alphabetic)))
+        {
+            // skip the attributes too, so values like `title="<meta charset=x>"` aren't looked at
+            pos += rest
+                .iter()
+                .position(|&b| is_space(b) || b == b'>')
+                .unwrap_or(rest.len());
+            while attribute(input, &mut pos).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += find(rest, b">").unwrap_or(rest.len());
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// The encoding declared by the attributes of a `<meta>` starting at the position, if any.
+fn meta_encoding(input: &[u8], pos: &mut usize) -> Option<&'static Encoding> {
+    let mut seen = Vec::new();
+    let mut got_pragma = false;
+    // if the encoding only counts with `http-equiv="Content-Type"`, like for `content`
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value)) = attribute(input, pos) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" => got_pragma |= value == b"content-type",
+            b"content" if charset.is_none() => {
+                if let Some(encoding) = charset_parameter(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    match need_pragma? {
+        true if !got_pragma => None,
+        _ => charset,
+    }
+}
+
+/// A `<meta>` can't switch to UTF-16 since it wouldn't be readable as ASCII then.
+fn fixup(encoding: &'static Encoding) -> &'static Encoding {
+    if encoding == UTF_16BE || encoding == UTF_16LE {
+        UTF_8
+    } else if encoding == X_USER_DEFINED {
+        WINDOWS_1252
+    } else {
+        encoding
+    }
+}
+
+/// Reads the next attribute of a tag, returning its lowercased name and value.
+///
+/// Returns `None` at the end of the tag, leaving the position at the `>`.
+fn attribute(input: &[u8], pos: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    let byte = |pos: usize| input.get(pos).copied();
+    while byte(*pos).is_some_and(|b| is_space(b) || b == b'/') {
+        *pos += 1;
+    }
+    if byte(*pos)? == b'>' {
+        return None;
+    }
+
+    let mut name = Vec::new();
+    loop {
+        match byte(*pos)? {
+            b'=' if !name.is_empty() => {
+                *pos += 1;
+                break;
+            }
+            b if is_space(b) => {
+                while byte(*pos).is_some_and(is_space) {
+                    *pos += 1;
+                }
+                if byte(*pos)? != b'=' {
+                    return Some((name, Vec::new()));
+                }
+                *pos += 1;
+                break;
+            }
+            b'/' | b'>' => return Some((name, Vec::new())),
+            b => name.push(b.to_ascii_lowercase()),
+        }
+        *pos += 1;
+    }
+
+    while byte(*pos).is_some_and(is_space) {
+        *pos += 1;
+    }
+    let mut value = Vec::new();
+    match byte(*pos)? {
+        quote @ (b'"' | b'\'') => {
+            *pos += 1;
+            let end = input[*pos..].iter().position(|&b| b == quote)?;
+            value.extend(input[*pos..*pos + end].to_ascii_lowercase());
+            *pos += end + 1;
+        }
+        b'>' => {}
+        _ => {
+            while let Some(b) = byte(*pos).filter(|&b| !is_space(b) && b != b'>') {
+                value.push(b.to_ascii_lowercase());
+                *pos += 1;
+            }
+        }
+    }
+    Some((name, value))
+}
+
+/// Extracts the encoding from a value like `text/html; charset="utf-8"`.
+fn charset_parameter(value: &[u8]) -> Option<&'static Encoding> {
+    let mut rest = value;
+    loop {
+        let start = find_ignore_case(rest, b"charset")?;
+        rest = rest[start + 7..].trim_ascii_start();
+        if let Some(after) = rest.strip_prefix(b"=") {
+            rest = after.trim_ascii_start();
+            break;
+        }
+    }
+
+    let label = match rest.first()? {
+        quote @ (b'"' | b'\'') => {
+            let end = rest[1..].iter().position(|b| b == quote)?;
+            &rest[1..=end]
+        }
+        _ => {
+            let end = rest
+                .iter()
+                .position(|&b| is_space(b) || b == b';')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        }
+    };
+    Encoding::for_label(label)
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+fn starts_with_ignore_case(haystack: &[u8], prefix: &[u8]) -> bool {
+    haystack
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{ISO_2022_JP, SHIFT_JIS, UTF_16LE, WINDOWS_1251};
+
+    #[test]
+    fn meta_charsets_are_found() {
+        let sniffed = |html: &str| sniff(html.as_bytes(), None).name();
+        assert_eq!(sniffed("<p>no declaration</p>"), "UTF-8");
+        assert_eq!(sniffed("<meta charset=\"windows-1252\">"), "windows-1252");
+        assert_eq!(sniffed("<META CharSet=latin1>"), "windows-1252");
+        assert_eq!(
+            sniffed("<meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">"),
+            "Shift_JIS"
+        );
+        // without `http-equiv`, `content` doesn't count
+        assert_eq!(sniffed("<meta content=\"charset=Shift_JIS\">"), "UTF-8");
+        // neither do comments, other tags' attributes or unknown encodings
+        assert_eq!(
+            sniffed(
+                "<!-- <meta charset=koi8-r> --><p title=\"<meta charset=koi8-r>\"><meta charset=nope><meta charset=\"cp1251\">"
+            ),
+            "windows-1251"
+        );
+        assert_eq!(sniffed("<meta charset=utf-16le>"), "UTF-8");
+        let late = format!("{}<meta charset=windows-1252>", " ".repeat(PRESCAN_LEN));
+        assert_eq!(sniffed(&late), "UTF-8");
+    }
+
+    #[test]
+    fn bom_and_transport_win() {
+        let html = b"<meta charset=windows-1251>";
+        assert_eq!(sniff(html, Some(SHIFT_JIS)), SHIFT_JIS);
+        let with_bom = [b"\xef\xbb\xbf".as_slice(), html].concat();
+        assert_eq!(sniff(&with_bom, Some(SHIFT_JIS)), UTF_8);
+        assert_eq!(sniff(b"\xff\xfe<\0", Some(WINDOWS_1251)), UTF_16LE);
+        assert_eq!(decode(&with_bom, UTF_8), "<meta charset=windows-1251>");
+    }
+
+    #[test]
+    fn strict_decoding_fails_on_malformed_input() {
+        assert_eq!(
+            decode_strict(b"\xef\xbb\xbfGr\xc3\xbc\xc3\x9fe", UTF_8).unwrap(),
+            "Grüße"
+        );
+        let err = decode_strict(b"\xef\xbb\xbfGr\xfc\xdfe", UTF_8).unwrap_err();
+        assert_eq!(err.offset, 5);
+        assert_eq!(err.to_string(), "invalid UTF-8 at byte offset 5");
+        assert_eq!(decode(b"Gr\xfc\xdfe", UTF_8), "Gr\u{fffd}\u{fffd}e");
+        assert_eq!(decode_strict(b"x\x81", SHIFT_JIS).unwrap_err().offset, 1);
+    }
+
+    fn encoded(chunks: &[&[u8]], encoding: &'static Encoding, unmappable: Unmappable) -> Vec<u8> {
+        let mut writer = EncodingWriter::new(Vec::new(), encoding, unmappable);
+        for chunk in chunks {
+            io::Write::write_all(&mut writer, chunk).unwrap();
+        }
+        io::Write::flush(&mut writer).unwrap();
+        writer.inner
+    }
+
+    #[test]
+    fn characters_split_across_writes() {
+        let text = "Grüße, €5".as_bytes();
+        let expected = b"Gr\xfc\xdfe, \x805";
+        let bytewise: Vec<_> = text.chunks(1).collect();
+        assert_eq!(
+            encoded(&bytewise, WINDOWS_1252, Unmappable::Error),
+            expected
+        );
+        // `€` is 3 bytes, split after its first and second one
+        let (start, euro) = text.split_at(text.len() - 4);
+        let chunks = [start, &euro[..1], &euro[1..2], &euro[2..]];
+        assert_eq!(encoded(&chunks, WINDOWS_1252, Unmappable::Error), expected);
+
+        let text = "日本".as_bytes();
+        let chunks = [&text[..2], &text[2..4], &text[4..]];
+        assert_eq!(
+            encoded(&chunks, SHIFT_JIS, Unmappable::Error),
+            b"\x93\xfa\x96{"
+        );
+    }
+
+    #[test]
+    fn unmappable_characters() {
+        let text = ["a→b".as_bytes()];
+        assert_eq!(
+            encoded(&text, WINDOWS_1252, Unmappable::CharacterReference),
+            b"a&#8594;b"
+        );
+        assert_eq!(
+            encoded(&text, WINDOWS_1252, Unmappable::QuestionMark),
+            b"a?b"
+        );
+
+        let mut writer = EncodingWriter::new(Vec::new(), WINDOWS_1252, Unmappable::Error);
+        let err = io::Write::write_all(&mut writer, "a→b".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "'→' can't be represented in windows-1252");
+    }
+
+    #[test]
+    fn flushing_ends_escape_sequences() {
+        let mut writer = EncodingWriter::new(Vec::new(), ISO_2022_JP, Unmappable::Error);
+        io::Write::write_all(&mut writer, "日".as_bytes()).unwrap();
+        io::Write::flush(&mut writer).unwrap();
+        io::Write::write_all(&mut writer, "本".as_bytes()).unwrap();
+        io::Write::flush(&mut writer).unwrap();
+        assert_eq!(writer.inner, b"\x1b$BF|\x1b(B\x1b$BK\\\x1b(B");
+    }
+
+    #[test]
+    fn content_type_charsets() {
+        assert_eq!(
+            from_content_type("text/html; charset=ISO-8859-1"),
+            Some(WINDOWS_1252)
+        );
+        assert_eq!(
+            from_content_type("text/html;charset=\"shift_jis\""),
+            Some(SHIFT_JIS)
+        );
+        assert_eq!(
+            from_content_type("text/html; CHARSET = utf-8 ; x=y"),
+            Some(UTF_8)
+        );
+        assert_eq!(from_content_type("text/html"), None);
+        assert_eq!(from_content_type("text/html; charset=unknown"), None);
+    }
+}
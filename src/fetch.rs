@@ -0,0 +1,133 @@
+use hq::encoding::{self, Encoding};
+use std::error::Error;
+use std::io;
+use url::Url;
+
+/// The body of a successful HTTP response, along with where it was actually fetched from.
+pub struct Fetched {
+    /// The URL of the final response, i.e. after following all redirects.
+    pub url: Url,
+    /// The encoding given by the `charset` of the response's `Content-Type`, if any.
+    pub encoding: Option<&'static Encoding>,
+    pub body: Box<dyn io::Read + Send + Sync>,
+}
+
+/// An extra header to send when fetching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+/// Parses a header in the `Name: value` form, as used by `--header`.
+pub fn parse_header(raw: &str) -> Result<Header, String> {
+    let Some((name, value)) = raw.split_once(':') else {
+        return Err("expected a header in the form `Name: value`".to_string());
+    };
+
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("header name is empty".to_string());
+    }
+    if let Some(invalid) = name.chars().find(|c| !is_token_char(*c)) {
+        return Err(format!(
+            "header name `{name}` contains invalid character {invalid:?}"
+        ));
+    }
+
+    let value = value.trim();
+    if let Some(invalid) = value.chars().find(|c| c.is_control() && *c != '\t') {
+        return Err(format!(
+            "header value contains control character {invalid:?}"
+        ));
+    }
+
+    Ok(Header {
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Characters allowed in header names, see `token` in RFC 9110.
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// If the given input path should be fetched over HTTP(S) instead of being read as file.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Fetches the given URL, failing on any response other than `200 OK`.
+pub fn fetch(
+    url: &str,
+    headers: &[Header],
+    user_agent: Option<&str>,
+) -> Result<Fetched, Box<dyn Error>> {
+    let mut request = ureq::get(url);
+    if let Some(user_agent) = user_agent {
+        request = request.set("User-Agent", user_agent);
+    }
+    for header in headers {
+        request = request.set(&header.name, &header.value);
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, response)) => {
+            return Err(format!("fetching {url} failed: {code} {}", response.status_text()).into());
+        }
+        Err(err) => return Err(format!("fetching {url} failed: {err}").into()),
+    };
+
+    if response.status() != 200 {
+        return Err(format!(
+            "fetching {url} failed: {} {}",
+            response.status(),
+            response.status_text()
+        )
+        .into());
+    }
+
+    let url = Url::parse(response.get_url())?;
+    let encoding = response
+        .header("Content-Type")
+        .and_then(encoding::from_content_type);
+    Ok(Fetched {
+        url,
+        encoding,
+        body: response.into_reader(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_is_split_and_trimmed() {
+        assert_eq!(
+            parse_header("Cookie:  session=abc:def "),
+            Ok(Header {
+                name: "Cookie".to_string(),
+                value: "session=abc:def".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn header_without_colon_is_rejected() {
+        assert!(parse_header("Cookie session=abc").is_err());
+    }
+
+    #[test]
+    fn header_with_invalid_name_is_rejected() {
+        assert!(parse_header(": value").is_err());
+        assert!(parse_header("Bad Name: value").is_err());
+    }
+
+    #[test]
+    fn header_with_newline_is_rejected() {
+        assert!(parse_header("X-Evil: a\r\nHost: example.org").is_err());
+    }
+}
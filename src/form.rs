@@ -0,0 +1,200 @@
+//! Describing forms and their fields as JSON, like for testing scrapers.
+
+use html5ever::local_name;
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{ElementData, NodeDataRef, NodeRef};
+use serde_json::{Map, Value, json};
+
+/// All forms in the node, including itself.
+pub fn forms(node: &NodeRef) -> Vec<NodeRef> {
+    node.inclusive_descendants()
+        .elements()
+        .filter(|elem| elem.name.local == local_name!("form"))
+        .map(|elem| elem.as_node().clone())
+        .collect()
+}
+
+/// Describes the form as an object with its `action`, `method` and `fields`.
+///
+/// The `action` is as it is in the document, `null` if it has none and submits to the page
+/// itself. The `method` is lowercased and defaults to `get`. The `fields` are all `input`,
+/// `select`, `textarea` and `button` elements in the form that have a `name`, in document order.
+pub fn describe(form: &NodeRef) -> Value {
+    let elem = form.as_element();
+    let attribute =
+        |name: &str| elem.and_then(|elem| elem.attributes.borrow().get(name).map(str::to_string));
+
+    let method = attribute("method").map_or_else(|| "get".to_string(), |m| m.to_lowercase());
+    let fields: Vec<_> = form
+        .descendants()
+        .elements()
+        .filter_map(|field| describe_field(&field))
+        .collect();
+
+    json!({
+        "action": attribute("action"),
+        "method": method,
+        "fields": fields,
+    })
+}
+
+/// Describes a field with its `name`, `type`, `value`, `required` and `disabled`,
+/// returning `None` if it isn't a field or has no name.
+///
+/// Checkboxes and radio buttons also have `checked`, while `select` elements have their
+/// `options` and the value of the first selected one as `value`.
+fn describe_field(field: &NodeDataRef<ElementData>) -> Option<Value> {
+    let name = &field.name.local;
+    if !(*name == local_name!("input")
+        || *name == local_name!("select")
+        || *name == local_name!("textarea")
+        || *name == local_name!("button"))
+    {
+        return None;
+    }
+
+    let attrs = field.attributes.borrow();
+    let field_name = attrs.get("name")?;
+    let lowercase_type = attrs.get("type").map(str::to_lowercase);
+    let mut object = Map::new();
+    object.insert("name".to_string(), Value::from(field_name));
+
+    match *name {
+        local_name!("select") => {
+            let multiple = attrs.contains("multiple");
+            let options: Vec<_> = field
+                .as_node()
+                .descendants()
+                .elements()
+                .filter(|option| option.name.local == local_name!("option"))
+                .map(|option| {
+                    let text = crate::serialize_text(option.as_node(), false);
+                    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    let option_attrs = option.attributes.borrow();
+                    json!({
+                        // like browsers, fall back to the text if there's no value
+                        "value": option_attrs.get("value").map_or_else(|| text.clone(), str::to_string),
+                        "text": text,
+                        "selected": option_attrs.contains("selected"),
+                    })
+                })
+                .collect();
+            let selected = options
+                .iter()
+                .find(|option| option["selected"] == true)
+                .or_else(|| options.first().filter(|_| !multiple))
+                .map_or(Value::Null, |option| option["value"].clone());
+
+            let kind = if multiple {
+                "select-multiple"
+            } else {
+                "select-one"
+            };
+            object.insert("type".to_string(), Value::from(kind));
+            object.insert("value".to_string(), selected);
+            object.insert("options".to_string(), Value::from(options));
+        }
+        local_name!("textarea") => {
+            object.insert("type".to_string(), Value::from("textarea"));
+            object.insert("value".to_string(), Value::from(field.text_contents()));
+        }
+        _ => {
+            let default_type = if *name == local_name!("button") {
+                "submit"
+            } else {
+                "text"
+            };
+            let kind = lowercase_type.unwrap_or_else(|| default_type.to_string());
+            let checkable = kind == "checkbox" || kind == "radio";
+            object.insert("type".to_string(), Value::from(kind));
+            object.insert("value".to_string(), Value::from(attrs.get("value")));
+            if checkable {
+                object.insert(
+                    "checked".to_string(),
+                    Value::from(attrs.contains("checked")),
+                );
+            }
+        }
+    }
+
+    object.insert(
+        "required".to_string(),
+        Value::from(attrs.contains("required")),
+    );
+    object.insert(
+        "disabled".to_string(),
+        Value::from(attrs.contains("disabled") || in_disabled_fieldset(field)),
+    );
+    Some(Value::Object(object))
+}
+
+/// If the field is inside a disabled `<fieldset>`, which disables it as well.
+fn in_disabled_fieldset(field: &NodeDataRef<ElementData>) -> bool {
+    field.as_node().ancestors().elements().any(|ancestor| {
+        ancestor.name.local == local_name!("fieldset")
+            && ancestor.attributes.borrow().contains("disabled")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn describe_first(html: &str) -> Value {
+        let doc = kuchikiki::parse_html().one(html);
+        describe(&forms(&doc)[0])
+    }
+
+    #[test]
+    fn fields_are_described() {
+        let form = describe_first(
+            "<form action=\"/login\" method=\"POST\">\
+            <input type=\"hidden\" name=\"csrf\" value=\"abc\">\
+            <input name=\"user\" required><input type=\"submit\" value=\"no name\">\
+            <input type=\"Checkbox\" name=\"remember\" checked>\
+            <textarea name=\"note\">hi</textarea>\
+            <fieldset disabled><button name=\"go\">Go</button></fieldset></form>",
+        );
+        assert_eq!(
+            form,
+            json!({
+                "action": "/login",
+                "method": "post",
+                "fields": [
+                    {"name": "csrf", "type": "hidden", "value": "abc", "required": false, "disabled": false},
+                    {"name": "user", "type": "text", "value": null, "required": true, "disabled": false},
+                    {"name": "remember", "type": "checkbox", "value": null, "checked": true, "required": false, "disabled": false},
+                    {"name": "note", "type": "textarea", "value": "hi", "required": false, "disabled": false},
+                    {"name": "go", "type": "submit", "value": null, "required": false, "disabled": true},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn selects_have_options() {
+        let form = describe_first(
+            "<form><select name=\"size\"><option>S</option>\
+            <option value=\"m\" selected> M </option></select>\
+            <select name=\"tags\" multiple><option>a</option></select></form>",
+        );
+        assert_eq!(form["action"], Value::Null);
+        assert_eq!(form["method"], "get");
+        assert_eq!(
+            form["fields"][0],
+            json!({
+                "name": "size",
+                "type": "select-one",
+                "value": "m",
+                "options": [
+                    {"value": "S", "text": "S", "selected": false},
+                    {"value": "m", "text": "M", "selected": true},
+                ],
+                "required": false,
+                "disabled": false,
+            })
+        );
+        assert_eq!(form["fields"][1]["value"], Value::Null);
+    }
+}
@@ -0,0 +1,161 @@
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+/// All files a glob pattern expanded to, plus all problems encountered while looking for them.
+pub struct Expanded {
+    /// Matching files in a stable order, namely depth-first and sorted by name per directory.
+    pub paths: Vec<String>,
+    /// Errors that didn't stop the walk, like symlink loops or unreadable directories.
+    pub errors: Vec<walkdir::Error>,
+}
+
+/// Checks that the pattern is a valid glob, as used by `--glob`.
+pub fn parse_pattern(pattern: &str) -> Result<String, globset::Error> {
+    matcher(pattern)?;
+    Ok(pattern.to_string())
+}
+
+fn matcher(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    Ok(GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher())
+}
+
+/// Walks the directory tree below the literal prefix of the pattern and collects all files matching it.
+pub fn expand(pattern: &str) -> Result<Expanded, globset::Error> {
+    let matcher = matcher(pattern)?;
+    let root = literal_prefix(pattern);
+    let walk_root = if root.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &root
+    };
+
+    let mut expanded = Expanded {
+        paths: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let walker = WalkDir::new(walk_root)
+        .follow_links(true)
+        .sort_by_file_name();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                expanded.errors.push(err);
+                continue;
+            }
+        };
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        // strip the `./` again that was only added for walking
+        let path = if root.as_os_str().is_empty() {
+            entry.path().strip_prefix(".").unwrap_or(entry.path())
+        } else {
+            entry.path()
+        };
+        if matcher.is_match(path) {
+            expanded.paths.push(path.to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// The leading path components of the pattern that don't contain any glob syntax.
+fn literal_prefix(pattern: &str) -> PathBuf {
+    let path = Path::new(pattern);
+    let mut prefix = PathBuf::new();
+    let mut components = path.components().peekable();
+
+    while let Some(component) = components.next() {
+        // the last component is matched against, never walked into
+        if components.peek().is_none() {
+            break;
+        }
+        if let Component::Normal(part) = component
+            && part.to_string_lossy().contains(['*', '?', '[', '{'])
+        {
+            break;
+        }
+        prefix.push(component);
+    }
+
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_in(dir: &Path, pattern: &str) -> Vec<String> {
+        let pattern = format!("{}/{pattern}", dir.display());
+        expand(&pattern)
+            .unwrap()
+            .paths
+            .into_iter()
+            .map(|path| {
+                Path::new(&path)
+                    .strip_prefix(dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    fn tree(files: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in files {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn prefix_stops_at_glob_syntax() {
+        assert_eq!(literal_prefix("docs/**/*.html"), PathBuf::from("docs"));
+        assert_eq!(literal_prefix("a/b/c*.html"), PathBuf::from("a/b"));
+        assert_eq!(literal_prefix("*.html"), PathBuf::new());
+        assert_eq!(literal_prefix("a/b.html"), PathBuf::from("a"));
+    }
+
+    #[test]
+    fn recursive_glob_is_sorted() {
+        let dir = tree(&["b.html", "a/z.html", "a/b/c.html", "a/x.txt", "c.html"]);
+        assert_eq!(
+            expand_in(dir.path(), "**/*.html"),
+            ["a/b/c.html", "a/z.html", "b.html", "c.html"]
+        );
+    }
+
+    #[test]
+    fn single_star_stays_in_directory() {
+        let dir = tree(&["b.html", "a/z.html"]);
+        assert_eq!(expand_in(dir.path(), "*.html"), ["b.html"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop_is_reported() {
+        let dir = tree(&["a/x.html"]);
+        std::os::unix::fs::symlink(dir.path().join("a"), dir.path().join("a/loop")).unwrap();
+
+        let pattern = format!("{}/**/*.html", dir.path().display());
+        let expanded = expand(&pattern).unwrap();
+        assert_eq!(expanded.paths.len(), 1);
+        assert!(
+            expanded
+                .errors
+                .iter()
+                .any(|err| err.loop_ancestor().is_some())
+        );
+    }
+}
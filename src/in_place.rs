@@ -0,0 +1,96 @@
+use crate::FileError;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Replaces the contents of the file, see `--in-place`.
+///
+/// They're written to a temporary file next to it first, which then atomically takes its place
+/// with the same permissions, so the file stays as it was if anything fails. With a `backup`
+/// suffix, a copy of the original is kept at the path with the suffix appended.
+pub fn replace(path: &str, contents: &[u8], backup: Option<&str>) -> Result<(), FileError> {
+    let target = Path::new(path);
+    let temporary = temporary_path(target);
+    let result = write_temporary(target, &temporary, contents).and_then(|()| {
+        if let Some(suffix) = backup {
+            let backup = format!("{path}{suffix}");
+            fs::copy(target, &backup).map_err(FileError::new("create", &backup))?;
+        }
+        fs::rename(&temporary, target).map_err(FileError::new("replace", path))
+    });
+    if result.is_err() {
+        // it might not even have been created, and there's nothing more to do about it anyway
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// A path in the same directory as the target, so it can be renamed over it.
+fn temporary_path(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    target.with_file_name(format!(".{name}.hq-{}", process::id()))
+}
+
+fn write_temporary(target: &Path, temporary: &Path, contents: &[u8]) -> Result<(), FileError> {
+    let path = temporary.display().to_string();
+    let permissions = fs::metadata(target)
+        .map_err(FileError::new("read", &target.display().to_string()))?
+        .permissions();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temporary)
+        .map_err(FileError::new("create", &path))?;
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .map_err(FileError::new("write", &path))?;
+    fs::set_permissions(temporary, permissions).map_err(FileError::new("write", &path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page.html");
+        fs::write(&path, "old").unwrap();
+        let path = path.to_str().unwrap();
+
+        replace(path, b"new", Some(".bak")).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(format!("{path}.bak")).unwrap(), "old");
+
+        replace(path, b"newer", None).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "newer");
+        // nothing is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions_are_kept() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.html");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+
+        replace(path.to_str().unwrap(), b"new", None).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+    }
+
+    #[test]
+    fn missing_file_is_not_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.html");
+        let path = path.to_str().unwrap();
+
+        assert!(replace(path, b"new", None).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,66 @@
+use kuchikiki::NodeRef;
+use serde_json::{Map, Value};
+
+/// Describes a matched node as an object with its `tag`, `attributes`, `text` and `html`.
+///
+/// If `attributes` is non-empty, only those attributes are included in the `attributes` map.
+pub fn describe(node: &NodeRef, attributes: &[String], text: String) -> Value {
+    let mut object = Map::new();
+
+    let tag = node
+        .as_element()
+        .map_or(Value::Null, |elem| Value::from(&*elem.name.local));
+    object.insert("tag".to_string(), tag);
+    object.insert("attributes".to_string(), attribute_map(node, attributes));
+    object.insert("text".to_string(), Value::from(text));
+    object.insert("html".to_string(), Value::from(node.to_string()));
+
+    Value::Object(object)
+}
+
+/// Like [`describe`], but only with the fields the user asked for.
+///
+/// The `attributes` field is included if `attributes` is non-empty,
+/// the `text` field if `text` is given.
+pub fn describe_selected(node: &NodeRef, attributes: &[String], text: Option<String>) -> Value {
+    let mut object = Map::new();
+    if !attributes.is_empty() {
+        object.insert("attributes".to_string(), attribute_map(node, attributes));
+    }
+    if let Some(text) = text {
+        object.insert("text".to_string(), Value::from(text));
+    }
+
+    Value::Object(object)
+}
+
+/// Collects either all attributes of the node or only the given ones, if any are given.
+///
+/// Wildcards like `data-*` work like for [`crate::select_attributes`].
+fn attribute_map(node: &NodeRef, attributes: &[String]) -> Value {
+    let mut attrs = Map::new();
+    let Some(elem) = node.as_element() else {
+        return Value::Object(attrs);
+    };
+
+    let elem_attrs = elem.attributes.borrow();
+    if attributes.is_empty() {
+        for (name, attr) in &elem_attrs.map {
+            attrs.insert(name.local.to_string(), Value::from(attr.value.as_str()));
+        }
+    } else {
+        for attr in attributes {
+            if let Some(prefix) = attr.strip_suffix('*') {
+                for (name, value) in &elem_attrs.map {
+                    if name.local.starts_with(prefix) {
+                        attrs.insert(name.local.to_string(), Value::from(value.value.as_str()));
+                    }
+                }
+            } else if let Some(val) = elem_attrs.get(attr.as_str()) {
+                attrs.insert(attr.clone(), Value::from(val));
+            }
+        }
+    }
+
+    Value::Object(attrs)
+}
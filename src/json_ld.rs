@@ -0,0 +1,73 @@
+//! Structured data embedded as JSON-LD, in `<script type="application/ld+json">`.
+
+use html5ever::local_name;
+use kuchikiki::NodeRef;
+use kuchikiki::traits::NodeIterator;
+use serde_json::Value;
+
+/// The content of all JSON-LD scripts in the node, including itself, in document order.
+///
+/// Comments and CDATA markers wrapped around the JSON are stripped, as is surrounding whitespace.
+pub fn blocks(node: &NodeRef) -> Vec<String> {
+    node.inclusive_descendants()
+        .elements()
+        .filter(|elem| {
+            elem.name.local == local_name!("script")
+                && elem.attributes.borrow().get("type").is_some_and(|kind| {
+                    let kind = kind.split(';').next().unwrap_or_default().trim();
+                    kind.eq_ignore_ascii_case("application/ld+json")
+                })
+        })
+        .map(|script| unwrap(&script.text_contents()).to_string())
+        .collect()
+}
+
+/// Parses a block returned by [`blocks`].
+pub fn parse(block: &str) -> serde_json::Result<Value> {
+    serde_json::from_str(block)
+}
+
+/// Strips wrappers like `<!-- … -->` and `//<![CDATA[ … //]]>` off the JSON.
+fn unwrap(mut text: &str) -> &str {
+    let wrappers = [
+        ("<!--", "-->"),
+        ("<![CDATA[", "]]>"),
+        ("//<![CDATA[", "//]]>"),
+    ];
+    loop {
+        text = text.trim();
+        let Some(inner) = wrappers.iter().find_map(|(start, end)| {
+            text.strip_prefix(start)
+                .and_then(|text| text.strip_suffix(end))
+        }) else {
+            return text;
+        };
+        text = inner;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    #[test]
+    fn only_json_ld_scripts_are_found() {
+        let doc = kuchikiki::parse_html().one(
+            "<script>var a = 1;</script>\
+            <script type=\"application/ld+json\">\n  {\"@type\": \"Product\"}\n</script>\
+            <script type=\"Application/LD+JSON; charset=utf-8\"><!-- [1] --></script>\
+            <script type=\"application/ld+json\">//<![CDATA[\n{}\n//]]></script>",
+        );
+        assert_eq!(blocks(&doc), ["{\"@type\": \"Product\"}", "[1]", "{}"]);
+    }
+
+    #[test]
+    fn blocks_are_parsed() {
+        assert_eq!(
+            parse("{\"a\": [1]}").unwrap(),
+            serde_json::json!({"a": [1]})
+        );
+        assert!(parse("{a: 1}").is_err());
+    }
+}
@@ -0,0 +1,609 @@
+//! Like jq, but for HTML.
+//!
+//! The center of hq is the [`Query`], which selects nodes out of a [`Document`] using CSS
+//! selectors or [XPath](xpath), cleans them up and outputs them in one of several ways:
+//!
+//! ```
+//! use hq::Query;
+//!
+//! let html = r#"<a class="title" href="/a">Hello<script>track()</script></a>"#;
+//! let matches = Query::new("a.title")
+//!     .remove("script")
+//!     .text_only(true)
+//!     .run(html.as_bytes())?;
+//!
+//! assert_eq!(matches.len(), 1);
+//! assert_eq!(matches[0].text(), "Hello");
+//! assert_eq!(matches[0].render(), "Hello\n");
+//! # Ok::<(), hq::Error>(())
+//! ```
+
+pub mod check;
+mod css;
+pub mod encoding;
+pub mod form;
+pub mod json;
+pub mod json_ld;
+pub mod link;
+pub mod markdown;
+pub mod meta;
+pub mod minify;
+pub mod outline;
+mod position;
+pub mod pretty_print;
+mod query;
+pub mod record;
+pub mod sanitize;
+pub mod serialize;
+pub mod sort;
+pub mod stats;
+pub mod table;
+pub mod template;
+pub mod text;
+pub mod wrap;
+pub mod xpath;
+
+pub use position::Position;
+pub use query::{Format, Match, Query};
+pub use text::TextOptions;
+
+use html5ever::{LocalName, QualName, namespace_url, ns};
+use kuchikiki::traits::TendrilSink;
+use kuchikiki::{Doctype, NodeRef};
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use url::Url;
+use xml5ever::driver::XmlParseOpts;
+
+/// A parsed HTML document, along with the URL it was retrieved from, if known.
+#[derive(Debug, Clone)]
+pub struct Document {
+    root: NodeRef,
+    url: Option<Url>,
+    positions: Option<Rc<position::Positions>>,
+    parse_errors: Vec<check::ParseError>,
+}
+
+impl Document {
+    /// Parses a whole HTML document, detecting its encoding using [`encoding::sniff`].
+    pub fn parse(reader: &mut impl io::Read) -> io::Result<Self> {
+        Self::parse_with(reader, &ParseOptions::default())
+    }
+
+    /// Like [`Document::parse`], but also remembers where in the input each element starts.
+    ///
+    /// See [`Document::position`].
+    pub fn parse_with_positions(reader: &mut impl io::Read) -> io::Result<Self> {
+        let options = ParseOptions {
+            positions: true,
+            ..ParseOptions::default()
+        };
+        Self::parse_with(reader, &options)
+    }
+
+    /// Parses a whole HTML document as configured by the options.
+    pub fn parse_with(reader: &mut impl io::Read, options: &ParseOptions) -> io::Result<Self> {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        let encoding = options
+            .encoding
+            .unwrap_or_else(|| encoding::sniff(&input, options.transport_encoding));
+        let input = if options.strict {
+            Cow::Owned(
+                encoding::decode_strict(&input, encoding)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            )
+        } else {
+            encoding::decode(&input, encoding)
+        };
+
+        if options.xml {
+            let sink = kuchikiki::parse_html().tokenizer.sink.sink;
+            let root = xml5ever::driver::parse_document(sink, XmlParseOpts::default()).one(&*input);
+            return Ok(Self::from(root));
+        }
+        if let Some(context) = &options.fragment {
+            return Ok(Self::from(parse_fragment(&input, context)));
+        }
+        if !options.positions && !options.parse_errors {
+            return Ok(Self::from(kuchikiki::parse_html().one(&*input)));
+        }
+        // positions are about the decoded input, which only differs for non-UTF-8 input
+        let (root, positions, parse_errors) =
+            position::parse(input.as_bytes(), options.parse_errors);
+        Ok(Self {
+            positions: options.positions.then(|| Rc::new(positions)),
+            parse_errors,
+            ..Self::from(root)
+        })
+    }
+
+    /// Remembers where the document is from, making links relative to it.
+    #[must_use]
+    pub fn with_url(mut self, url: Option<Url>) -> Self {
+        self.url = url;
+        self
+    }
+
+    /// The document node, i.e. the parent of the root element.
+    pub fn root(&self) -> &NodeRef {
+        &self.root
+    }
+
+    pub fn url(&self) -> Option<&Url> {
+        self.url.as_ref()
+    }
+
+    /// The errors the parser recovered from, in the order they occurred.
+    ///
+    /// Only collected if [parsed](Document::parse_with) with [`ParseOptions::parse_errors`].
+    pub fn parse_errors(&self) -> &[check::ParseError] {
+        &self.parse_errors
+    }
+
+    /// Where the start tag of the node is in the input.
+    ///
+    /// Only known if parsed using [`Document::parse_with_positions`], and then only for elements
+    /// that had a start tag in the input at all. For anything else, this falls back to the first
+    /// descendant and then to the closest ancestor with a known position.
+    pub fn position(&self, node: &NodeRef) -> Option<Position> {
+        let positions = self.positions.as_ref()?;
+        let lookup = |node: NodeRef| positions.get(&position::key(&node)).copied();
+        node.inclusive_descendants()
+            .find_map(lookup)
+            .or_else(|| node.ancestors().find_map(lookup))
+    }
+}
+
+impl From<NodeRef> for Document {
+    fn from(root: NodeRef) -> Self {
+        Self {
+            root,
+            url: None,
+            positions: None,
+            parse_errors: Vec::new(),
+        }
+    }
+}
+
+/// How [`Document::parse_with`] reads the input.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// The encoding the input was declared to be in when transferred, like the `charset` of
+    /// an HTTP `Content-Type`. Takes precedence over a `<meta charset>` in the document.
+    pub transport_encoding: Option<&'static encoding::Encoding>,
+    /// Decode the input using this encoding, regardless of what it is declared to be in.
+    pub encoding: Option<&'static encoding::Encoding>,
+    /// Fail on input that isn't valid in its encoding, instead of replacing it by U+FFFD.
+    pub strict: bool,
+    /// Remember where in the input each element starts, see [`Document::position`].
+    ///
+    /// Not supported for XML.
+    pub positions: bool,
+    /// Collect the errors the parser recovered from, like a stray end tag,
+    /// see [`Document::parse_errors`].
+    ///
+    /// Not supported for XML or fragments.
+    pub parse_errors: bool,
+    /// Parse the input as XML instead of HTML, like for RSS feeds or SVG.
+    ///
+    /// Tag names are then case-sensitive and there is no error recovery adding or moving
+    /// elements. Elements in a namespace like `<atom:link>` are selected by their local name,
+    /// like `link`.
+    pub xml: bool,
+    /// Parse the input as fragment in the context of an element with this name, like `body`
+    /// or `tr`, instead of as whole document.
+    ///
+    /// The top-level nodes of the input are then the children of the document node, without
+    /// any `<html>`, `<head>` or `<body>` around them. So `:root` matches each top-level element.
+    /// Not supported together with `positions` or `xml`.
+    pub fragment: Option<String>,
+}
+
+/// Parses the input as fragment, moving the nodes out of the `<html>` the parser puts them in.
+pub(crate) fn parse_fragment(input: &str, context: &str) -> NodeRef {
+    let context = QualName::new(None, ns!(html), LocalName::from(context));
+    let document = kuchikiki::parse_fragment(context, Vec::new()).one(input);
+    if let Some(html) = document.first_child() {
+        for child in html.children().collect::<Vec<_>>() {
+            document.append(child);
+        }
+        html.detach();
+    }
+    document
+}
+
+/// Which part of a [`Query`] a selector belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectorRole {
+    /// The selector deciding which nodes match, see [`Query::new`].
+    Main,
+    /// A selector applied within the matches of the previous one, see [`Query::then_select`].
+    Stage,
+    /// The selector of the matches to group, see [`Query::then_select_grouped`].
+    Group,
+    /// A selector of nodes to remove, see [`Query::remove`].
+    Removal,
+    /// A selector of elements to unwrap, see [`Query::unwrap_elements`].
+    Unwrap,
+}
+
+/// Everything that can go wrong while running a [`Query`].
+#[derive(Debug)]
+pub enum Error {
+    /// One of the query's CSS selectors couldn't be parsed.
+    InvalidSelector {
+        selector: String,
+        role: SelectorRole,
+    },
+    /// The XPath expression given to [`Query::xpath`] couldn't be parsed.
+    InvalidXPath {
+        expression: String,
+        error: xpath::ParseError,
+    },
+    /// A match lacks an attribute used in the template, see [`Query::strict_template`].
+    MissingAttribute { name: String },
+    /// The index given to [`Query::nth`] doesn't exist.
+    NthOutOfRange { nth: isize, len: usize },
+    /// Reading the input or writing the output failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSelector { selector, role } => {
+                let role = match role {
+                    SelectorRole::Main => "",
+                    SelectorRole::Stage => " to select within matches",
+                    SelectorRole::Group => " to select groups within matches",
+                    SelectorRole::Removal => " of nodes to remove",
+                    SelectorRole::Unwrap => " of elements to unwrap",
+                };
+                write!(f, "invalid CSS selector{role} `{selector}`")
+            }
+            Self::InvalidXPath { expression, error } => {
+                write!(f, "invalid XPath expression `{expression}`: {error}")
+            }
+            Self::MissingAttribute { name } => {
+                write!(f, "a match has no attribute `{name}` used in the template")
+            }
+            Self::NthOutOfRange { nth, len } => {
+                write!(f, "index {nth} is out of range, only {len} nodes matched")
+            }
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::InvalidXPath { error, .. } => Some(error),
+            Self::Io(err) => Some(err),
+            Self::InvalidSelector { .. }
+            | Self::MissingAttribute { .. }
+            | Self::NthOutOfRange { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Io(err.into())
+    }
+}
+
+/// How [`select_attributes`] writes the values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttributeOptions {
+    /// Prefix each value with the attribute name, like `src=logo.png`.
+    ///
+    /// Backslashes, newlines, carriage returns and tabs in values are then escaped
+    /// like `\n`, so each line stays one attribute.
+    pub with_names: bool,
+    /// With `with_names`, separate names and values by a tab instead of `=`.
+    pub tsv: bool,
+    /// Write this instead of attributes the node doesn't have, so there is one line per
+    /// requested attribute. Wildcards like `data-*` are still skipped if nothing matches.
+    pub missing: Option<String>,
+    /// End each value with a NUL byte instead of a newline, so values can contain newlines.
+    pub null_terminated: bool,
+}
+
+/// Writes the values of the given attributes of the node, one per line,
+/// or [ended by NUL bytes](AttributeOptions::null_terminated), returning how many of them the node has.
+///
+/// Attributes the node doesn't have are skipped, unless there is a
+/// [placeholder](AttributeOptions::missing) for them. A name ending in `*` like `data-*` stands
+/// for all attributes starting with the rest, in the order they are on the element, and a bare
+/// `*` for all of them. Those are always written with their names, as if `with_names` was given.
+/// Like names themselves, this is case-sensitive, and HTML attribute names are lowercase.
+pub fn select_attributes(
+    node: &NodeRef,
+    attributes: &[String],
+    options: &AttributeOptions,
+    output: &mut dyn io::Write,
+) -> io::Result<usize> {
+    let mut found = 0;
+    let elem_atts = node
+        .as_element()
+        .and_then(|elem| elem.attributes.try_borrow().ok());
+    for attr in attributes {
+        if let Some(prefix) = attr.strip_suffix('*') {
+            for (name, value) in elem_atts.iter().flat_map(|atts| &atts.map) {
+                if name.local.starts_with(prefix) {
+                    found += 1;
+                    write_named_attribute(&name.local, &value.value, options, output)?;
+                }
+            }
+            continue;
+        }
+
+        let val = match elem_atts.as_ref().and_then(|atts| atts.get(attr.as_str())) {
+            Some(val) => {
+                found += 1;
+                val
+            }
+            None => match &options.missing {
+                Some(placeholder) => placeholder,
+                None => continue,
+            },
+        };
+        if options.with_names {
+            write_named_attribute(attr, val, options, output)?;
+        } else {
+            write!(output, "{val}{}", terminator(options))?;
+        }
+    }
+    Ok(found)
+}
+
+/// Writes the class names of the node, one per line or [ended by NUL
+/// bytes](AttributeOptions::null_terminated), returning how many there are.
+///
+/// They're split on ASCII whitespace like browsers do, so empty ones like between two spaces
+/// are skipped. Nodes without a `class` attribute have none.
+pub fn select_classes(
+    node: &NodeRef,
+    options: &AttributeOptions,
+    output: &mut dyn io::Write,
+) -> io::Result<usize> {
+    let Some(elem) = node.as_element() else {
+        return Ok(0);
+    };
+    let attributes = elem.attributes.borrow();
+    let mut found = 0;
+    for class in attributes
+        .get("class")
+        .into_iter()
+        .flat_map(str::split_ascii_whitespace)
+    {
+        found += 1;
+        write!(output, "{class}{}", terminator(options))?;
+    }
+    Ok(found)
+}
+
+fn write_named_attribute(
+    name: &str,
+    value: &str,
+    options: &AttributeOptions,
+    output: &mut dyn io::Write,
+) -> io::Result<()> {
+    let separator = if options.tsv { '\t' } else { '=' };
+    let value = escape_line(value);
+    write!(output, "{name}{separator}{value}{}", terminator(options))
+}
+
+fn terminator(options: &AttributeOptions) -> char {
+    if options.null_terminated { '\0' } else { '\n' }
+}
+
+/// Escapes the value so it fits on one line and can be told apart from a separating tab.
+fn escape_line(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serializes the doctype like `<!DOCTYPE html>`.
+///
+/// Unlike html5ever, this keeps the public and system identifier of legacy doctypes, which decide
+/// whether browsers render the document in quirks mode.
+pub fn serialize_doctype(doctype: &Doctype) -> String {
+    let mut serialized = "<!DOCTYPE".to_string();
+    if !doctype.name.is_empty() {
+        serialized.push(' ');
+        serialized.push_str(&doctype.name);
+    }
+    if !doctype.public_id.is_empty() {
+        serialized.push_str(" PUBLIC ");
+        push_quoted(&mut serialized, &doctype.public_id);
+        if !doctype.system_id.is_empty() {
+            serialized.push(' ');
+            push_quoted(&mut serialized, &doctype.system_id);
+        }
+    } else if !doctype.system_id.is_empty() {
+        serialized.push_str(" SYSTEM ");
+        push_quoted(&mut serialized, &doctype.system_id);
+    }
+    serialized.push('>');
+    serialized
+}
+
+/// Identifiers can't contain their own quote, but either quote.
+fn push_quoted(serialized: &mut String, id: &str) {
+    let quote = if id.contains('"') { '\'' } else { '"' };
+    serialized.push(quote);
+    serialized.push_str(id);
+    serialized.push(quote);
+}
+
+/// Concatenates all text contained in the node, except that of scripts and styles in it.
+///
+/// With `ignore_whitespace`, text nodes consisting only of whitespace are skipped
+/// and all others are terminated by a newline. See [`text::extract`] for more options.
+pub fn serialize_text(node: &NodeRef, ignore_whitespace: bool) -> String {
+    let options = TextOptions {
+        raw: true,
+        ignore_whitespace,
+        ..TextOptions::default()
+    };
+    text::extract(node, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes_of(html: &str, attributes: &[&str], options: &AttributeOptions) -> String {
+        let doc = kuchikiki::parse_html().one(html);
+        let node = doc.select_first("body > *").unwrap();
+        let attributes: Vec<_> = attributes.iter().map(ToString::to_string).collect();
+        let mut output = Vec::new();
+        select_attributes(node.as_node(), &attributes, options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn fragments_have_no_wrapper() {
+        let options = ParseOptions {
+            fragment: Some("tr".to_string()),
+            ..ParseOptions::default()
+        };
+        let document = Document::parse_with(&mut "<td>a</td>b<td>c</td>".as_bytes(), &options);
+        let root = document.unwrap().root().clone();
+        let roots: Vec<_> = root.select(":root").unwrap().collect();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].name.local.as_ref(), "td");
+        assert_eq!(root.children().count(), 3);
+        assert_eq!(root.text_contents(), "abc");
+    }
+
+    #[test]
+    fn attribute_values() {
+        let html = "<img src=\"logo.png\" alt=\"Lo\ngo\">";
+        let options = AttributeOptions::default();
+        assert_eq!(
+            attributes_of(html, &["src", "title", "alt"], &options),
+            "logo.png\nLo\ngo\n"
+        );
+    }
+
+    #[test]
+    fn attribute_names() {
+        let html = "<img src=\"logo.png\" alt=\"Lo\ngo\\\tx\">";
+        let mut options = AttributeOptions {
+            with_names: true,
+            ..AttributeOptions::default()
+        };
+        assert_eq!(
+            attributes_of(html, &["src", "title", "alt"], &options),
+            "src=logo.png\nalt=Lo\\ngo\\\\\\tx\n"
+        );
+        options.tsv = true;
+        assert_eq!(
+            attributes_of(html, &["alt", "src"], &options),
+            "alt\tLo\\ngo\\\\\\tx\nsrc\tlogo.png\n"
+        );
+    }
+
+    #[test]
+    fn null_terminated_attributes() {
+        let html = "<img src=\"logo.png\" alt=\"Lo\ngo\">";
+        let mut options = AttributeOptions {
+            null_terminated: true,
+            ..AttributeOptions::default()
+        };
+        assert_eq!(
+            attributes_of(html, &["src", "alt"], &options),
+            "logo.png\0Lo\ngo\0"
+        );
+        options.with_names = true;
+        assert_eq!(attributes_of(html, &["alt"], &options), "alt=Lo\\ngo\0");
+    }
+
+    #[test]
+    fn classes_are_split_on_whitespace() {
+        let classes = |html: &str, options: &AttributeOptions| {
+            let doc = kuchikiki::parse_html().one(html);
+            let node = doc.select_first("body > *").unwrap();
+            let mut output = Vec::new();
+            let found = select_classes(node.as_node(), options, &mut output).unwrap();
+            (found, String::from_utf8(output).unwrap())
+        };
+        let options = AttributeOptions::default();
+        assert_eq!(
+            classes("<p class=\"  a  b\tc\n\nd\r\x0ce \">", &options),
+            (5, "a\nb\nc\nd\ne\n".to_string())
+        );
+        assert_eq!(classes("<p class=\" \">", &options), (0, String::new()));
+        assert_eq!(classes("<p>", &options), (0, String::new()));
+
+        let options = AttributeOptions {
+            null_terminated: true,
+            ..AttributeOptions::default()
+        };
+        assert_eq!(
+            classes("<p class=\"a b\">", &options),
+            (2, "a\0b\0".to_string())
+        );
+    }
+
+    #[test]
+    fn attribute_wildcards() {
+        let html = "<div data-id=\"7\" class=\"widget\" data-state=\"open\" data=\"x\" DATA-Mode=\"a\"></div>";
+        let options = AttributeOptions::default();
+        assert_eq!(
+            attributes_of(html, &["data-*"], &options),
+            "data-id=7\ndata-state=open\ndata-mode=a\n"
+        );
+        assert_eq!(
+            attributes_of(html, &["class", "*"], &options),
+            "widget\ndata-id=7\nclass=widget\ndata-state=open\ndata=x\ndata-mode=a\n"
+        );
+        assert_eq!(attributes_of(html, &["Data-*", "aria-*"], &options), "");
+    }
+
+    #[test]
+    fn missing_attribute_placeholders() {
+        let doc = kuchikiki::parse_html().one("<a href=\"/a\">A</a>");
+        let link = doc.select_first("a").unwrap();
+        let attributes = ["href", "title", "data-*"].map(String::from);
+        let mut options = AttributeOptions {
+            missing: Some(String::new()),
+            ..AttributeOptions::default()
+        };
+
+        let mut output = Vec::new();
+        let found = select_attributes(link.as_node(), &attributes, &options, &mut output);
+        assert_eq!(found.unwrap(), 1);
+        assert_eq!(output, b"/a\n\n");
+
+        options.missing = Some("-".to_string());
+        options.with_names = true;
+        let text = link.as_node().first_child().unwrap();
+        let mut output = Vec::new();
+        let found = select_attributes(&text, &attributes, &options, &mut output);
+        assert_eq!(found.unwrap(), 0);
+        assert_eq!(output, b"href=-\ntitle=-\n");
+    }
+}
@@ -1,33 +1,491 @@
 use html5ever::local_name;
-use kuchikiki::NodeRef;
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{Attributes, NodeRef};
+use std::cell::RefCell;
 use url::Url;
 
-pub fn rewrite_relative_url(node: &NodeRef, base: &Url) {
+/// Which elements [`links`] looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKinds {
+    /// Only links that can be followed, i.e. `a` and `area`.
+    Navigation,
+    /// Also `link` elements, like stylesheets and feeds.
+    All,
+}
+
+/// A link found by [`links`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The `href` as it is in the document.
+    pub url: String,
+    /// The contained text, with all whitespace collapsed to single spaces.
+    pub text: String,
+}
+
+/// Finds all elements with an `href` in the node, including the node itself.
+pub fn links(node: &NodeRef, kinds: LinkKinds) -> Vec<Link> {
+    node.inclusive_descendants()
+        .elements()
+        .filter(|elem| {
+            let name = &elem.name.local;
+            *name == local_name!("a")
+                || *name == local_name!("area")
+                || (kinds == LinkKinds::All && *name == local_name!("link"))
+        })
+        .filter_map(|elem| {
+            let url = elem.attributes.borrow().get("href")?.to_string();
+            let text = crate::serialize_text(elem.as_node(), false);
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            Some(Link { url, text })
+        })
+        .collect()
+}
+
+/// Which attributes of which elements hold URLs, see [`rewrite_relative_url`].
+const URL_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("a", &["href"]),
+    ("area", &["href"]),
+    ("audio", &["src"]),
+    ("blockquote", &["cite"]),
+    ("button", &["formaction"]),
+    ("del", &["cite"]),
+    ("embed", &["src"]),
+    ("form", &["action"]),
+    ("iframe", &["src", "longdesc"]),
+    ("img", &["src", "srcset", "longdesc"]),
+    ("input", &["src", "formaction"]),
+    ("ins", &["cite"]),
+    ("link", &["href"]),
+    ("object", &["data"]),
+    ("q", &["cite"]),
+    ("script", &["src"]),
+    ("source", &["src", "srcset"]),
+    ("track", &["src"]),
+    ("video", &["src", "poster"]),
+];
+
+/// How [`rewrite_relative_url`] and [`relativize_url`] rewrite URLs.
+#[derive(Debug, Clone, Default)]
+pub struct RewriteOptions {
+    /// Further attributes holding URLs on any element, like `data-src` used for lazy loading.
+    pub extra_attributes: Vec<String>,
+    /// Also resolve references to anchors like `#section`, which are kept as they are otherwise.
+    pub fragments: bool,
+    /// Also resolve `url(…)` references in `style` attributes and `<style>` elements.
+    pub css: bool,
+    /// Put relative URLs after the base as they are, without percent-encoding them or
+    /// resolving `..` segments. By default, the result is a valid, normalized URL.
+    pub verbatim: bool,
+    /// Called about values that are left as they are since they are malformed,
+    /// like a `srcset` with unknown descriptors.
+    pub warn: Option<fn(&str)>,
+}
+
+/// Like [`rewrite_relative_url`], but for all elements in the node, including itself.
+pub fn rewrite_relative_urls(node: &NodeRef, base: &Url, options: &RewriteOptions) {
+    for node in node.inclusive_descendants() {
+        rewrite_relative_url(&node, base, options);
+    }
+}
+
+/// Resolves the URLs in the attributes of the node against the base.
+///
+/// Which attributes hold URLs depends on the element, like `href` for `a` and `src` for `img`,
+/// plus the [extra attributes](RewriteOptions::extra_attributes) and the URL in the `content`
+/// of a `<meta http-equiv="refresh">`.
+/// Each of the URLs in a `srcset` is resolved on its own. URLs that have a scheme already, like
+/// `https:`, `mailto:` or `javascript:`, are kept exactly as they are,
+/// as are references to anchors unless [asked for](RewriteOptions::fragments).
+pub fn rewrite_relative_url(node: &NodeRef, base: &Url, options: &RewriteOptions) {
+    if options.verbatim {
+        rewrite_urls(node, options, &|url| join_verbatim(url, base));
+    } else {
+        rewrite_urls(node, options, &|url| resolve(url, base));
+    }
+}
+
+/// Like [`relativize_url`], but for all elements in the node, including itself.
+pub fn relativize_urls(node: &NodeRef, prefix: &Url, options: &RewriteOptions) {
+    for node in node.inclusive_descendants() {
+        relativize_url(&node, prefix, options);
+    }
+}
+
+/// Turns absolute URLs in the attributes of the node into ones relative to the prefix,
+/// the opposite of [`rewrite_relative_url`].
+///
+/// Only URLs with the same origin as the prefix are touched, all others are kept as they are.
+/// Like for resolving, the last segment of the prefix is a page unless it ends with a slash,
+/// so relative to `https://example.com/docs/`, `https://example.com/docs/a.html` becomes
+/// `a.html` and `https://example.com/img/x.png` becomes `../img/x.png`.
+pub fn relativize_url(node: &NodeRef, prefix: &Url, options: &RewriteOptions) {
+    rewrite_urls(node, options, &|url| relativize(url, prefix));
+}
+
+/// Replaces all URLs in the attributes of the node by what `rewrite` makes of them.
+fn rewrite_urls(node: &NodeRef, options: &RewriteOptions, rewrite: &dyn Fn(&str) -> String) {
     let Some(elem) = node.as_element() else {
         return;
     };
-    if !(local_name!("a") == elem.name.local
-        || local_name!("link") == elem.name.local
-        || local_name!("area") == elem.name.local)
-    {
-        return;
+    if options.css {
+        if elem.name.local == local_name!("style") {
+            for text in node.children().text_nodes() {
+                let rewritten = rewrite_css_urls(&text.borrow(), options, rewrite);
+                *text.borrow_mut() = rewritten;
+            }
+        }
+        if let Some(style) = elem.attributes.borrow_mut().get_mut("style") {
+            *style = rewrite_css_urls(style, options, rewrite);
+        }
     }
+    if elem.name.local == local_name!("meta") {
+        rewrite_refresh(&elem.attributes, options, rewrite);
+    }
+    let standard = URL_ATTRIBUTES
+        .iter()
+        .find(|(name, _)| elem.name.local == **name)
+        .map_or(&[][..], |(_, attributes)| attributes);
+    let extra = options.extra_attributes.iter().map(String::as_str);
     let mut attrs = elem.attributes.borrow_mut();
 
-    if attrs.contains("href") {
-        let Some(url) = attrs.get_mut("href") else {
-            return;
+    for name in standard.iter().copied().chain(extra) {
+        if let Some(url) = attrs.get_mut(name) {
+            if url.starts_with('#') && !options.fragments {
+                continue;
+            }
+            if name != "srcset" {
+                *url = rewrite(url);
+                continue;
+            }
+            match rewrite_srcset(url, rewrite) {
+                Some(rewritten) => *url = rewritten,
+                None => {
+                    if let Some(warn) = options.warn {
+                        warn(&format!("leaving malformed srcset `{url}` as it is"));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Resolves the `action` of all forms in the node, including itself, against the base.
+pub fn rewrite_form_actions(node: &NodeRef, base: &Url) {
+    for form in node.inclusive_descendants().elements() {
+        if form.name.local != local_name!("form") {
+            continue;
+        }
+        if let Some(action) = form.attributes.borrow_mut().get_mut("action") {
+            *action = resolve(action, base);
+        }
+    }
+}
+
+/// Resolves the possibly relative URL against the base, falling back to the base if invalid.
+///
+/// This follows the URL standard, so protocol-relative URLs like `//cdn.example.com/app.js`
+/// only take the scheme of the base, while absolute paths like `/app.js` take its origin.
+/// URLs with a scheme of their own are returned as they are, without normalizing them.
+pub(crate) fn resolve(url: &str, base: &Url) -> String {
+    if Url::parse(url).is_ok() {
+        return url.to_string();
+    }
+    if url.starts_with("////") {
+        return url.trim_start_matches('/').to_string();
+    }
+    base.join(url)
+        .ok()
+        .unwrap_or_else(|| base.to_owned())
+        .to_string()
+}
+
+/// Like [`resolve`], but only replacing the part of the base the relative URL replaces,
+/// without touching the relative URL itself.
+fn join_verbatim(url: &str, base: &Url) -> String {
+    if Url::parse(url).is_ok() {
+        return url.to_string();
+    }
+    let kept = if url.starts_with("//") {
+        &base[..url::Position::AfterScheme]
+    } else if url.starts_with('/') {
+        &base[..url::Position::BeforePath]
+    } else if url.starts_with('?') {
+        &base[..url::Position::AfterPath]
+    } else if url.starts_with('#') {
+        &base[..url::Position::AfterQuery]
+    } else {
+        let path = &base[..url::Position::AfterPath];
+        &path[..path.rfind('/').map_or(path.len(), |idx| idx + 1)]
+    };
+    let separator = if url.starts_with("//") { ":" } else { "" };
+    format!("{kept}{separator}{url}")
+}
+
+/// The URL relative to the prefix if it is absolute and has the same origin,
+/// otherwise the URL as it is.
+fn relativize(url: &str, prefix: &Url) -> String {
+    let Ok(absolute) = Url::parse(url) else {
+        return url.to_string();
+    };
+    if absolute.origin() != prefix.origin() {
+        return url.to_string();
+    }
+    match prefix.make_relative(&absolute) {
+        // the prefix itself, which can't be referred to by nothing if it's a directory
+        Some(relative) if relative.is_empty() && prefix.path().ends_with('/') => "./".to_string(),
+        Some(relative) => relative,
+        None => url.to_string(),
+    }
+}
+
+/// Rewrites each URL in the comma-separated list of image candidates, keeping their descriptors.
+///
+/// Returns `None` if the list is malformed, like with a descriptor that isn't a width,
+/// height or pixel density.
+fn rewrite_srcset(srcset: &str, rewrite: &dyn Fn(&str) -> String) -> Option<String> {
+    let candidates = parse_srcset(srcset)?;
+    let rewritten: Vec<_> = candidates
+        .into_iter()
+        .map(|(url, descriptors)| {
+            let url = rewrite(url);
+            if descriptors.is_empty() {
+                url
+            } else {
+                format!("{url} {}", descriptors.join(" "))
+            }
+        })
+        .collect();
+    Some(rewritten.join(", "))
+}
+
+/// Splits the `srcset` into its URLs along with their descriptors, like `2x` or `640w`.
+fn parse_srcset(srcset: &str) -> Option<Vec<(&str, Vec<&str>)>> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            return Some(candidates);
+        }
+
+        // URLs can contain commas themselves, only whitespace or a trailing comma ends them
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (url, after) = rest.split_at(end);
+        let (url, descriptors) = match url.strip_suffix(',') {
+            Some(url) => {
+                rest = after;
+                (url.trim_end_matches(','), Vec::new())
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                rest = &after[end..];
+                (url, after[..end].split_ascii_whitespace().collect())
+            }
         };
-        if url.starts_with("////") {
-            *url = url.trim_start_matches('/').to_string();
-            return;
+
+        if descriptors.len() > 2 || !descriptors.iter().all(|d| is_srcset_descriptor(d)) {
+            return None;
         }
-        let new_url = base.join(url).ok().unwrap_or_else(|| base.to_owned());
-        attrs.insert("href", new_url.to_string());
+        candidates.push((url, descriptors));
     }
 }
 
-pub fn detect_base(document: &NodeRef) -> Option<Url> {
+/// If this is a width like `640w`, a height like `480h` or a pixel density like `1.5x`.
+fn is_srcset_descriptor(descriptor: &str) -> bool {
+    let Some(number) = descriptor.strip_suffix(['w', 'h', 'x']) else {
+        return false;
+    };
+    !number.is_empty()
+        && !number.starts_with(['+', '-'])
+        && number.parse::<f64>().is_ok_and(f64::is_finite)
+}
+
+/// Rewrites the URLs of all `url(…)` references in the CSS, like in `background: url(a.png)`.
+///
+/// URLs with escapes in them are kept as they are, as are `data:` URLs.
+fn rewrite_css_urls(
+    css: &str,
+    options: &RewriteOptions,
+    rewrite: &dyn Fn(&str) -> String,
+) -> String {
+    let mut rewritten = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = find_url_function(rest) {
+        let (before, argument) = rest.split_at(start + "url(".len());
+        rewritten.push_str(before);
+        rest = argument;
+
+        let Some(reference) = parse_url_argument(argument) else {
+            continue;
+        };
+        let url = &argument[reference.start..reference.end];
+        let keep = url.is_empty()
+            || url.contains('\\')
+            || (url.starts_with('#') && !options.fragments)
+            || url
+                .get(..5)
+                .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"));
+
+        rewritten.push_str(&argument[..reference.start]);
+        if keep {
+            rewritten.push_str(url);
+        } else {
+            rewritten.push_str(&escape_css_url(&rewrite(url), reference.quote));
+        }
+        rest = &argument[reference.end..];
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Where the URL of a `url(…)` reference is, relative to after the opening parenthesis.
+struct CssUrl {
+    start: usize,
+    end: usize,
+    quote: Option<char>,
+}
+
+/// The byte index of the next `url(` that isn't the end of a longer name like `myurl(`.
+fn find_url_function(css: &str) -> Option<usize> {
+    let lowercase = css.to_ascii_lowercase();
+    lowercase
+        .match_indices("url(")
+        .map(|(idx, _)| idx)
+        .find(|&idx| {
+            !css[..idx]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Locates the URL in what follows `url(`, returning `None` if there is no closing parenthesis.
+fn parse_url_argument(argument: &str) -> Option<CssUrl> {
+    let is_space = |c: char| c.is_ascii_whitespace();
+    let start = argument.len() - argument.trim_start_matches(is_space).len();
+    let quote = argument[start..]
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '"' | '\''));
+
+    let (start, end, after) = match quote {
+        Some(quote) => {
+            let mut escaped = false;
+            let len = argument[start + 1..].find(|c| {
+                let is_end = !escaped && (c == quote || c == '\n');
+                escaped = !escaped && c == '\\';
+                is_end
+            })?;
+            let end = start + 1 + len;
+            if argument[end..].starts_with('\n') {
+                return None;
+            }
+            (start + 1, end, end + 1)
+        }
+        None => {
+            let len = argument[start..].find([')', '"', '\'', '('])?;
+            let url = argument[start..start + len].trim_end_matches(is_space);
+            if url.contains(is_space) {
+                return None;
+            }
+            (start, start + url.len(), start + url.len())
+        }
+    };
+
+    argument[after..]
+        .trim_start_matches(is_space)
+        .starts_with(')')
+        .then_some(CssUrl { start, end, quote })
+}
+
+/// Escapes what would end the URL early when put back into the `url(…)`.
+fn escape_css_url(url: &str, quote: Option<char>) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        let needs_escape = match quote {
+            Some(quote) => c == quote || c == '\\',
+            None => matches!(c, '(' | ')' | '"' | '\'' | '\\') || c.is_ascii_whitespace(),
+        };
+        if needs_escape {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Rewrites the URL in the `content` of a `<meta http-equiv="refresh">`,
+/// keeping the delay and everything around the URL as it is.
+fn rewrite_refresh(
+    attrs: &RefCell<Attributes>,
+    options: &RewriteOptions,
+    rewrite: &dyn Fn(&str) -> String,
+) {
+    let mut attrs = attrs.borrow_mut();
+    let is_refresh = attrs
+        .get("http-equiv")
+        .is_some_and(|equiv| equiv.trim().eq_ignore_ascii_case("refresh"));
+    if !is_refresh {
+        return;
+    }
+    let Some(content) = attrs.get_mut("content") else {
+        return;
+    };
+    let Some((start, end)) = refresh_url(content) else {
+        return;
+    };
+
+    let url = &content[start..end];
+    if url.starts_with('#') && !options.fragments {
+        return;
+    }
+    *content = format!("{}{}{}", &content[..start], rewrite(url), &content[end..]);
+}
+
+/// Where the URL is in a refresh like `0; url=/next`, `5, 'next'` or `3;URL = "/next"`.
+fn refresh_url(content: &str) -> Option<(usize, usize)> {
+    let is_space = |c: char| c.is_ascii_whitespace();
+    let rest = content.trim_start_matches(is_space);
+    let delay = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(rest.len());
+    if delay == 0 {
+        return None;
+    }
+
+    let rest = rest[delay..]
+        .trim_start_matches(is_space)
+        .strip_prefix([';', ','])?
+        .trim_start_matches(is_space);
+    let rest = match rest.get(..3) {
+        Some(name) if name.eq_ignore_ascii_case("url") => {
+            match rest[3..].trim_start_matches(is_space).strip_prefix('=') {
+                Some(value) => value.trim_start_matches(is_space),
+                None => rest,
+            }
+        }
+        _ => rest,
+    };
+    let (rest, quote) = match rest.strip_prefix(['"', '\'']) {
+        Some(unquoted) => (unquoted, rest.chars().next()),
+        None => (rest, None),
+    };
+
+    let len = match quote {
+        Some(quote) => rest.find(quote).unwrap_or(rest.len()),
+        None => rest.trim_end_matches(is_space).len(),
+    };
+    let start = content.len() - rest.len();
+    (len > 0).then_some((start, start + len))
+}
+
+/// Looks up the URL of the first `<base>` element in the document, if there is any.
+///
+/// Like in browsers, a relative one like `/en/` is resolved against the URL of the document,
+/// and ignored if that isn't known.
+pub fn detect_base(document: &NodeRef, document_url: Option<&Url>) -> Option<Url> {
     let Ok(node) = document.select_first("base") else {
         return None;
     };
@@ -38,7 +496,10 @@ pub fn detect_base(document: &NodeRef) -> Option<Url> {
         let href = attrs
             .get("href")
             .expect("should have retrieved href from node attributes");
-        return Url::parse(href).ok();
+        return match document_url {
+            Some(document_url) => document_url.join(href.trim()).ok(),
+            None => Url::parse(href.trim()).ok(),
+        };
     }
 
     None
@@ -63,7 +524,7 @@ mod tests {
                     .expect("Failed to parse CSS selector while doing link rewriting")
                 {
                     let node = css_match.as_node();
-                    rewrite_relative_url(&node, &base);
+                    rewrite_relative_url(&node, &base, &RewriteOptions::default());
                 }
 
                 let result = serialize_doc(&doc);
@@ -80,7 +541,7 @@ mod tests {
             fn $name() {
                 let (mut input, expected) = $value;
                 let doc = make_doc(&mut input);
-                let result = detect_base(&doc);
+                let result = detect_base(&doc, None);
                 assert_eq!(expected, result);
             }
         )*
@@ -113,12 +574,370 @@ mod tests {
             "<html><head></head><body><map name=\"primary\"><area coords=\"75,75,75\" href=\"left.html\" shape=\"circle\"></map></body></html>".to_string(),
             "<html><head></head><body><map name=\"primary\"><area coords=\"75,75,75\" href=\"https://mgdm.net/left.html\" shape=\"circle\"></map></body></html>".to_string()
         ),
+        rewrite_protocol_relative_url: (
+            "<html><head><link href=\"//cdn.example.com/app.css\"></head><body><a href=\"//example.org/x?y#z\">Hello</a></body></html>".to_string(),
+            "<html><head><link href=\"https://cdn.example.com/app.css\"></head><body><a href=\"https://example.org/x?y#z\">Hello</a></body></html>".to_string(),
+        ),
+        rewrite_absolute_path_url: (
+            "<html><head></head><body><a href=\"/a/../c\">Hello</a></body></html>".to_string(),
+            "<html><head></head><body><a href=\"https://mgdm.net/c\">Hello</a></body></html>".to_string(),
+        ),
         do_not_rewrite_absolute_url: (
             "<html><head></head><body><a href=\"https://example.org/foo/bar\">Hello</a></body></html>".to_string(),
             "<html><head></head><body><a href=\"https://example.org/foo/bar\">Hello</a></body></html>".to_string(),
         ),
     }
 
+    #[test]
+    fn path_relative_urls_are_resolved() {
+        let base = Url::parse("https://example.com/docs/intro.html?lang=en").unwrap();
+        assert_eq!(
+            resolve("../img/a.png", &base),
+            "https://example.com/img/a.png"
+        );
+        assert_eq!(
+            resolve("page2.html", &base),
+            "https://example.com/docs/page2.html"
+        );
+        assert_eq!(resolve("./", &base), "https://example.com/docs/");
+        assert_eq!(
+            resolve("#top", &base),
+            "https://example.com/docs/intro.html?lang=en#top"
+        );
+        assert_eq!(
+            resolve("?page=2", &base),
+            "https://example.com/docs/intro.html?page=2"
+        );
+        assert_eq!(
+            resolve("", &base),
+            "https://example.com/docs/intro.html?lang=en"
+        );
+    }
+
+    #[test]
+    fn urls_with_schemes_are_kept() {
+        let urls = [
+            "mailto:Me@Example.com?subject=Hi%20there",
+            "tel:+1-555-0100",
+            "javascript:void(0)",
+            "JavaScript:alert('hi')",
+            "data:text/plain,a%20b",
+            "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a&dn=x",
+            "HTTPS://Example.org/a/../b",
+        ];
+        let base = Url::parse("https://mgdm.net/a/").unwrap();
+        for url in urls {
+            let mut html = format!("<a href=\"{url}\">x</a><img srcset=\"{url} 2x\">");
+            let doc = make_doc(&mut html);
+            rewrite_relative_urls(&doc, &base, &RewriteOptions::default());
+
+            let link = doc.select_first("a").unwrap();
+            assert_eq!(link.attributes.borrow().get("href"), Some(url));
+            let image = doc.select_first("img").unwrap();
+            assert_eq!(
+                image.attributes.borrow().get("srcset"),
+                Some(format!("{url} 2x").as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn fragments_are_only_resolved_if_asked_for() {
+        let mut html = "<a href=\"#top\">Up</a>".to_string();
+        let doc = make_doc(&mut html);
+        let base = Url::parse("https://mgdm.net/a/page?x").unwrap();
+        let href = || {
+            doc.select_first("a")
+                .unwrap()
+                .attributes
+                .borrow()
+                .get("href")
+                .map(str::to_string)
+        };
+
+        rewrite_relative_urls(&doc, &base, &RewriteOptions::default());
+        assert_eq!(href().as_deref(), Some("#top"));
+
+        let options = RewriteOptions {
+            fragments: true,
+            ..RewriteOptions::default()
+        };
+        rewrite_relative_urls(&doc, &base, &options);
+        assert_eq!(href().as_deref(), Some("https://mgdm.net/a/page?x#top"));
+    }
+
+    #[test]
+    fn srcsets_are_resolved() {
+        let base = Url::parse("https://mgdm.net/a/").unwrap();
+        let resolved = |srcset| rewrite_srcset(srcset, &|url| resolve(url, &base));
+        assert_eq!(
+            resolved(" img/a.png 1x,img/b.png   2x  ,, /c.png, d.png 640w 480h ").as_deref(),
+            Some(
+                "https://mgdm.net/a/img/a.png 1x, https://mgdm.net/a/img/b.png 2x, \
+                https://mgdm.net/c.png, https://mgdm.net/a/d.png 640w 480h"
+            )
+        );
+        assert_eq!(
+            resolved("a,b.png 1.5x").as_deref(),
+            Some("https://mgdm.net/a/a,b.png 1.5x")
+        );
+        assert_eq!(resolved("").as_deref(), Some(""));
+        assert_eq!(resolved("a.png large"), None);
+        assert_eq!(resolved("a.png 2x 3x 4x"), None);
+        assert_eq!(resolved("a.png -2x"), None);
+
+        let mut html = "<picture><source srcset=\"a.webp 2x\"><img srcset=\"a.png big\"></picture>"
+            .to_string();
+        let doc = make_doc(&mut html);
+        rewrite_relative_urls(&doc, &base, &RewriteOptions::default());
+        assert!(serialize_doc(&doc).contains(
+            "<source srcset=\"https://mgdm.net/a/a.webp 2x\"><img srcset=\"a.png big\">"
+        ));
+    }
+
+    #[test]
+    fn css_urls_are_resolved() {
+        let base = Url::parse("https://mgdm.net/a/").unwrap();
+        let options = RewriteOptions {
+            css: true,
+            ..RewriteOptions::default()
+        };
+        let resolved = |css| rewrite_css_urls(css, &options, &|url| resolve(url, &base));
+        assert_eq!(
+            resolved("background: url(/img/hero.jpg) no-repeat, URL( 'b.png' ), url(\"c d.png\")"),
+            "background: url(https://mgdm.net/img/hero.jpg) no-repeat, \
+            URL( 'https://mgdm.net/a/b.png' ), url(\"https://mgdm.net/a/c%20d.png\")"
+        );
+        assert_eq!(
+            resolved("a { b: url(\"x\\\".png\"); c: url('it\\'s.png') }"),
+            "a { b: url(\"x\\\".png\"); c: url('it\\'s.png') }"
+        );
+        assert_eq!(
+            resolved("url(data:image/png;base64,iVBO=) url(#grad) url() myurl(x) url(a b) url(x"),
+            "url(data:image/png;base64,iVBO=) url(#grad) url() myurl(x) url(a b) url(x"
+        );
+        assert_eq!(
+            resolved("url(\"it's.png\") url('\"q\".png') url(\"a\nb\")"),
+            "url(\"https://mgdm.net/a/it's.png\") url('https://mgdm.net/a/%22q%22.png') url(\"a\nb\")"
+        );
+    }
+
+    #[test]
+    fn style_elements_are_resolved_if_asked_for() {
+        let doc = make_doc(
+            &mut "<style>body { background: url(bg.png) }</style><p style=\"background: url('/p.png')\">"
+                .to_string(),
+        );
+        let base = Url::parse("https://mgdm.net/a/").unwrap();
+        rewrite_relative_urls(&doc, &base, &RewriteOptions::default());
+        assert!(serialize_doc(&doc).contains("url(bg.png)"));
+
+        let options = RewriteOptions {
+            css: true,
+            ..RewriteOptions::default()
+        };
+        rewrite_relative_urls(&doc, &base, &options);
+        let html = serialize_doc(&doc);
+        assert!(
+            html.contains("<style>body { background: url(https://mgdm.net/a/bg.png) }</style>")
+        );
+        assert!(html.contains("<p style=\"background: url('https://mgdm.net/p.png')\">"));
+    }
+
+    #[test]
+    fn refresh_urls_are_resolved() {
+        let base = Url::parse("https://mgdm.net/a/").unwrap();
+        let refreshed = |content: &str| {
+            let mut html = format!("<meta http-equiv=\"Refresh\" content=\"{content}\">");
+            let doc = make_doc(&mut html);
+            rewrite_relative_urls(&doc, &base, &RewriteOptions::default());
+            let meta = doc.select_first("meta").unwrap();
+            meta.attributes.borrow().get("content").unwrap().to_string()
+        };
+        assert_eq!(refreshed("0; url=/next"), "0; url=https://mgdm.net/next");
+        assert_eq!(
+            refreshed(" 5 ,URL = 'b c'"),
+            " 5 ,URL = 'https://mgdm.net/a/b%20c'"
+        );
+        assert_eq!(
+            refreshed("3;next.html  "),
+            "3;https://mgdm.net/a/next.html  "
+        );
+        assert_eq!(
+            refreshed("1.5; url=&quot;../up&quot; x"),
+            "1.5; url=\"https://mgdm.net/up\" x"
+        );
+        assert_eq!(refreshed("0; urls"), "0; https://mgdm.net/a/urls");
+        for unchanged in ["30", "0;", "0; url=", "0; url=#top", "soon; url=/x", ""] {
+            assert_eq!(refreshed(unchanged), unchanged);
+        }
+
+        let mut html = "<meta name=\"refresh\" content=\"0; url=/next\">".to_string();
+        let doc = make_doc(&mut html);
+        rewrite_relative_urls(&doc, &base, &RewriteOptions::default());
+        assert!(serialize_doc(&doc).contains("content=\"0; url=/next\""));
+    }
+
+    #[test]
+    fn urls_are_relativized() {
+        let relativized = |url, prefix| relativize(url, &Url::parse(prefix).unwrap());
+        let prefix = "https://example.com/docs/";
+        assert_eq!(
+            relativized("https://example.com/docs/a/b.html", prefix),
+            "a/b.html"
+        );
+        assert_eq!(
+            relativized("https://example.com/img/x.png", prefix),
+            "../img/x.png"
+        );
+        assert_eq!(relativized("https://example.com/docs/", prefix), "./");
+        assert_eq!(
+            relativized("https://example.com/docs/a.html?q=1#top", prefix),
+            "a.html?q=1#top"
+        );
+        assert_eq!(relativized("https://example.com/docs/?q=1", prefix), "?q=1");
+
+        // without a trailing slash, `docs` is a page in `/`
+        let prefix = "https://example.com/docs";
+        assert_eq!(
+            relativized("https://example.com/docs/a/b.html", prefix),
+            "docs/a/b.html"
+        );
+        assert_eq!(
+            relativized("https://example.com/img/x.png", prefix),
+            "img/x.png"
+        );
+
+        for unchanged in [
+            "http://example.com/docs/a.html",
+            "https://example.com:8080/docs/a.html",
+            "https://cdn.example.com/docs/a.html",
+            "mailto:me@example.com",
+            "a/b.html",
+            "/docs/a.html",
+        ] {
+            assert_eq!(
+                relativized(unchanged, "https://example.com/docs/"),
+                unchanged
+            );
+        }
+    }
+
+    #[test]
+    fn attributes_are_relativized() {
+        let mut html = "<a href=\"https://mgdm.net/a/b/c.html#x\"></a>\
+            <img srcset=\"https://mgdm.net/img/1.png 1x, https://other.net/2.png 2x\">"
+            .to_string();
+        let doc = make_doc(&mut html);
+        let prefix = Url::parse("https://mgdm.net/a/").unwrap();
+        relativize_urls(&doc, &prefix, &RewriteOptions::default());
+        assert!(serialize_doc(&doc).contains(
+            "<a href=\"b/c.html#x\"></a><img srcset=\"../img/1.png 1x, https://other.net/2.png 2x\">"
+        ));
+    }
+
+    #[test]
+    fn resolved_urls_are_normalized() {
+        let base = Url::parse("https://user:pw@mgdm.net/a/b/").unwrap();
+        assert_eq!(
+            resolve("c d.png", &base),
+            "https://user:pw@mgdm.net/a/b/c%20d.png"
+        );
+        assert_eq!(
+            resolve("../ü/ß", &base),
+            "https://user:pw@mgdm.net/a/%C3%BC/%C3%9F"
+        );
+        assert_eq!(
+            resolve("./x/../../y", &base),
+            "https://user:pw@mgdm.net/a/y"
+        );
+        assert_eq!(
+            resolve("/p q?r s#t u", &base),
+            "https://user:pw@mgdm.net/p%20q?r%20s#t%20u"
+        );
+    }
+
+    #[test]
+    fn verbatim_urls_are_only_joined() {
+        let base = Url::parse("https://user:pw@mgdm.net/a/b/page?x#y").unwrap();
+        assert_eq!(
+            join_verbatim("c d.png", &base),
+            "https://user:pw@mgdm.net/a/b/c d.png"
+        );
+        assert_eq!(
+            join_verbatim("../ü", &base),
+            "https://user:pw@mgdm.net/a/b/../ü"
+        );
+        assert_eq!(
+            join_verbatim("/x//y", &base),
+            "https://user:pw@mgdm.net/x//y"
+        );
+        assert_eq!(join_verbatim("//cdn.net/a b", &base), "https://cdn.net/a b");
+        assert_eq!(
+            join_verbatim("?q r", &base),
+            "https://user:pw@mgdm.net/a/b/page?q r"
+        );
+        assert_eq!(
+            join_verbatim("#z", &base),
+            "https://user:pw@mgdm.net/a/b/page?x#z"
+        );
+        assert_eq!(join_verbatim("mailto:x y", &base), "mailto:x y");
+    }
+
+    #[test]
+    fn url_attributes_are_resolved() {
+        let mut html = "<img src=\"logo.png\" srcset=\"a.png 1x,b.png   2x, data:image/png;base64,x,y 3x,c.png\">\
+            <video src=\"/v.mp4\" poster=\"p.jpg\"></video><q cite=\"/src\" src=\"no\"></q>\
+            <div data-src=\"lazy.png\" src=\"no\"></div>"
+            .to_string();
+        let doc = make_doc(&mut html);
+        let options = RewriteOptions {
+            extra_attributes: vec!["data-src".to_string()],
+            ..RewriteOptions::default()
+        };
+        rewrite_relative_urls(&doc, &Url::parse("https://mgdm.net/a/").unwrap(), &options);
+        assert_eq!(
+            serialize_doc(&doc),
+            "<html><head></head><body><img src=\"https://mgdm.net/a/logo.png\" \
+            srcset=\"https://mgdm.net/a/a.png 1x, https://mgdm.net/a/b.png 2x, \
+            data:image/png;base64,x,y 3x, https://mgdm.net/a/c.png\">\
+            <video src=\"https://mgdm.net/v.mp4\" poster=\"https://mgdm.net/a/p.jpg\"></video>\
+            <q cite=\"https://mgdm.net/src\" src=\"no\"></q>\
+            <div data-src=\"https://mgdm.net/a/lazy.png\" src=\"no\"></div></body></html>"
+        );
+    }
+
+    #[test]
+    fn links_are_found() {
+        let mut html = "<head><link href=\"/feed\" rel=\"alternate\"></head>\
+            <p><a href=\"a.html\">First\n  <b>link</b></a><a name=\"x\">no</a>\
+            <map><area href=\"/b\"></map></p>"
+            .to_string();
+        let doc = make_doc(&mut html);
+        let link = |url: &str, text: &str| Link {
+            url: url.to_string(),
+            text: text.to_string(),
+        };
+
+        assert_eq!(
+            links(&doc, LinkKinds::Navigation),
+            [link("a.html", "First link"), link("/b", "")]
+        );
+        assert_eq!(links(&doc, LinkKinds::All)[0], link("/feed", ""));
+    }
+
+    #[test]
+    fn form_actions_are_resolved() {
+        let mut html = "<form action=\"search\"></form><form></form>".to_string();
+        let doc = make_doc(&mut html);
+        rewrite_form_actions(&doc, &Url::parse("https://mgdm.net/a/").unwrap());
+        assert_eq!(
+            serialize_doc(&doc),
+            "<html><head></head><body><form action=\"https://mgdm.net/a/search\"></form>\
+            <form></form></body></html>"
+        );
+    }
+
     detect_base_tests! {
         base_ok: (
             "<html><head><base href=\"https://example.org\"></head><body><a href=\"https://example.org/foo/bar\">Hello</a></body></html>".to_string(),
@@ -128,5 +947,34 @@ mod tests {
             "<html><head></head><body><a href=\"https://example.org/foo/bar\">Hello</a></body></html>".to_string(),
             None
         ),
+        relative_base_without_document_url: (
+            "<html><head><base href=\"/en/\"></head><body></body></html>".to_string(),
+            None
+        ),
+    }
+
+    #[test]
+    fn relative_base_is_resolved_against_document_url() {
+        let detected = |base: &str, document_url: Option<&str>| {
+            let mut html = format!("<head>{base}</head>");
+            let doc = make_doc(&mut html);
+            let document_url = document_url.map(|url| Url::parse(url).unwrap());
+            detect_base(&doc, document_url.as_ref()).map(String::from)
+        };
+        let page = Some("https://example.org/docs/intro.html");
+        assert_eq!(
+            detected("<base href=\"https://cdn.example.net/x/\">", page).as_deref(),
+            Some("https://cdn.example.net/x/")
+        );
+        assert_eq!(
+            detected("<base href=\" /en/ \">", page).as_deref(),
+            Some("https://example.org/en/")
+        );
+        assert_eq!(
+            detected("<base href=\"v2/\">", page).as_deref(),
+            Some("https://example.org/docs/v2/")
+        );
+        assert_eq!(detected("", page), None);
+        assert_eq!(detected("<base target=\"_blank\">", page), None);
     }
 }
@@ -1,43 +1,594 @@
-mod link;
-mod pretty_print;
+#[cfg(feature = "decompress")]
+mod decompress;
+#[cfg(feature = "fetch")]
+mod fetch;
+mod glob;
+mod in_place;
+mod output_pattern;
 
-use clap::Parser;
-use kuchikiki::NodeRef;
-use kuchikiki::traits::{NodeIterator, TendrilSink};
+use clap::{ArgGroup, Parser};
+use hq::check::{self, Severity};
+use hq::encoding::{Encoding, EncodingWriter, Unmappable};
+use hq::link::LinkKinds;
+use hq::pretty_print::Indent;
+use hq::record::Field;
+use hq::serialize::{AttributeOrder, Comments};
+use hq::sort::SortKey;
+use hq::template::Template;
+use hq::wrap::Wrapper;
+use hq::{Document, Format, ParseOptions, Query, SelectorRole};
+use output_pattern::{OutputPattern, Split};
+use regex::Regex;
+use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use url::Url;
 
 #[derive(Debug, Clone, Parser)]
 #[command(version, author, about)]
+#[command(group(
+    ArgGroup::new("comment_removal")
+        .args(["strip_comments", "remove_comments"])
+        .multiple(true)
+))]
 #[expect(clippy::struct_excessive_bools)] // ok since it's a "central point" for options
 struct Config {
-    /// What CSS selector to filter with.
-    #[arg(default_value = ":root")]
-    selector: String,
+    /// What CSS selector to filter with. Defaults to `:root`, the whole document.
+    ///
+    /// The arguments after it are selectors too, up to the first input: the first one that is
+    /// `-`, a URL, an existing path, contains a `/` or ends in an extension like `.html`.
+    /// Arguments after `--` are always inputs, like in `hq h1 h2 -- README`. Elements matching
+    /// any of the selectors are filtered then, in document order and each only once.
+    /// With `--xpath` or `-e`, this is the first input instead, and with `--in-place` too if it
+    /// is one of the inputs described above.
+    selector: Option<String>,
 
-    /// Where to read HTML input from.
-    #[arg(short = 'f', long = "filename", default_value = "-")]
-    input_path: String,
+    /// Filter with this XPath 1.0 expression instead of a CSS selector.
+    ///
+    /// Can also select text nodes and attributes, like `//a/@href`.
+    #[arg(short = 'x', long, value_name = "EXPR")]
+    xpath: Option<String>,
+
+    /// Filter with this CSS selector, within the nodes filtered by the previous one.
+    ///
+    /// Can be given multiple times to build a pipeline like `-e div.results -e a.title`, where
+    /// each selector only matches descendants of what the previous one matched, like
+    /// `querySelectorAll`. Also applies within what `--xpath` matched.
+    /// All positional arguments are inputs then.
+    #[arg(short = 'e', long = "expression", value_name = "SELECTOR")]
+    expressions: Vec<String>,
+
+    /// Filter with this CSS selector within each filtered node, outputting what it matched in
+    /// each of them as a group.
+    ///
+    /// Groups are separated by an empty line or `--separator`, even if they're empty, so they
+    /// stay aligned with the nodes they're from. `--first`, `--limit` and `--nth` apply to each
+    /// group.
+    #[arg(
+        long = "then",
+        value_name = "SELECTOR",
+        conflicts_with_all = [
+            "json", "ndjson", "count", "invert", "stats", "in_place", "output_pattern",
+            "set_attr", "insert_before", "insert_after",
+        ],
+    )]
+    then: Option<String>,
+
+    /// Where to read HTML input from. Can also be an HTTP(S) URL to fetch.
+    ///
+    /// Can be given multiple times. Defaults to stdin (-) if no input is given at all.
+    #[arg(short = 'f', long = "filename")]
+    input_path: Vec<String>,
+
+    /// Further inputs to read HTML from, like with `--filename`, after any further selectors.
+    #[arg(value_name = "INPUT")]
+    inputs: Vec<String>,
+
+    /// Further inputs after `--`, which are never taken as selectors.
+    #[arg(value_name = "INPUT", last = true)]
+    literal_inputs: Vec<String>,
+
+    /// Read HTML input from all files matching this glob pattern, like `docs/**/*.html`.
+    ///
+    /// Can be given multiple times. Files are processed sorted by path.
+    #[arg(long = "glob", value_parser = glob::parse_pattern)]
+    globs: Vec<String>,
+
+    /// Do not print `==> filename <==` headers when processing more than one input.
+    #[arg(long)]
+    no_filename: bool,
 
     /// Where to write the filtered HTML to.
     #[arg(short = 'o', long = "output", default_value = "-")]
     output_path: String,
 
-    /// What URL to prepend to links without an origin, i.e. starting with a slash (/).
-    #[arg(short, long)]
-    base: Option<String>,
+    /// Add to the end of the `--output` file instead of overwriting it.
+    #[arg(long, conflicts_with_all = ["output_pattern", "in_place"])]
+    append: bool,
+
+    /// Write each match to its own file instead, at this path with the placeholders filled in.
+    ///
+    /// `n` in braces is replaced by the index of the match starting at 1, `{n:03}` by it padded
+    /// to 3 digits, `{id}` by the id of the element and `{tag}` by its name. Matches without an id
+    /// use their index instead, which is also appended to ids used by an earlier match already.
+    /// Directories are created as needed.
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        value_parser = OutputPattern::parse,
+        conflicts_with_all = ["output_path", "separator", "count", "json", "ndjson"],
+    )]
+    output_pattern: Option<OutputPattern>,
+
+    /// Replace each input file by the whole document after cleaning up and editing the
+    /// filtered nodes in it, like `sed -i`.
+    ///
+    /// With a suffix like `--in-place=.bak`, a copy of the original is kept at its path with the suffix
+    /// appended. Files without any matches are left as they are. Only HTML can be output then.
+    #[arg(
+        short = 'I',
+        long = "in-place",
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        conflicts_with_all = ["output_path", "output_pattern"],
+    )]
+    in_place: Option<String>,
+
+    /// With `--output-pattern`, overwrite files that exist already instead of failing.
+    #[arg(long, requires = "output_pattern")]
+    force: bool,
+
+    /// Decode the input as this encoding, like `windows-1252` or `shift_jis`.
+    ///
+    /// By default, the encoding is detected from a byte order mark, the `Content-Type` when
+    /// fetching and `<meta charset>`, falling back to UTF-8.
+    #[arg(long, value_name = "LABEL", value_parser = parse_encoding)]
+    input_encoding: Option<&'static Encoding>,
+
+    /// Encode the output as this encoding instead of UTF-8.
+    ///
+    /// Characters the encoding can't represent are written as numeric character references
+    /// like `&#8364;` in HTML output, and as `?` in any other output.
+    #[arg(long, value_name = "LABEL", value_parser = parse_output_encoding)]
+    output_encoding: Option<&'static Encoding>,
+
+    /// Parse the input as XML instead of HTML, like RSS feeds, sitemaps or SVG.
+    ///
+    /// Tag names are case-sensitive then. Namespaced elements like `<atom:link>`
+    /// are selected by their local name, like `link`.
+    #[arg(long)]
+    xml: bool,
+
+    /// Parse the input as HTML fragment, without adding `<html>`, `<head>` and `<body>` around it.
+    ///
+    /// The top-level elements of the input are matched by the default selector then,
+    /// so the output of hq can be fed to hq again.
+    #[arg(long, conflicts_with_all = ["xml", "with_position"])]
+    fragment: bool,
+
+    /// The element the fragment is parsed in, like `tr` for a fragment of `<td>` cells.
+    #[arg(
+        long,
+        value_name = "TAG",
+        default_value = "body",
+        requires = "fragment",
+        value_parser = parse_fragment_context,
+    )]
+    fragment_context: String,
+
+    /// Fail on input that isn't valid in its encoding, instead of replacing it by U+FFFD.
+    ///
+    /// Also fail on characters `--output-encoding` can't represent in non-HTML output.
+    #[arg(long)]
+    strict: bool,
+
+    /// Extra header to send when fetching the input from a URL, in the form `Name: value`.
+    #[cfg(feature = "fetch")]
+    #[arg(short = 'H', long = "header", value_parser = fetch::parse_header)]
+    headers: Vec<fetch::Header>,
+
+    /// What User-Agent to send when fetching the input from a URL.
+    #[cfg(feature = "fetch")]
+    #[arg(long)]
+    user_agent: Option<String>,
+
+    /// What URL to resolve relative links against, like `/about` or `../img/logo.png`.
+    ///
+    /// Like in browsers, the path of the URL counts too,
+    /// so `page2.html` is resolved next to `https://example.com/docs/intro.html`.
+    /// If the input is fetched from a URL, that URL is used by default.
+    /// Without a scheme, like `example.com/docs/`, `https://` is assumed.
+    #[arg(short, long, value_parser = parse_base)]
+    base: Option<Url>,
 
     /// Look for the `<base>` tag in input for the base.
+    ///
+    /// It takes precedence over the URL the input was fetched from, but not over `--base`.
     #[arg(short = 'B', long)]
     detect_base: bool,
 
+    /// Let a base found by `--detect-base` take precedence over `--base`,
+    /// which a relative one like `/en/` is resolved against.
+    #[arg(long, requires = "detect_base")]
+    prefer_detected_base: bool,
+
+    /// Turn absolute URLs with the same origin as this one into ones relative to it,
+    /// like for mirroring pages.
+    ///
+    /// With a trailing slash, `https://example.com/docs/` turns `https://example.com/img/x.png`
+    /// into `../img/x.png`. Without, the last segment is a page like for `--base`.
+    #[arg(long, value_name = "URL")]
+    relativize: Option<Url>,
+
+    /// Also resolve URLs in these attributes on any element, like `data-src` for lazy loading.
+    ///
+    /// Standard ones like `href`, `src`, `srcset`, `poster` and `action` are always resolved.
+    #[arg(long, value_name = "ATTRS", value_delimiter = ',')]
+    rewrite_attrs: Vec<String>,
+
+    /// Also resolve links to anchors like `#section`, which are kept as they are otherwise.
+    ///
+    /// Links with a scheme, like `mailto:` or `javascript:`, are always kept as they are.
+    #[arg(long)]
+    rewrite_fragments: bool,
+
+    /// Put relative URLs after the base as they are while resolving them, without
+    /// percent-encoding them or resolving `..` segments, for keeping paths byte for byte.
+    #[arg(long)]
+    no_url_normalize: bool,
+
+    /// Also resolve `url(…)` references in `style` attributes and `<style>` elements,
+    /// like background images.
+    #[arg(long)]
+    rewrite_css: bool,
+
+    /// Warn about input that is worked around silently otherwise,
+    /// like malformed `srcset` attributes left as they are while resolving URLs.
+    #[arg(long)]
+    verbose: bool,
+
+    /// Output nothing, only exit with 0 if anything matched and 1 otherwise, like `grep -q`.
+    ///
+    /// Stops at the first match, without reading any further inputs.
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = [
+            "output_path", "append", "output_pattern", "in_place", "invert", "check",
+        ],
+    )]
+    quiet: bool,
+
     /// Output only the contained text of the filtered nodes, not the entire HTML.
     #[arg(short, long = "text")]
     text_only: bool,
 
+    /// Extract text by concatenating all text nodes as they are, without line breaks
+    /// for block-level elements like `<p>` and `<br>`.
+    #[arg(long)]
+    text_raw: bool,
+
+    /// Collapse runs of whitespace in text to a single space and trim lines, like browsers do.
+    ///
+    /// Text inside `<pre>`, `<code>` and `<textarea>` is kept as it is.
+    #[arg(long, conflicts_with = "text_raw")]
+    collapse_whitespace: bool,
+
+    /// Include the text of `<script>`, `<style>`, `<template>` and `<noscript>` elements
+    /// in text output. By default, it is skipped unless a filtered node is one itself.
+    #[arg(long)]
+    keep_scripts_text: bool,
+
+    /// Skip text hidden by the `hidden` attribute, `aria-hidden="true"` or inline styles
+    /// like `display: none`. Stylesheets are not looked at.
+    #[arg(long)]
+    visible_only: bool,
+
+    /// Follow each link in text output by a footnote number like `[1]`
+    /// and list the URLs they point to after the text.
+    ///
+    /// Links to the same URL share a number. Links to anchors on the same page
+    /// and `javascript:` links get none.
+    #[arg(long, conflicts_with = "text_raw")]
+    link_footnotes: bool,
+
+    /// Include the `alt` text of images in text output, where the image is.
+    ///
+    /// This covers `<img>`, `<area>` and `<input type="image">`. Empty `alt` texts are skipped.
+    #[arg(long)]
+    alt_text: bool,
+
+    /// How to wrap `alt` texts included by `--alt-text`, with `{}` replaced by the text.
+    #[arg(
+        long,
+        value_name = "WRAPPER",
+        default_value = "[{}]",
+        requires = "alt_text",
+        value_parser = parse_alt_text_format,
+    )]
+    alt_text_format: String,
+
+    /// Output each match by filling in this template, like `{@href}\t{text}`.
+    ///
+    /// `{@name}` is replaced by the value of the attribute `name`, `{text}` by the contained text,
+    /// `{html}` by the HTML and `{tag}` by the element name. `\t`, `\n`, `\\`, `\{` and `\}`
+    /// are replaced by what they escape.
+    #[arg(
+        long = "format",
+        value_name = "TEMPLATE",
+        value_parser = Template::parse,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "count", "json", "ndjson",
+        ],
+    )]
+    template: Option<Template>,
+
+    /// Fail if a match lacks an attribute used in the `--format` template,
+    /// instead of filling in nothing.
+    #[arg(long, requires = "template")]
+    format_strict: bool,
+
+    /// Output the URL and text of each `a` and `area` link in the filtered nodes, tab-separated.
+    ///
+    /// Relative URLs are resolved like with `--base` and `--detect-base`, if possible.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "count", "json", "ndjson", "invert",
+        ],
+    )]
+    links: bool,
+
+    /// Like `--links`, but also output `link` elements, like stylesheets.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "links", "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "count", "json", "ndjson", "invert",
+        ],
+    )]
+    links_all: bool,
+
+    /// Output the tables in the filtered nodes as CSV, separated by an empty line.
+    ///
+    /// Cells spanning several columns or rows are repeated in each of them.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "count", "json", "ndjson", "invert", "unique",
+        ],
+    )]
+    table_csv: bool,
+
+    /// Output the tables in the filtered nodes as JSON arrays, one per line.
+    ///
+    /// Each row after the header row becomes an object keyed by the header cells.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "count", "json", "ndjson", "invert", "unique",
+            "table_csv",
+        ],
+    )]
+    table_json: bool,
+
+    /// Output the forms in the filtered nodes as JSON objects with their action, method and fields.
+    ///
+    /// One object is output per line. Fields without a name are skipped.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "count", "json", "ndjson", "invert", "unique",
+            "table_csv", "table_json",
+        ],
+    )]
+    forms: bool,
+
+    /// Output the title, `<meta>` properties and canonical link in `<head>`, as `key<TAB>value`.
+    ///
+    /// With `--json`, all of them are output as one array instead.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "count", "ndjson", "invert", "unique",
+            "table_csv", "table_json", "forms",
+        ],
+    )]
+    meta: bool,
+
+    /// Output the content of `<script type="application/ld+json">` elements, one after another.
+    ///
+    /// Blocks that aren't valid JSON are warned about. With `--json`, all valid ones are output
+    /// as one array instead.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "count", "ndjson", "invert", "unique",
+            "table_csv", "table_json", "forms", "meta",
+        ],
+    )]
+    json_ld: bool,
+
+    /// Output the filtered nodes converted to Markdown.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "count", "json", "ndjson",
+            "table_csv", "table_json", "forms", "meta", "json_ld",
+        ],
+    )]
+    markdown: bool,
+
+    /// Output a table of the elements in the filtered nodes instead, with how often each occurs,
+    /// how much text is directly in them, their number of attributes and how deeply they're
+    /// nested, sorted by how often they occur.
+    ///
+    /// With `--json`, the table is output as an array of objects instead.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "count", "ndjson", "invert", "unique",
+            "table_csv", "table_json", "forms", "meta", "json_ld", "markdown", "classes",
+            "separator", "output_pattern", "in_place",
+        ],
+    )]
+    stats: bool,
+
+    /// Output an outline of the elements in the filtered nodes instead, one per line and
+    /// indented by how deeply they're nested, written like `div#content.main` with their id
+    /// and classes.
+    ///
+    /// Runs of siblings written the same way are output once with their number, like `li ×12`,
+    /// and elements with only one child element on one line with it, like `ul.nav > li`.
+    /// Text is shortened to a preview.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "table_csv", "table_json", "forms", "meta",
+            "json_ld", "markdown", "classes", "stats", "json", "ndjson", "in_place",
+        ],
+    )]
+    outline: bool,
+
+    /// Output a CSV row for each filtered node with the value of this field in it, after
+    /// a header of the field names. Can be given multiple times.
+    ///
+    /// The value is the text of the first element matching the selector in the node, or its
+    /// attribute with `@ATTR`, like `url=a@href`. Leave out the selector to take the attribute
+    /// from the node itself, like `id=@id`. Fields without a match are empty.
+    /// With `--json`, each node is output as object instead.
+    #[arg(
+        long,
+        value_name = "NAME=SELECTOR[@ATTR]",
+        value_parser = Field::parse,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "inner", "tag_names", "css_path",
+            "template", "links", "links_all", "table_csv", "table_json", "forms", "meta",
+            "json_ld", "markdown", "classes", "stats", "outline", "invert", "then",
+            "output_pattern", "in_place",
+        ],
+    )]
+    field: Vec<Field>,
+
+    /// Join the values of all elements matching the selector of a field by this separator,
+    /// `, ` if not given, instead of taking the first.
+    #[arg(
+        long,
+        value_name = "SEP",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ", ",
+        requires = "field",
+    )]
+    field_all: Option<String>,
+
+    /// Show only this many levels of elements in the outline, ending elements whose children
+    /// are left out with `…`.
+    #[arg(long, value_name = "N", requires = "outline")]
+    max_depth: Option<usize>,
+
+    /// Output this between matches instead of ending each with a newline, like `'\n---\n'`.
+    ///
+    /// `\n`, `\t`, `\0` and `\\` are replaced by what they escape.
+    /// Nothing is output after the last match.
+    #[arg(
+        long,
+        value_name = "STR",
+        value_parser = parse_separator,
+        conflicts_with_all = ["count", "json", "ndjson", "table_csv"],
+    )]
+    separator: Option<String>,
+
+    /// Don't end each match with a newline, so nothing is between them unless `--separator` is
+    /// given.
+    #[arg(short = 'j', long)]
+    no_newline: bool,
+
+    /// End each attribute value, text or expanded template with a NUL byte instead of a newline,
+    /// like for `xargs -0`.
+    #[arg(short = '0', long, conflicts_with_all = ["pretty_print", "separator"])]
+    print0: bool,
+
+    /// Prefix each output match with the `line:column:` of its start tag in the input.
+    #[arg(long, conflicts_with = "xml")]
+    with_position: bool,
+
+    /// Report the errors the parser recovered from, like stray end tags, along with likely
+    /// mistakes in the structure, like duplicate ids or `<li>` outside of lists, instead of
+    /// selecting anything.
+    ///
+    /// Each problem is output as `line:column: severity: message`, prefixed by the input when
+    /// there's more than one. No selector is taken, so the first argument is an input.
+    /// Exits with 1 if there were any errors, but not for only warnings.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "xml", "fragment", "xpath", "json", "ndjson", "count", "output_path", "in_place",
+            "output_pattern",
+        ],
+    )]
+    check: bool,
+
+    /// Output a selector matching only that node for each of the filtered nodes.
+    ///
+    /// With `--attributes`, their values are output after each path.
+    #[arg(long, conflicts_with_all = ["text_only", "pretty_print", "tag_names", "inner"])]
+    css_path: bool,
+
+    /// Output only the element names of the filtered nodes, one per line.
+    #[arg(long, conflicts_with_all = ["text_only", "attributes", "pretty_print"])]
+    tag_names: bool,
+
+    /// Output the class names of the filtered nodes, one per line.
+    ///
+    /// Like for `hq div --classes | sort | uniq -c`. With `--unique`, each is only output once.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "text_only", "attributes", "pretty_print", "tag_names", "css_path", "template",
+            "inner",
+        ],
+    )]
+    classes: bool,
+
+    /// Output only the children of the filtered nodes, without their own tags.
+    #[arg(long)]
+    inner: bool,
+
+    /// Leave out the `<html>`, `<head>` and `<body>` the parser added when outputting the root.
+    ///
+    /// Only the contents of `<head>` and `<body>` are output then. They count as added by the
+    /// parser as long as none of them has any attributes.
+    #[arg(long)]
+    bare: bool,
+
+    /// Start the HTML output of every match with the doctype of the document.
+    ///
+    /// By default, that's only done when outputting the root element, like with the default selector.
+    #[arg(long)]
+    document: bool,
+
     /// Skip over text nodes whose text that is solely whitespace.
+    ///
+    /// With `--text-raw`, the remaining text nodes are each followed by a newline.
     #[arg(short, long)]
     ignore_whitespace: bool,
 
@@ -45,110 +596,1117 @@ struct Config {
     #[arg(short, long = "pretty")]
     pretty_print: bool,
 
+    /// Output the HTML as short as possible, without whitespace and comments that don't matter.
+    #[arg(long, conflicts_with = "pretty_print")]
+    minify: bool,
+
+    /// Output the attributes of each element sorted by name, to make outputs easier to diff.
+    ///
+    /// With `--sort-attributes=smart`, `id` and `class` come first.
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "name",
+        value_parser = parse_attribute_order,
+    )]
+    sort_attributes: Option<AttributeOrder>,
+
+    /// When to color the HTML output: `always`, `never`, or `auto` to color it when writing to a
+    /// terminal and the `NO_COLOR` environment variable isn't set.
+    #[arg(
+        long,
+        value_name = "WHEN",
+        default_value = "auto",
+        value_parser = parse_color
+    )]
+    color: ColorChoice,
+
+    /// Leave out comments when outputting HTML.
+    #[arg(long)]
+    strip_comments: bool,
+
+    /// Output the text of each comment in the matches instead, or in the whole document
+    /// without a selector.
+    #[arg(long, conflicts_with_all = ["remove_comments", "strip_comments"])]
+    comments: bool,
+
+    /// With `--comments`, output the comments with their `<!--` and `-->`.
+    #[arg(long, requires = "comments")]
+    comments_html: bool,
+
+    /// Remove all comments from the matches, or from the whole document when it's output.
+    ///
+    /// Unlike `--strip-comments`, this applies to all ways of outputting matches, like `--json`.
+    #[arg(long)]
+    remove_comments: bool,
+
+    /// With `--strip-comments` or `--remove-comments`, keep conditional comments like
+    /// `<!--[if IE]>`, which can contain markup.
+    #[arg(long, requires = "comment_removal")]
+    keep_conditional_comments: bool,
+
+    /// With `--pretty`, indent each level of nesting by this many spaces.
+    #[arg(long, value_name = "N", default_value_t = 2, requires = "pretty_print")]
+    indent: usize,
+
+    /// With `--pretty`, indent each level of nesting by a tab instead of spaces.
+    #[arg(long, requires = "pretty_print", conflicts_with = "indent")]
+    indent_tabs: bool,
+
+    /// With `--pretty`, put each attribute on its own line if the start tag would end past
+    /// this column otherwise.
+    ///
+    /// The attributes are indented one level deeper than the tag,
+    /// and the `>` follows right after the last one.
+    #[arg(long, value_name = "N", requires = "pretty_print")]
+    wrap_attributes: Option<usize>,
+
+    /// With `--pretty`, break text onto the next line where it would end past this column.
+    ///
+    /// Only whitespace is replaced by line breaks, and text in `<pre>` or `<code>` isn't wrapped.
+    /// Words longer than that stay as they are.
+    #[arg(long, value_name = "N", requires = "pretty_print")]
+    max_width: Option<usize>,
+
     /// Do not output the nodes matching any of these selectors.
     #[arg(short, long)]
     remove_nodes: Vec<String>,
 
+    /// Replace the elements matching any of these selectors by their children.
+    ///
+    /// Like `--remove-nodes`, this applies to what's in each match, which is the whole document
+    /// with the default selector, so `hq :root --unwrap 'span.highlight, font'` strips those
+    /// wrappers from the page.
+    #[arg(long, value_name = "SELECTOR")]
+    unwrap: Vec<String>,
+
     /// Output only the contents of the given attributes.
+    ///
+    /// A trailing `*` like in `data-*` selects all attributes starting with the rest, and a bare
+    /// `*` all of them. Those are output as `name=value`, in the order they are on the element.
     #[arg(short, long)]
     attributes: Vec<String>,
+
+    /// Prefix each attribute value with its name, like `src=logo.png`.
+    ///
+    /// Backslashes, newlines, carriage returns and tabs in values are escaped like `\n`,
+    /// so each line is one attribute.
+    #[arg(long, requires = "attributes")]
+    attributes_with_names: bool,
+
+    /// Output this instead of attributes a match doesn't have, so each match has one line
+    /// per attribute. Attributes like `data-*` are still skipped if there are none.
+    #[arg(long, value_name = "VALUE", requires = "attributes")]
+    attribute_missing: Option<String>,
+
+    /// Output the text of matches that have none of the requested attributes instead,
+    /// like for menus mixing `<a href>` with plain labels.
+    #[arg(long, requires = "attributes")]
+    attribute_fallback_text: bool,
+
+    /// Separate attribute names and values by a tab instead of `=`.
+    #[arg(long, requires = "attributes_with_names")]
+    tsv: bool,
+
+    /// Output only how many nodes matched.
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["text_only", "attributes", "pretty_print", "json", "ndjson"],
+    )]
+    count: bool,
+
+    /// Output only matches whose text matches this regex.
+    ///
+    /// The text is the same as output by `--text`, also respecting `--ignore-whitespace`.
+    #[arg(long, value_name = "REGEX")]
+    text_matches: Option<Regex>,
+
+    /// Output only matches whose text does not match this regex.
+    #[arg(long, value_name = "REGEX")]
+    text_not_matches: Option<Regex>,
+
+    /// Output only elements with this attribute matching the regex, in the form `name=REGEX`.
+    ///
+    /// Can be given multiple times, then all of them have to match.
+    #[arg(long, value_name = "NAME=REGEX", value_parser = parse_attr_matches)]
+    attr_matches: Vec<(String, Regex)>,
+
+    /// Remove scripts, frames, forms, stylesheets, `<base>`, `<meta>`, SVG animations, event
+    /// handlers, `javascript:` URLs and styles from each match, so it's safe to embed in another
+    /// page.
+    #[arg(long)]
+    sanitize: bool,
+
+    /// With `--sanitize`, keep `style` attributes.
+    #[arg(long, requires = "sanitize")]
+    sanitize_keep_style: bool,
+
+    /// Remove these attributes from each match and all elements in it, separated by commas.
+    ///
+    /// A `*` stands for any number of characters, like in `--strip-attrs 'class,style,on*,data-*'`.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    strip_attrs: Vec<String>,
+
+    /// Wrap each match in a new element, given as a tag name followed by attributes like
+    /// `div class="card"`. With `--inner`, its contents are wrapped instead.
+    #[arg(long, value_name = "TAG", value_parser = Wrapper::parse)]
+    wrap: Option<Wrapper>,
+
+    /// Replace what the regex matches in the text of each match, in the form `REGEX=REPLACEMENT`.
+    ///
+    /// `$1` in the replacement refers to the first capture group. Attributes and the text of
+    /// scripts and styles are left alone. Can be given multiple times, the replacements are
+    /// made in order. To match `=` itself, use `\x3D`.
+    #[arg(long, value_name = "REGEX=REPLACEMENT", value_parser = parse_text_replacement)]
+    replace_text: Vec<(Regex, String)>,
+
+    /// Insert this HTML before each match and output the whole document.
+    ///
+    /// `{@name}` in it is replaced by the value of the attribute `name` of the match.
+    #[arg(long, value_name = "HTML")]
+    insert_before: Option<String>,
+
+    /// Insert this HTML after each match and output the whole document, like `--insert-before`.
+    ///
+    /// For example, `hq h2 --insert-after '<a href="#{@id}">#</a>'` links each heading to itself.
+    #[arg(long, value_name = "HTML")]
+    insert_after: Option<String>,
+
+    /// Set this attribute on each match, in the form `name=value`, and output the whole document.
+    ///
+    /// Can be given multiple times. With `--text`, `--attributes` and other ways of outputting
+    /// something other than HTML, the edited matches are output instead.
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_attribute_assignment)]
+    set_attr: Vec<(String, String)>,
+
+    /// Skip output that was already output for an earlier match in the same input.
+    ///
+    /// With `--attributes` and `--links`, each line is compared on its own.
+    /// Skipped matches don't count towards `--limit` and `--nth`.
+    #[arg(short = 'u', long, conflicts_with_all = ["invert", "json", "ndjson"])]
+    unique: bool,
+
+    /// Output the matches sorted by their text or an attribute, like `text` or `@data-count`.
+    ///
+    /// Append `:numeric` to compare them as numbers, with those that aren't sorted last, and
+    /// `:desc` to sort in descending order, like `@data-count:numeric:desc`. Matches comparing
+    /// equal stay in document order. Sorting happens before `--limit` and `--nth`.
+    #[arg(
+        long,
+        value_name = "KEY",
+        value_parser = SortKey::parse,
+        conflicts_with = "invert",
+    )]
+    sort_by: Option<SortKey>,
+
+    /// Skip filtered nodes inside other filtered nodes, so nested ones like in `hq div` are
+    /// output only once, as part of the outermost one.
+    #[arg(long, conflicts_with = "invert")]
+    outermost: bool,
+
+    /// Skip filtered nodes containing other filtered nodes, so only the most specific ones
+    /// like in `hq '[data-widget]'` are output.
+    #[arg(long, conflicts_with_all = ["invert", "outermost"])]
+    innermost: bool,
+
+    /// Output the matches in reverse, the last one first.
+    ///
+    /// This happens after `--sort-by` and before `--limit` and `--nth`, so `--reverse --limit 3`
+    /// outputs the last three matches.
+    #[arg(long, conflicts_with = "invert")]
+    reverse: bool,
+
+    /// Output the whole document without the matches instead, like `grep -v`.
+    #[arg(short = 'v', long, conflicts_with = "attributes")]
+    invert: bool,
+
+    /// Output at most this many matches per input.
+    #[arg(short, long, value_name = "N")]
+    limit: Option<NonZeroUsize>,
+
+    /// Output only the first match per input, same as `--limit 1`.
+    #[arg(long, conflicts_with = "limit")]
+    first: bool,
+
+    /// Output only the match at this 1-based index per input. Negative indices count from the end.
+    #[arg(
+        short,
+        long,
+        value_name = "INDEX",
+        allow_negative_numbers = true,
+        value_parser = parse_nth,
+        conflicts_with_all = ["limit", "first"],
+    )]
+    nth: Option<isize>,
+
+    /// Exit successfully even if the selector matched nothing.
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Output all matches as one JSON array of objects with their tag, attributes, text and HTML.
+    #[arg(long)]
+    json: bool,
+
+    /// Output each match as a JSON object on its own line, as soon as it is found.
+    ///
+    /// With `--attributes` or `--text`, only the requested fields are included.
+    #[arg(long, conflicts_with = "json")]
+    ndjson: bool,
 }
 
-fn select_attributes(node: &NodeRef, attributes: &[String], output: &mut dyn io::Write) {
-    if let Some(as_element) = node.as_element() {
-        for attr in attributes {
-            if let Ok(elem_atts) = as_element.attributes.try_borrow()
-                && let Some(val) = elem_atts.get(attr.as_str())
-            {
-                writeln!(output, "{val}").ok();
+/// An I/O operation on a file that failed.
+#[derive(Debug)]
+struct FileError {
+    /// What was tried to do with the file, like `open`.
+    action: &'static str,
+    path: String,
+    source: io::Error,
+}
+
+impl FileError {
+    fn new(action: &'static str, path: &str) -> impl FnOnce(io::Error) -> Self {
+        move |source| Self {
+            action,
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the OS error code is just noise for the user
+        let message = self.source.to_string();
+        let message = match message.rfind(" (os error ") {
+            Some(idx) => &message[..idx],
+            None => &message,
+        };
+        write!(f, "cannot {} '{}': {message}", self.action, self.path)
+    }
+}
+
+impl Error for FileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+struct Input {
+    reader: Box<dyn io::Read>,
+    /// How the input was given on the command line.
+    path: String,
+    /// Where the input was fetched from, if it was fetched at all.
+    url: Option<Url>,
+    /// The encoding the input was declared to be in while fetching it.
+    encoding: Option<&'static Encoding>,
+}
+
+fn open_input(
+    path: &str,
+    #[cfg_attr(not(feature = "fetch"), expect(unused_variables))] config: &Config,
+) -> Result<Input, Box<dyn Error>> {
+    #[cfg(feature = "fetch")]
+    if fetch::is_url(path) {
+        let fetched = fetch::fetch(path, &config.headers, config.user_agent.as_deref())?;
+        return Ok(Input {
+            reader: maybe_decompress(fetched.body, path)?,
+            path: path.to_string(),
+            url: Some(fetched.url),
+            encoding: fetched.encoding,
+        });
+    }
+
+    let reader: Box<dyn io::Read> = match path {
+        "-" => Box::new(std::io::stdin()),
+        f => Box::new(File::open(f).map_err(FileError::new("open", f))?),
+    };
+    Ok(Input {
+        reader: maybe_decompress(reader, path)?,
+        path: path.to_string(),
+        url: None,
+        encoding: None,
+    })
+}
+
+/// Decompresses the input if it's compressed, see [`decompress::decompress`].
+fn maybe_decompress(
+    reader: Box<dyn io::Read>,
+    #[cfg_attr(not(feature = "decompress"), expect(unused_variables))] path: &str,
+) -> Result<Box<dyn io::Read>, FileError> {
+    #[cfg(feature = "decompress")]
+    let reader = decompress::decompress(reader).map_err(FileError::new("read", path))?;
+    Ok(reader)
+}
+
+/// When to color the output, see `--color`.
+#[derive(Debug, Clone, Copy)]
+enum ColorChoice {
+    Always,
+    Never,
+    Auto,
+}
+
+impl Config {
+    fn query(&self) -> Query {
+        let selectors = self.selectors();
+        let query = match (&self.xpath, self.expressions.first()) {
+            (Some(xpath), _) => Query::xpath(xpath),
+            (None, Some(expression)) => Query::new(expression),
+            // one selector list matches in document order and each element only once already
+            (None, None) if !selectors.is_empty() => Query::new(selectors.join(", ")),
+            // comments can be outside of the root element too
+            (None, None) if self.comments => Query::xpath("/"),
+            (None, None) => Query::new(":root"),
+        };
+        let mut query = query
+            .detect_base(self.detect_base)
+            .prefer_detected_base(self.prefer_detected_base)
+            .rewrite_fragments(self.rewrite_fragments)
+            .rewrite_css(self.rewrite_css)
+            .verbatim_urls(self.no_url_normalize)
+            .unique(self.unique)
+            .reverse(self.reverse)
+            .outermost(self.outermost)
+            .innermost(self.innermost)
+            .invert(self.invert)
+            .whole_document(self.in_place.is_some())
+            .limit(self.limit())
+            .strict_template(self.format_strict)
+            .table_csv(self.table_csv)
+            .table_json(self.table_json)
+            .forms(self.forms)
+            .meta(self.meta)
+            .json_ld(self.json_ld)
+            .markdown(self.markdown)
+            .stats(self.stats)
+            .outline(self.outline)
+            .outline_max_depth(self.max_depth)
+            .on_warning(|warning| eprintln!("hq: warning: {warning}"))
+            .with_position(self.with_position)
+            .css_path(self.css_path)
+            .tag_names(self.tag_names)
+            .classes(self.classes)
+            .inner(self.inner)
+            .bare(self.bare)
+            .always_doctype(self.document)
+            .text_only(self.text_only || (self.comments && !self.comments_html))
+            .select_comments(self.comments)
+            .raw_text(self.text_raw)
+            .collapse_whitespace(self.collapse_whitespace)
+            .keep_scripts_text(self.keep_scripts_text)
+            .visible_only(self.visible_only)
+            .alt_text(self.alt_text.then(|| self.alt_text_format.clone()))
+            .link_footnotes(self.link_footnotes)
+            .ignore_whitespace(self.ignore_whitespace)
+            .pretty(self.pretty_print)
+            .minify(self.minify)
+            .indent(if self.indent_tabs {
+                Indent::Tabs
+            } else {
+                Indent::Spaces(self.indent)
+            })
+            .wrap_attributes(self.wrap_attributes)
+            .max_width(self.max_width)
+            .attribute_order(self.sort_attributes.unwrap_or_default())
+            .color(self.colors())
+            .comments(self.kept_comments(self.strip_comments))
+            .keep_comments(self.kept_comments(self.remove_comments))
+            .attribute_names(self.attributes_with_names)
+            .attribute_tsv(self.tsv)
+            .print0(self.print0)
+            .no_newline(self.no_newline)
+            .attribute_missing(self.attribute_missing.clone())
+            .attribute_fallback_text(self.attribute_fallback_text);
+
+        // the first expression is the main selector, unless there's an XPath expression
+        let stages = if self.xpath.is_some() {
+            &self.expressions[..]
+        } else {
+            self.expressions.get(1..).unwrap_or_default()
+        };
+        query = stages.iter().fold(query, Query::then_select);
+        if let Some(selector) = &self.then {
+            query = query.then_select_grouped(selector);
+        }
+        query = self.remove_nodes.iter().fold(query, Query::remove);
+        query = self.unwrap.iter().fold(query, Query::unwrap_elements);
+        query = self.attributes.iter().fold(query, Query::attribute);
+        query = self.field.iter().cloned().fold(query, Query::field);
+        query = query.all_field_values(self.field_all.clone());
+        query = self
+            .rewrite_attrs
+            .iter()
+            .fold(query, Query::rewrite_attribute);
+        if self.verbose {
+            query = query.on_rewrite_warning(|warning| eprintln!("hq: warning: {warning}"));
+        }
+
+        if let Some(base) = &self.base {
+            query = query.base(base.clone());
+        }
+        if let Some(prefix) = &self.relativize {
+            query = query.relativize(prefix.clone());
+        }
+        if let Some(key) = &self.sort_by {
+            query = query.sort_by(key.clone());
+        }
+        if let Some(nth) = self.nth {
+            query = query.nth(nth);
+        }
+        if let Some(regex) = &self.text_matches {
+            query = query.text_matches(regex.clone());
+        }
+        if let Some(regex) = &self.text_not_matches {
+            query = query.text_not_matches(regex.clone());
+        }
+        if let Some(separator) = &self.separator {
+            query = query.separator(separator);
+        }
+        if let Some(template) = &self.template {
+            query = query.template(template.clone());
+        }
+        if self.links_all {
+            query = query.links(LinkKinds::All);
+        } else if self.links {
+            query = query.links(LinkKinds::Navigation);
+        }
+        for (name, regex) in &self.attr_matches {
+            query = query.attribute_matches(name, regex.clone());
+        }
+        if let Some(wrapper) = &self.wrap {
+            query = query.wrap(wrapper.clone());
+        }
+        if let Some(html) = &self.insert_before {
+            query = query.insert_before(html);
+        }
+        if let Some(html) = &self.insert_after {
+            query = query.insert_after(html);
+        }
+        query = query
+            .sanitize(self.sanitize)
+            .sanitize_keep_style(self.sanitize_keep_style);
+        query = self
+            .strip_attrs
+            .iter()
+            .map(|pattern| pattern.trim())
+            .fold(query, Query::strip_attribute);
+        for (name, value) in &self.set_attr {
+            query = query.set_attribute(name, value);
+        }
+        for (regex, replacement) in &self.replace_text {
+            query = query.replace_text(regex.clone(), replacement);
+        }
+        if self.verbose {
+            query = query.on_text_replaced(|count| {
+                TEXT_REPLACEMENTS.fetch_add(count, Ordering::Relaxed);
+            });
+        }
+
+        let format = if self.json {
+            Format::Json
+        } else if self.ndjson {
+            Format::Ndjson
+        } else if self.count {
+            Format::Count
+        } else {
+            Format::Plain
+        };
+        query.format(format)
+    }
+
+    /// If the HTML output is to be colored, see `--color`.
+    fn colors(&self) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                self.output_path == "-"
+                    && io::stdout().is_terminal()
+                    && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+
+    /// How many matches to output at most per input.
+    fn limit(&self) -> usize {
+        if self.first {
+            1
+        } else {
+            self.limit.map_or(usize::MAX, NonZeroUsize::get)
+        }
+    }
+
+    /// Which comments are kept when leaving them out, see `--keep-conditional-comments`.
+    fn kept_comments(&self, leave_out: bool) -> Comments {
+        match (leave_out, self.keep_conditional_comments) {
+            (false, _) => Comments::All,
+            (true, false) => Comments::None,
+            (true, true) => Comments::Conditional,
+        }
+    }
+
+    /// All CSS selectors given as positional arguments, see `selector`.
+    fn selectors(&self) -> Vec<&str> {
+        // without a CSS selector, there's no need to take the first positional argument for it
+        let Some(first) = self.selector.as_ref().filter(|_| !self.takes_no_selector()) else {
+            return Vec::new();
+        };
+        let further = self
+            .inputs
+            .iter()
+            .take_while(|argument| !is_input(argument));
+        std::iter::once(first)
+            .chain(further)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// If no positional argument is a selector, because the nodes are selected otherwise or,
+    /// with `--in-place`, the first one is an input already, like in `hq -I -r script a.html`.
+    fn takes_no_selector(&self) -> bool {
+        let input_first = self.in_place.is_some() && self.selector.as_deref().is_some_and(is_input);
+        self.xpath.is_some() || !self.expressions.is_empty() || self.check || input_first
+    }
+
+    /// All inputs to process in order, defaulting to stdin.
+    ///
+    /// Problems while expanding globs are reported right away, `failed` is then set.
+    fn input_paths(&self, failed: &mut bool) -> Vec<String> {
+        let positional_input = self.selector.as_ref().filter(|_| self.takes_no_selector());
+        // the first selector is `self.selector`, the rest come first in `self.inputs`
+        let further_selectors = self.selectors().len().saturating_sub(1);
+        let mut paths: Vec<_> = self
+            .input_path
+            .iter()
+            .chain(positional_input)
+            .chain(&self.inputs[further_selectors..])
+            .chain(&self.literal_inputs)
+            .cloned()
+            .collect();
+
+        for pattern in &self.globs {
+            match glob::expand(pattern) {
+                Ok(expanded) => {
+                    for err in expanded.errors {
+                        eprintln!("hq: {pattern}: {err}");
+                        *failed = true;
+                    }
+                    paths.extend(expanded.paths);
+                }
+                Err(err) => {
+                    eprintln!("hq: {err}");
+                    *failed = true;
+                }
             }
         }
+
+        if paths.is_empty() && self.globs.is_empty() {
+            paths.push("-".to_string());
+        }
+        paths
     }
 }
 
-fn serialize_text(node: &NodeRef, ignore_whitespace: bool) -> String {
-    let mut result = String::new();
-    for text_node in node.inclusive_descendants().text_nodes() {
-        if ignore_whitespace && text_node.borrow().trim().is_empty() {
+fn parse_nth(raw: &str) -> Result<isize, String> {
+    match raw.parse::<isize>() {
+        Ok(0) => Err("indices start at 1, use -1 for the last match".to_string()),
+        Ok(nth) => Ok(nth),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_separator(raw: &str) -> Result<String, String> {
+    let mut separator = String::new();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            separator.push(c);
             continue;
         }
+        separator.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some(other) => return Err(format!("unknown escape `\\{other}`")),
+            None => return Err("dangling `\\` at the end".to_string()),
+        });
+    }
+    Ok(separator)
+}
 
-        result.push_str(&text_node.borrow());
-
-        if ignore_whitespace {
-            result.push('\n');
+fn parse_base(raw: &str) -> Result<Url, String> {
+    match Url::parse(raw) {
+        // `localhost:8080` parses as URL with the scheme `localhost`
+        Ok(url) if !url.cannot_be_a_base() => Ok(url),
+        Ok(_) | Err(url::ParseError::RelativeUrlWithoutBase) => {
+            let url = Url::parse(&format!("https://{raw}")).map_err(|err| err.to_string())?;
+            eprintln!("hq: note: assuming `{url}` for the base `{raw}`");
+            Ok(url)
         }
+        Err(err) => Err(err.to_string()),
     }
+}
+
+/// Labels of encodings likely to be looked for, out of all those encoding_rs knows.
+const COMMON_ENCODINGS: &[&str] = &[
+    "utf-8",
+    "windows-1252",
+    "iso-8859-15",
+    "windows-1251",
+    "koi8-r",
+    "shift_jis",
+    "euc-jp",
+    "iso-2022-jp",
+    "gbk",
+    "gb18030",
+    "big5",
+    "euc-kr",
+    "utf-16le",
+    "utf-16be",
+];
 
-    result
+fn parse_encoding(raw: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label_no_replacement(raw.as_bytes()).ok_or_else(|| {
+        format!(
+            "unknown encoding, common ones are {}",
+            COMMON_ENCODINGS.join(", ")
+        )
+    })
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let config = Config::parse();
+fn parse_output_encoding(raw: &str) -> Result<&'static Encoding, String> {
+    let encoding = parse_encoding(raw)?;
+    if encoding.output_encoding() != encoding {
+        return Err(format!("{} can only be read, not written", encoding.name()));
+    }
+    Ok(encoding)
+}
 
-    let mut input: Box<dyn io::Read> = match config.input_path.as_ref() {
-        "-" => Box::new(std::io::stdin()),
-        f => Box::new(File::open(f).expect("should have opened input file")),
+fn parse_fragment_context(raw: &str) -> Result<String, String> {
+    if raw.is_empty() || !raw.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err("expected a tag name like `body` or `tr`".to_string());
+    }
+    Ok(raw.to_ascii_lowercase())
+}
+
+fn parse_attribute_order(raw: &str) -> Result<AttributeOrder, String> {
+    match raw {
+        "name" => Ok(AttributeOrder::Sorted),
+        "smart" => Ok(AttributeOrder::Smart),
+        _ => Err("expected `name` or `smart`".to_string()),
+    }
+}
+
+fn parse_color(raw: &str) -> Result<ColorChoice, String> {
+    match raw {
+        "always" => Ok(ColorChoice::Always),
+        "never" => Ok(ColorChoice::Never),
+        "auto" => Ok(ColorChoice::Auto),
+        _ => Err("expected `always`, `never` or `auto`".to_string()),
+    }
+}
+
+fn parse_alt_text_format(raw: &str) -> Result<String, String> {
+    if raw.contains("{}") {
+        Ok(raw.to_string())
+    } else {
+        Err("must contain `{}` to be replaced by the alt text".to_string())
+    }
+}
+
+fn parse_attr_matches(raw: &str) -> Result<(String, Regex), String> {
+    let Some((name, regex)) = raw.split_once('=') else {
+        return Err("expected `name=REGEX`".to_string());
+    };
+    if name.is_empty() {
+        return Err("the attribute name is empty".to_string());
+    }
+    let regex = Regex::new(regex).map_err(|err| err.to_string())?;
+    Ok((name.to_string(), regex))
+}
+
+fn parse_text_replacement(raw: &str) -> Result<(Regex, String), String> {
+    let Some((regex, replacement)) = raw.split_once('=') else {
+        return Err("expected `REGEX=REPLACEMENT`".to_string());
     };
+    let regex = Regex::new(regex).map_err(|err| err.to_string())?;
+    Ok((regex, replacement.to_string()))
+}
 
-    let stdout = std::io::stdout();
-    let mut output: Box<dyn io::Write> = match config.output_path.as_ref() {
-        "-" => Box::new(stdout.lock()),
-        f => Box::new(File::create(f).expect("should have created output file")),
+fn parse_attribute_assignment(raw: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = raw.split_once('=') else {
+        return Err("expected `name=value`".to_string());
     };
+    if name.is_empty() {
+        return Err("the attribute name is empty".to_string());
+    }
+    if name.contains(|c: char| c.is_ascii_whitespace() || matches!(c, '"' | '\'' | '>' | '/')) {
+        return Err(format!("`{name}` is not a valid attribute name"));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// How many text replacements were made over all inputs, see `--replace-text`.
+static TEXT_REPLACEMENTS: AtomicUsize = AtomicUsize::new(0);
 
-    let document = kuchikiki::parse_html().from_utf8().read_from(&mut input)?;
+/// How a run of hq went, ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Matched,
+    /// The selector didn't match anything in any input.
+    NoMatches,
+    /// Something was wrong with the invocation or the inputs.
+    Error,
+    /// One of the given CSS selectors or the XPath expression couldn't be parsed.
+    InvalidSelector,
+    /// `--check` found errors in one of the inputs.
+    Invalid,
+}
 
-    let base: Option<Url> = match (&config.base, &config.detect_base) {
-        (Some(base), true) => link::detect_base(&document).or(Url::parse(base).ok()),
-        (Some(base), false) => Url::parse(base).ok(),
-        (None, true) => link::detect_base(&document),
-        _ => None,
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Matched => ExitCode::SUCCESS,
+            Status::NoMatches => ExitCode::from(1),
+            // same as clap uses for usage errors
+            Status::Error => ExitCode::from(2),
+            Status::InvalidSelector => ExitCode::from(3),
+            Status::Invalid => ExitCode::from(1),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let config = Config::parse();
+    if config.check {
+        return check(&config).into();
+    }
+
+    // the selectors are joined into one, so point out which argument was wrong on its own
+    let selectors = config.selectors();
+    if selectors.len() > 1
+        && let Some((i, selector)) = selectors
+            .iter()
+            .enumerate()
+            .find(|(_, selector)| Query::new(**selector).validate().is_err())
+    {
+        eprintln!(
+            "hq: invalid CSS selector `{selector}` given as argument {}",
+            i + 1
+        );
+        return Status::InvalidSelector.into();
+    }
+
+    let query = config.query();
+    if let Err(err) = query.validate() {
+        if let hq::Error::InvalidSelector { selector, role } = &err {
+            let origin = match role {
+                SelectorRole::Main if !config.expressions.is_empty() => "to -e",
+                SelectorRole::Main => "as selector",
+                SelectorRole::Stage => "to -e",
+                SelectorRole::Removal => "to --remove-nodes",
+                SelectorRole::Unwrap => "to --unwrap",
+                SelectorRole::Group => "to --then",
+            };
+            eprintln!("hq: invalid CSS selector `{selector}` given {origin}");
+        } else if let hq::Error::InvalidXPath { expression, error } = &err {
+            // point at the problem, assuming the expression fits on one line
+            let offset: String = expression
+                .chars()
+                .take(error.position)
+                .map(|_| ' ')
+                .collect();
+            eprintln!("hq: invalid XPath expression: {error}");
+            eprintln!("  {expression}");
+            eprintln!("  {offset}^");
+        } else {
+            eprintln!("hq: {err}");
+        }
+        return Status::InvalidSelector.into();
+    }
+
+    let stdout = std::io::stdout();
+    let mut output: Box<dyn io::Write> = match config.output_path.as_ref() {
+        "-" => Box::new(stdout.lock()),
+        f => match OpenOptions::new()
+            .write(true)
+            .append(config.append)
+            .truncate(!config.append)
+            .create(true)
+            .open(f)
+            .map_err(FileError::new("create", f))
+        {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("hq: {err}");
+                return Status::Error.into();
+            }
+        },
     };
+    output = encode_output(output, &config, &query);
 
-    for node in document
-        .select(&config.selector)
-        .expect("Failed to parse CSS selector")
+    let mut failed = false;
+    let paths = config.input_paths(&mut failed);
+    if config.in_place.is_some()
+        && let Some(path) = paths.iter().find(|path| !is_file(path))
     {
-        let node = node.as_node();
+        let name = if path == "-" { "stdin" } else { path };
+        eprintln!("hq: --in-place can only replace files, not {name}");
+        return Status::Error.into();
+    }
+    if config.in_place.is_some() && !query.writes_html() {
+        // anything else would replace the file by only a part of it
+        eprintln!("hq: --in-place can only write back whole documents as HTML");
+        return Status::Error.into();
+    }
+    let show_filenames = paths.len() > 1 && !config.no_filename;
+    let mut matches = 0;
+    let mut split = config
+        .output_pattern
+        .as_ref()
+        .map(|pattern| Split::new(pattern, config.force));
 
-        // detach those nodes that should be removed
-        if let Ok(targets) = node.select(&config.remove_nodes.join(",")) {
-            for target in targets {
-                target.as_node().detach();
+    for (i, path) in paths.iter().enumerate() {
+        let read = |input| read_document(input, &config);
+        let document = match open_input(path, &config).and_then(read) {
+            Ok(document) => document,
+            Err(err) => {
+                eprintln!("hq: {err}");
+                failed = true;
+                continue;
             }
-        }
+        };
 
-        if let Some(base) = &base {
-            link::rewrite_relative_url(node, base);
+        if config.quiet {
+            match query
+                .select(&document)
+                .map(|mut matches| matches.any(|matched| !matched.lacks_attributes()))
+            {
+                Ok(true) => return Status::Matched.into(),
+                Ok(false) => {}
+                Err(err) => {
+                    eprintln!("hq: {path}: {err}");
+                    failed = true;
+                }
+            }
+            continue;
         }
 
-        if !config.attributes.is_empty() {
-            select_attributes(node, &config.attributes, &mut output);
+        if let Some(split) = &mut split {
+            let open = |matched: &_| -> Result<_, Box<dyn Error>> {
+                let file = io::BufWriter::new(split.open(matched)?);
+                Ok(encode_output(Box::new(file), &config, &query))
+            };
+            match query.write_each(&document, open) {
+                Ok(count) => matches += count,
+                Err(err) => {
+                    eprintln!("hq: {err}");
+                    failed = true;
+                }
+            }
             continue;
         }
 
-        if config.text_only {
-            // let content = serialize_text(node, config.ignore_whitespace);
-            // output.write_all(format!("{}\n", content).as_ref()).ok();
-            writeln!(output, "{}", serialize_text(node, config.ignore_whitespace)).ok();
+        if let Some(suffix) = &config.in_place {
+            let mut contents = Vec::new();
+            let mut output = encode_output(Box::new(&mut contents), &config, &query);
+            let result = query
+                .write(&document, &mut output)
+                .and_then(|count| Ok(output.flush().map(|()| count)?));
+            drop(output);
+            match result {
+                // don't replace the file by nothing
+                Ok(0) => {}
+                Ok(count) => {
+                    matches += count;
+                    let backup = Some(suffix.as_str()).filter(|suffix| !suffix.is_empty());
+                    if let Err(err) = in_place::replace(path, &contents, backup) {
+                        eprintln!("hq: {err}");
+                        failed = true;
+                    }
+                }
+                Err(err) => {
+                    eprintln!("hq: {path}: {err}");
+                    failed = true;
+                }
+            }
             continue;
         }
 
-        if config.pretty_print {
-            // let content = pretty_print::pretty_print(node);
-            // output.write_all(content.as_ref()).ok();
-            writeln!(output, "{}", pretty_print::pretty_print(node)).ok();
-            continue;
+        let result = if show_filenames {
+            write_filename(&mut output, path, i == 0).map_err(Into::into)
+        } else {
+            Ok(())
         }
+        .and_then(|()| query.write(&document, &mut output));
 
-        writeln!(output, "{}", node.to_string()).ok();
-        // let mut content: Vec<u8> = Vec::new();
-        // let Ok(_) = node.serialize(&mut content) else {
-        //     return
-        // };
-        // output.write_all(format!("{}\n", content).as_ref()).ok();
+        match result {
+            Ok(count) => matches += count,
+            // whoever reads our output isn't interested in any more of it
+            Err(hq::Error::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {
+                return Status::Matched.into();
+            }
+            Err(hq::Error::Io(err)) => {
+                eprintln!("hq: {}", FileError::new("write", &config.output_path)(err));
+                failed = true;
+            }
+            Err(hq::Error::NthOutOfRange { nth, len }) => {
+                eprintln!("hq: {path}: --nth {nth} is out of range, only {len} nodes matched");
+                failed = true;
+            }
+            Err(err) => {
+                eprintln!("hq: {path}: {err}");
+                failed = true;
+            }
+        }
     }
 
-    Ok(())
+    if let Err(err) = output.flush() {
+        if err.kind() == io::ErrorKind::BrokenPipe {
+            return Status::Matched.into();
+        }
+        eprintln!("hq: {}", FileError::new("write", &config.output_path)(err));
+        failed = true;
+    }
+
+    if config.verbose && !config.replace_text.is_empty() {
+        let count = TEXT_REPLACEMENTS.load(Ordering::Relaxed);
+        eprintln!("hq: made {count} text replacements");
+    }
+
+    let status = if failed {
+        Status::Error
+    } else if matches == 0 && !config.allow_empty {
+        Status::NoMatches
+    } else {
+        Status::Matched
+    };
+    status.into()
+}
+
+/// Writes the problems in all inputs, see `--check`.
+fn check(config: &Config) -> Status {
+    let mut failed = false;
+    let mut invalid = false;
+    let paths = config.input_paths(&mut failed);
+    let show_filenames = paths.len() > 1 && !config.no_filename;
+    let mut stdout = io::stdout().lock();
+    let output: &mut dyn io::Write = &mut stdout;
+
+    for path in &paths {
+        let read = |input| read_document(input, config);
+        let document = match open_input(path, config).and_then(read) {
+            Ok(document) => document,
+            Err(err) => {
+                eprintln!("hq: {err}");
+                failed = true;
+                continue;
+            }
+        };
+
+        for problem in check::check(&document) {
+            invalid |= problem.severity == Severity::Error;
+            let result = if show_filenames {
+                writeln!(output, "{path}:{problem}")
+            } else {
+                writeln!(output, "{problem}")
+            };
+            match result {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Status::Matched,
+                Err(err) => {
+                    eprintln!("hq: {}", FileError::new("write", "-")(err));
+                    return Status::Error;
+                }
+            }
+        }
+    }
+
+    if failed {
+        Status::Error
+    } else if invalid {
+        Status::Invalid
+    } else {
+        Status::Matched
+    }
+}
+
+fn read_document(mut input: Input, config: &Config) -> Result<Document, Box<dyn Error>> {
+    let options = ParseOptions {
+        transport_encoding: input.encoding,
+        encoding: config.input_encoding,
+        strict: config.strict,
+        positions: config.with_position || config.check,
+        parse_errors: config.check,
+        xml: config.xml,
+        fragment: config.fragment.then(|| config.fragment_context.clone()),
+    };
+    let document = Document::parse_with(&mut input.reader, &options)
+        .map_err(FileError::new("read", &input.path))?;
+    Ok(document.with_url(input.url))
+}
+
+/// Wraps the output to transcode it, see `--output-encoding`.
+fn encode_output<'o>(
+    output: Box<dyn io::Write + 'o>,
+    config: &Config,
+    query: &Query,
+) -> Box<dyn io::Write + 'o> {
+    let Some(encoding) = config.output_encoding else {
+        return output;
+    };
+    let unmappable = if query.writes_html() {
+        Unmappable::CharacterReference
+    } else if config.strict {
+        Unmappable::Error
+    } else {
+        Unmappable::QuestionMark
+    };
+    Box::new(EncodingWriter::new(output, encoding, unmappable))
+}
+
+/// Extensions of files that are taken as inputs even if they don't exist, so a typo
+/// in a path is reported instead of taken as a selector.
+const INPUT_EXTENSIONS: &[&str] = &[
+    "html", "htm", "xhtml", "shtml", "xml", "svg", "rss", "atom", "gz", "zst",
+];
+
+/// If the positional argument is an input rather than a selector, see `Config::selector`.
+fn is_input(argument: &str) -> bool {
+    #[cfg(feature = "fetch")]
+    if fetch::is_url(argument) {
+        return true;
+    }
+    let path = Path::new(argument);
+    // selectors only contain a `/` in attribute values, which further selectors rarely need
+    argument == "-"
+        || argument.contains('/')
+        || path.exists()
+        || path.extension().is_some_and(|extension| {
+            INPUT_EXTENSIONS
+                .iter()
+                .any(|input| extension.eq_ignore_ascii_case(input))
+        })
+}
+
+/// If the input is a file, as opposed to stdin or a URL.
+fn is_file(path: &str) -> bool {
+    #[cfg(feature = "fetch")]
+    if fetch::is_url(path) {
+        return false;
+    }
+    path != "-"
+}
+
+/// Writes a `==> path <==` header, separated by an empty line from the previous input's output.
+fn write_filename(output: &mut dyn io::Write, path: &str, first: bool) -> io::Result<()> {
+    if !first {
+        writeln!(output)?;
+    }
+    writeln!(output, "==> {path} <==")
 }
@@ -0,0 +1,482 @@
+//! Converting HTML to Markdown, like for pulling articles out of pages.
+
+use html5ever::local_name;
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{NodeData, NodeRef};
+
+/// Renders the node and everything in it as Markdown.
+///
+/// Headings, paragraphs, emphasis, links, images, lists, blockquotes, inline code and code
+/// blocks are converted, other elements are replaced by what is inside them.
+/// Scripts, styles and the like are skipped.
+pub fn to_markdown(node: &NodeRef) -> String {
+    let blocks = blocks(std::iter::once(node.clone()));
+    join(&blocks, "\n\n")
+}
+
+/// A rendered block-level element, like a paragraph.
+struct Block {
+    text: String,
+    /// If it is a list, which list items put right below their text, without an empty line.
+    list: bool,
+}
+
+impl Block {
+    fn new(text: String) -> Self {
+        Self { text, list: false }
+    }
+}
+
+/// Elements whose content is never output.
+fn is_skipped(name: &str) -> bool {
+    matches!(
+        name,
+        "head" | "script" | "style" | "template" | "noscript" | "iframe" | "object" | "svg"
+    )
+}
+
+/// Elements that are rendered inline, even if they contain blocks.
+fn is_inline(name: &str) -> bool {
+    matches!(
+        name,
+        "a" | "abbr"
+            | "b"
+            | "br"
+            | "cite"
+            | "code"
+            | "em"
+            | "i"
+            | "img"
+            | "kbd"
+            | "mark"
+            | "q"
+            | "s"
+            | "samp"
+            | "small"
+            | "strong"
+            | "sub"
+            | "sup"
+            | "time"
+            | "tt"
+            | "u"
+            | "var"
+    )
+}
+
+/// Elements that always start a block of their own.
+fn is_block_name(name: &str) -> bool {
+    matches!(
+        name,
+        "address"
+            | "article"
+            | "aside"
+            | "blockquote"
+            | "body"
+            | "dd"
+            | "details"
+            | "div"
+            | "dl"
+            | "dt"
+            | "fieldset"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "form"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "header"
+            | "hr"
+            | "html"
+            | "li"
+            | "main"
+            | "nav"
+            | "ol"
+            | "p"
+            | "pre"
+            | "section"
+            | "summary"
+            | "table"
+            | "tbody"
+            | "td"
+            | "tfoot"
+            | "th"
+            | "thead"
+            | "tr"
+            | "ul"
+    )
+}
+
+/// If the node is rendered as block, which is also the case for unknown elements containing
+/// blocks.
+fn is_block(node: &NodeRef) -> bool {
+    match node.data() {
+        NodeData::Document(_) | NodeData::DocumentFragment => true,
+        NodeData::Element(elem) => {
+            let name = &*elem.name.local;
+            is_block_name(name)
+                || (!is_inline(name) && !is_skipped(name) && node.children().any(|c| is_block(&c)))
+        }
+        _ => false,
+    }
+}
+
+/// Renders the nodes, collecting consecutive inline content into paragraphs.
+fn blocks(nodes: impl Iterator<Item = NodeRef>) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut paragraph = String::new();
+    for node in nodes {
+        if is_block(&node) {
+            push_paragraph(&mut blocks, &std::mem::take(&mut paragraph));
+            render_block(&node, &mut blocks);
+        } else {
+            paragraph.push_str(&inline(&node));
+        }
+    }
+    push_paragraph(&mut blocks, &paragraph);
+    blocks
+}
+
+fn push_paragraph(blocks: &mut Vec<Block>, inline: &str) {
+    let text = tidy(inline);
+    if !text.is_empty() {
+        blocks.push(Block::new(text));
+    }
+}
+
+fn render_block(node: &NodeRef, blocks: &mut Vec<Block>) {
+    let Some(elem) = node.as_element() else {
+        blocks.extend(self::blocks(node.children()));
+        return;
+    };
+
+    match elem.name.local {
+        local_name!("h1")
+        | local_name!("h2")
+        | local_name!("h3")
+        | local_name!("h4")
+        | local_name!("h5")
+        | local_name!("h6") => {
+            let level: usize = elem.name.local[1..].parse().unwrap_or(1);
+            let text = tidy(&inline_children(node));
+            if !text.is_empty() {
+                blocks.push(Block::new(format!("{} {text}", "#".repeat(level))));
+            }
+        }
+        local_name!("p") => push_paragraph(blocks, &inline_children(node)),
+        local_name!("ul") | local_name!("ol") => {
+            let list = list(node, elem.name.local == local_name!("ol"));
+            if !list.is_empty() {
+                blocks.push(Block {
+                    text: list,
+                    list: true,
+                });
+            }
+        }
+        local_name!("pre") => blocks.push(Block::new(code_block(node))),
+        local_name!("blockquote") => {
+            let inner = join(&self::blocks(node.children()), "\n\n");
+            if !inner.is_empty() {
+                let quoted: Vec<_> = inner
+                    .lines()
+                    .map(|line| {
+                        if line.is_empty() {
+                            ">".to_string()
+                        } else {
+                            format!("> {line}")
+                        }
+                    })
+                    .collect();
+                blocks.push(Block::new(quoted.join("\n")));
+            }
+        }
+        local_name!("hr") => blocks.push(Block::new("---".to_string())),
+        _ => blocks.extend(self::blocks(node.children())),
+    }
+}
+
+/// Renders the `li` children of the list, continuing lines indented below the marker.
+fn list(node: &NodeRef, ordered: bool) -> String {
+    let start = node
+        .as_element()
+        .and_then(|elem| elem.attributes.borrow().get("start")?.parse::<usize>().ok())
+        .unwrap_or(1);
+
+    let items = node
+        .children()
+        .elements()
+        .filter(|child| child.name.local == local_name!("li"));
+    let mut rendered = Vec::new();
+    for (i, item) in items.enumerate() {
+        let marker = if ordered {
+            format!("{}. ", start + i)
+        } else {
+            "- ".to_string()
+        };
+
+        let mut body = String::new();
+        for (j, block) in self::blocks(item.as_node().children()).iter().enumerate() {
+            if j > 0 {
+                // keep nested lists tight, but separate paragraphs
+                body.push_str(if block.list { "\n" } else { "\n\n" });
+            }
+            body.push_str(&block.text);
+        }
+
+        let indent = " ".repeat(marker.len());
+        let mut lines = body.lines();
+        let mut item_text = format!("{marker}{}", lines.next().unwrap_or_default());
+        for line in lines {
+            item_text.push('\n');
+            if !line.is_empty() {
+                item_text.push_str(&indent);
+                item_text.push_str(line);
+            }
+        }
+        rendered.push(item_text.trim_end().to_string());
+    }
+    rendered.join("\n")
+}
+
+/// Renders a `<pre>` as fenced code block, taking the language from a `language-*` class.
+fn code_block(node: &NodeRef) -> String {
+    let code = node.text_contents();
+    let code = code.trim_end_matches('\n');
+    let language = node
+        .descendants()
+        .elements()
+        .find(|elem| elem.name.local == local_name!("code"))
+        .and_then(|elem| {
+            let attrs = elem.attributes.borrow();
+            attrs
+                .get("class")?
+                .split_ascii_whitespace()
+                .find_map(|class| {
+                    class
+                        .strip_prefix("language-")
+                        .or_else(|| class.strip_prefix("lang-"))
+                        .map(str::to_string)
+                })
+        })
+        .unwrap_or_default();
+
+    let fence = "`".repeat(longest_backtick_run(code).max(2) + 1);
+    format!("{fence}{language}\n{code}\n{fence}")
+}
+
+fn inline_children(node: &NodeRef) -> String {
+    node.children().map(|child| inline(&child)).collect()
+}
+
+fn inline(node: &NodeRef) -> String {
+    let elem = match node.data() {
+        NodeData::Text(text) => return escape(&collapse_whitespace(&text.borrow())),
+        NodeData::Element(elem) => elem,
+        _ => return String::new(),
+    };
+    let attribute = |name: &str| elem.attributes.borrow().get(name).map(str::to_string);
+
+    match elem.name.local {
+        ref name if is_skipped(name) => String::new(),
+        local_name!("br") => "\\\n".to_string(),
+        local_name!("img") => {
+            let alt = escape(&collapse_whitespace(&attribute("alt").unwrap_or_default()));
+            match attribute("src") {
+                Some(src) => format!("![{alt}]({})", destination(&src)),
+                None => alt,
+            }
+        }
+        local_name!("code") | local_name!("kbd") | local_name!("samp") | local_name!("tt") => {
+            let code = collapse_whitespace(&node.text_contents());
+            if code.trim().is_empty() {
+                return code;
+            }
+            let ticks = "`".repeat(longest_backtick_run(&code) + 1);
+            let pad = if code.starts_with('`') || code.ends_with('`') {
+                " "
+            } else {
+                ""
+            };
+            format!("{ticks}{pad}{code}{pad}{ticks}")
+        }
+        local_name!("em") | local_name!("i") => wrap(&inline_children(node), "*"),
+        local_name!("strong") | local_name!("b") => wrap(&inline_children(node), "**"),
+        local_name!("a") => {
+            let text = inline_children(node);
+            match attribute("href") {
+                Some(href) if text.trim().is_empty() => format!("{text}<{href}>"),
+                Some(href) => {
+                    let (lead, trimmed, trail) = split_whitespace(&text);
+                    format!("{lead}[{trimmed}]({}){trail}", destination(&href))
+                }
+                None => text,
+            }
+        }
+        _ => inline_children(node),
+    }
+}
+
+/// Surrounds the text with the marker, keeping surrounding whitespace outside of it.
+fn wrap(text: &str, marker: &str) -> String {
+    let (lead, trimmed, trail) = split_whitespace(text);
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    format!("{lead}{marker}{trimmed}{marker}{trail}")
+}
+
+fn split_whitespace(text: &str) -> (&str, &str, &str) {
+    let trimmed = text.trim();
+    let lead = if text.starts_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    let trail = if text.ends_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    (lead, trimmed, trail)
+}
+
+/// Wraps link destinations in `<>` if they'd otherwise end the link early.
+fn destination(url: &str) -> String {
+    if url.contains([' ', '(', ')']) {
+        format!("<{url}>")
+    } else {
+        url.to_string()
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Replaces all runs of whitespace by a single space, like browsers do.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(c);
+            in_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+/// Collapses the spaces left over from joining inline content and trims each line.
+fn tidy(text: &str) -> String {
+    let lines: Vec<_> = text
+        .split('\n')
+        .map(|line| collapse_whitespace(line).trim().to_string())
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+fn join(blocks: &[Block], separator: &str) -> String {
+    let texts: Vec<_> = blocks.iter().map(|block| block.text.as_str()).collect();
+    texts.join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn markdown(html: &str) -> String {
+        let doc = kuchikiki::parse_html().one(html);
+        let article = doc.select_first("article").unwrap();
+        to_markdown(article.as_node())
+    }
+
+    #[test]
+    fn article() {
+        let html = r#"<article>
+  <h1>Parsing  <em>HTML</em></h1>
+  <p>Browsers are <strong>forgiving</strong>, see the
+    <a href="https://html.spec.whatwg.org/">spec</a> and <code>parse_html()</code>.</p>
+  <script>track();</script>
+  <p><img src="/tree.png" alt="A tree"><br>Figure *1*</p>
+  <ul>
+    <li>Tokenizing
+      <ol start="3">
+        <li>Tags</li>
+        <li>Text with <i>style</i></li>
+      </ol>
+    </li>
+    <li><p>Tree building</p><p>Second paragraph</p></li>
+  </ul>
+  <blockquote><p>Be liberal</p><p>in what you accept</p></blockquote>
+  <pre><code class="language-rust">fn main() {
+    println!("```");
+}
+</code></pre>
+  <custom-note><div>Unknown <span>elements</span></div> still show</custom-note>
+</article>"#;
+        assert_eq!(
+            markdown(html),
+            r#"# Parsing *HTML*
+
+Browsers are **forgiving**, see the [spec](https://html.spec.whatwg.org/) and `parse_html()`.
+
+![A tree](/tree.png)\
+Figure \*1\*
+
+- Tokenizing
+  3. Tags
+  4. Text with *style*
+- Tree building
+
+  Second paragraph
+
+> Be liberal
+>
+> in what you accept
+
+````rust
+fn main() {
+    println!("```");
+}
+````
+
+Unknown elements
+
+still show"#
+        );
+    }
+
+    #[test]
+    fn inline_edge_cases() {
+        assert_eq!(
+            markdown(
+                "<article>a<em> b </em>c <a href=\"/x y\">link</a> <code>`t`</code></article>"
+            ),
+            "a *b* c [link](</x y>) `` `t` ``"
+        );
+        assert_eq!(
+            markdown("<article><a href=\"/x\"></a><b> </b>!</article>"),
+            "</x> !"
+        );
+    }
+}
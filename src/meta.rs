@@ -0,0 +1,72 @@
+//! Metadata of documents, like the title and OpenGraph properties.
+
+use html5ever::local_name;
+use kuchikiki::NodeRef;
+use kuchikiki::traits::NodeIterator;
+
+/// All metadata in the `<head>` within the node, as key-value pairs in document order.
+///
+/// These are the `<title>` as `title`, `<meta>` elements by their `name` or `property`
+/// with their `content` and `<link rel="canonical">` as `canonical`. Keys can repeat.
+pub fn entries(node: &NodeRef) -> Vec<(String, String)> {
+    node.inclusive_descendants()
+        .elements()
+        .filter(|elem| {
+            elem.as_node()
+                .inclusive_ancestors()
+                .elements()
+                .any(|ancestor| ancestor.name.local == local_name!("head"))
+        })
+        .filter_map(|elem| {
+            let attrs = elem.attributes.borrow();
+            match elem.name.local {
+                local_name!("title") => {
+                    let text = elem.text_contents();
+                    Some(("title".to_string(), text.trim().to_string()))
+                }
+                local_name!("meta") => {
+                    let key = attrs.get("property").or_else(|| attrs.get("name"))?;
+                    let content = attrs.get("content")?;
+                    Some((key.to_string(), content.to_string()))
+                }
+                local_name!("link") => {
+                    let is_canonical = attrs
+                        .get("rel")?
+                        .split_ascii_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("canonical"));
+                    let href = attrs.get("href").filter(|_| is_canonical)?;
+                    Some(("canonical".to_string(), href.to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    #[test]
+    fn head_is_scanned() {
+        let doc = kuchikiki::parse_html().one(
+            "<head><title> Page </title><meta charset=\"utf-8\">\
+            <meta property=\"og:image\" content=\"/a.png\"><meta property=\"og:image\" content=\"/b.png\">\
+            <meta name=\"twitter:card\" content=\"summary\"><link rel=\"Canonical\" href=\"/page\">\
+            <link rel=\"stylesheet\" href=\"/style.css\"></head>\
+            <body><meta name=\"ignored\" content=\"x\"><svg><title>no</title></svg></body>",
+        );
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            entries(&doc),
+            [
+                pair("title", "Page"),
+                pair("og:image", "/a.png"),
+                pair("og:image", "/b.png"),
+                pair("twitter:card", "summary"),
+                pair("canonical", "/page"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Serializing nodes as HTML with everything left out that doesn't change the document.
+
+use crate::pretty_print::{is_ascii_space, is_inline, is_verbatim};
+use crate::serialize::{SerializeOptions, order_attributes, serialize_attribute};
+use html5ever::QualName;
+use html5ever::serialize::{HtmlSerializer, SerializeOpts, Serializer};
+use kuchikiki::{ElementData, NodeData, NodeRef};
+use std::io::{self, Write};
+
+/// Serializes the node and its descendants as HTML, as short as it can be while parsing to the
+/// same document.
+///
+/// Whitespace between block elements is left out and runs of it are collapsed to one space,
+/// except in `<pre>`, `<textarea>`, `<script>` and `<style>`. Comments are always left out, and
+/// attribute values are only quoted if they have to be. Of the options, only
+/// [`SerializeOptions::attribute_order`] has an effect.
+pub fn minify(node: &NodeRef, options: &SerializeOptions) -> String {
+    let mut minify = Minify::new(options);
+    minify.node(node).unwrap();
+    String::from_utf8(minify.inner.writer).unwrap()
+}
+
+/// Like [`minify`], but only serializes the children of the node.
+pub fn minify_children(node: &NodeRef, options: &SerializeOptions) -> String {
+    let mut minify = Minify::new(options);
+    minify.children(node).unwrap();
+    String::from_utf8(minify.inner.writer).unwrap()
+}
+
+/// Walks the tree itself instead of being an html5ever [`Serializer`], since whether whitespace
+/// matters depends on what's around it.
+struct Minify<'o> {
+    options: &'o SerializeOptions,
+    verbatim: usize,
+    inner: HtmlSerializer<Vec<u8>>,
+}
+
+impl<'o> Minify<'o> {
+    fn new(options: &'o SerializeOptions) -> Self {
+        Self {
+            options,
+            verbatim: 0,
+            inner: HtmlSerializer::new(Vec::new(), SerializeOpts::default()),
+        }
+    }
+
+    fn node(&mut self, node: &NodeRef) -> io::Result<()> {
+        match node.data() {
+            NodeData::Element(elem) => self.element(node, elem),
+            NodeData::Text(text) => self.text(node, &text.borrow()),
+            NodeData::Doctype(doctype) => self
+                .inner
+                .writer
+                .write_all(crate::serialize_doctype(doctype).as_bytes()),
+            NodeData::ProcessingInstruction(contents) => {
+                let contents = contents.borrow();
+                self.inner
+                    .write_processing_instruction(&contents.0, &contents.1)
+            }
+            NodeData::Comment(_) => Ok(()),
+            NodeData::Document(_) | NodeData::DocumentFragment => self.children(node),
+        }
+    }
+
+    fn children(&mut self, node: &NodeRef) -> io::Result<()> {
+        node.children().try_for_each(|child| self.node(&child))
+    }
+
+    fn element(&mut self, node: &NodeRef, elem: &ElementData) -> io::Result<()> {
+        let attrs = elem.attributes.borrow();
+        let names: Vec<_> = attrs
+            .map
+            .iter()
+            .map(|(name, attr)| {
+                let name = QualName::new(attr.prefix.clone(), name.ns.clone(), name.local.clone());
+                (name, &*attr.value)
+            })
+            .collect();
+        let mut attrs: Vec<_> = names.iter().map(|(name, value)| (name, *value)).collect();
+        order_attributes(&mut attrs, self.options.attribute_order);
+
+        // let html5ever keep track of the element, but write the attributes here
+        let start = self.inner.writer.len();
+        self.inner
+            .start_elem(elem.name.clone(), std::iter::empty())?;
+        if self.inner.writer.len() > start {
+            self.inner.writer.pop();
+            for attr in attrs {
+                self.inner.writer.write_all(b" ")?;
+                let attr = unquote(serialize_attribute(&elem.name, attr)?);
+                self.inner.writer.write_all(&attr)?;
+            }
+            self.inner.writer.write_all(b">")?;
+        }
+
+        let verbatim = is_verbatim(&elem.name.local);
+        self.verbatim += usize::from(verbatim);
+        self.children(node)?;
+        self.verbatim -= usize::from(verbatim);
+
+        self.inner.end_elem(elem.name.clone())
+    }
+
+    fn text(&mut self, node: &NodeRef, text: &str) -> io::Result<()> {
+        if self.verbatim > 0 {
+            return self.inner.write_text(text);
+        }
+
+        let mut collapsed = String::with_capacity(text.len());
+        for c in text.chars() {
+            if !is_ascii_space(c) {
+                collapsed.push(c);
+            } else if !collapsed.ends_with(' ') {
+                collapsed.push(' ');
+            }
+        }
+        if collapsed.ends_with(' ') && is_boundary(node, node.following_siblings()) {
+            collapsed.pop();
+        }
+        if collapsed.starts_with(' ') && is_boundary(node, node.preceding_siblings()) {
+            collapsed.remove(0);
+        }
+
+        if collapsed.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_text(&collapsed)
+    }
+}
+
+/// If whitespace between the node and the first of the siblings isn't rendered,
+/// which is the case next to a block element and at the start or end of one.
+fn is_boundary(node: &NodeRef, mut siblings: impl Iterator<Item = NodeRef>) -> bool {
+    // comments are left out, so they don't separate anything
+    let sibling = siblings.find(|sibling| sibling.as_comment().is_none());
+    let neighbour = match sibling {
+        Some(sibling) => sibling,
+        None => match node.parent() {
+            Some(parent) => parent,
+            None => return true,
+        },
+    };
+    match neighbour.as_element() {
+        Some(elem) => !is_inline(&elem.name.local),
+        None => neighbour.as_text().is_none(),
+    }
+}
+
+/// Leaves out the quotes around the value of the attribute serialized as `name="value"`
+/// where they aren't needed, and the whole value if it's empty.
+fn unquote(mut attr: Vec<u8>) -> Vec<u8> {
+    let Some(equals) = attr.iter().position(|&b| b == b'=') else {
+        return attr;
+    };
+    // skip `="` and `"`
+    let value = &attr[equals + 2..attr.len() - 1];
+    if value.is_empty() {
+        attr.truncate(equals);
+        return attr;
+    }
+    let needs_quotes = value.iter().any(|&b| {
+        is_ascii_space(b.into()) || matches!(b, b'"' | b'\'' | b'=' | b'<' | b'>' | b'`')
+    });
+    if needs_quotes {
+        return attr;
+    }
+    [&attr[..=equals], value].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn minified(html: &str) -> String {
+        let doc = kuchikiki::parse_html().one(html);
+        minify(&doc, &SerializeOptions::default())
+    }
+
+    /// The name and attributes of all elements, and the text with whitespace collapsed.
+    fn structure(html: &str) -> Vec<String> {
+        let doc = kuchikiki::parse_html().one(html);
+        doc.descendants()
+            .filter_map(|node| {
+                if let Some(text) = node.as_text() {
+                    let text = text
+                        .borrow()
+                        .split_ascii_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    return (!text.is_empty()).then_some(text);
+                }
+                let elem = node.as_element()?;
+                let attrs = elem.attributes.borrow();
+                Some(format!("{} {:?}", elem.name.local, attrs.map))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn minified_html_parses_to_the_same_document() {
+        let html = "<!DOCTYPE html>\n<html>\n  <head>\n    <title> Title </title>\n  </head>\n  \
+            <body class=\"a b\" data-x=\"1\" hidden=\"\">\n    <!-- note -->\n    <ul>\n      \
+            <li><a href=\"/a?b=c&amp;d\" title='say \"hi\"'>one</a></li>\n      <li>two   \
+            words</li>\n    </ul>\n    <pre>  keep\n    this  </pre>\n    \
+            <script>if (a < b) {  }</script>\n  </body>\n</html>\n";
+        let output = minified(html);
+
+        assert_eq!(
+            output,
+            "<!DOCTYPE html><html><head><title>Title</title></head>\
+            <body class=\"a b\" data-x=1 hidden><ul>\
+            <li><a href=\"/a?b=c&amp;d\" title=\"say &quot;hi&quot;\">one</a></li>\
+            <li>two words</li></ul><pre>  keep\n    this  </pre>\
+            <script>if (a < b) {  }</script></body></html>"
+        );
+        assert_eq!(structure(&output), structure(html));
+        assert_eq!(minified(&output), output);
+    }
+
+    #[test]
+    fn space_between_inline_elements_is_kept() {
+        assert_eq!(
+            minified("<p>\n  <b>bold</b>   \n  <i>italic</i>\n</p>"),
+            "<html><head></head><body><p><b>bold</b> <i>italic</i></p></body></html>"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Condensed views of the structure of nodes, like `div#content > ul.nav > li ×12`.
+
+use crate::pretty_print::is_ascii_space;
+use crate::serialize::palette;
+use kuchikiki::NodeRef;
+
+/// How [`outline`] condenses the tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutlineOptions {
+    /// How many levels of elements to show, starting with the node itself. Elements whose
+    /// children are left out because of this end in `…`.
+    pub max_depth: Option<usize>,
+    /// Dim counts and text previews with ANSI escape codes, for reading in a terminal.
+    pub color: bool,
+}
+
+/// How many characters of text are shown at most.
+const PREVIEW_LENGTH: usize = 30;
+
+/// An indented tree of the elements in the node, each written like a CSS selector with its id and
+/// classes, and previews of their text.
+///
+/// Runs of siblings written the same way are written once, with how many there are like
+/// `li ×12`, followed by the children of the first of them. Elements with only one child element
+/// are written on one line with it, like `ul.nav > li`. Comments are left out.
+pub fn outline(node: &NodeRef, options: &OutlineOptions) -> String {
+    let mut output = String::new();
+    let entries = match node.as_element() {
+        Some(_) => vec![Entry::new(node.clone())],
+        None => entries(node),
+    };
+    write_entries(&entries, 0, 1, options, &mut output);
+    output
+}
+
+/// A node in the outline, standing for `count` siblings looking the same.
+struct Entry {
+    node: NodeRef,
+    label: String,
+    count: usize,
+}
+
+impl Entry {
+    fn new(node: NodeRef) -> Self {
+        let label = label(&node).unwrap_or_default();
+        Self {
+            node,
+            label,
+            count: 1,
+        }
+    }
+
+    fn is_element(&self) -> bool {
+        self.node.as_element().is_some()
+    }
+
+    fn write_label(&self, options: &OutlineOptions, output: &mut String) {
+        output.push_str(&self.label);
+        if self.count > 1 {
+            output.push(' ');
+            output.push_str(&dimmed(&format!("×{}", self.count), options));
+        }
+    }
+}
+
+/// The children of the node that are shown, with runs of the same label collapsed.
+fn entries(node: &NodeRef) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for child in node.children() {
+        let Some(label) = label(&child) else {
+            continue;
+        };
+        if let Some(last) = entries.last_mut()
+            && last.is_element()
+            && last.label == label
+        {
+            last.count += 1;
+            continue;
+        }
+        entries.push(Entry {
+            node: child,
+            label,
+            count: 1,
+        });
+    }
+    entries
+}
+
+/// How the node is written, or `None` if it isn't shown.
+fn label(node: &NodeRef) -> Option<String> {
+    if let Some(text) = node.as_text() {
+        return preview(&text.borrow()).map(|preview| format!("\"{preview}\""));
+    }
+    let elem = node.as_element()?;
+    let mut label = elem.name.local.to_string();
+    let attributes = elem.attributes.borrow();
+    if let Some(id) = attributes.get("id").filter(|id| !id.is_empty()) {
+        label.push('#');
+        label.push_str(id);
+    }
+    for class in attributes
+        .get("class")
+        .into_iter()
+        .flat_map(str::split_ascii_whitespace)
+    {
+        label.push('.');
+        label.push_str(class);
+    }
+    Some(label)
+}
+
+/// The text with whitespace collapsed, shortened to [`PREVIEW_LENGTH`] characters,
+/// or `None` if it's only whitespace.
+fn preview(text: &str) -> Option<String> {
+    let words: Vec<_> = text
+        .split(is_ascii_space)
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let collapsed = words.join(" ");
+    if collapsed.chars().count() <= PREVIEW_LENGTH {
+        return Some(collapsed);
+    }
+    let shortened: String = collapsed.chars().take(PREVIEW_LENGTH - 1).collect();
+    let mut shortened = shortened.trim_end().to_string();
+    shortened.push('…');
+    Some(shortened)
+}
+
+/// Writes each entry on its own line, indented by `indent`, with `depth` being how deeply the
+/// entries are nested in the outlined node.
+fn write_entries(
+    entries: &[Entry],
+    indent: usize,
+    depth: usize,
+    options: &OutlineOptions,
+    output: &mut String,
+) {
+    for entry in entries {
+        output.push_str(&"  ".repeat(indent));
+        write_chain(entry, indent, depth, options, output);
+    }
+}
+
+/// Writes the entry along with its only child element and their only child element and so on,
+/// followed by the children of the last of them.
+fn write_chain(
+    entry: &Entry,
+    indent: usize,
+    mut depth: usize,
+    options: &OutlineOptions,
+    output: &mut String,
+) {
+    if !entry.is_element() {
+        output.push_str(&dimmed(&entry.label, options));
+        output.push('\n');
+        return;
+    }
+    entry.write_label(options, output);
+
+    let mut node = entry.node.clone();
+    loop {
+        let children = entries(&node);
+        if options
+            .max_depth
+            .is_some_and(|max_depth| depth >= max_depth)
+        {
+            if !children.is_empty() {
+                output.push_str(" …");
+            }
+            output.push('\n');
+            return;
+        }
+        if let [only] = children.as_slice()
+            && only.is_element()
+        {
+            output.push_str(" > ");
+            only.write_label(options, output);
+            depth += 1;
+            node = only.node.clone();
+            continue;
+        }
+
+        output.push('\n');
+        write_entries(&children, indent + 1, depth + 1, options, output);
+        return;
+    }
+}
+
+fn dimmed(text: &str, options: &OutlineOptions) -> String {
+    if !options.color {
+        return text.to_string();
+    }
+    let dim = String::from_utf8_lossy(palette::DIM);
+    let reset = String::from_utf8_lossy(palette::RESET);
+    format!("{dim}{text}{reset}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn outlined(html: &str, options: &OutlineOptions) -> String {
+        let doc = kuchikiki::parse_html().one(html);
+        outline(doc.select_first("body").unwrap().as_node(), options)
+    }
+
+    #[test]
+    fn siblings_are_collapsed() {
+        let html = "<div id=\"content\" class=\"main\">\n  <ul class=\"nav\">\n    \
+            <li><a href=\"/\">Home</a></li>\n    <li>About</li>\n    <li>Blog</li>\n  </ul>\n</div>\
+            <!-- note --><p>A  rather\n long paragraph that goes on and on</p><p>b</p><br><p>c</p>";
+        assert_eq!(
+            outlined(html, &OutlineOptions::default()),
+            "body\n  \
+            div#content.main > ul.nav > li ×3 > a\n    \
+            \"Home\"\n  \
+            p ×2\n    \
+            \"A rather long paragraph that…\"\n  \
+            br\n  \
+            p\n    \
+            \"c\"\n"
+        );
+    }
+
+    #[test]
+    fn depth_can_be_limited() {
+        let html = "<main><section><h2>a</h2><p>b</p></section></main><footer></footer>";
+        let outline = |max_depth| {
+            outlined(
+                html,
+                &OutlineOptions {
+                    max_depth: Some(max_depth),
+                    ..OutlineOptions::default()
+                },
+            )
+        };
+        assert_eq!(outline(1), "body …\n");
+        assert_eq!(outline(2), "body\n  main …\n  footer\n");
+        assert_eq!(outline(3), "body\n  main > section …\n  footer\n");
+        assert_eq!(
+            outline(4),
+            "body\n  main > section\n    h2 …\n    p …\n  footer\n"
+        );
+    }
+
+    #[test]
+    fn counts_and_text_are_dimmed() {
+        let options = OutlineOptions {
+            color: true,
+            ..OutlineOptions::default()
+        };
+        assert_eq!(
+            outlined("<i>x</i><i></i>", &options),
+            "body > i \x1b[2m×2\x1b[0m\n  \x1b[2m\"x\"\x1b[0m\n"
+        );
+    }
+}
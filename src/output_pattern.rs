@@ -0,0 +1,186 @@
+use crate::FileError;
+use hq::Match;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+
+/// Where to write each match to, as given to `--output-pattern`, like `out/section-{n}.html`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputPattern {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    /// The 1-based index of the match, padded with zeroes to this width.
+    Index(usize),
+    Id,
+    Tag,
+}
+
+impl OutputPattern {
+    /// Parses the placeholders `{n}`, `{n:03}`, `{id}` and `{tag}` in the pattern.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = raw;
+        while let Some(start) = rest.find('{') {
+            let (literal, after) = rest.split_at(start);
+            if !literal.is_empty() {
+                parts.push(Part::Literal(literal.to_string()));
+            }
+            let end = after
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder `{after}`"))?;
+            let part = match &after[1..end] {
+                "n" => Part::Index(0),
+                "id" => Part::Id,
+                "tag" => Part::Tag,
+                placeholder => match placeholder.strip_prefix("n:0").map(str::parse) {
+                    Some(Ok(width)) => Part::Index(width),
+                    _ => {
+                        return Err(format!(
+                            "unknown placeholder `{{{placeholder}}}`, \
+                            expected `{{n}}`, `{{n:03}}`, `{{id}}` or `{{tag}}`"
+                        ));
+                    }
+                },
+            };
+            parts.push(part);
+            rest = &after[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    /// The path for the match with this 1-based index, id and tag name.
+    ///
+    /// Matches without an id use their index instead.
+    fn expand(&self, n: usize, id: Option<&str>, tag: &str) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Index(width) => format!("{n:0width$}"),
+                Part::Id => id.map_or_else(|| n.to_string(), String::from),
+                Part::Tag => tag.to_string(),
+            })
+            .collect()
+    }
+
+    fn has_id(&self) -> bool {
+        self.parts.contains(&Part::Id)
+    }
+}
+
+/// Makes the id usable as part of a file name, by replacing everything but letters, digits,
+/// `-`, `_` and `.` with `_`. Leading dots are left out, so it can't become `..` or hidden.
+fn sanitize(id: &str) -> Option<String> {
+    let sanitized: String = id
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') => c,
+            _ => '_',
+        })
+        .collect();
+    let sanitized = sanitized.trim_start_matches('.');
+    (!sanitized.is_empty()).then(|| sanitized.to_string())
+}
+
+/// Opens a file for each match, see `--output-pattern`.
+pub struct Split<'p> {
+    pattern: &'p OutputPattern,
+    /// If to overwrite existing files instead of refusing to.
+    force: bool,
+    /// How many matches got a file so far, over all inputs.
+    count: usize,
+    written: HashSet<PathBuf>,
+}
+
+impl<'p> Split<'p> {
+    pub fn new(pattern: &'p OutputPattern, force: bool) -> Self {
+        Self {
+            pattern,
+            force,
+            count: 0,
+            written: HashSet::new(),
+        }
+    }
+
+    /// Creates the file for the next match, along with the directories it's in.
+    ///
+    /// If another match with the same id was written to that path already,
+    /// the index is appended to the id.
+    pub fn open(&mut self, matched: &Match) -> Result<File, FileError> {
+        self.count += 1;
+        let n = self.count;
+        let id = matched
+            .node()
+            .as_element()
+            .and_then(|elem| elem.attributes.borrow().get("id").and_then(sanitize));
+        let tag = matched.tag_name().unwrap_or_else(|| "node".to_string());
+
+        let mut path = PathBuf::from(self.pattern.expand(n, id.as_deref(), &tag));
+        if let Some(id) = id.filter(|_| self.pattern.has_id() && self.written.contains(&path)) {
+            path = PathBuf::from(self.pattern.expand(n, Some(&format!("{id}-{n}")), &tag));
+        }
+
+        let display = path.display().to_string();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).map_err(FileError::new("create", &display))?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(!self.force)
+            .open(&path)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::AlreadyExists => {
+                    io::Error::new(err.kind(), "file exists, use --force to overwrite it")
+                }
+                _ => err,
+            })
+            .map_err(FileError::new("create", &display))?;
+        self.written.insert(path);
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_expanded() {
+        let pattern = OutputPattern::parse("out/{tag}-{n:03}-{id}.html").unwrap();
+        assert_eq!(
+            pattern.expand(7, Some("intro"), "section"),
+            "out/section-007-intro.html"
+        );
+        assert_eq!(pattern.expand(12, None, "p"), "out/p-012-12.html");
+        assert_eq!(
+            OutputPattern::parse("{n}").unwrap().expand(1234, None, "p"),
+            "1234"
+        );
+
+        assert!(OutputPattern::parse("out/{name}.html").is_err());
+        assert!(OutputPattern::parse("out/{n:3}.html").is_err());
+        assert!(OutputPattern::parse("out/{n.html").is_err());
+    }
+
+    #[test]
+    fn ids_are_sanitized() {
+        assert_eq!(sanitize("intro"), Some("intro".to_string()));
+        assert_eq!(sanitize("../etc/passwd"), Some("_etc_passwd".to_string()));
+        assert_eq!(sanitize("a b:c"), Some("a_b_c".to_string()));
+        assert_eq!(sanitize("..."), None);
+        assert_eq!(sanitize(""), None);
+    }
+}
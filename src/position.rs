@@ -0,0 +1,393 @@
+//! Where in the input elements come from.
+//!
+//! html5ever only reports the line it is at, so the start tags are also looked up in the raw
+//! input and matched up with the elements created for them by name and line.
+
+use crate::check::{self, ParseError};
+use html5ever::tendril::StrTendril;
+use html5ever::tree_builder::{ElementFlags, NodeOrText, QuirksMode, TreeSink};
+use html5ever::{Attribute, ExpandedName, QualName};
+use kuchikiki::traits::TendrilSink;
+use kuchikiki::{Node, NodeRef};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A location in the input. Both are 1-based, the column is counted in bytes like `grep --column`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Where the start tag of each element is, keyed by the address of the element's node.
+pub(crate) type Positions = HashMap<*const Node, Position>;
+
+pub(crate) fn key(node: &NodeRef) -> *const Node {
+    std::ptr::from_ref::<Node>(node)
+}
+
+/// Parses the whole input as HTML document, also returning where each element starts and,
+/// if `errors` is set, the errors the parser recovered from.
+///
+/// Elements the parser inserted on its own, like an omitted `<tbody>`, have no position.
+pub(crate) fn parse(input: &[u8], errors: bool) -> (NodeRef, Positions, Vec<ParseError>) {
+    let sink = LineSink {
+        inner: kuchikiki::parse_html().tokenizer.sink.sink,
+        line: 1,
+        created: Vec::new(),
+        errors: errors.then(Vec::new),
+    };
+    let mut opts = html5ever::ParseOpts::default();
+    // the exact messages name the offending tags, which the short ones don't
+    opts.tokenizer.exact_errors = errors;
+    opts.tree_builder.exact_errors = errors;
+    let (root, created, errors) = html5ever::parse_document(sink, opts).from_utf8().one(input);
+
+    let tags = scan_start_tags(input);
+    let line_starts: Vec<_> = std::iter::once(0)
+        .chain(newlines(input).map(|idx| idx + 1))
+        .collect();
+    let position_of = |offset: usize| {
+        let line = line_starts.partition_point(|&start| start <= offset);
+        Position {
+            line,
+            column: offset - line_starts[line - 1] + 1,
+        }
+    };
+
+    let mut positions = HashMap::new();
+    // index of the first tag which hasn't been matched up with an element yet
+    let mut next = 0;
+    for (node, line) in created {
+        let Some(elem) = node.as_element() else {
+            continue;
+        };
+        let name = elem.name.local.to_ascii_lowercase().to_string();
+        let Some(skipped) = tags[next..].iter().position(|tag| tag.name == name) else {
+            continue;
+        };
+
+        let idx = next + skipped;
+        let position = position_of(tags[idx].offset);
+        // tags in between were ignored by the parser, unless this element was inserted by the
+        // parser itself and the tag actually belongs to a later one
+        if skipped > 0 && (can_be_implied(&name) || position.line > line) {
+            continue;
+        }
+
+        next = idx + 1;
+        positions.insert(key(&node), position);
+    }
+
+    (root, positions, errors.unwrap_or_default())
+}
+
+fn newlines(input: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    input
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'\n')
+        .map(|(idx, _)| idx)
+}
+
+/// If the parser might create an element of this name without a start tag for it,
+/// like for a `<tr>` without `<tbody>` or when reopening formatting elements.
+fn can_be_implied(name: &str) -> bool {
+    matches!(
+        name,
+        "html"
+            | "head"
+            | "body"
+            | "tbody"
+            | "tr"
+            | "colgroup"
+            | "p"
+            | "br"
+            | "a"
+            | "b"
+            | "big"
+            | "code"
+            | "em"
+            | "font"
+            | "i"
+            | "nobr"
+            | "s"
+            | "small"
+            | "strike"
+            | "strong"
+            | "tt"
+            | "u"
+    )
+}
+
+/// Elements whose content isn't parsed as HTML.
+fn is_raw_text(name: &str) -> bool {
+    matches!(
+        name,
+        "script"
+            | "style"
+            | "xmp"
+            | "iframe"
+            | "noembed"
+            | "noframes"
+            | "noscript"
+            | "textarea"
+            | "title"
+            | "plaintext"
+    )
+}
+
+struct StartTag {
+    /// The lowercased name.
+    name: String,
+    /// Where the `<` is.
+    offset: usize,
+}
+
+/// Finds all start tags in the input, in order, skipping comments and raw text.
+fn scan_start_tags(input: &[u8]) -> Vec<StartTag> {
+    let find = |from: usize, needle: &[u8]| {
+        input[from.min(input.len())..]
+            .windows(needle.len())
+            .position(|window| window.eq_ignore_ascii_case(needle))
+            .map(|idx| from + idx)
+    };
+
+    let mut tags = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] != b'<' {
+            i += 1;
+            continue;
+        }
+
+        let rest = &input[i..];
+        if rest.starts_with(b"<!--") {
+            i = find(i + 4, b"-->").map_or(input.len(), |end| end + 3);
+            continue;
+        }
+        if !rest.get(1).is_some_and(u8::is_ascii_alphabetic) {
+            // end tags, doctypes and the like, or just a stray `<`
+            if matches!(rest.get(1), Some(b'/' | b'!' | b'?')) {
+                i = find(i, b">").map_or(input.len(), |end| end + 1);
+            } else {
+                i += 1;
+            }
+            continue;
+        }
+
+        let offset = i;
+        i += 1;
+        while i < input.len()
+            && !(input[i].is_ascii_whitespace() || matches!(input[i], b'/' | b'>'))
+        {
+            i += 1;
+        }
+        let name = String::from_utf8_lossy(&input[offset + 1..i]).to_ascii_lowercase();
+        i = skip_attributes(input, i);
+
+        if is_raw_text(&name) {
+            i = if name == "plaintext" {
+                input.len()
+            } else {
+                find(i, format!("</{name}").as_bytes()).unwrap_or(input.len())
+            };
+        }
+        tags.push(StartTag { name, offset });
+    }
+    tags
+}
+
+/// Skips to after the `>` ending the tag, ignoring those in quoted attribute values.
+fn skip_attributes(input: &[u8], mut i: usize) -> usize {
+    while i < input.len() {
+        match input[i] {
+            b'>' => return i + 1,
+            b'=' => {
+                i += 1;
+                while input.get(i).is_some_and(u8::is_ascii_whitespace) {
+                    i += 1;
+                }
+                if let Some(&quote @ (b'"' | b'\'')) = input.get(i) {
+                    i = input[i + 1..]
+                        .iter()
+                        .position(|&c| c == quote)
+                        .map_or(input.len(), |end| i + 1 + end + 1);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    i
+}
+
+/// Forwards everything to kuchikiki, but remembers which line each node was created on
+/// and, if wanted, the parse errors.
+struct LineSink {
+    inner: kuchikiki::Sink,
+    line: u64,
+    created: Vec<(NodeRef, usize)>,
+    errors: Option<Vec<ParseError>>,
+}
+
+impl LineSink {
+    fn line(&self) -> usize {
+        usize::try_from(self.line).unwrap_or(usize::MAX)
+    }
+}
+
+impl TreeSink for LineSink {
+    type Handle = NodeRef;
+    type Output = (NodeRef, Vec<(NodeRef, usize)>, Option<Vec<ParseError>>);
+
+    fn finish(self) -> Self::Output {
+        (self.inner.finish(), self.created, self.errors)
+    }
+
+    fn set_current_line(&mut self, line_number: u64) {
+        self.line = line_number;
+    }
+
+    fn create_element(
+        &mut self,
+        name: QualName,
+        attrs: Vec<Attribute>,
+        flags: ElementFlags,
+    ) -> NodeRef {
+        let node = self.inner.create_element(name, attrs, flags);
+        self.created.push((node.clone(), self.line()));
+        node
+    }
+
+    fn parse_error(&mut self, msg: Cow<'static, str>) {
+        let line = self.line();
+        if let Some(errors) = &mut self.errors {
+            errors.push(ParseError {
+                line,
+                message: check::describe_parse_error(&msg),
+            });
+        }
+    }
+
+    fn get_document(&mut self) -> NodeRef {
+        self.inner.get_document()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a NodeRef) -> ExpandedName<'a> {
+        self.inner.elem_name(target)
+    }
+
+    fn create_comment(&mut self, text: StrTendril) -> NodeRef {
+        self.inner.create_comment(text)
+    }
+
+    fn create_pi(&mut self, target: StrTendril, data: StrTendril) -> NodeRef {
+        self.inner.create_pi(target, data)
+    }
+
+    fn append(&mut self, parent: &NodeRef, child: NodeOrText<NodeRef>) {
+        self.inner.append(parent, child);
+    }
+
+    fn append_based_on_parent_node(
+        &mut self,
+        element: &NodeRef,
+        prev_element: &NodeRef,
+        child: NodeOrText<NodeRef>,
+    ) {
+        self.inner
+            .append_based_on_parent_node(element, prev_element, child);
+    }
+
+    fn append_doctype_to_document(
+        &mut self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    ) {
+        self.inner
+            .append_doctype_to_document(name, public_id, system_id);
+    }
+
+    fn get_template_contents(&mut self, target: &NodeRef) -> NodeRef {
+        self.inner.get_template_contents(target)
+    }
+
+    fn same_node(&self, x: &NodeRef, y: &NodeRef) -> bool {
+        self.inner.same_node(x, y)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.inner.set_quirks_mode(mode);
+    }
+
+    fn append_before_sibling(&mut self, sibling: &NodeRef, new_node: NodeOrText<NodeRef>) {
+        self.inner.append_before_sibling(sibling, new_node);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: &NodeRef, attrs: Vec<Attribute>) {
+        self.inner.add_attrs_if_missing(target, attrs);
+    }
+
+    fn remove_from_parent(&mut self, target: &NodeRef) {
+        self.inner.remove_from_parent(target);
+    }
+
+    fn reparent_children(&mut self, node: &NodeRef, new_parent: &NodeRef) {
+        self.inner.reparent_children(node, new_parent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::NodeIterator;
+
+    fn positions(html: &str) -> Vec<(String, Option<Position>)> {
+        let (root, positions, _) = parse(html.as_bytes(), false);
+        root.descendants()
+            .elements()
+            .map(|elem| {
+                let position = positions.get(&key(elem.as_node())).copied();
+                (elem.name.local.to_string(), position)
+            })
+            .collect()
+    }
+
+    fn at(line: usize, column: usize) -> Option<Position> {
+        Some(Position { line, column })
+    }
+
+    #[test]
+    fn start_tags_are_found() {
+        let html = "<!DOCTYPE html>\n<html><body>\n  <p title=\"a > b\">x</p><!-- <p> -->\n\
+            <script>if (a <b) {}</script>\n<table><tr><td>1</td></tr></table>";
+        assert_eq!(
+            positions(html),
+            [
+                ("html".to_string(), at(2, 1)),
+                ("head".to_string(), None),
+                ("body".to_string(), at(2, 7)),
+                ("p".to_string(), at(3, 3)),
+                ("script".to_string(), at(4, 1)),
+                ("table".to_string(), at(5, 1)),
+                ("tbody".to_string(), None),
+                ("tr".to_string(), at(5, 8)),
+                ("td".to_string(), at(5, 12)),
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_are_bytes() {
+        let found = positions("<p>ä</p><p>b</p>");
+        assert_eq!(found[4], ("p".to_string(), at(1, 10)));
+    }
+}
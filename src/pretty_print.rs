@@ -1,4 +1,5 @@
 use html5ever::QualName;
+use html5ever::local_name;
 use html5ever::serialize::AttrRef;
 use html5ever::serialize::HtmlSerializer;
 use html5ever::serialize::Serialize;
@@ -6,6 +7,9 @@ use html5ever::serialize::SerializeOpts;
 use html5ever::serialize::Serializer;
 use html5ever::serialize::TraversalScope;
 // use kuchiki::traits::TendrilSink;
+use crate::serialize::{
+    SerializeOptions, order_attributes, paint, palette, visible_width, write_start_tag,
+};
 use kuchikiki::NodeRef;
 use lazy_static::lazy_static;
 use std::collections::HashSet;
@@ -26,67 +30,326 @@ lazy_static! {
     .collect();
 }
 
-fn is_inline(name: &str) -> bool {
+pub(crate) fn is_inline(name: &str) -> bool {
     INLINE_ELEMENTS.contains(name)
 }
 
-struct PrettyPrint<W: Write> {
-    indent: usize,
+/// Whitespace as HTML collapses it, unlike non-breaking spaces.
+pub(crate) fn is_ascii_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\x0c' | '\r')
+}
+
+/// Elements whose whitespace matters, so their contents are serialized as they are.
+pub(crate) fn is_verbatim(name: &str) -> bool {
+    matches!(name, "pre" | "textarea" | "script" | "style")
+}
+
+/// Elements without an end tag, like `<hr>`.
+fn is_void(name: &str) -> bool {
+    matches!(
+        name,
+        "area"
+            | "base"
+            | "basefont"
+            | "bgsound"
+            | "br"
+            | "col"
+            | "embed"
+            | "frame"
+            | "hr"
+            | "img"
+            | "input"
+            | "keygen"
+            | "link"
+            | "meta"
+            | "param"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+/// How [`pretty_print_with`] lays out the HTML.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrettyOptions {
+    /// What each level of nesting is indented by.
+    pub indent: Indent,
+    /// Put each attribute on its own line if the start tag would end past this column otherwise.
+    ///
+    /// The attributes are indented one level deeper than the tag, with the closing `>` right
+    /// after the last one. Columns are counted in characters, so a tab counts as one.
+    pub wrap_attributes: Option<usize>,
+    /// Break text onto the next line where a word would end past this column otherwise.
+    ///
+    /// Only whitespace in text is replaced by line breaks, so the rendering stays the same.
+    /// Words longer than this are never broken, and text in `<pre>` or `<code>` never wrapped.
+    pub max_width: Option<usize>,
+    /// How the markup itself is written, like without pretty printing.
+    pub serialize: SerializeOptions,
+}
+
+/// One level of indentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tabs,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Self::Spaces(2)
+    }
+}
+
+impl Indent {
+    fn write_to(self, depth: usize, output: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Spaces(width) => output.write_all(&vec![b' '; depth * width]),
+            Self::Tabs => output.write_all(&vec![b'\t'; depth]),
+        }
+    }
+}
+
+struct PrettyPrint<'o> {
+    /// How many elements the one being serialized is nested in.
+    depth: usize,
+    /// How many elements deep the serializer is inside a verbatim element, including it.
+    verbatim: usize,
+    /// How many `<code>` elements the serializer is inside, whose text isn't wrapped.
+    unwrapped: usize,
     previous_was_block: bool,
-    inner: HtmlSerializer<W>,
+    options: &'o PrettyOptions,
+    inner: HtmlSerializer<Vec<u8>>,
+}
+
+impl PrettyPrint<'_> {
+    /// What was written on the line so far.
+    fn current_line(&self) -> &str {
+        let written = &self.inner.writer;
+        let line_start = written
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |idx| idx + 1);
+        str::from_utf8(&written[line_start..]).expect("should only write UTF-8")
+    }
+
+    /// Writes the text, replacing whitespace by line breaks where the next word would end past
+    /// the maximum width otherwise.
+    fn write_wrapped(&mut self, text: &str, max_width: usize) -> io::Result<()> {
+        let mut rest = text;
+        while !rest.is_empty() {
+            let space_len = rest
+                .find(|c: char| !is_ascii_space(c))
+                .unwrap_or(rest.len());
+            let (space, after) = rest.split_at(space_len);
+            let word_len = after.find(is_ascii_space).unwrap_or(after.len());
+            let (word, after) = after.split_at(word_len);
+
+            let line = self.current_line();
+            let width = visible_width(line) + 1 + word.chars().count();
+            let breaks = !space.is_empty()
+                && !word.is_empty()
+                && width > max_width
+                && !line.trim().is_empty();
+            if breaks {
+                self.newline()?;
+            } else {
+                self.inner.write_text(space)?;
+            }
+            self.inner.write_text(word)?;
+            rest = after;
+        }
+        Ok(())
+    }
+
+    /// Moves the start tag written since `start` onto the next line if it ends past the maximum
+    /// width and there's whitespace before it to replace, returning where it starts now.
+    fn break_before(&mut self, start: usize) -> io::Result<usize> {
+        let Some(max_width) = self.options.max_width.filter(|_| self.unwrapped == 0) else {
+            return Ok(start);
+        };
+        let written = &self.inner.writer;
+        let space_start = written[..start]
+            .iter()
+            .rposition(|&b| !is_ascii_space(char::from(b)))
+            .map_or(0, |idx| idx + 1);
+        let line_is_empty = written[..space_start].last().is_none_or(|&b| b == b'\n');
+        if space_start == start
+            || line_is_empty
+            || written[space_start..].contains(&b'\n')
+            || visible_width(self.current_line()) <= max_width
+        {
+            return Ok(start);
+        }
+
+        let tag = self.inner.writer.split_off(start);
+        self.inner.writer.truncate(space_start);
+        self.newline()?;
+        let start = self.inner.writer.len();
+        self.inner.writer.extend(tag);
+        Ok(start)
+    }
+
+    /// Starts a new line at the current indentation.
+    fn newline(&mut self) -> io::Result<()> {
+        self.inner.writer.write_all(b"\n")?;
+        self.options
+            .indent
+            .write_to(self.depth, &mut self.inner.writer)
+    }
+
+    /// Rewrites the start tag written since `start` with each attribute on its own line if it's
+    /// too long, and in color if enabled.
+    fn rewrite_start_tag(
+        &mut self,
+        start: usize,
+        name: &QualName,
+        attrs: &[AttrRef<'_>],
+    ) -> io::Result<()> {
+        // nothing inside a verbatim element is moved
+        let wrap = self.verbatim <= 1
+            && attrs.len() >= 2
+            && self
+                .options
+                .wrap_attributes
+                .is_some_and(|max_width| visible_width(self.current_line()) > max_width);
+        if !(wrap || self.options.serialize.color) || self.inner.writer.len() == start {
+            return Ok(());
+        }
+
+        self.inner.writer.truncate(start);
+        let (indent, depth) = (self.options.indent, self.depth + 1);
+        write_start_tag(
+            &mut self.inner.writer,
+            name,
+            attrs,
+            &self.options.serialize,
+            |writer| {
+                if wrap {
+                    writer.write_all(b"\n")?;
+                    indent.write_to(depth, writer)
+                } else {
+                    writer.write_all(b" ")
+                }
+            },
+        )
+    }
+
+    fn end_tag(&mut self, name: QualName) -> io::Result<()> {
+        let start = self.inner.writer.len();
+        self.inner.end_elem(name)?;
+        self.paint(start, palette::TAG);
+        Ok(())
+    }
+
+    fn paint(&mut self, start: usize, color: &[u8]) {
+        if self.options.serialize.color {
+            paint(&mut self.inner.writer, start, color);
+        }
+    }
 }
 
-impl<W: Write> Serializer for PrettyPrint<W> {
+impl Serializer for PrettyPrint<'_> {
     fn start_elem<'a, AttrIter>(&mut self, name: QualName, attrs: AttrIter) -> io::Result<()>
     where
         AttrIter: Iterator<Item = AttrRef<'a>>,
     {
+        let mut attrs: Vec<_> = attrs.collect();
+        order_attributes(&mut attrs, self.options.serialize.attribute_order);
+        if self.verbatim > 0 {
+            self.verbatim += 1;
+            let start = self.inner.writer.len();
+            self.inner.start_elem(name.clone(), attrs.iter().copied())?;
+            return self.rewrite_start_tag(start, &name, &attrs);
+        }
+        if is_verbatim(&name.local) {
+            self.verbatim = 1;
+        }
+
+        if name.local == local_name!("code") {
+            self.unwrapped += 1;
+        }
+
         let inline = is_inline(&name.local);
         if !inline || self.previous_was_block {
-            self.inner.writer.write_all(b"\n")?;
-            self.inner.writer.write_all(&vec![b' '; self.indent])?;
+            self.newline()?;
         }
 
-        self.indent += 2;
-        self.inner.start_elem(name, attrs)?;
+        let start = self.inner.writer.len();
+        self.inner.start_elem(name.clone(), attrs.iter().copied())?;
+        let start = self.break_before(start)?;
+        self.rewrite_start_tag(start, &name, &attrs)?;
+        self.depth += 1;
 
         Ok(())
     }
 
     fn end_elem(&mut self, name: QualName) -> io::Result<()> {
-        self.indent -= 2;
+        if self.verbatim > 1 {
+            self.verbatim -= 1;
+            return self.end_tag(name);
+        }
+        self.depth -= 1;
+        if self.verbatim == 1 {
+            // like after a text, the closing tag has to stay where it is
+            self.verbatim = 0;
+            self.previous_was_block = !is_inline(&name.local);
+            return self.end_tag(name);
+        }
+        if name.local == local_name!("code") {
+            self.unwrapped -= 1;
+        }
 
         if is_inline(&name.local) {
             self.previous_was_block = false;
+        } else if is_void(&name.local) {
+            // there's no end tag to put on its own line
+            self.previous_was_block = true;
         } else {
-            self.inner.writer.write_all(b"\n")?;
-            self.inner.writer.write_all(&vec![b' '; self.indent])?;
+            self.newline()?;
             self.previous_was_block = true;
         }
 
-        self.inner.end_elem(name)
+        self.end_tag(name)
     }
 
     fn write_text(&mut self, text: &str) -> io::Result<()> {
-        if text.trim().is_empty() {
+        if self.verbatim > 0 {
+            self.inner.write_text(text)
+        } else if text.trim().is_empty() {
             Ok(())
         } else {
             if self.previous_was_block {
-                self.inner.writer.write_all(b"\n")?;
-                self.inner.writer.write_all(&vec![b' '; self.indent])?;
+                self.newline()?;
             }
 
             self.previous_was_block = false;
-            self.inner.write_text(text)
+            match self.options.max_width {
+                Some(max_width) if self.unwrapped == 0 => self.write_wrapped(text, max_width),
+                _ => self.inner.write_text(text),
+            }
         }
     }
 
     fn write_comment(&mut self, text: &str) -> io::Result<()> {
-        self.inner.write_comment(text)
+        if !self.options.serialize.comments.keeps(text) {
+            return Ok(());
+        }
+        if self.verbatim == 0 {
+            self.newline()?;
+            self.previous_was_block = true;
+        }
+        let start = self.inner.writer.len();
+        self.inner.write_comment(text)?;
+        self.paint(start, palette::COMMENT);
+        Ok(())
     }
 
     fn write_doctype(&mut self, name: &str) -> io::Result<()> {
-        self.inner.write_doctype(name)
+        let start = self.inner.writer.len();
+        self.inner.write_doctype(name)?;
+        self.paint(start, palette::COMMENT);
+        Ok(())
     }
 
     fn write_processing_instruction(&mut self, target: &str, data: &str) -> io::Result<()> {
@@ -94,19 +357,185 @@ impl<W: Write> Serializer for PrettyPrint<W> {
     }
 }
 
+/// Serializes the node and its descendants as HTML, with block elements indented on their own lines.
 pub fn pretty_print(node: &NodeRef) -> String {
-    let mut content: Vec<u8> = Vec::new();
+    pretty_print_with(node, &PrettyOptions::default())
+}
+
+/// Like [`pretty_print`], but only serializes the children of the node.
+pub fn pretty_print_children(node: &NodeRef) -> String {
+    pretty_print_children_with(node, &PrettyOptions::default())
+}
+
+/// Like [`pretty_print`], laid out as configured by the options.
+pub fn pretty_print_with(node: &NodeRef, options: &PrettyOptions) -> String {
+    pretty_print_scope(node, TraversalScope::IncludeNode, options)
+}
+
+/// Like [`pretty_print_children`], laid out as configured by the options.
+pub fn pretty_print_children_with(node: &NodeRef, options: &PrettyOptions) -> String {
+    pretty_print_scope(node, TraversalScope::ChildrenOnly(None), options)
+}
+
+fn pretty_print_scope(node: &NodeRef, scope: TraversalScope, options: &PrettyOptions) -> String {
     let mut pp = PrettyPrint {
-        indent: 0,
+        depth: 0,
+        verbatim: 0,
+        unwrapped: 0,
         previous_was_block: false,
+        options,
         inner: HtmlSerializer::new(
-            &mut content,
+            Vec::new(),
             SerializeOpts {
-                traversal_scope: TraversalScope::IncludeNode,
+                traversal_scope: scope.clone(),
                 ..Default::default()
             },
         ),
     };
-    Serialize::serialize(node, &mut pp, TraversalScope::IncludeNode).unwrap();
-    str::from_utf8(content.as_ref()).unwrap().to_owned()
+    Serialize::serialize(node, &mut pp, scope).unwrap();
+    String::from_utf8(pp.inner.writer).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn pretty(html: &str, indent: Indent) -> String {
+        let options = PrettyOptions {
+            indent,
+            ..PrettyOptions::default()
+        };
+        pretty_with(html, &options)
+    }
+
+    fn pretty_with(html: &str, options: &PrettyOptions) -> String {
+        let doc = kuchikiki::parse_html().one(html);
+        let body = doc.select_first("body").unwrap();
+        pretty_print_children_with(body.as_node(), options)
+    }
+
+    /// The name and attributes of all elements, which pretty printing must not change.
+    fn elements(html: &str) -> Vec<String> {
+        let doc = kuchikiki::parse_html().one(html);
+        doc.descendants()
+            .filter_map(|node| {
+                let elem = node.as_element()?;
+                let attrs = elem.attributes.borrow();
+                Some(format!("{} {:?}", elem.name.local, attrs.map))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn whitespace_is_kept_where_it_matters() {
+        let code = "fn main() {\n    <span class=\"s\">\"hi\"</span>\n\n  <i> x </i>\n}\n";
+        let html = format!(
+            "<section><pre>{code}</pre><textarea>  a\n b</textarea>\
+            <script> if (a < b) {{\n}} </script><p>after</p></section>"
+        );
+        assert_eq!(
+            pretty(&html, Indent::default()),
+            format!(
+                "\n<section>\n  <pre>{code}</pre>\n  <textarea>  a\n b</textarea>\
+                <script> if (a < b) {{\n}} </script>\n  <p>after\n  </p>\n</section>"
+            )
+        );
+    }
+
+    #[test]
+    fn long_start_tags_are_wrapped() {
+        let html = "<div class=\"card shadow-lg rounded\" data-id=\"7\" title='\"b\" &amp; c'>\
+            <p id=\"x\">in</p><img src=\"a.png\" alt=\"\"><span lang=\"en\" dir=\"ltr\" hidden>!</span></div>";
+        let mut options = PrettyOptions {
+            wrap_attributes: Some(24),
+            ..PrettyOptions::default()
+        };
+        let wrapped = pretty_with(html, &options);
+        assert_eq!(
+            wrapped,
+            "\n<div\n  class=\"card shadow-lg rounded\"\n  data-id=\"7\"\n  title=\"&quot;b&quot; &amp; c\">\
+            \n  <p id=\"x\">in\n  </p>\n  <img\n    src=\"a.png\"\n    alt=\"\"><span\n    lang=\"en\"\n    dir=\"ltr\"\
+            \n    hidden=\"\">!</span>\n</div>"
+        );
+        assert_eq!(elements(&wrapped), elements(html));
+
+        options.indent = Indent::Tabs;
+        options.wrap_attributes = Some(40);
+        assert_eq!(
+            pretty_with(html, &options),
+            "\n<div\n\tclass=\"card shadow-lg rounded\"\n\tdata-id=\"7\"\n\ttitle=\"&quot;b&quot; &amp; c\">\
+            \n\t<p id=\"x\">in\n\t</p>\n\t<img src=\"a.png\" alt=\"\"><span\n\t\tlang=\"en\"\n\t\tdir=\"ltr\"\
+            \n\t\thidden=\"\">!</span>\n</div>"
+        );
+    }
+
+    #[test]
+    fn text_is_wrapped() {
+        let html = "<p>Some words, a <a href=\"/link\">link with text</a>, <em>emphasis</em>! \
+            And <code>code that is not wrapped</code> then\u{a0}nbsp and averyveryverylongword.</p>";
+        let options = PrettyOptions {
+            max_width: Some(20),
+            ..PrettyOptions::default()
+        };
+        let wrapped = pretty_with(html, &options);
+        assert_eq!(
+            wrapped,
+            "\n<p>Some words, a\n  <a href=\"/link\">link\n    with text</a>,\n  <em>emphasis</em>!\
+            \n  And <code>code that is not wrapped</code>\n  then&nbsp;nbsp and\n  averyveryverylongword.\n</p>"
+        );
+        assert_eq!(elements(&wrapped), elements(html));
+    }
+
+    #[test]
+    fn color_does_not_change_the_layout() {
+        let html = "<div class=\"card shadow-lg\" data-id=\"7\"><!-- a -->\
+            <p>one two three <em title=\"x\">four</em> five</p></div>";
+        let mut options = PrettyOptions {
+            wrap_attributes: Some(20),
+            max_width: Some(16),
+            ..PrettyOptions::default()
+        };
+        let plain = pretty_with(html, &options);
+        options.serialize.color = true;
+        let colored = pretty_with(html, &options);
+
+        assert_ne!(colored, plain);
+        let escapes = regex::Regex::new("\x1b\\[[0-9]+m").unwrap();
+        assert_eq!(escapes.replace_all(&colored, ""), plain);
+    }
+
+    #[test]
+    fn comments_are_on_their_own_lines() {
+        let html = "<div><!-- a --><p>x<!-- b --> y</p><pre>1<!-- c -->\n2</pre></div>";
+        assert_eq!(
+            pretty(html, Indent::default()),
+            "\n<div>\n  <!-- a -->\n  <p>\n    x\n    <!-- b -->\n     y\n  </p>\n  <pre>1<!-- c -->\n2</pre>\n</div>"
+        );
+    }
+
+    #[test]
+    fn void_elements_have_no_end_tag_line() {
+        assert_eq!(
+            pretty("<div><hr><p>a</p><br><b>b</b></div>", Indent::default()),
+            "\n<div>\n  <hr>\n  <p>\n    a\n  </p>\n  <br>\n  <b>\n    b</b>\n</div>"
+        );
+    }
+
+    #[test]
+    fn indentation_is_configurable() {
+        let html = "<ul><li><p>a <b>b</b></p></li></ul>";
+        assert_eq!(
+            pretty(html, Indent::default()),
+            "\n<ul>\n  <li>\n    <p>a <b>b</b>\n    </p>\n  </li>\n</ul>"
+        );
+        assert_eq!(
+            pretty(html, Indent::Spaces(4)),
+            "\n<ul>\n    <li>\n        <p>a <b>b</b>\n        </p>\n    </li>\n</ul>"
+        );
+        assert_eq!(
+            pretty(html, Indent::Tabs),
+            "\n<ul>\n\t<li>\n\t\t<p>a <b>b</b>\n\t\t</p>\n\t</li>\n</ul>"
+        );
+    }
 }
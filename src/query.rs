@@ -0,0 +1,3272 @@
+use crate::css::Selector as CssSelector;
+use crate::link::{LinkKinds, RewriteOptions};
+use crate::outline::{self, OutlineOptions};
+use crate::pretty_print::{Indent, PrettyOptions};
+use crate::record::Field;
+use crate::sanitize::{self, SanitizeOptions};
+use crate::serialize::{self, AttributeOrder, Comments};
+use crate::sort::SortKey;
+use crate::stats;
+use crate::template::Template;
+use crate::text::TextOptions;
+use crate::wrap::Wrapper;
+use crate::xpath::XPath;
+use crate::{
+    AttributeOptions, Document, Error, Position, SelectorRole, form, json, json_ld, link, markdown,
+    meta, minify, pretty_print, table, text,
+};
+use html5ever::local_name;
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{Attributes, Node, NodeRef};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io;
+use std::rc::Rc;
+use url::Url;
+
+/// What form the output of [`Query::write`] takes as a whole.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Each match on its own, see [`Match::render`].
+    #[default]
+    Plain,
+    /// All matches in one JSON array, see [`json::describe`].
+    Json,
+    /// Each match as JSON object on its own line, flushed right away.
+    Ndjson,
+    /// Only the number of matches.
+    Count,
+}
+
+/// How each match is turned into output.
+#[derive(Debug, Clone, Default)]
+struct Rendering {
+    template: Option<Template>,
+    links: Option<LinkKinds>,
+    table_csv: bool,
+    table_json: bool,
+    forms: bool,
+    meta: bool,
+    json_ld: bool,
+    markdown: bool,
+    stats: bool,
+    outline: bool,
+    outline_options: OutlineOptions,
+    fields: Vec<Field>,
+    /// Joins all values of a field instead of taking the first, see [`Query::all_field_values`].
+    field_separator: Option<String>,
+    /// Called with problems that don't stop the output, see [`Query::on_warning`].
+    warn: Option<fn(&str)>,
+    with_position: bool,
+    css_path: bool,
+    tag_names: bool,
+    classes: bool,
+    inner: bool,
+    bare: bool,
+    always_doctype: bool,
+    text_only: bool,
+    text: TextOptions,
+    pretty: bool,
+    pretty_options: PrettyOptions,
+    minify: bool,
+    attributes: Vec<String>,
+    attribute_options: AttributeOptions,
+    attribute_fallback_text: bool,
+    print0: bool,
+}
+
+impl Rendering {
+    /// The text of the node, see [`text::extract`].
+    fn text(&self, node: &NodeRef) -> String {
+        text::extract(node, &self.text)
+    }
+
+    /// If matches are rendered as HTML, as opposed to text or one of the other formats.
+    fn is_html(&self) -> bool {
+        self.template.is_none()
+            && self.links.is_none()
+            && !(self.table_csv || self.table_json || self.forms || self.meta || self.json_ld)
+            && !(self.stats || self.outline)
+            && self.fields.is_empty()
+            && !(self.markdown || self.css_path || self.tag_names || self.classes)
+            && !self.text_only
+            && self.attributes.is_empty()
+    }
+
+    /// If matches are rendered as only their requested attributes, see [`Query::attribute`].
+    fn is_attributes(&self) -> bool {
+        self.template.is_none()
+            && self.links.is_none()
+            && !(self.table_csv || self.table_json || self.forms || self.meta || self.json_ld)
+            && !(self.stats || self.outline)
+            && self.fields.is_empty()
+            && !(self.markdown || self.css_path || self.tag_names || self.classes)
+            && !self.attributes.is_empty()
+    }
+
+    /// What ends each attribute value, text and expanded template, see [`Query::print0`].
+    fn terminator(&self) -> char {
+        if self.print0 { '\0' } else { '\n' }
+    }
+
+    /// If each line of a rendered match is a value of its own, like for attributes.
+    fn has_one_value_per_line(&self) -> bool {
+        self.template.is_none()
+            && (self.links.is_some()
+                || self.classes
+                || (!self.css_path && !self.tag_names && !self.attributes.is_empty()))
+    }
+}
+
+/// How a query decides which nodes match.
+#[derive(Debug, Clone)]
+enum Selector {
+    Css(String),
+    XPath(String),
+}
+
+/// Keeps only matches whose text matches the regex, or doesn't if `inverted`.
+#[derive(Debug, Clone)]
+struct TextFilter {
+    regex: Regex,
+    inverted: bool,
+}
+
+/// Selects nodes out of documents and describes how to output them.
+///
+/// Constructed using [`Query::new`] or [`Query::xpath`] and then configured builder-style. A query can be applied
+/// to any number of documents.
+#[derive(Debug, Clone)]
+pub struct Query {
+    selector: Selector,
+    /// Selectors each applied within the matches of the previous one, see [`Query::then_select`].
+    stages: Vec<String>,
+    grouped_stage: Option<String>,
+    removals: Vec<String>,
+    unwraps: Vec<String>,
+    text_filters: Vec<TextFilter>,
+    /// Attributes whose value has to match the regex.
+    attribute_filters: Vec<(String, Regex)>,
+    base: Option<Url>,
+    relativize: Option<Url>,
+    detect_base: bool,
+    prefer_detected_base: bool,
+    rewrite: RewriteOptions,
+    strict_template: bool,
+    separator: Option<String>,
+    no_newline: bool,
+    unique: bool,
+    outermost: bool,
+    innermost: bool,
+    sort_key: Option<SortKey>,
+    reverse: bool,
+    invert: bool,
+    sanitize: bool,
+    sanitize_options: SanitizeOptions,
+    /// If the comments in each match are yielded instead of the match.
+    select_comments: bool,
+    /// Which comments are left in matches, the others are removed.
+    kept_comments: Comments,
+    /// Patterns of attributes to remove from each match and its descendants.
+    strip_attributes: Vec<String>,
+    /// Attributes to set on each match, in order.
+    set_attributes: Vec<(String, String)>,
+    wrapper: Option<Wrapper>,
+    /// HTML to insert before and after each match.
+    insert_before: Option<String>,
+    insert_after: Option<String>,
+    /// If the whole document is yielded after editing the matches, even without other edits.
+    whole_document: bool,
+    /// Regexes replaced in the text of each match, in order.
+    text_replacements: Vec<(Regex, String)>,
+    /// Called with how many text replacements were made in each match.
+    on_text_replaced: Option<fn(usize)>,
+    limit: usize,
+    nth: Option<isize>,
+    format: Format,
+    rendering: Rendering,
+}
+
+impl Query {
+    /// Creates a query matching all elements that match the given CSS selector.
+    ///
+    /// As an extension to CSS, `:contains("text")` only matches elements whose
+    /// [text](crate::serialize_text) contains the given text, `:icontains` ignoring case.
+    /// They can be used on any compound selector, also in [`Query::remove`].
+    pub fn new(selector: impl Into<String>) -> Self {
+        Self::with_selector(Selector::Css(selector.into()))
+    }
+
+    /// Creates a query matching all nodes the XPath expression evaluates to.
+    ///
+    /// Unlike with CSS selectors, this can also match text nodes and attributes,
+    /// see [`XPath::select`].
+    pub fn xpath(expression: impl Into<String>) -> Self {
+        Self::with_selector(Selector::XPath(expression.into()))
+    }
+
+    fn with_selector(selector: Selector) -> Self {
+        Self {
+            selector,
+            stages: Vec::new(),
+            grouped_stage: None,
+            removals: Vec::new(),
+            unwraps: Vec::new(),
+            text_filters: Vec::new(),
+            attribute_filters: Vec::new(),
+            base: None,
+            relativize: None,
+            detect_base: false,
+            prefer_detected_base: false,
+            rewrite: RewriteOptions::default(),
+            strict_template: false,
+            separator: None,
+            no_newline: false,
+            unique: false,
+            outermost: false,
+            innermost: false,
+            sort_key: None,
+            reverse: false,
+            invert: false,
+            sanitize: false,
+            sanitize_options: SanitizeOptions::default(),
+            select_comments: false,
+            kept_comments: Comments::All,
+            strip_attributes: Vec::new(),
+            set_attributes: Vec::new(),
+            wrapper: None,
+            insert_before: None,
+            insert_after: None,
+            whole_document: false,
+            text_replacements: Vec::new(),
+            on_text_replaced: None,
+            limit: usize::MAX,
+            nth: None,
+            format: Format::default(),
+            rendering: Rendering::default(),
+        }
+    }
+
+    /// Matches the descendants of each match matching the CSS selector instead, like
+    /// `querySelectorAll` called on it.
+    ///
+    /// Can be given multiple times, each applying to the matches of the previous one. Nodes
+    /// inside several of those are matched only once. Removals and the like apply to the final
+    /// matches only.
+    #[must_use]
+    pub fn then_select(mut self, selector: impl Into<String>) -> Self {
+        self.stages.push(selector.into());
+        self
+    }
+
+    /// Like [`Query::then_select`] as last stage, but [`Query::write`] writes the matches in
+    /// groups, one for each match of the previous selector, even if it's empty.
+    ///
+    /// In the [`Format::Plain`] format, groups are separated by an empty line or the
+    /// [separator](Query::separator) if given, which then only goes between groups.
+    /// The [limit](Query::limit) and [nth](Query::nth) match apply to each group on its own.
+    /// Other formats and edits of the whole document don't group matches.
+    #[must_use]
+    pub fn then_select_grouped(mut self, selector: impl Into<String>) -> Self {
+        self.grouped_stage = Some(selector.into());
+        self
+    }
+
+    /// Removes all nodes matching the CSS selector.
+    ///
+    /// Matches that would be removed themselves or are inside removed nodes are skipped,
+    /// otherwise only their matching descendants are removed.
+    #[must_use]
+    pub fn remove(mut self, selector: impl Into<String>) -> Self {
+        self.removals.push(selector.into());
+        self
+    }
+
+    /// Replaces all elements matching the CSS selector by their children, after
+    /// [removing](Query::remove) nodes.
+    ///
+    /// Like with removals, only the matching descendants of matches are unwrapped.
+    /// Nested elements are unwrapped too, and text nodes next to each other stay separate.
+    #[must_use]
+    pub fn unwrap_elements(mut self, selector: impl Into<String>) -> Self {
+        self.unwraps.push(selector.into());
+        self
+    }
+
+    /// Only yields matches whose text matches the regex, can be given multiple times.
+    ///
+    /// The text is the same as output by [`Query::text_only`], after removing nodes.
+    #[must_use]
+    pub fn text_matches(mut self, regex: Regex) -> Self {
+        self.text_filters.push(TextFilter {
+            regex,
+            inverted: false,
+        });
+        self
+    }
+
+    /// Only yields matches whose text doesn't match the regex, the opposite of
+    /// [`Query::text_matches`].
+    #[must_use]
+    pub fn text_not_matches(mut self, regex: Regex) -> Self {
+        self.text_filters.push(TextFilter {
+            regex,
+            inverted: true,
+        });
+        self
+    }
+
+    /// Only yields elements that have the attribute with a value matching the regex.
+    ///
+    /// Can be given multiple times, then all attributes have to match.
+    #[must_use]
+    pub fn attribute_matches(mut self, name: impl Into<String>, regex: Regex) -> Self {
+        self.attribute_filters.push((name.into(), regex));
+        self
+    }
+
+    /// Resolves relative links in matches against this URL, see [`link::rewrite_relative_url`].
+    ///
+    /// Takes precedence over the [URL of the document](Document::with_url)
+    /// and, unless [preferred](Query::prefer_detected_base), a [detected](Query::detect_base) one.
+    #[must_use]
+    pub fn base(mut self, base: Url) -> Self {
+        self.base = Some(base);
+        self
+    }
+
+    /// Turns absolute URLs in matches into ones relative to this prefix,
+    /// after resolving relative ones. See [`link::relativize_url`].
+    #[must_use]
+    pub fn relativize(mut self, prefix: Url) -> Self {
+        self.relativize = Some(prefix);
+        self
+    }
+
+    /// Looks for the `<base>` element in documents for resolving relative links.
+    ///
+    /// If one is found, it takes precedence over the [URL of the document](Document::with_url),
+    /// which a relative one is resolved against. See [`link::detect_base`].
+    #[must_use]
+    pub fn detect_base(mut self, detect_base: bool) -> Self {
+        self.detect_base = detect_base;
+        self
+    }
+
+    /// Lets a [detected](Query::detect_base) base take precedence over [`Query::base`],
+    /// which a relative one is resolved against then.
+    #[must_use]
+    pub fn prefer_detected_base(mut self, prefer_detected_base: bool) -> Self {
+        self.prefer_detected_base = prefer_detected_base;
+        self
+    }
+
+    /// Also resolves URLs in this attribute on any element, can be given multiple times.
+    ///
+    /// See [`RewriteOptions::extra_attributes`].
+    #[must_use]
+    pub fn rewrite_attribute(mut self, name: impl Into<String>) -> Self {
+        self.rewrite.extra_attributes.push(name.into());
+        self
+    }
+
+    /// Also resolves references to anchors like `#section`, see [`RewriteOptions::fragments`].
+    #[must_use]
+    pub fn rewrite_fragments(mut self, rewrite_fragments: bool) -> Self {
+        self.rewrite.fragments = rewrite_fragments;
+        self
+    }
+
+    /// Joins relative URLs onto the base without normalizing them,
+    /// see [`RewriteOptions::verbatim`].
+    #[must_use]
+    pub fn verbatim_urls(mut self, verbatim_urls: bool) -> Self {
+        self.rewrite.verbatim = verbatim_urls;
+        self
+    }
+
+    /// Also resolves URLs in inline CSS, see [`RewriteOptions::css`].
+    #[must_use]
+    pub fn rewrite_css(mut self, rewrite_css: bool) -> Self {
+        self.rewrite.css = rewrite_css;
+        self
+    }
+
+    /// Calls the function with values left as they are while resolving relative URLs
+    /// since they are malformed, see [`RewriteOptions::warn`].
+    #[must_use]
+    pub fn on_rewrite_warning(mut self, warn: fn(&str)) -> Self {
+        self.rewrite.warn = Some(warn);
+        self
+    }
+
+    /// Skips output that was already rendered for an earlier match in the same document.
+    ///
+    /// For [links](Query::links) and [attributes](Query::attribute), each line counts on its
+    /// own, so a match is only skipped if all of its values were already seen.
+    /// Otherwise, the whole rendered match has to be the same.
+    /// Skipped matches don't count towards [`Query::limit`] and [`Query::nth`].
+    #[must_use]
+    pub fn unique(mut self, unique: bool) -> Self {
+        self.unique = unique;
+        self
+    }
+
+    /// Skips matches inside other matches, yielding only the outermost ones.
+    ///
+    /// Only matches that are yielded count, so a match inside one skipped by a
+    /// [text filter](Query::text_matches) is still yielded.
+    #[must_use]
+    pub fn outermost(mut self, outermost: bool) -> Self {
+        self.outermost = outermost;
+        self
+    }
+
+    /// Skips matches containing other matches, yielding only the innermost ones.
+    ///
+    /// All matches are collected first then. Takes precedence over [`Query::outermost`].
+    #[must_use]
+    pub fn innermost(mut self, innermost: bool) -> Self {
+        self.innermost = innermost;
+        self
+    }
+
+    /// Yields the matches ordered by the key instead of in document order, with those whose keys
+    /// are equal still in document order.
+    ///
+    /// This happens before the [limit](Query::limit) and [nth](Query::nth) match are applied.
+    /// All matches are collected first then, instead of being yielded as they are found.
+    #[must_use]
+    pub fn sort_by(mut self, key: SortKey) -> Self {
+        self.sort_key = Some(key);
+        self
+    }
+
+    /// Yields the matches in reverse, last first, after [sorting](Query::sort_by) them if asked to.
+    ///
+    /// Like with sorting, this happens before the [limit](Query::limit) and [nth](Query::nth)
+    /// match are applied, so these count from the end.
+    #[must_use]
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    /// Yields the whole document without the matches instead, like `grep -v`.
+    ///
+    /// Nothing is yielded if no element is left in the document.
+    #[must_use]
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    /// Yields the comments in each match instead of the match itself, in document order.
+    ///
+    /// Like any other match, they're filtered by [text](Query::text_matches), which is what's
+    /// inside them, and then [limited](Query::limit). Comments inside nested matches are only
+    /// yielded once. A [`Query::xpath`] of `/` covers comments outside of the root element too.
+    #[must_use]
+    pub fn select_comments(mut self, select_comments: bool) -> Self {
+        self.select_comments = select_comments;
+        self
+    }
+
+    /// Removes all comments from each match except these, and from the document when it's
+    /// yielded as a whole.
+    ///
+    /// Unlike [`Query::comments`], this changes the matches themselves instead of only how they're
+    /// serialized, so it applies to all renderings. Matches that are removed comments themselves,
+    /// as selected by [XPath](Query::xpath), are skipped.
+    #[must_use]
+    pub fn keep_comments(mut self, kept: Comments) -> Self {
+        self.kept_comments = kept;
+        self
+    }
+
+    /// [Sanitizes](sanitize::sanitize) each match, so it can be embedded in another page.
+    ///
+    /// Matches inside the removed elements are skipped, like with [`Query::remove`].
+    #[must_use]
+    pub fn sanitize(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    /// Keeps `style` attributes when [sanitizing](Query::sanitize).
+    #[must_use]
+    pub fn sanitize_keep_style(mut self, keep_style: bool) -> Self {
+        self.sanitize_options.keep_style = keep_style;
+        self
+    }
+
+    /// Removes the attributes with names matching the pattern from each match and all elements
+    /// in it, before [setting attributes](Query::set_attribute). Can be given multiple times.
+    ///
+    /// A `*` in the pattern stands for any number of characters, like in `on*` or `data-*`.
+    /// Like names themselves, this is case-sensitive, and HTML attribute names are lowercase.
+    #[must_use]
+    pub fn strip_attribute(mut self, pattern: impl Into<String>) -> Self {
+        self.strip_attributes.push(pattern.into());
+        self
+    }
+
+    /// Sets the attribute on each match, replacing it if it's there already. Can be given multiple
+    /// times, they're then set in order.
+    ///
+    /// When rendering matches as HTML in the [`Format::Plain`] format, the whole document is
+    /// yielded instead of the matches, see [`Query::edits_document`]. Other formats and renderings
+    /// yield the edited matches.
+    #[must_use]
+    pub fn set_attribute(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.set_attributes.push((name.into(), value.into()));
+        self
+    }
+
+    /// Replaces what the regex matches in each text node of each match, except in `<script>` and
+    /// `<style>`. Can be given multiple times, the replacements are made in order.
+    ///
+    /// `$1` and `${name}` in the replacement refer to capture groups, see [`Regex::replace_all`].
+    /// Attributes are left alone, and text nodes are replaced one by one, so the regex can't
+    /// match across elements.
+    #[must_use]
+    pub fn replace_text(mut self, regex: Regex, replacement: impl Into<String>) -> Self {
+        self.text_replacements.push((regex, replacement.into()));
+        self
+    }
+
+    /// Calls the function with the number of [text replacements](Query::replace_text) made
+    /// in each match.
+    #[must_use]
+    pub fn on_text_replaced(mut self, report: fn(usize)) -> Self {
+        self.on_text_replaced = Some(report);
+        self
+    }
+
+    /// Wraps each match in a new element, after editing its attributes, which is then output in
+    /// its place.
+    ///
+    /// With [`Query::inner`], the element is put inside the match around its children instead.
+    #[must_use]
+    pub fn wrap(mut self, wrapper: Wrapper) -> Self {
+        self.wrapper = Some(wrapper);
+        self
+    }
+
+    /// Inserts the HTML before each match, with `{@name}` replaced by the value of the attribute
+    /// `name` of the match. The whole document is yielded then, like with
+    /// [`Query::set_attribute`].
+    ///
+    /// The HTML is parsed as the contents of the parent of the match, so it can be something like
+    /// `<td>` too. Placeholders are replaced after parsing, in text and attribute values,
+    /// so their values don't need to be escaped.
+    #[must_use]
+    pub fn insert_before(mut self, html: impl Into<String>) -> Self {
+        self.insert_before = Some(html.into());
+        self
+    }
+
+    /// Like [`Query::insert_before`], but inserts the HTML after each match.
+    #[must_use]
+    pub fn insert_after(mut self, html: impl Into<String>) -> Self {
+        self.insert_after = Some(html.into());
+        self
+    }
+
+    /// Yields the whole document after cleaning up and editing the matches instead of them,
+    /// like [`Query::set_attribute`] does, or nothing if nothing matched.
+    #[must_use]
+    pub fn whole_document(mut self, whole_document: bool) -> Self {
+        self.whole_document = whole_document;
+        self
+    }
+
+    /// If the matches are edited and the whole document is yielded instead of them,
+    /// see [`Query::set_attribute`], [`Query::insert_before`] and [`Query::whole_document`].
+    pub fn edits_document(&self) -> bool {
+        let edits = !self.set_attributes.is_empty()
+            || self.insert_before.is_some()
+            || self.insert_after.is_some()
+            || self.whole_document;
+        edits && self.writes_html()
+    }
+
+    /// Yields at most this many matches per document.
+    #[must_use]
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Only yields the match at this 1-based index, counting from the end if negative.
+    ///
+    /// Takes precedence over [`Query::limit`].
+    /// Only negative indices require looking at all matches first.
+    #[must_use]
+    pub fn nth(mut self, nth: isize) -> Self {
+        self.nth = Some(nth);
+        self
+    }
+
+    #[must_use]
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Renders matches by expanding the template for each of them, see [`Template`].
+    #[must_use]
+    pub fn template(mut self, template: Template) -> Self {
+        self.rendering.template = Some(template);
+        self
+    }
+
+    /// Makes [`Query::write`] put this between matches in the [`Format::Plain`] format,
+    /// instead of ending each with a newline.
+    ///
+    /// Nothing is put after the last match. Matches rendering to nothing are skipped.
+    #[must_use]
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = Some(separator.into());
+        self
+    }
+
+    /// Makes [`Query::write`] leave out the newline ending each match in the [`Format::Plain`]
+    /// format, so nothing is between matches unless there's a [`Query::separator`].
+    #[must_use]
+    pub fn no_newline(mut self, no_newline: bool) -> Self {
+        self.no_newline = no_newline;
+        self
+    }
+
+    /// Makes [`Query::write`] fail if a match lacks an attribute used in the template,
+    /// instead of expanding it to nothing.
+    #[must_use]
+    pub fn strict_template(mut self, strict: bool) -> Self {
+        self.strict_template = strict;
+        self
+    }
+
+    /// Renders matches as the links in them, one `URL<TAB>text` per line.
+    ///
+    /// All links in matches are resolved against the base, not only matches that are links
+    /// themselves. See [`link::links`].
+    #[must_use]
+    pub fn links(mut self, kinds: LinkKinds) -> Self {
+        self.rendering.links = Some(kinds);
+        self
+    }
+
+    /// Renders the tables in matches as CSV, see [`table::grid`].
+    ///
+    /// Matches that are no tables themselves render all tables inside them.
+    /// Tables are separated by an empty line.
+    #[must_use]
+    pub fn table_csv(mut self, table_csv: bool) -> Self {
+        self.rendering.table_csv = table_csv;
+        self
+    }
+
+    /// Renders the tables in matches as JSON arrays of objects, one per row and line.
+    ///
+    /// See [`table::records`]. Like with [`Query::table_csv`], matches that are no tables
+    /// themselves render all tables inside them.
+    #[must_use]
+    pub fn table_json(mut self, table_json: bool) -> Self {
+        self.rendering.table_json = table_json;
+        self
+    }
+
+    /// Renders the forms in matches as JSON objects, one per line, see [`form::describe`].
+    ///
+    /// Their actions are resolved against the base.
+    #[must_use]
+    pub fn forms(mut self, forms: bool) -> Self {
+        self.rendering.forms = forms;
+        self
+    }
+
+    /// Renders the metadata in the `<head>` of matches, one `key<TAB>value` per line.
+    ///
+    /// See [`meta::entries`]. With [`Format::Json`], all entries are output in one array
+    /// of objects with their `key` and `value`. The canonical link is resolved against the base.
+    #[must_use]
+    pub fn meta(mut self, meta: bool) -> Self {
+        self.rendering.meta = meta;
+        self
+    }
+
+    /// Renders the JSON-LD blocks in matches, one after another, see [`json_ld::blocks`].
+    ///
+    /// Blocks that aren't valid JSON cause a [warning](Query::on_warning).
+    /// With [`Format::Json`], all valid blocks are output in one array.
+    #[must_use]
+    pub fn json_ld(mut self, json_ld: bool) -> Self {
+        self.rendering.json_ld = json_ld;
+        self
+    }
+
+    /// Renders matches as Markdown, see [`markdown::to_markdown`].
+    ///
+    /// All links in matches are resolved against the base, like with [`Query::links`].
+    #[must_use]
+    pub fn markdown(mut self, markdown: bool) -> Self {
+        self.rendering.markdown = markdown;
+        self
+    }
+
+    /// Makes [`Query::write`] write [statistics](stats::collect) of the elements in all matches
+    /// together instead of the matches, as a table or with [`Format::Json`] as an array of
+    /// objects.
+    #[must_use]
+    pub fn stats(mut self, stats: bool) -> Self {
+        self.rendering.stats = stats;
+        self
+    }
+
+    /// Renders matches as an [outline](outline::outline) of the elements in them,
+    /// like `div#content > ul.nav > li ×12`.
+    #[must_use]
+    pub fn outline(mut self, outline: bool) -> Self {
+        self.rendering.outline = outline;
+        self
+    }
+
+    /// Shows only this many levels of elements in [outlines](Query::outline).
+    #[must_use]
+    pub fn outline_max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.rendering.outline_options.max_depth = max_depth;
+        self
+    }
+
+    /// Renders matches as records with a value for each of these fields, as rows of CSV
+    /// or with [`Format::Json`] as objects keyed by the field names.
+    ///
+    /// [`Query::write`] starts the CSV with a header of the field names. A field without any
+    /// value in a record is empty, one with several takes the first unless
+    /// [all are joined](Query::all_field_values). URLs are resolved against the base like
+    /// any other in matches.
+    #[must_use]
+    pub fn field(mut self, field: Field) -> Self {
+        self.rendering.fields.push(field);
+        self
+    }
+
+    /// Joins all values of a [field](Query::field) in a record by the separator.
+    #[must_use]
+    pub fn all_field_values(mut self, separator: Option<String>) -> Self {
+        self.rendering.field_separator = separator;
+        self
+    }
+
+    /// Calls the function with problems in the input that don't stop the output,
+    /// like invalid JSON in [JSON-LD blocks](Query::json_ld).
+    #[must_use]
+    pub fn on_warning(mut self, warn: fn(&str)) -> Self {
+        self.rendering.warn = Some(warn);
+        self
+    }
+
+    /// Prefixes each rendered match with its `line:column:` in the input, like `grep -n`.
+    ///
+    /// See [`Match::position`]. The prefix is `?:?:` if the position is unknown.
+    #[must_use]
+    pub fn with_position(mut self, with_position: bool) -> Self {
+        self.rendering.with_position = with_position;
+        self
+    }
+
+    /// Renders matches as a selector matching only them, see [`Match::css_path`].
+    ///
+    /// Combined with [`Query::attribute`], the attribute values follow the path.
+    /// Matches that aren't elements are skipped.
+    #[must_use]
+    pub fn css_path(mut self, css_path: bool) -> Self {
+        self.rendering.css_path = css_path;
+        self
+    }
+
+    /// Renders matches only as their element name, see [`Match::tag_name`].
+    ///
+    /// Matches that aren't elements are skipped.
+    #[must_use]
+    pub fn tag_names(mut self, tag_names: bool) -> Self {
+        self.rendering.tag_names = tag_names;
+        self
+    }
+
+    /// Renders matches only as their class names, one per line, see [`crate::select_classes`].
+    ///
+    /// Matches without classes are skipped. With [`Query::unique`], each class is only
+    /// rendered once.
+    #[must_use]
+    pub fn classes(mut self, classes: bool) -> Self {
+        self.rendering.classes = classes;
+        self
+    }
+
+    /// Renders matches without their own tags, only their children.
+    ///
+    /// See [`Match::inner_html`].
+    #[must_use]
+    pub fn inner(mut self, inner: bool) -> Self {
+        self.rendering.inner = inner;
+        self
+    }
+
+    /// Renders the root `<html>` only as the contents of its `<head>` and `<body>`, if it looks
+    /// like the parser added them. See [`Match::scaffolding`].
+    #[must_use]
+    pub fn bare(mut self, bare: bool) -> Self {
+        self.rendering.bare = bare;
+        self
+    }
+
+    /// Starts the HTML of every match with the doctype of its document, not only that of the
+    /// root element. See [`Match::doctype`].
+    #[must_use]
+    pub fn always_doctype(mut self, always_doctype: bool) -> Self {
+        self.rendering.always_doctype = always_doctype;
+        self
+    }
+
+    /// Renders matches only as their contained text, see [`text::extract`].
+    #[must_use]
+    pub fn text_only(mut self, text_only: bool) -> Self {
+        self.rendering.text_only = text_only;
+        self
+    }
+
+    /// Extracts text by concatenating all text nodes as they are, without line breaks for
+    /// block-level elements. See [`TextOptions::raw`].
+    #[must_use]
+    pub fn raw_text(mut self, raw_text: bool) -> Self {
+        self.rendering.text.raw = raw_text;
+        self
+    }
+
+    /// Collapses runs of whitespace in text to a single space, except in `<pre>` and the like.
+    ///
+    /// See [`TextOptions::collapse_whitespace`]. Has no effect on [raw text](Query::raw_text).
+    #[must_use]
+    pub fn collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.rendering.text.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    /// Skips whitespace-only text nodes when extracting text,
+    /// see [`TextOptions::ignore_whitespace`].
+    #[must_use]
+    pub fn ignore_whitespace(mut self, ignore_whitespace: bool) -> Self {
+        self.rendering.text.ignore_whitespace = ignore_whitespace;
+        self
+    }
+
+    /// Skips hidden text when extracting text, see [`TextOptions::visible_only`].
+    #[must_use]
+    pub fn visible_only(mut self, visible_only: bool) -> Self {
+        self.rendering.text.visible_only = visible_only;
+        self
+    }
+
+    /// Includes the `alt` text of images when extracting text, with `{}` in the wrapper
+    /// replaced by it. See [`TextOptions::alt_text`].
+    #[must_use]
+    pub fn alt_text(mut self, wrapper: Option<String>) -> Self {
+        self.rendering.text.alt_text = wrapper;
+        self
+    }
+
+    /// Follows links with footnote numbers and lists their URLs after the text,
+    /// see [`TextOptions::link_footnotes`].
+    #[must_use]
+    pub fn link_footnotes(mut self, link_footnotes: bool) -> Self {
+        self.rendering.text.link_footnotes = link_footnotes;
+        self
+    }
+
+    /// Includes the text of scripts and styles inside matches when extracting text,
+    /// see [`TextOptions::keep_scripts`].
+    #[must_use]
+    pub fn keep_scripts_text(mut self, keep_scripts_text: bool) -> Self {
+        self.rendering.text.keep_scripts = keep_scripts_text;
+        self
+    }
+
+    /// Renders matches as reformatted HTML, see [`pretty_print::pretty_print`].
+    #[must_use]
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.rendering.pretty = pretty;
+        self
+    }
+
+    /// Renders matches as HTML with everything left out that doesn't change the document,
+    /// see [`minify::minify`]. Takes precedence over [`Query::pretty`].
+    #[must_use]
+    pub fn minify(mut self, minify: bool) -> Self {
+        self.rendering.minify = minify;
+        self
+    }
+
+    /// With [`Query::pretty`], indents each level of nesting by this.
+    #[must_use]
+    pub fn indent(mut self, indent: Indent) -> Self {
+        self.rendering.pretty_options.indent = indent;
+        self
+    }
+
+    /// With [`Query::pretty`], puts each attribute on its own line if the start tag would be
+    /// longer than this, see [`PrettyOptions::wrap_attributes`].
+    #[must_use]
+    pub fn wrap_attributes(mut self, max_width: Option<usize>) -> Self {
+        self.rendering.pretty_options.wrap_attributes = max_width;
+        self
+    }
+
+    /// Writes the attributes of elements in this order, in both plain and pretty printed HTML.
+    #[must_use]
+    pub fn attribute_order(mut self, order: AttributeOrder) -> Self {
+        self.rendering.pretty_options.serialize.attribute_order = order;
+        self
+    }
+
+    /// Colors the markup for reading it in a terminal, in both plain and pretty printed HTML,
+    /// see [`serialize::SerializeOptions::color`]. [Outlines](Query::outline) are dimmed in parts.
+    #[must_use]
+    pub fn color(mut self, color: bool) -> Self {
+        self.rendering.pretty_options.serialize.color = color;
+        self.rendering.outline_options.color = color;
+        self
+    }
+
+    /// Writes only these comments, in both plain and pretty printed HTML.
+    #[must_use]
+    pub fn comments(mut self, comments: Comments) -> Self {
+        self.rendering.pretty_options.serialize.comments = comments;
+        self
+    }
+
+    /// With [`Query::pretty`], breaks text onto the next line where it would be longer than this,
+    /// see [`PrettyOptions::max_width`].
+    #[must_use]
+    pub fn max_width(mut self, max_width: Option<usize>) -> Self {
+        self.rendering.pretty_options.max_width = max_width;
+        self
+    }
+
+    /// Renders matches only as the values of this attribute, can be given multiple times.
+    ///
+    /// See [`crate::select_attributes`].
+    #[must_use]
+    pub fn attribute(mut self, name: impl Into<String>) -> Self {
+        self.rendering.attributes.push(name.into());
+        self
+    }
+
+    /// Prefixes attribute values with their names, see [`AttributeOptions::with_names`].
+    #[must_use]
+    pub fn attribute_names(mut self, attribute_names: bool) -> Self {
+        self.rendering.attribute_options.with_names = attribute_names;
+        self
+    }
+
+    /// Outputs this instead of attributes a match doesn't have,
+    /// see [`AttributeOptions::missing`].
+    #[must_use]
+    pub fn attribute_missing(mut self, placeholder: Option<String>) -> Self {
+        self.rendering.attribute_options.missing = placeholder;
+        self
+    }
+
+    /// Renders the text instead for matches that have none of the requested attributes.
+    #[must_use]
+    pub fn attribute_fallback_text(mut self, attribute_fallback_text: bool) -> Self {
+        self.rendering.attribute_fallback_text = attribute_fallback_text;
+        self
+    }
+
+    /// Ends each attribute value, text and expanded template with a NUL byte instead of a
+    /// newline, so they can contain newlines themselves.
+    #[must_use]
+    pub fn print0(mut self, print0: bool) -> Self {
+        self.rendering.print0 = print0;
+        self.rendering.attribute_options.null_terminated = print0;
+        self
+    }
+
+    /// Separates attribute names and values by tabs, see [`AttributeOptions::tsv`].
+    #[must_use]
+    pub fn attribute_tsv(mut self, attribute_tsv: bool) -> Self {
+        self.rendering.attribute_options.tsv = attribute_tsv;
+        self
+    }
+
+    /// If the output is HTML, as opposed to text or one of the other formats.
+    pub fn writes_html(&self) -> bool {
+        self.format == Format::Plain && self.rendering.is_html()
+    }
+
+    /// Checks that all selectors of the query are valid.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.compile().map(|_| ())
+    }
+
+    /// Parses the document from the reader and collects all matches in it.
+    ///
+    /// If the matches are to be [rendered with their position](Query::with_position),
+    /// the positions are recorded while parsing.
+    pub fn run(&self, mut reader: impl io::Read) -> Result<Vec<Match>, Error> {
+        let document = if self.rendering.with_position {
+            Document::parse_with_positions(&mut reader)?
+        } else {
+            Document::parse(&mut reader)?
+        };
+        Ok(self.select(&document)?.collect())
+    }
+
+    /// Lazily yields all matches in the document.
+    ///
+    /// Note that this modifies the document while iterating,
+    /// by removing nodes and rewriting links.
+    pub fn select(
+        &self,
+        document: &Document,
+    ) -> Result<impl Iterator<Item = Match> + use<>, Error> {
+        self.select_in(document, None)
+    }
+
+    /// Like [`Query::select`], but only applies the [grouped stage](Query::then_select_grouped)
+    /// within `group` if given.
+    fn select_in(
+        &self,
+        document: &Document,
+        group: Option<&NodeRef>,
+    ) -> Result<impl Iterator<Item = Match> + use<>, Error> {
+        let Compiled {
+            matcher,
+            stages,
+            grouped,
+            removals,
+            unwraps,
+        } = self.compile()?;
+        let removals = removals.map(Rc::new);
+        let unwraps = unwraps.map(Rc::new);
+        let clean_unwraps = unwraps.clone();
+        let base = self.base_for(document).map(Rc::new);
+        let mut rendering = self.rendering.clone();
+        rendering.text.base = base.as_deref().cloned();
+        let text_rendering = rendering.clone();
+        let remaining_rendering = rendering.clone();
+        let rendering = Rc::new(rendering);
+        let positioned = document.clone();
+        let wrap = move |node| Match {
+            position: positioned.position(&node),
+            document: positioned.root().clone(),
+            node,
+            rendering: Rc::clone(&rendering),
+            body: None,
+        };
+
+        let candidates = match group {
+            Some(group) => select_within(Box::new(std::iter::once(group.clone())), grouped),
+            None => {
+                let stages = stages.into_iter().chain(grouped);
+                matched_nodes(document.root(), matcher, stages)
+            }
+        };
+        let (filter_removals, clean_removals) = (removals.clone(), removals.clone());
+        let clean_base = base.clone();
+        let rewrite = self.rewrite.clone();
+        let relativize = self.relativize.clone();
+        let attribute_filters = self.attribute_filters.clone();
+        let sanitizing = self.sanitize.then(|| self.sanitize_options.clone());
+        let clean_sanitizing = sanitizing.clone();
+        let kept_comments = self.kept_comments;
+        let candidates = candidates.filter(move |node| {
+            if let Some(comment) = node.as_comment()
+                && !kept_comments.keeps(&comment.borrow())
+            {
+                return false;
+            }
+            filter_removals
+                .as_ref()
+                .is_none_or(|removals| !is_removed(node, removals))
+                && (sanitizing.is_none() || !sanitize::is_removed(node))
+                && attributes_match(node, &attribute_filters)
+        });
+        // Cleaning detaches nodes, which would cut the lazy traversal short
+        // if it happened while still walking the document.
+        let detaches = clean_removals.is_some()
+            || clean_unwraps.is_some()
+            || clean_sanitizing.is_some()
+            || kept_comments != Comments::All;
+        let candidates: Box<dyn Iterator<Item = NodeRef>> = if detaches {
+            Box::new(candidates.collect::<Vec<_>>().into_iter())
+        } else {
+            Box::new(candidates)
+        };
+
+        let cleaned = candidates.inspect(move |node| {
+            if let Some(removals) = &clean_removals {
+                remove_descendants(node, removals);
+            }
+            if let Some(unwraps) = &clean_unwraps {
+                unwrap_descendants(node, unwraps);
+            }
+            if let Some(options) = &clean_sanitizing {
+                sanitize::sanitize(node, options);
+            }
+            if kept_comments != Comments::All {
+                remove_comments(node, kept_comments);
+            }
+            if let Some(base) = &clean_base {
+                link::rewrite_relative_urls(node, base, &rewrite);
+            }
+            if let Some(prefix) = &relativize {
+                link::relativize_urls(node, prefix, &rewrite);
+            }
+        });
+
+        let cleaned: Box<dyn Iterator<Item = NodeRef>> = if self.select_comments {
+            let mut seen = HashSet::new();
+            Box::new(cleaned.flat_map(move |node| {
+                node.inclusive_descendants()
+                    .comments()
+                    .map(|comment| comment.as_node().clone())
+                    // nested matches contain the same comments
+                    .filter(|comment| seen.insert(std::ptr::from_ref::<Node>(comment)))
+                    .collect::<Vec<_>>()
+            }))
+        } else {
+            Box::new(cleaned)
+        };
+
+        // filter only after cleaning up so removed nodes don't contribute to the text
+        let text_filters = self.text_filters.clone();
+        let filtered = cleaned.filter(move |node| {
+            if text_filters.is_empty() {
+                return true;
+            }
+            let text = text_rendering.text(node);
+            text_filters
+                .iter()
+                .all(|filter| filter.regex.is_match(&text) != filter.inverted)
+        });
+
+        let filtered: Box<dyn Iterator<Item = NodeRef>> = if self.innermost {
+            // any match can contain another, so all of them are needed
+            let matches: Vec<_> = filtered.collect();
+            let mut containing = HashSet::new();
+            for node in &matches {
+                for ancestor in node.ancestors() {
+                    // the ones further up have been marked by an earlier match already
+                    if !containing.insert(std::ptr::from_ref::<Node>(&ancestor)) {
+                        break;
+                    }
+                }
+            }
+            Box::new(
+                matches
+                    .into_iter()
+                    .filter(move |node| !containing.contains(&std::ptr::from_ref::<Node>(node))),
+            )
+        } else if self.outermost {
+            // ancestors come before their descendants, so they've been seen already
+            let mut kept = HashSet::new();
+            Box::new(filtered.filter(move |node| {
+                let nested = node
+                    .ancestors()
+                    .any(|ancestor| kept.contains(&std::ptr::from_ref::<Node>(&ancestor)));
+                if !nested {
+                    kept.insert(std::ptr::from_ref::<Node>(node));
+                }
+                !nested
+            }))
+        } else {
+            Box::new(filtered)
+        };
+
+        let wrapped = filtered.map(wrap);
+        let deduplicated: Box<dyn Iterator<Item = Match>> = if self.unique {
+            let mut seen = HashSet::new();
+            Box::new(wrapped.filter_map(move |matched| matched.without_seen(&mut seen)))
+        } else {
+            Box::new(wrapped)
+        };
+
+        let sorted: Box<dyn Iterator<Item = Match>> = match &self.sort_key {
+            Some(key) => {
+                // sorting needs all matches, so only then they're collected
+                Box::new(key.sort(deduplicated.collect()).into_iter())
+            }
+            None => deduplicated,
+        };
+        let sorted: Box<dyn Iterator<Item = Match>> = if self.reverse {
+            Box::new(sorted.collect::<Vec<_>>().into_iter().rev())
+        } else {
+            sorted
+        };
+
+        let picked: Box<dyn Iterator<Item = _>> = match self.nth {
+            Some(nth) => Box::new(std::iter::once(pick_nth(sorted, nth)?)),
+            None => Box::new(sorted.take(self.limit)),
+        };
+        let (strip_attributes, set_attributes) =
+            (self.strip_attributes.clone(), self.set_attributes.clone());
+        let (text_replacements, on_text_replaced) =
+            (self.text_replacements.clone(), self.on_text_replaced);
+        let edited: Box<dyn Iterator<Item = _>> = Box::new(picked.inspect(move |matched| {
+            if !text_replacements.is_empty() {
+                let count = replace_descendant_text(&matched.node, &text_replacements);
+                if let Some(report) = on_text_replaced {
+                    report(count);
+                }
+            }
+            if !strip_attributes.is_empty() {
+                strip_descendant_attributes(&matched.node, &strip_attributes);
+            }
+            if let Some(elem) = matched.node.as_element() {
+                let mut attributes = elem.attributes.borrow_mut();
+                for (name, value) in &set_attributes {
+                    attributes.insert(name.as_str(), value.clone());
+                }
+            }
+        }));
+
+        let (wrapper, inner) = (self.wrapper.clone(), self.rendering.inner);
+        let edited: Box<dyn Iterator<Item = _>> = match wrapper {
+            Some(wrapper) => Box::new(edited.map(move |mut matched| {
+                matched.node = wrap_node(&matched.node, &wrapper, inner);
+                matched
+            })),
+            None => edited,
+        };
+
+        let edits_document = self.edits_document();
+        if !self.invert && !edits_document {
+            return Ok(edited);
+        }
+
+        // collect first, detaching while traversing would cut the traversal short
+        // and inserted nodes could be matched themselves
+        let picked: Vec<_> = edited.collect();
+        if self.whole_document && picked.is_empty() {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        if self.invert {
+            for matched in picked {
+                matched.node.detach();
+            }
+        } else {
+            for matched in &picked {
+                if let Some(html) = &self.insert_before {
+                    insert_html(&matched.node, html, Side::Before);
+                }
+                if let Some(html) = &self.insert_after {
+                    insert_html(&matched.node, html, Side::After);
+                }
+            }
+        }
+
+        let root = document.root().clone();
+        if let Some(removals) = &removals {
+            remove_descendants(&root, removals);
+        }
+        if let Some(unwraps) = &unwraps {
+            unwrap_descendants(&root, unwraps);
+        }
+        if self.sanitize {
+            sanitize::sanitize(&root, &self.sanitize_options);
+        }
+        if self.kept_comments != Comments::All {
+            remove_comments(&root, self.kept_comments);
+        }
+        if let Some(base) = &base {
+            link::rewrite_relative_urls(&root, base, &self.rewrite);
+        }
+        if let Some(prefix) = &self.relativize {
+            link::relativize_urls(&root, prefix, &self.rewrite);
+        }
+        let remaining = root.children().elements().next().is_some().then(|| Match {
+            position: document.position(&root),
+            document: root.clone(),
+            node: root,
+            rendering: Rc::new(remaining_rendering),
+            body: None,
+        });
+        Ok(Box::new(remaining.into_iter()))
+    }
+
+    /// Writes all matches in the document to the output according to the [`Format`],
+    /// returning how many there were, not counting those that [lack the requested
+    /// attributes](Match::lacks_attributes) in the [`Format::Plain`] format.
+    pub fn write(&self, document: &Document, output: &mut dyn io::Write) -> Result<usize, Error> {
+        if self.grouped_stage.is_some()
+            && self.format == Format::Plain
+            && !(self.invert || self.edits_document())
+        {
+            return self.write_groups(document, output);
+        }
+
+        let mut count = 0;
+        let mut json_matches = Vec::new();
+        let mut counted = Vec::new();
+        if self.format == Format::Plain && !self.rendering.fields.is_empty() {
+            let header = self
+                .rendering
+                .fields
+                .iter()
+                .map(|field| field.name().to_string());
+            table::write_csv(&[header.collect()], output)?;
+        }
+        // whether a table or separated match was output already, for separating it from the next
+        let mut wrote_any = false;
+
+        for matched in self.select(document)? {
+            self.check_template(&matched)?;
+            if self.format != Format::Plain || !matched.lacks_attributes() {
+                count += 1;
+            }
+            match self.format {
+                Format::Plain | Format::Json if self.rendering.stats => counted.push(matched.node),
+                Format::Plain if self.rendering.table_csv => {
+                    let rendered = matched.render();
+                    if !rendered.is_empty() {
+                        if wrote_any {
+                            writeln!(output)?;
+                        }
+                        output.write_all(rendered.as_bytes())?;
+                        wrote_any = true;
+                    }
+                }
+                Format::Plain if let Some(separator) = &self.separator => {
+                    let rendered = matched.render();
+                    let rendered = rendered.strip_suffix('\n').unwrap_or(&rendered);
+                    if !rendered.is_empty() {
+                        if wrote_any {
+                            output.write_all(separator.as_bytes())?;
+                        }
+                        output.write_all(rendered.as_bytes())?;
+                        wrote_any = true;
+                    }
+                }
+                Format::Plain if self.no_newline => {
+                    let rendered = matched.render();
+                    let rendered = rendered.strip_suffix('\n').unwrap_or(&rendered);
+                    output.write_all(rendered.as_bytes())?;
+                }
+                Format::Plain => matched.write_to(output)?,
+                Format::Json if self.rendering.meta => {
+                    json_matches.extend(
+                        matched
+                            .meta()
+                            .into_iter()
+                            .map(|(key, value)| serde_json::json!({ "key": key, "value": value })),
+                    );
+                }
+                Format::Json if self.rendering.json_ld => {
+                    json_matches.extend(matched.json_ld_values());
+                }
+                Format::Json => json_matches.push(matched.to_json()),
+                Format::Ndjson => {
+                    serde_json::to_writer(&mut *output, &matched.to_ndjson())?;
+                    writeln!(output)?;
+                    output.flush()?;
+                }
+                Format::Count => {}
+            }
+        }
+
+        if self.rendering.stats {
+            let stats = stats::collect(&counted);
+            match self.format {
+                Format::Plain => stats::write_table(&stats, output)?,
+                Format::Json => json_matches.extend(stats.iter().map(stats::TagStats::to_json)),
+                Format::Ndjson | Format::Count => {}
+            }
+        }
+
+        match self.format {
+            Format::Json => {
+                serde_json::to_writer(&mut *output, &json_matches)?;
+                writeln!(output)?;
+            }
+            Format::Count => writeln!(output, "{count}")?,
+            Format::Plain | Format::Ndjson => {}
+        }
+
+        Ok(count)
+    }
+
+    /// Writes the matches of the [grouped stage](Query::then_select_grouped) within each match
+    /// of the stages before it, with separators between the groups.
+    fn write_groups(
+        &self,
+        document: &Document,
+        output: &mut dyn io::Write,
+    ) -> Result<usize, Error> {
+        let Compiled {
+            matcher, stages, ..
+        } = self.compile()?;
+        // collected, since selecting within them modifies the document
+        let groups: Vec<_> = matched_nodes(document.root(), matcher, stages).collect();
+        let separator = self.separator.as_deref().unwrap_or("\n");
+
+        let mut count = 0;
+        for (i, group) in groups.iter().enumerate() {
+            if i > 0 {
+                output.write_all(separator.as_bytes())?;
+            }
+            for matched in self.select_in(document, Some(group))? {
+                self.check_template(&matched)?;
+                if !matched.lacks_attributes() {
+                    count += 1;
+                }
+                matched.write_to(output)?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Like [`Query::write`] in the [`Format::Plain`] format, but writes each match to the writer
+    /// `open` returns for it, returning how many matches there were, not counting those that
+    /// [lack the requested attributes](Match::lacks_attributes).
+    pub fn write_each<W, E>(
+        &self,
+        document: &Document,
+        mut open: impl FnMut(&Match) -> Result<W, E>,
+    ) -> Result<usize, E>
+    where
+        W: io::Write,
+        E: From<Error> + From<io::Error>,
+    {
+        let mut count = 0;
+        for matched in self.select(document)? {
+            self.check_template(&matched)?;
+            if !matched.lacks_attributes() {
+                count += 1;
+            }
+
+            let mut output = open(&matched)?;
+            let rendered = matched.render();
+            let rendered = if self.no_newline {
+                rendered.strip_suffix('\n').unwrap_or(&rendered)
+            } else {
+                &rendered
+            };
+            output.write_all(rendered.as_bytes())?;
+            output.flush()?;
+        }
+        Ok(count)
+    }
+
+    /// Fails if the match lacks an attribute used in the template, see [`Query::strict_template`].
+    fn check_template(&self, matched: &Match) -> Result<(), Error> {
+        if self.strict_template
+            && let Some(template) = &self.rendering.template
+            && let Some(name) = template.missing_attribute(matched.node())
+        {
+            return Err(Error::MissingAttribute {
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn compile(&self) -> Result<Compiled, Error> {
+        let compile = |selector: &str, role| {
+            CssSelector::compile(selector).map_err(|()| Error::InvalidSelector {
+                selector: selector.to_string(),
+                role,
+            })
+        };
+
+        let matcher = match &self.selector {
+            Selector::Css(selector) => Matcher::Css(compile(selector, SelectorRole::Main)?),
+            Selector::XPath(expression) => Matcher::XPath(XPath::parse(expression).map_err(
+                |error| Error::InvalidXPath {
+                    expression: expression.clone(),
+                    error,
+                },
+            )?),
+        };
+
+        let compile_all = |selectors: &[String], role| {
+            // compile one by one first so the error can point out the specific one
+            for selector in selectors {
+                compile(selector, role)?;
+            }
+            if selectors.is_empty() {
+                Ok(None)
+            } else {
+                compile(&selectors.join(","), role).map(Some)
+            }
+        };
+
+        let stages = self
+            .stages
+            .iter()
+            .map(|selector| compile(selector, SelectorRole::Stage))
+            .collect::<Result<_, _>>()?;
+        let grouped = self
+            .grouped_stage
+            .as_deref()
+            .map(|selector| compile(selector, SelectorRole::Group))
+            .transpose()?;
+
+        Ok(Compiled {
+            matcher,
+            stages,
+            grouped,
+            removals: compile_all(&self.removals, SelectorRole::Removal)?,
+            unwraps: compile_all(&self.unwraps, SelectorRole::Unwrap)?,
+        })
+    }
+
+    fn base_for(&self, document: &Document) -> Option<Url> {
+        if let Some(base) = &self.base
+            && !(self.detect_base && self.prefer_detected_base)
+        {
+            return Some(base.clone());
+        }
+
+        let document_url = self.base.as_ref().or(document.url());
+        let detected = self
+            .detect_base
+            .then(|| link::detect_base(document.root(), document_url))
+            .flatten();
+        detected.or_else(|| document_url.cloned())
+    }
+}
+
+/// All compiled selectors of a query.
+struct Compiled {
+    matcher: Matcher,
+    stages: Vec<CssSelector>,
+    grouped: Option<CssSelector>,
+    removals: Option<CssSelector>,
+    unwraps: Option<CssSelector>,
+}
+
+/// A compiled [`Selector`].
+enum Matcher {
+    Css(CssSelector),
+    XPath(XPath),
+}
+
+/// A node that matched a [`Query`], already cleaned up and with links rewritten.
+#[derive(Debug, Clone)]
+pub struct Match {
+    node: NodeRef,
+    /// The document node, which also keeps the node's ancestors from being dropped.
+    document: NodeRef,
+    position: Option<Position>,
+    rendering: Rc<Rendering>,
+    /// What to output instead of rendering the node, if only parts of it are to be output.
+    body: Option<String>,
+}
+
+impl Match {
+    pub fn node(&self) -> &NodeRef {
+        &self.node
+    }
+
+    /// Where the node starts in the input, see [`Document::position`].
+    pub fn position(&self) -> Option<Position> {
+        self.position
+    }
+
+    /// The outer HTML of the node.
+    pub fn html(&self) -> String {
+        let options = &self.rendering.pretty_options.serialize;
+        if self.node.as_document().is_none() {
+            return serialize::serialize_with(&self.node, options);
+        }
+        // html5ever would leave out the identifiers of the doctype
+        self.node
+            .children()
+            .map(|child| match child.as_doctype() {
+                Some(doctype) => {
+                    let doctype = crate::serialize_doctype(doctype);
+                    serialize::painted(doctype, serialize::palette::COMMENT, options)
+                }
+                None => serialize::serialize_with(&child, options),
+            })
+            .collect()
+    }
+
+    /// A selector like `html > body > ul > li:nth-child(3)` that matches only this node.
+    ///
+    /// Uses ids where present, so the document has to be unchanged for it to match again.
+    pub fn css_path(&self) -> Option<String> {
+        crate::css::path_to(&self.node)
+    }
+
+    /// The metadata in the node, see [`meta::entries`].
+    pub fn meta(&self) -> Vec<(String, String)> {
+        meta::entries(&self.node)
+    }
+
+    /// The JSON-LD blocks in the node, see [`json_ld::blocks`], along with their parsed value.
+    ///
+    /// The value is `None` for blocks that aren't valid JSON, reporting a warning for them.
+    fn json_ld(&self) -> Vec<(String, Option<Value>)> {
+        json_ld::blocks(&self.node)
+            .into_iter()
+            .map(|block| {
+                let value = json_ld::parse(&block);
+                if let (Err(err), Some(warn)) = (&value, self.rendering.warn) {
+                    warn(&format!("JSON-LD block is not valid JSON: {err}"));
+                }
+                (block, value.ok())
+            })
+            .collect()
+    }
+
+    /// Like [`Match::json_ld`], but only the values of valid blocks.
+    fn json_ld_values(&self) -> Vec<Value> {
+        self.json_ld()
+            .into_iter()
+            .filter_map(|(_, value)| value)
+            .collect()
+    }
+
+    /// The lowercased local name of the node, if it is an element.
+    pub fn tag_name(&self) -> Option<String> {
+        self.node
+            .as_element()
+            .map(|elem| elem.name.local.to_lowercase())
+    }
+
+    /// The HTML of all children of the node, including text and comments.
+    pub fn inner_html(&self) -> String {
+        serialize::serialize_children_with(&self.node, &self.rendering.pretty_options.serialize)
+    }
+
+    /// If the node is the root `<html>` and it, its `<head>` and its `<body>` look like they were
+    /// added by the parser, those elements that would be left once they are stripped.
+    ///
+    /// The parser adds them without attributes, so any attributes on them mean they were in the
+    /// input. There's no telling apart an `<html>` written without attributes from one the parser
+    /// added though, so those count as added as well. An empty `<head>` isn't included.
+    ///
+    /// If the node is the document itself, like when it's yielded as a whole, this is about
+    /// its `<html>`, as long as there's nothing but a doctype next to it.
+    pub fn scaffolding(&self) -> Option<Vec<NodeRef>> {
+        let html = if self.node.as_document().is_some() {
+            let mut children = self
+                .node
+                .children()
+                .filter(|child| child.as_doctype().is_none());
+            let html = children.next()?;
+            if children.next().is_some() {
+                return None;
+            }
+            html
+        } else {
+            self.node.clone()
+        };
+        let elem = html.as_element()?;
+        let is_root = html
+            .parent()
+            .is_some_and(|parent| parent.as_document().is_some());
+        if elem.name.local != local_name!("html") || !is_root {
+            return None;
+        }
+
+        let mut parts = Vec::new();
+        for node in std::iter::once(html.clone()).chain(html.children()) {
+            let Some(elem) = node.as_element() else {
+                continue;
+            };
+            if !elem.attributes.borrow().map.is_empty() {
+                return None;
+            }
+            let is_empty_head =
+                elem.name.local == local_name!("head") && node.first_child().is_none();
+            if node != html && !is_empty_head {
+                parts.push(node);
+            }
+        }
+        Some(parts)
+    }
+
+    /// The serialized doctype to put before the HTML of the match, if any.
+    ///
+    /// That is the doctype of the document if the match is its root element,
+    /// or for any match with [`Query::always_doctype`].
+    pub fn doctype(&self) -> Option<String> {
+        let is_root = self.node.as_element().is_some()
+            && self
+                .node
+                .parent()
+                .is_some_and(|parent| parent == self.document);
+        // the document itself is serialized with its doctype anyway
+        if !(is_root || self.rendering.always_doctype) || self.node == self.document {
+            return None;
+        }
+        let doctype = self
+            .document
+            .children()
+            .find_map(|child| child.as_doctype().map(crate::serialize_doctype))?;
+        let options = &self.rendering.pretty_options.serialize;
+        Some(serialize::painted(
+            doctype,
+            serialize::palette::COMMENT,
+            options,
+        ))
+    }
+
+    /// The text contained in the node, see [`text::extract`].
+    pub fn text(&self) -> String {
+        self.rendering.text(&self.node)
+    }
+
+    /// The value of each [field](Query::field) in the node, in the order they were given.
+    pub fn record(&self) -> Vec<String> {
+        let rendering = &self.rendering;
+        rendering
+            .fields
+            .iter()
+            .map(|field| {
+                let values = field.values(&self.node, &rendering.text);
+                match &rendering.field_separator {
+                    Some(separator) => values.join(separator),
+                    None => values.into_iter().next().unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    /// The node as the query would output it in the [`Format::Plain`] format.
+    pub fn render(&self) -> String {
+        let mut content = Vec::new();
+        self.write_to(&mut content)
+            .expect("writing to a Vec should never fail");
+        String::from_utf8(content).expect("all rendered parts should be UTF-8")
+    }
+
+    /// Writes the node the same way as [`Match::render`] would return it.
+    pub fn write_to(&self, output: &mut dyn io::Write) -> io::Result<()> {
+        if !self.rendering.with_position {
+            return self.write_body(output);
+        }
+
+        // matches that render to nothing shouldn't leave a lone prefix behind
+        let mut body = Vec::new();
+        self.write_body(&mut body)?;
+        if !body.is_empty() {
+            match self.position {
+                Some(position) => write!(output, "{position}:")?,
+                None => write!(output, "?:?:")?,
+            }
+            output.write_all(&body)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the parts of the rendering that are already in `seen` and adds the rest to it,
+    /// returning `None` if nothing is left. See [`Query::unique`].
+    fn without_seen(mut self, seen: &mut HashSet<String>) -> Option<Self> {
+        let mut body = Vec::new();
+        self.write_body(&mut body)
+            .expect("writing to a Vec should never fail");
+        let body = String::from_utf8(body).expect("all rendered parts should be UTF-8");
+
+        let fresh: String = if self.rendering.has_one_value_per_line() {
+            // links are always one per line
+            let terminator = match self.rendering.links {
+                Some(_) => '\n',
+                None => self.rendering.terminator(),
+            };
+            body.split_inclusive(terminator)
+                .filter(|line| seen.insert((*line).to_string()))
+                .collect()
+        } else if seen.insert(body.clone()) {
+            body
+        } else {
+            String::new()
+        };
+
+        if fresh.is_empty() {
+            return None;
+        }
+        self.body = Some(fresh);
+        Some(self)
+    }
+
+    fn write_body(&self, output: &mut dyn io::Write) -> io::Result<()> {
+        if let Some(body) = &self.body {
+            return output.write_all(body.as_bytes());
+        }
+
+        let rendering = &self.rendering;
+
+        if let Some(template) = &rendering.template {
+            let expanded = template.expand(&self.node, rendering.text.ignore_whitespace);
+            return write!(output, "{expanded}{}", rendering.terminator());
+        }
+
+        if let Some(kinds) = rendering.links {
+            for link in link::links(&self.node, kinds) {
+                writeln!(output, "{}\t{}", link.url, link.text)?;
+            }
+            return Ok(());
+        }
+
+        if !rendering.fields.is_empty() {
+            return table::write_csv(&[self.record()], output);
+        }
+
+        if rendering.table_csv {
+            for (i, table) in table::tables(&self.node).iter().enumerate() {
+                if i > 0 {
+                    writeln!(output)?;
+                }
+                table::write_csv(&table::grid(table), output)?;
+            }
+            return Ok(());
+        }
+
+        if rendering.table_json {
+            for table in table::tables(&self.node) {
+                serde_json::to_writer(&mut *output, &table::records(&table))?;
+                writeln!(output)?;
+            }
+            return Ok(());
+        }
+
+        if rendering.forms {
+            for form in form::forms(&self.node) {
+                serde_json::to_writer(&mut *output, &form::describe(&form))?;
+                writeln!(output)?;
+            }
+            return Ok(());
+        }
+
+        if rendering.meta {
+            for (key, value) in self.meta() {
+                writeln!(output, "{key}\t{value}")?;
+            }
+            return Ok(());
+        }
+
+        if rendering.json_ld {
+            for (block, _) in self.json_ld() {
+                writeln!(output, "{block}")?;
+            }
+            return Ok(());
+        }
+
+        if rendering.markdown {
+            return writeln!(output, "{}", markdown::to_markdown(&self.node));
+        }
+
+        if rendering.outline {
+            let outline = outline::outline(&self.node, &rendering.outline_options);
+            return output.write_all(outline.as_bytes());
+        }
+
+        if rendering.css_path {
+            let Some(path) = self.css_path() else {
+                return Ok(());
+            };
+            writeln!(output, "{path}")?;
+            return self.write_attributes(output);
+        }
+
+        if rendering.tag_names {
+            return match self.tag_name() {
+                Some(name) => writeln!(output, "{name}"),
+                None => Ok(()),
+            };
+        }
+
+        if rendering.classes {
+            return crate::select_classes(&self.node, &rendering.attribute_options, output)
+                .map(|_| ());
+        }
+
+        if !rendering.attributes.is_empty() {
+            return self.write_attributes(output);
+        }
+
+        if rendering.text_only {
+            return write!(output, "{}{}", self.text(), rendering.terminator());
+        }
+
+        if rendering.bare
+            && let Some(parts) = self.scaffolding()
+        {
+            let content: String = parts
+                .iter()
+                .map(|part| {
+                    if rendering.minify {
+                        minify::minify_children(part, &rendering.pretty_options.serialize)
+                    } else if rendering.pretty {
+                        pretty_print::pretty_print_children_with(part, &rendering.pretty_options)
+                    } else {
+                        serialize::serialize_children_with(
+                            part,
+                            &rendering.pretty_options.serialize,
+                        )
+                    }
+                })
+                .collect();
+            return writeln!(output, "{content}");
+        }
+
+        let serialize = &rendering.pretty_options.serialize;
+        let html = match (rendering.minify, rendering.pretty, rendering.inner) {
+            (true, _, true) => minify::minify_children(&self.node, serialize),
+            (true, _, false) => minify::minify(&self.node, serialize),
+            (false, true, true) => {
+                pretty_print::pretty_print_children_with(&self.node, &rendering.pretty_options)
+            }
+            (false, true, false) => {
+                pretty_print::pretty_print_with(&self.node, &rendering.pretty_options)
+            }
+            (false, false, true) => self.inner_html(),
+            (false, false, false) => self.html(),
+        };
+        if let Some(doctype) = self.doctype().filter(|_| !rendering.inner) {
+            // pretty printed HTML already starts on a new line
+            let separator = if html.starts_with('\n') { "" } else { "\n" };
+            write!(output, "{doctype}{separator}")?;
+        }
+        writeln!(output, "{html}")
+    }
+
+    /// If the match is rendered as its attributes but has none of the requested ones,
+    /// so that nothing of it is output except maybe [placeholders](Query::attribute_missing).
+    ///
+    /// Such matches aren't counted by [`Query::write`].
+    #[must_use]
+    pub fn lacks_attributes(&self) -> bool {
+        let rendering = &self.rendering;
+        rendering.is_attributes()
+            && !rendering.attribute_fallback_text
+            && crate::select_attributes(
+                &self.node,
+                &rendering.attributes,
+                &rendering.attribute_options,
+                &mut io::sink(),
+            )
+            .is_ok_and(|found| found == 0)
+    }
+
+    /// Writes the requested attributes, or the text if the node has none of them
+    /// and that is asked for.
+    fn write_attributes(&self, output: &mut dyn io::Write) -> io::Result<()> {
+        let rendering = &self.rendering;
+        let mut values = Vec::new();
+        let found = crate::select_attributes(
+            &self.node,
+            &rendering.attributes,
+            &rendering.attribute_options,
+            &mut values,
+        )?;
+        if found == 0 && rendering.attribute_fallback_text {
+            return write!(output, "{}{}", self.text(), rendering.terminator());
+        }
+        output.write_all(&values)
+    }
+
+    /// The [record](Match::record) as JSON object, keyed by the field names.
+    fn record_json(&self) -> Value {
+        let names = self
+            .rendering
+            .fields
+            .iter()
+            .map(|field| field.name().to_string());
+        let values = self.record().into_iter().map(Value::String);
+        Value::Object(names.zip(values).collect())
+    }
+
+    /// Describes the node as JSON object, see [`json::describe`].
+    ///
+    /// With [fields](Query::field), this is an object of their values instead.
+    pub fn to_json(&self) -> Value {
+        if !self.rendering.fields.is_empty() {
+            return self.record_json();
+        }
+        json::describe(&self.node, &self.rendering.attributes, self.text())
+    }
+
+    /// Like [`Match::to_json`], but only with the requested attributes and text, if any.
+    pub fn to_ndjson(&self) -> Value {
+        let rendering = &self.rendering;
+        if !rendering.fields.is_empty() {
+            self.record_json()
+        } else if rendering.attributes.is_empty() && !rendering.text_only {
+            self.to_json()
+        } else {
+            let text = rendering.text_only.then(|| self.text());
+            json::describe_selected(&self.node, &rendering.attributes, text)
+        }
+    }
+}
+
+/// The nodes the matcher matches in the document, with each stage applied in turn.
+fn matched_nodes(
+    root: &NodeRef,
+    matcher: Matcher,
+    stages: impl IntoIterator<Item = CssSelector>,
+) -> Box<dyn Iterator<Item = NodeRef>> {
+    let candidates: Box<dyn Iterator<Item = NodeRef>> = match matcher {
+        Matcher::Css(selector) => Box::new(
+            root.inclusive_descendants()
+                .elements()
+                .filter(move |elem| selector.matches(elem))
+                .map(|elem| elem.as_node().clone()),
+        ),
+        Matcher::XPath(xpath) => Box::new(xpath.select(root).into_iter()),
+    };
+    stages.into_iter().fold(candidates, select_within)
+}
+
+/// The descendants of the nodes matching the selector, each only once.
+fn select_within(
+    nodes: Box<dyn Iterator<Item = NodeRef>>,
+    selector: impl Into<Option<CssSelector>>,
+) -> Box<dyn Iterator<Item = NodeRef>> {
+    let Some(selector) = selector.into() else {
+        return nodes;
+    };
+    let selector = Rc::new(selector);
+    let mut seen = HashSet::new();
+    Box::new(
+        nodes
+            .flat_map(move |node| {
+                let selector = Rc::clone(&selector);
+                node.descendants()
+                    .elements()
+                    .filter(move |elem| selector.matches(elem))
+                    .map(|elem| elem.as_node().clone())
+            })
+            .filter(move |node| seen.insert(std::ptr::from_ref::<Node>(node))),
+    )
+}
+
+/// Removes the attributes matching any of the patterns from the node and all elements in it.
+fn strip_descendant_attributes(node: &NodeRef, patterns: &[String]) {
+    for elem in node.inclusive_descendants().elements() {
+        elem.attributes.borrow_mut().map.retain(|name, _| {
+            !patterns
+                .iter()
+                .any(|pattern| glob_matches(pattern, &name.local))
+        });
+    }
+}
+
+/// If the name matches the pattern, where `*` stands for any number of characters.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    if parts.peek().is_none() {
+        return rest.is_empty();
+    }
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.len() >= part.len() && rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// If the node is to be removed, either by itself or by being inside a node to be removed.
+fn is_removed(node: &NodeRef, removals: &CssSelector) -> bool {
+    node.inclusive_ancestors()
+        .elements()
+        .any(|ancestor| removals.matches(&ancestor))
+}
+
+/// If the node has all the attributes with values matching their regex.
+fn attributes_match(node: &NodeRef, filters: &[(String, Regex)]) -> bool {
+    if filters.is_empty() {
+        return true;
+    }
+    let Some(elem) = node.as_element() else {
+        return false;
+    };
+
+    let attributes = elem.attributes.borrow();
+    filters.iter().all(|(name, regex)| {
+        attributes
+            .get(name.as_str())
+            .is_some_and(|value| regex.is_match(value))
+    })
+}
+
+/// Detaches all descendants of the node that are to be removed, keeping the node itself.
+fn remove_descendants(node: &NodeRef, removals: &CssSelector) {
+    // collect first, detaching while traversing would cut the traversal short
+    let targets: Vec<_> = node
+        .descendants()
+        .elements()
+        .filter(|descendant| removals.matches(descendant))
+        .collect();
+
+    for target in targets {
+        target.as_node().detach();
+    }
+}
+
+/// Makes the replacements in all text nodes of the node outside of scripts and styles,
+/// returning how many there were.
+fn replace_descendant_text(node: &NodeRef, replacements: &[(Regex, String)]) -> usize {
+    let mut count = 0;
+    for text in node.inclusive_descendants().text_nodes() {
+        let in_code = text.as_node().parent().is_some_and(|parent| {
+            parent.as_element().is_some_and(|elem| {
+                matches!(
+                    elem.name.local,
+                    local_name!("script") | local_name!("style")
+                )
+            })
+        });
+        if in_code {
+            continue;
+        }
+
+        let mut text = text.borrow_mut();
+        for (regex, replacement) in replacements {
+            let found = regex.find_iter(&text).count();
+            if found > 0 {
+                count += found;
+                *text = regex.replace_all(&text, replacement.as_str()).into_owned();
+            }
+        }
+    }
+    count
+}
+
+/// Where to insert HTML next to a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Before,
+    After,
+}
+
+/// Parses the HTML in the context of the parent of the node and inserts it next to the node,
+/// with `{@name}` placeholders replaced by the attributes of the node.
+fn insert_html(node: &NodeRef, html: &str, side: Side) {
+    let context = node
+        .parent()
+        .and_then(|parent| parent.as_element().map(|elem| elem.name.local.to_string()))
+        .unwrap_or_else(|| "body".to_string());
+    let fragment = crate::parse_fragment(html, &context);
+
+    let attributes = node
+        .as_element()
+        .map(|elem| elem.attributes.borrow().clone());
+    let expand = |raw: &str| expand_attribute_placeholders(raw, attributes.as_ref());
+    for descendant in fragment.descendants() {
+        if let Some(text) = descendant.as_text() {
+            let expanded = expand(&text.borrow());
+            *text.borrow_mut() = expanded;
+        } else if let Some(elem) = descendant.as_element() {
+            for attr in elem.attributes.borrow_mut().map.values_mut() {
+                attr.value = expand(&attr.value);
+            }
+        }
+    }
+
+    let mut anchor = node.clone();
+    for child in fragment.children().collect::<Vec<_>>() {
+        match side {
+            Side::Before => node.insert_before(child),
+            Side::After => {
+                anchor.insert_after(child.clone());
+                anchor = child;
+            }
+        }
+    }
+}
+
+/// Replaces each `{@name}` in the text by the value of the attribute `name`,
+/// or by nothing if there's no such attribute.
+fn expand_attribute_placeholders(raw: &str, attributes: Option<&Attributes>) -> String {
+    let mut expanded = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find("{@") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        let name = &rest[start + 2..start + end];
+        if let Some(value) = attributes.and_then(|attributes| attributes.get(name)) {
+            expanded.push_str(value);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Puts the node into a new element where it was, returning that element.
+/// With `inner`, the node stays and its children are put into the element instead.
+fn wrap_node(node: &NodeRef, wrapper: &Wrapper, inner: bool) -> NodeRef {
+    let element = wrapper.element();
+    if inner {
+        let children: Vec<_> = node.children().collect();
+        for child in children {
+            element.append(child);
+        }
+        node.append(element);
+        return node.clone();
+    }
+    node.insert_before(element.clone());
+    element.append(node.clone());
+    element
+}
+
+/// Removes all comments in the node that aren't kept.
+fn remove_comments(node: &NodeRef, kept: Comments) {
+    // collect first, detaching while traversing would cut the traversal short
+    let removed: Vec<_> = node
+        .descendants()
+        .comments()
+        .filter(|comment| !kept.keeps(&comment.borrow()))
+        .collect();
+
+    for comment in removed {
+        comment.as_node().detach();
+    }
+}
+
+/// Replaces all descendants of the node matching the selector by their children.
+fn unwrap_descendants(node: &NodeRef, unwraps: &CssSelector) {
+    let targets: Vec<_> = node
+        .descendants()
+        .elements()
+        .filter(|descendant| unwraps.matches(descendant))
+        .collect();
+
+    // deepest first, descendants come after their ancestors in document order
+    for target in targets.into_iter().rev() {
+        let target = target.as_node();
+        let children: Vec<_> = target.children().collect();
+        for child in children {
+            target.insert_before(child);
+        }
+        target.detach();
+    }
+}
+
+/// Picks the match at the 1-based `nth` position, counting from the end if negative.
+fn pick_nth<T>(mut matches: impl Iterator<Item = T>, nth: isize) -> Result<T, Error> {
+    let out_of_range = |len| Error::NthOutOfRange { nth, len };
+
+    if nth > 0 {
+        let index = nth.unsigned_abs() - 1;
+        let mut len = 0;
+        for candidate in matches.by_ref() {
+            if len == index {
+                return Ok(candidate);
+            }
+            len += 1;
+        }
+        Err(out_of_range(len))
+    } else {
+        let mut all: Vec<_> = matches.collect();
+        let len = all.len();
+        let from_end = nth.unsigned_abs();
+        if from_end == 0 || from_end > len {
+            return Err(out_of_range(len));
+        }
+        Ok(all.swap_remove(len - from_end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(query: &Query, html: &str) -> Vec<String> {
+        query
+            .run(html.as_bytes())
+            .unwrap()
+            .iter()
+            .map(Match::render)
+            .collect()
+    }
+
+    #[test]
+    fn select_html() {
+        let query = Query::new(".hi");
+        assert_eq!(
+            run(
+                &query,
+                "<div class=\"hi\"><a href=\"/foo\">Hello</a></div><p class=\"hi\"></p>"
+            ),
+            [
+                "<div class=\"hi\"><a href=\"/foo\">Hello</a></div>\n",
+                "<p class=\"hi\"></p>\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn doctypes_round_trip() {
+        let doctypes = [
+            "<!DOCTYPE html>",
+            "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">",
+            "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">",
+            "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">",
+            "<!DOCTYPE html SYSTEM \"about:legacy-compat\">",
+        ];
+        let quirks_mode = |html: &str| {
+            let document = Document::parse(&mut html.as_bytes()).unwrap();
+            document.root().as_document().unwrap().quirks_mode()
+        };
+
+        for doctype in doctypes {
+            let html = format!("{doctype}<p>x</p>");
+            for query in [Query::new(":root"), Query::new(":root").pretty(true)] {
+                let rendered = run(&query, &html).concat();
+                assert!(
+                    rendered.starts_with(&format!("{doctype}\n<html>")),
+                    "{rendered}"
+                );
+                assert_eq!(quirks_mode(&rendered), quirks_mode(&html), "{doctype}");
+            }
+        }
+
+        let html = "<!DOCTYPE html><p>x</p>";
+        assert_eq!(run(&Query::new("p"), html), ["<p>x</p>\n"]);
+        assert_eq!(
+            run(&Query::new("p").always_doctype(true), html),
+            ["<!DOCTYPE html>\n<p>x</p>\n"]
+        );
+        assert_eq!(
+            run(&Query::new(":root").inner(true), "<!DOCTYPE html>")[0],
+            "<head></head><body></body>\n"
+        );
+    }
+
+    #[test]
+    fn select_text_and_attributes() {
+        let html = "<a href=\"/a\" title=\"A\">one</a><a href=\"/b\">two</a>";
+        assert_eq!(
+            run(&Query::new("a").text_only(true), html),
+            ["one\n", "two\n"]
+        );
+        assert_eq!(
+            run(&Query::new("a").attribute("title").attribute("href"), html),
+            ["A\n/a\n", "/b\n"]
+        );
+    }
+
+    #[test]
+    fn remove_keeps_match() {
+        let query = Query::new("article").remove("script").remove("style");
+        assert_eq!(
+            run(
+                &query,
+                "<article><script>1</script><p>a</p><style>2</style><script>3</script></article>"
+            ),
+            ["<article><p>a</p></article>\n"]
+        );
+    }
+
+    #[test]
+    fn remove_skips_removed_matches() {
+        let query = Query::new("p").remove(".ad").text_only(true);
+        assert_eq!(
+            run(
+                &query,
+                "<p class=\"ad\">a</p><div class=\"ad\"><p>b</p></div><p>c</p>"
+            ),
+            ["c\n"]
+        );
+    }
+
+    #[test]
+    fn limit_and_nth() {
+        let html = "<li>1</li><li>2</li><li>3</li>";
+        let query = Query::new("li").text_only(true);
+        assert_eq!(run(&query.clone().limit(2), html), ["1\n", "2\n"]);
+        assert_eq!(run(&query.clone().nth(2), html), ["2\n"]);
+        assert_eq!(run(&query.clone().nth(-1), html), ["3\n"]);
+        assert!(matches!(
+            query.nth(4).run(html.as_bytes()),
+            Err(Error::NthOutOfRange { nth: 4, len: 3 })
+        ));
+    }
+
+    #[test]
+    fn text_filters_apply_before_limit() {
+        let html = "<li>v1.0</li><li>notes</li><li>v1.1 <s>beta</s></li><li>v2.0 beta</li>";
+        let query = Query::new("li")
+            .remove("s")
+            .text_matches(Regex::new(r"^v\d+\.\d+").unwrap())
+            .text_not_matches(Regex::new("beta").unwrap())
+            .text_only(true);
+        assert_eq!(run(&query, html), ["v1.0\n", "v1.1 \n"]);
+        assert_eq!(run(&query.limit(1), html), ["v1.0\n"]);
+    }
+
+    #[test]
+    fn attribute_filters_all_have_to_match() {
+        let html = "<a href=\"https://github.com/a\" rel=\"me\">1</a>\
+            <a href=\"https://github.com/b\">2</a><a rel=\"me\">3</a>";
+        let query = Query::new("a")
+            .attribute_matches("href", Regex::new("^https://github\\.com/").unwrap())
+            .text_only(true);
+        assert_eq!(run(&query, html), ["1\n", "2\n"]);
+        let query = query.attribute_matches("rel", Regex::new("^me$").unwrap());
+        assert_eq!(run(&query, html), ["1\n"]);
+    }
+
+    #[test]
+    fn invert_outputs_the_rest() {
+        let html = "<nav>n</nav><p>a <a href=\"/x\">x</a></p><footer><p>f</p></footer>";
+        let query = Query::new("nav, footer")
+            .invert(true)
+            .base(Url::parse("https://example.org").unwrap());
+        assert_eq!(
+            run(&query, html),
+            [
+                "<html><head></head><body><p>a <a href=\"https://example.org/x\">x</a></p></body></html>\n"
+            ]
+        );
+        assert!(run(&Query::new("html").invert(true), html).is_empty());
+    }
+
+    #[test]
+    fn set_attributes_edit_the_document() {
+        let html = "<p><a href=\"https://a.example\">a</a> <a href=\"/b\" target=\"x\">b</a></p>";
+        let query = Query::new("a")
+            .limit(1)
+            .set_attribute("target", "_blank")
+            .set_attribute("rel", "noopener");
+        assert_eq!(
+            run(&query, html),
+            [
+                "<html><head></head><body><p><a href=\"https://a.example\" target=\"_blank\" rel=\"noopener\">a</a> \
+                <a href=\"/b\" target=\"x\">b</a></p></body></html>\n"
+            ]
+        );
+        assert_eq!(
+            run(&query.limit(usize::MAX).attribute("target"), html),
+            ["_blank\n", "_blank\n"]
+        );
+    }
+
+    #[test]
+    fn globs_match_attribute_names() {
+        for (pattern, name, expected) in [
+            ("class", "class", true),
+            ("class", "classes", false),
+            ("on*", "onclick", true),
+            ("on*", "on", true),
+            ("on*", "hidden", false),
+            ("data-*", "data-id", true),
+            ("*-id", "data-id", true),
+            ("*-id", "id", false),
+            ("a*b*c", "abc", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "acb", false),
+            ("*", "anything", true),
+        ] {
+            assert_eq!(glob_matches(pattern, name), expected, "{pattern} {name}");
+        }
+    }
+
+    #[test]
+    fn stages_select_within_previous_matches() {
+        let html = "<div class=\"r\"><a>1</a><div class=\"r\"><a>2</a></div></div>\
+            <ul><li>3</li></ul><section><ul><li>4</li><li>5</li></ul></section><a>6</a>";
+        let query = Query::new("div.r").then_select("a");
+        assert_eq!(run(&query, html), ["<a>1</a>\n", "<a>2</a>\n"]);
+
+        // matches aren't their own descendants
+        let query = Query::new("div").then_select("div");
+        assert_eq!(run(&query, html), ["<div class=\"r\"><a>2</a></div>\n"]);
+
+        let query = Query::new("section").then_select("ul").then_select("li");
+        assert_eq!(run(&query, html), ["<li>4</li>\n", "<li>5</li>\n"]);
+        let query = Query::xpath("//section").then_select("li:nth-child(2)");
+        assert_eq!(run(&query, html), ["<li>5</li>\n"]);
+
+        assert!(matches!(
+            Query::new("p").then_select("a[").validate(),
+            Err(Error::InvalidSelector {
+                role: SelectorRole::Stage,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unwrap_nested_and_sibling_elements() {
+        let html = "<p>a <span class=\"hl\">b <font>c <span class=\"hl\">d</span></font> e</span>\
+            <span class=\"hl\">f</span><span class=\"hl\">g</span> <b>h</b></p>";
+        let query = Query::new("p")
+            .unwrap_elements("span.hl")
+            .unwrap_elements("font");
+        assert_eq!(run(&query, html), ["<p>a b c d efg <b>h</b></p>\n"]);
+
+        // text nodes stay separate
+        let matches = query.run(html.as_bytes()).unwrap();
+        let p = matches[0].node();
+        assert_eq!(p.children().count(), 9);
+        assert_eq!(p.children().text_nodes().count(), 8);
+    }
+
+    #[test]
+    fn unwrap_only_inside_matches() {
+        let html = "<div><i>a</i></div><i>b</i>";
+        let query = Query::new("div").unwrap_elements("i");
+        assert_eq!(run(&query, html), ["<div>a</div>\n"]);
+        assert!(matches!(
+            Query::new("p").unwrap_elements("<").validate(),
+            Err(Error::InvalidSelector {
+                role: SelectorRole::Unwrap,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn replace_text_in_matches() {
+        let html = "<p title=\"a@b.org\">Mail a@b.org or <b>c@d.org</b>, colour!\
+            <script>x = 'a@b.org'</script><style>/* a@b.org */</style></p><p>e@f.org</p>";
+        let query = Query::new("p:first-child")
+            .replace_text(Regex::new(r"(\w+)@[\w.]+").unwrap(), "$1@…")
+            .replace_text(Regex::new("colou?r").unwrap(), "color");
+        assert_eq!(
+            run(&query, html),
+            ["<p title=\"a@b.org\">Mail a@… or <b>c@…</b>, color!\
+            <script>x = 'a@b.org'</script><style>/* a@b.org */</style></p>\n"]
+        );
+
+        // in order, so later replacements see earlier ones
+        let query = Query::new("p")
+            .replace_text(Regex::new("a").unwrap(), "b")
+            .replace_text(Regex::new("b").unwrap(), "c");
+        assert_eq!(run(&query, "<p>ab</p>"), ["<p>cc</p>\n"]);
+    }
+
+    #[test]
+    fn insert_html_next_to_matches() {
+        let html = "<h2 id=\"intro\">Intro</h2><p>a</p><h2>Usage</h2>";
+        let query = Query::new("h2")
+            .insert_before("<hr>")
+            .insert_after("<a class=\"anchor\" href=\"#{@id}\" title='\"{@id}\"'>#</a> {@id}!");
+        assert_eq!(
+            run(&query, html),
+            ["<html><head></head><body><hr><h2 id=\"intro\">Intro</h2>\
+                <a class=\"anchor\" href=\"#intro\" title=\"&quot;intro&quot;\">#</a> intro!\
+                <p>a</p><hr><h2>Usage</h2><a class=\"anchor\" href=\"#\" title=\"&quot;&quot;\">#</a> !\
+                </body></html>\n"]
+        );
+
+        // parsed in the context of the parent, and inserted nodes aren't matched themselves
+        let query = Query::new("td").insert_after("<td>{@class}</td>");
+        assert_eq!(
+            run(&query, "<table><tr><td class=\"x\">a</td></tr></table>"),
+            [
+                "<html><head></head><body><table><tbody><tr><td class=\"x\">a</td><td>x</td>\
+            </tr></tbody></table></body></html>\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn wrap_matches() {
+        let html = "<p><img src=\"a.png\"><img src=\"b.png\"></p>";
+        let query = Query::new("img").wrap(Wrapper::parse("figure class=\"photo\"").unwrap());
+        assert_eq!(
+            run(&query, html),
+            [
+                "<figure class=\"photo\"><img src=\"a.png\"></figure>\n",
+                "<figure class=\"photo\"><img src=\"b.png\"></figure>\n"
+            ]
+        );
+
+        let query = Query::new("li").wrap(Wrapper::parse("b").unwrap());
+        assert_eq!(
+            run(&query.clone().inner(true), "<li>a <i>b</i></li>"),
+            ["<b>a <i>b</i></b>\n"]
+        );
+        // the document is edited too
+        assert_eq!(
+            run(&query.set_attribute("class", "x"), "<ul><li>a</li></ul>"),
+            ["<html><head></head><body><ul><b><li class=\"x\">a</li></b></ul></body></html>\n"]
+        );
+    }
+
+    #[test]
+    fn select_comments_in_matches() {
+        let html = "<!-- build 42 --><div><!-- #include header --><div><!-- ab: b --></div></div>\
+            <p><!-- elsewhere --></p>";
+        let query = Query::new("div").select_comments(true);
+        assert_eq!(
+            run(&query.clone(), html),
+            ["<!-- #include header -->\n", "<!-- ab: b -->\n"]
+        );
+
+        let query = query.text_only(true);
+        assert_eq!(run(&query.clone(), html), ["#include header\n", "ab: b\n"]);
+        assert_eq!(
+            run(
+                &query.clone().text_matches(Regex::new("^ab").unwrap()),
+                html
+            ),
+            ["ab: b\n"]
+        );
+
+        let query = Query::xpath("/").select_comments(true).text_only(true);
+        assert_eq!(
+            run(&query.clone().limit(2), html),
+            ["build 42\n", "#include header\n"]
+        );
+    }
+
+    #[test]
+    fn remove_comments_everywhere() {
+        let html = "<!-- top --><div><!-- a --><p>x<!-- b --><b><!-- c --></b><!-- d --></p>\
+            <!--[if IE]><p>old</p><![endif]--><!-- e --></div>";
+        let query = Query::new("div").keep_comments(Comments::None);
+        assert_eq!(run(&query, html), ["<div><p>x<b></b></p></div>\n"]);
+        assert_eq!(
+            run(&query.clone().keep_comments(Comments::Conditional), html),
+            ["<div><p>x<b></b></p><!--[if IE]><p>old</p><![endif]--></div>\n"]
+        );
+        assert_eq!(
+            run(&query.clone().inner(true).invert(true), html),
+            ["<html><head></head><body></body></html>\n"]
+        );
+        assert!(
+            run(
+                &Query::xpath("//comment()").keep_comments(Comments::None),
+                html
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn sanitize_matches() {
+        let html = "<div onclick=\"f()\"><a href=\"javascript:f()\">a</a><script>f()</script></div>\
+            <form><div>inside</div></form>";
+        let query = Query::new("div").sanitize(true);
+        assert_eq!(run(&query, html), ["<div><a>a</a></div>\n"]);
+        assert_eq!(run(&query.text_only(true), html), ["a\n"]);
+    }
+
+    #[test]
+    fn strip_attributes_only_in_matches() {
+        let html = "<div class=\"outer\"><article class=\"a\" style=\"s\" data-id=\"1\">\
+            <a href=\"/x\" onclick=\"f()\" class=\"l\">x</a></article></div>";
+        let query = Query::new("article")
+            .strip_attribute("class")
+            .strip_attribute("on*")
+            .strip_attribute("data-*")
+            .strip_attribute("style");
+        assert_eq!(
+            run(&query, html),
+            ["<article><a href=\"/x\">x</a></article>\n"]
+        );
+
+        // the rest of the document is left alone, and attributes are set after stripping
+        let query = query.set_attribute("class", "card");
+        assert_eq!(
+            run(&query, html),
+            [
+                "<html><head></head><body><div class=\"outer\"><article class=\"card\">\
+                <a href=\"/x\">x</a></article></div></body></html>\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn inner_skips_own_tags() {
+        let html = "<div class=\"content\">a <!--c--><b>b</b></div><div></div>";
+        let query = Query::new("div").inner(true);
+        assert_eq!(run(&query, html), ["a <!--c--><b>b</b>\n", "\n"]);
+        assert_eq!(
+            run(&query.pretty(true), "<div><p>a</p></div>"),
+            ["\n<p>a\n</p>\n"]
+        );
+    }
+
+    #[test]
+    fn matches_are_sorted() {
+        let html = "<a data-n=\"10\">  b  x</a><a data-n=\"9\">a</a><a data-n=\"n/a\">b x</a>\
+            <a>c</a><a data-n=\"10\">d</a><a data-n=\"-1.5\">e</a>";
+        let sorted = |key: &str| {
+            let query = Query::new("a")
+                .sort_by(SortKey::parse(key).unwrap())
+                .text_only(true);
+            run(&query, html).concat()
+        };
+        // equal keys keep document order
+        assert_eq!(sorted("text"), "a\n  b  x\nb x\nc\nd\ne\n");
+        assert_eq!(sorted("text:desc"), "e\nd\nc\n  b  x\nb x\na\n");
+        assert_eq!(sorted("@data-n"), "c\ne\n  b  x\nd\na\nb x\n");
+        assert_eq!(sorted("@data-n:numeric"), "e\na\n  b  x\nd\nb x\nc\n");
+        assert_eq!(sorted("@data-n:numeric:desc"), "  b  x\nd\na\ne\nb x\nc\n");
+
+        // before picking matches
+        let query = Query::new("a")
+            .sort_by(SortKey::parse("text:desc").unwrap())
+            .limit(2);
+        assert_eq!(
+            run(&query, html),
+            ["<a data-n=\"-1.5\">e</a>\n", "<a data-n=\"10\">d</a>\n"]
+        );
+    }
+
+    #[test]
+    fn only_outermost_matches() {
+        let html = "<div id=\"a\"><div id=\"b\"><div id=\"c\">x</div></div></div>\
+            <section><div id=\"d\"><p><div id=\"e\"></div></p></div></section>\
+            <div id=\"f\">y</div>";
+        let ids = |query: Query| -> Vec<String> {
+            let matches = query.outermost(true).run(html.as_bytes()).unwrap();
+            matches
+                .iter()
+                .map(|matched| matched.node().as_element().unwrap().attributes.borrow())
+                .map(|attributes| attributes.get("id").unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(ids(Query::new("div")), ["a", "d", "f"]);
+        assert_eq!(ids(Query::new("div div")), ["b", "e"]);
+        assert_eq!(ids(Query::xpath("//div[@id != 'a']")), ["b", "d", "f"]);
+        // only yielded matches hide those inside them
+        assert_eq!(
+            ids(Query::new("div").text_matches(Regex::new("x").unwrap())),
+            ["a"]
+        );
+        assert_eq!(
+            ids(Query::new("div").text_not_matches(Regex::new("x").unwrap())),
+            ["d", "f"]
+        );
+    }
+
+    #[test]
+    fn only_innermost_matches() {
+        let html = "<div id=\"a\"><div id=\"b\"><div id=\"c\">x</div></div><div id=\"d\"></div></div>\
+            <section><div id=\"e\"><p><div id=\"f\"></div><div id=\"g\"></div></p></div></section>\
+            <div id=\"h\">y</div>";
+        let ids = |query: Query| -> Vec<String> {
+            let matches = query.innermost(true).run(html.as_bytes()).unwrap();
+            matches
+                .iter()
+                .map(|matched| matched.node().as_element().unwrap().attributes.borrow())
+                .map(|attributes| attributes.get("id").unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(ids(Query::new("div")), ["c", "d", "f", "g", "h"]);
+        assert_eq!(ids(Query::new("#a, #b, #e, #h")), ["b", "e", "h"]);
+        assert_eq!(ids(Query::xpath("//div[@id = 'a' or @id = 'd']")), ["d"]);
+        assert_eq!(
+            ids(Query::new("div").outermost(true)),
+            ["c", "d", "f", "g", "h"]
+        );
+    }
+
+    #[test]
+    fn matches_are_reversed() {
+        let html = "<li>1</li><li>2<i>x</i></li><li>3</li><li>4</li>";
+        let query = Query::new("li").remove("i").reverse(true).text_only(true);
+        assert_eq!(run(&query.clone().limit(3), html), ["4\n", "3\n", "2\n"]);
+        assert_eq!(run(&query.clone().nth(-1), html), ["1\n"]);
+        let sorted = query.sort_by(SortKey::parse("text").unwrap());
+        assert_eq!(run(&sorted, html), ["4\n", "3\n", "2\n", "1\n"]);
+    }
+
+    #[test]
+    fn grouped_matches_are_separated() {
+        let html =
+            "<ul><li><a>1</a><a>2</a></li><li>none</li><li><i><a>3</a></i></li><li></li></ul>";
+        let write = |query: Query| {
+            let mut output = Vec::new();
+            let document = Document::parse(&mut html.as_bytes()).unwrap();
+            let count = query.write(&document, &mut output).unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+
+        let query = Query::new("li").then_select_grouped("a").text_only(true);
+        // empty groups still get a separator, but there's none after the last group
+        assert_eq!(write(query.clone()), (3, "1\n2\n\n\n3\n\n".to_string()));
+        assert_eq!(
+            write(query.clone().separator("--\n")),
+            (3, "1\n2\n--\n--\n3\n--\n".to_string())
+        );
+        assert_eq!(
+            write(query.clone().limit(1)),
+            (2, "1\n\n\n3\n\n".to_string())
+        );
+
+        // other formats aren't grouped
+        assert_eq!(write(query.format(Format::Count)), (3, "3\n".to_string()));
+        assert_eq!(
+            run(&Query::new("li").then_select_grouped("a"), html),
+            ["<a>1</a>\n", "<a>2</a>\n", "<a>3</a>\n"]
+        );
+    }
+
+    #[test]
+    fn stats_of_all_matches() {
+        let html = "<ul><li>a</li><li><b>b</b></li></ul><ol><li>c</li></ol>";
+        let query = Query::new("ul, ol").stats(true);
+        let mut output = Vec::new();
+        let document = Document::parse(&mut html.as_bytes()).unwrap();
+        assert_eq!(query.write(&document, &mut output).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tag  count  text  attributes  depth\n\
+             li       3     2           0      4\n\
+             b        1     1           0      5\n\
+             ol       1     0           0      3\n\
+             ul       1     0           0      3\n"
+        );
+
+        let mut output = Vec::new();
+        query
+            .format(Format::Json)
+            .write(&document, &mut output)
+            .unwrap();
+        let stats: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            stats[0],
+            serde_json::json!({"tag": "li", "count": 3, "text_length": 2, "attributes": 0, "max_depth": 4})
+        );
+    }
+
+    #[test]
+    fn classes_of_matches() {
+        let html = "<div class=\"card wide\"></div><div></div><div class=\"card\ttall\"></div>";
+        let query = Query::new("div").classes(true);
+        assert_eq!(
+            run(&query.clone(), html),
+            ["card\nwide\n", "", "card\ntall\n"]
+        );
+        assert_eq!(
+            run(&query.clone().unique(true), html),
+            ["card\nwide\n", "tall\n"]
+        );
+        assert_eq!(run(&query.limit(1), html), ["card\nwide\n"]);
+    }
+
+    #[test]
+    fn tag_names_skip_non_elements() {
+        let html = "<main><svg><foreignObject></foreignObject></svg>text<p></p></main>";
+        assert_eq!(
+            run(&Query::new("main *").tag_names(true), html),
+            ["svg\n", "foreignobject\n", "p\n"]
+        );
+        assert_eq!(
+            run(&Query::xpath("//main/node()").tag_names(true), html),
+            ["svg\n", "", "p\n"]
+        );
+    }
+
+    #[test]
+    fn css_path_round_trips() {
+        let html = "<div id=\"content\"><ul><li>1</li><li>2</li><li><a>3</a></li></ul>\
+            <p id=\"x\"></p><p id=\"x\"></p><p id=\"1 a\"></p><svg><foreignObject/></svg></div>";
+        let matches = Query::new("*").run(html.as_bytes()).unwrap();
+        let paths: Vec<_> = matches.iter().filter_map(Match::css_path).collect();
+
+        assert_eq!(paths.len(), 14);
+        assert!(
+            paths.contains(&"html > body > div#content > ul > li:nth-child(3) > a".to_string())
+        );
+        assert!(paths.contains(&"html > body > div#content > p#x:nth-child(3)".to_string()));
+        for (path, expected) in paths.iter().zip(&matches) {
+            let found = Query::new(path).run(html.as_bytes()).unwrap();
+            assert_eq!(found.len(), 1, "{path}");
+            assert_eq!(found[0].html(), expected.html(), "{path}");
+        }
+    }
+
+    #[test]
+    fn with_position_prefixes_matches() {
+        let html = "<ul>\n  <li>a</li><li>b</li>\n</ul>";
+        let query = Query::new("li").text_only(true).with_position(true);
+        assert_eq!(run(&query, html), ["2:3:a\n", "2:13:b\n"]);
+        let query = Query::new("li").tag_names(true).with_position(true);
+        assert_eq!(run(&query, html), ["2:3:li\n", "2:13:li\n"]);
+    }
+
+    #[test]
+    fn template_strictness() {
+        let html = "<a href=\"/a\">A</a><a>B</a>";
+        let template = Template::parse("{@href}\\t{text}").unwrap();
+        let query = Query::new("a").template(template);
+        assert_eq!(run(&query, html), ["/a\tA\n", "\tB\n"]);
+
+        let mut reader = html.as_bytes();
+        let document = Document::parse(&mut reader).unwrap();
+        let mut output = Vec::new();
+        assert!(matches!(
+            query.strict_template(true).write(&document, &mut output),
+            Err(Error::MissingAttribute { name }) if name == "href"
+        ));
+        assert_eq!(output, b"/a\tA\n");
+    }
+
+    #[test]
+    fn links_inside_matches_are_resolved() {
+        let html = "<nav><a href=\"/a\">A</a></nav><main><a href=\"b\">  B\n b </a></main>";
+        let query = Query::new("main, nav").links(LinkKinds::Navigation);
+        assert_eq!(run(&query, html), ["/a\tA\n", "b\tB b\n"]);
+        let query = query.base(Url::parse("https://example.org/x/").unwrap());
+        assert_eq!(
+            run(&query, html),
+            [
+                "https://example.org/a\tA\n",
+                "https://example.org/x/b\tB b\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn detected_base_is_combined_with_explicit_one() {
+        let href = |head: &str, base: Option<&str>| {
+            let html = format!("<head>{head}</head><a href=\"x\">x</a>");
+            let mut query = Query::new("a")
+                .detect_base(true)
+                .prefer_detected_base(true)
+                .attribute("href");
+            if let Some(base) = base {
+                query = query.base(Url::parse(base).unwrap());
+            }
+            query.run(html.as_bytes()).unwrap()[0].render()
+        };
+        let base = Some("https://example.org/docs/intro.html");
+        let absolute = "<base href=\"https://cdn.example.net/\">";
+        let relative = "<base href=\"v2/\">";
+
+        assert_eq!(href(absolute, base), "https://cdn.example.net/x\n");
+        assert_eq!(href(absolute, None), "https://cdn.example.net/x\n");
+        assert_eq!(href(relative, base), "https://example.org/docs/v2/x\n");
+        assert_eq!(href(relative, None), "x\n");
+        assert_eq!(href("", base), "https://example.org/docs/x\n");
+        assert_eq!(href("", None), "x\n");
+    }
+
+    #[test]
+    fn explicit_base_wins_over_detected_one() {
+        let html = "<head><base href=\"https://bogus.example/\"></head><a href=\"x\">x</a>";
+        let href = |query: Query| {
+            // queries rewrite the links in the document, so each needs its own
+            let document = Document::parse(&mut html.as_bytes())
+                .unwrap()
+                .with_url(Some(Url::parse("https://example.org/page").unwrap()));
+            let mut output = Vec::new();
+            query
+                .attribute("href")
+                .write(&document, &mut output)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let explicit = Url::parse("https://explicit.example/").unwrap();
+
+        assert_eq!(
+            href(Query::new("a").detect_base(true).base(explicit.clone())),
+            "https://explicit.example/x\n"
+        );
+        assert_eq!(
+            href(Query::new("a").detect_base(true)),
+            "https://bogus.example/x\n"
+        );
+        assert_eq!(href(Query::new("a")), "https://example.org/x\n");
+    }
+
+    #[test]
+    fn attributes_fall_back_to_text() {
+        let html = "<nav><a href=\"/a\">A</a><span> Just   a label </span>\
+            <a title=\"B\">B</a><a href=\"/c\">C</a></nav>";
+        let query = Query::new("nav > *")
+            .attribute("href")
+            .attribute_fallback_text(true)
+            .collapse_whitespace(true);
+        let rendered: Vec<_> = query
+            .run(html.as_bytes())
+            .unwrap()
+            .iter()
+            .map(Match::render)
+            .collect();
+        assert_eq!(rendered, ["/a\n", "Just a label\n", "B\n", "/c\n"]);
+
+        let query = Query::new("nav > *")
+            .attribute("href")
+            .attribute("title")
+            .attribute_missing(Some("-".to_string()))
+            .attribute_fallback_text(true);
+        let rendered: Vec<_> = query
+            .run(html.as_bytes())
+            .unwrap()
+            .iter()
+            .map(Match::render)
+            .collect();
+        assert_eq!(
+            rendered,
+            ["/a\n-\n", " Just   a label \n", "-\nB\n", "/c\n-\n"]
+        );
+    }
+
+    #[test]
+    fn unique_applies_before_limit() {
+        let html =
+            "<a href=\"/1\">1</a><a href=\"/2\">2</a><a href=\"/1\">1</a><a href=\"/3\">3</a>";
+        let query = Query::new("a").attribute("href").unique(true);
+        assert_eq!(run(&query, html), ["/1\n", "/2\n", "/3\n"]);
+        assert_eq!(run(&query.limit(3), html), ["/1\n", "/2\n", "/3\n"]);
+
+        let query = Query::new(":root")
+            .links(LinkKinds::Navigation)
+            .unique(true);
+        assert_eq!(run(&query, html), ["/1\t1\n/2\t2\n/3\t3\n"]);
+        let query = Query::new("a").unique(true);
+        assert_eq!(run(&query, html).len(), 3);
+    }
+
+    #[test]
+    fn tables_are_separated() {
+        let html = "<table><tr><td>1</td></tr></table><p></p>\
+            <table><tr><th>a, b</th><td>2</td></tr></table>";
+        let mut reader = html.as_bytes();
+        let document = Document::parse(&mut reader).unwrap();
+        let mut output = Vec::new();
+        let query = Query::new("table, p").table_csv(true);
+        assert_eq!(query.write(&document, &mut output).unwrap(), 3);
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n\n\"a, b\",2\n");
+    }
+
+    #[test]
+    fn table_json_outputs_one_line_per_table() {
+        let html = "<table><tr><th>a</th></tr><tr><td>1</td></tr></table>\
+            <table><tr><td>b</td></tr></table>";
+        let query = Query::new(":root").table_json(true);
+        assert_eq!(run(&query, html), ["[{\"a\":\"1\"}]\n[]\n"]);
+    }
+
+    #[test]
+    fn meta_is_resolved_and_json() {
+        let html = "<head><title>T</title><link rel=\"canonical\" href=\"/page\"></head>";
+        let query = Query::new(":root")
+            .meta(true)
+            .base(Url::parse("https://example.org").unwrap());
+        assert_eq!(
+            run(&query, html),
+            ["title\tT\ncanonical\thttps://example.org/page\n"]
+        );
+
+        let mut reader = html.as_bytes();
+        let document = Document::parse(&mut reader).unwrap();
+        let mut output = Vec::new();
+        Query::new("head")
+            .meta(true)
+            .format(Format::Json)
+            .write(&document, &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"key\":\"title\",\"value\":\"T\"},{\"key\":\"canonical\",\"value\":\"/page\"}]\n"
+        );
+    }
+
+    #[test]
+    fn json_ld_blocks_are_validated() {
+        let html = "<script type=\"application/ld+json\">{\"a\": 1}</script>\
+            <script type=\"application/ld+json\">{oops</script>";
+        let write = |format| {
+            let mut reader = html.as_bytes();
+            let document = Document::parse(&mut reader).unwrap();
+            let mut output = Vec::new();
+            Query::new(":root")
+                .json_ld(true)
+                .format(format)
+                .write(&document, &mut output)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(write(Format::Plain), "{\"a\": 1}\n{oops\n");
+        assert_eq!(write(Format::Json), "[{\"a\":1}]\n");
+    }
+
+    #[test]
+    fn markdown_links_are_resolved() {
+        let html = "<main><p>See <a href=\"/docs\">the docs</a>.</p></main>";
+        let query = Query::new("main")
+            .markdown(true)
+            .base(Url::parse("https://example.org").unwrap());
+        assert_eq!(
+            run(&query, html),
+            ["See [the docs](https://example.org/docs).\n"]
+        );
+    }
+
+    #[test]
+    fn text_breaks_lines_at_blocks() {
+        let html = "<div>\n  <p>one</p>\n  <p>two <b>and</b><br>three</p><ul><li>a</li><li>b</li></ul>\n</div>";
+        let query = Query::new("div").text_only(true);
+        assert_eq!(run(&query, html), ["one\ntwo and\nthree\na\nb\n"]);
+        assert_eq!(
+            run(&query.clone().ignore_whitespace(true), html),
+            ["one\ntwo and\nthree\na\nb\n"]
+        );
+        assert_eq!(
+            run(&query.clone().raw_text(true), html),
+            ["\n  one\n  two andthreeab\n\n"]
+        );
+        assert_eq!(
+            run(&query.raw_text(true).ignore_whitespace(true), html),
+            ["one\ntwo \nand\nthree\na\nb\n\n"]
+        );
+    }
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        let html = "<div>\n  <p>  one\n   <b>two</b> <i>three</i>  </p>\
+            <pre>  keep\n   this </pre><p>a <code>x  y</code><br>  b</p>\n</div>";
+        let query = Query::new("div").text_only(true).collapse_whitespace(true);
+        assert_eq!(
+            run(&query.clone(), html),
+            ["one two three\n  keep\n   this \na x  y\nb\n"]
+        );
+        assert_eq!(
+            run(&query.ignore_whitespace(true), html),
+            ["one twothree\n  keep\n   this \na x  y\nb\n"]
+        );
+    }
+
+    #[test]
+    fn separator_goes_between_matches() {
+        let html = "<p>a\nb</p><p></p><p>c</p>";
+        let write = |separator: &str| {
+            let mut reader = html.as_bytes();
+            let document = Document::parse(&mut reader).unwrap();
+            let mut output = Vec::new();
+            let query = Query::new("p").text_only(true).separator(separator);
+            let count = query.write(&document, &mut output).unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+        assert_eq!(write("\n---\n"), (3, "a\nb\n---\nc".to_string()));
+        assert_eq!(write(""), (3, "a\nbc".to_string()));
+    }
+
+    #[test]
+    fn each_match_gets_its_own_writer() {
+        let html = "<p id=\"a\">one</p><p>two</p>";
+        let document = Document::parse(&mut html.as_bytes()).unwrap();
+        /// Appends to the last of the outputs.
+        struct Output(Rc<std::cell::RefCell<Vec<String>>>);
+        impl io::Write for Output {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let mut outputs = self.0.borrow_mut();
+                outputs
+                    .last_mut()
+                    .unwrap()
+                    .push_str(str::from_utf8(buf).unwrap());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let outputs = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let count = Query::new("p")
+            .write_each(&document, |matched| {
+                let id = matched
+                    .node()
+                    .as_element()
+                    .unwrap()
+                    .attributes
+                    .borrow()
+                    .get("id")
+                    .map(String::from);
+                outputs.borrow_mut().push(format!("{id:?} "));
+                Ok::<_, Error>(Output(Rc::clone(&outputs)))
+            })
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(
+            *outputs.borrow(),
+            ["Some(\"a\") <p id=\"a\">one</p>\n", "None <p>two</p>\n"]
+        );
+    }
+
+    #[test]
+    fn base_of_document_is_used() {
+        let query = Query::new("a");
+        let mut html = "<a href=\"/foo\">Hello</a>".as_bytes();
+        let url = Url::parse("https://example.org/page").unwrap();
+        let document = Document::parse(&mut html).unwrap().with_url(Some(url));
+
+        let rendered: Vec<_> = query
+            .select(&document)
+            .unwrap()
+            .map(|m| m.render())
+            .collect();
+        assert_eq!(
+            rendered,
+            ["<a href=\"https://example.org/foo\">Hello</a>\n"]
+        );
+    }
+
+    #[test]
+    fn xpath_goes_through_pipeline() {
+        let html = "<dl><dt>Price</dt><dd>5 <script>x</script></dd><dt>Size</dt><dd>L</dd></dl>";
+        let query = Query::xpath("//dt[. = 'Price']/following-sibling::dd[1]").remove("script");
+        assert_eq!(run(&query, html), ["<dd>5 </dd>\n"]);
+        assert!(matches!(
+            Query::xpath("//dd[").validate(),
+            Err(Error::InvalidXPath { error, .. }) if error.position == 5
+        ));
+    }
+
+    #[test]
+    fn invalid_selectors_are_reported() {
+        assert!(matches!(
+            Query::new("div[").validate(),
+            Err(Error::InvalidSelector {
+                role: SelectorRole::Main,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Query::new("div").remove("a").remove("p >> a").validate(),
+            Err(Error::InvalidSelector { selector, role: SelectorRole::Removal }) if selector == "p >> a"
+        ));
+    }
+
+    #[test]
+    fn write_formats() {
+        let html = "<li>1</li><li>2</li>";
+        let write = |query: Query| {
+            let mut content = Vec::new();
+            let mut reader = html.as_bytes();
+            let document = Document::parse(&mut reader).unwrap();
+            let count = query.write(&document, &mut content).unwrap();
+            (count, String::from_utf8(content).unwrap())
+        };
+
+        assert_eq!(
+            write(Query::new("li").format(Format::Count)),
+            (2, "2\n".to_string())
+        );
+        assert_eq!(
+            write(Query::new("li").text_only(true).format(Format::Ndjson)),
+            (2, "{\"text\":\"1\"}\n{\"text\":\"2\"}\n".to_string())
+        );
+    }
+}
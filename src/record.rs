@@ -0,0 +1,134 @@
+//! Extracting records out of matches, with one value per named field, see [`Query::field`].
+//!
+//! [`Query::field`]: crate::Query::field
+
+use crate::css::Selector;
+use crate::text::{self, TextOptions};
+use kuchikiki::NodeRef;
+use kuchikiki::traits::NodeIterator;
+use std::sync::Arc;
+
+/// A named value of each record, given like `title=.title` or `url=a@href`.
+#[derive(Debug, Clone)]
+pub struct Field {
+    name: String,
+    /// What the value is taken from, or the record itself if `None`.
+    selector: Option<Arc<Selector>>,
+    /// The attribute holding the value, or `None` for the text.
+    attribute: Option<String>,
+}
+
+impl Field {
+    /// Parses `name=selector`, optionally followed by `@attribute`.
+    ///
+    /// The selector can be left out to take the value from the record itself, like `id=@id`.
+    /// An `@` is only taken as start of the attribute if what follows is a valid attribute name,
+    /// so `a[title="@x"]` is just a selector.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let Some((name, source)) = raw.split_once('=') else {
+            return Err(format!(
+                "expected `NAME=SELECTOR` or `NAME=SELECTOR@ATTR`, got `{raw}`"
+            ));
+        };
+        if name.is_empty() {
+            return Err("the field name is empty".to_string());
+        }
+
+        let (selector, attribute) = match source.rsplit_once('@') {
+            Some((selector, attribute)) if is_attribute_name(attribute) => {
+                (selector.trim(), Some(attribute.to_ascii_lowercase()))
+            }
+            _ => (source.trim(), None),
+        };
+        let selector = match selector {
+            "" if attribute.is_none() => {
+                return Err(format!(
+                    "the field `{name}` lacks a selector and an attribute"
+                ));
+            }
+            "" => None,
+            _ => Some(Arc::new(Selector::compile(selector).map_err(|()| {
+                format!("invalid CSS selector `{selector}` for the field `{name}`")
+            })?)),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            selector,
+            attribute,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The values of the field in the record, in document order.
+    ///
+    /// Text is trimmed. Elements lacking the attribute have no value.
+    pub(crate) fn values(&self, record: &NodeRef, options: &TextOptions) -> Vec<String> {
+        let nodes: Vec<NodeRef> = match &self.selector {
+            Some(selector) => record
+                .descendants()
+                .elements()
+                .filter(|elem| selector.matches(elem))
+                .map(|elem| elem.as_node().clone())
+                .collect(),
+            None => vec![record.clone()],
+        };
+        nodes
+            .iter()
+            .filter_map(|node| match &self.attribute {
+                Some(attribute) => {
+                    let elem = node.as_element()?;
+                    let value = elem
+                        .attributes
+                        .borrow()
+                        .get(attribute.as_str())?
+                        .to_string();
+                    Some(value)
+                }
+                None => Some(text::extract(node, options).trim().to_string()),
+            })
+            .collect()
+    }
+}
+
+fn is_attribute_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn values(field: &str, html: &str) -> Vec<String> {
+        let doc = kuchikiki::parse_html().one(html);
+        let record = doc.select_first("div").unwrap();
+        Field::parse(field)
+            .unwrap()
+            .values(record.as_node(), &TextOptions::default())
+    }
+
+    #[test]
+    fn values_are_taken_from_matching_descendants() {
+        let html = "<div id=\"r\"><h2 class=\"title\">\n  A <i>b</i>\n</h2>\
+            <a href=\"/1\">x</a><a>y</a><a href=\"/2\" title=\"@z\">z</a></div>";
+        assert_eq!(values("title=.title", html), ["A b"]);
+        assert_eq!(values("url=a@href", html), ["/1", "/2"]);
+        assert_eq!(values("id=@id", html), ["r"]);
+        assert_eq!(values("named=a[title=\"@z\"]", html), ["z"]);
+        assert_eq!(values("price=.price", html), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        for field in ["title", "=.title", "title=", "title=a[", "x=@"] {
+            assert!(Field::parse(field).is_err(), "{field:?}");
+        }
+    }
+}
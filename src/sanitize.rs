@@ -0,0 +1,191 @@
+//! Making HTML safe to embed in other pages, by removing everything that can run code.
+
+use kuchikiki::NodeRef;
+use kuchikiki::traits::NodeIterator;
+
+/// How [`sanitize`] cleans up nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SanitizeOptions {
+    /// Keep `style` attributes, which can't run code but can make content look like
+    /// the page around it.
+    pub keep_style: bool,
+}
+
+/// Elements that are removed along with everything in them.
+///
+/// Besides the ones running code, that's those that can affect the page around them, like by
+/// redirecting it, and SVG animations, which can set attributes to `javascript:` URLs.
+const REMOVED_ELEMENTS: &[&str] = &[
+    "script", "iframe", "object", "embed", "form", "base", "meta", "link", "style", "animate",
+    "set",
+];
+
+/// Attributes holding URLs, which are removed if they'd run code.
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "action"];
+
+/// Removes everything from the node that can run code, submit data or change the page around it:
+/// the [`REMOVED_ELEMENTS`] like `<script>` and `<form>`, event handler attributes like `onclick`
+/// and URLs in `href`, `src` and `action` with the `javascript:`, `vbscript:` or `data:` scheme,
+/// except for `data:image/*`. `style` attributes are removed too, unless
+/// [kept](SanitizeOptions::keep_style).
+///
+/// The node itself is only cleaned up, not removed, even if it's one of those elements.
+pub fn sanitize(node: &NodeRef, options: &SanitizeOptions) {
+    // collect first, detaching while traversing would cut the traversal short
+    let removed: Vec<_> = node.descendants().filter(is_removed_element).collect();
+    for descendant in removed {
+        descendant.detach();
+    }
+
+    for elem in node.inclusive_descendants().elements() {
+        elem.attributes
+            .borrow_mut()
+            .map
+            .retain(|name, attr| keeps_attribute(&name.local, &attr.value, options));
+    }
+}
+
+/// If the node is one of the elements removed by [`sanitize`], or inside one.
+pub(crate) fn is_removed(node: &NodeRef) -> bool {
+    node.inclusive_ancestors()
+        .any(|ancestor| is_removed_element(&ancestor))
+}
+
+fn is_removed_element(node: &NodeRef) -> bool {
+    node.as_element()
+        .is_some_and(|elem| REMOVED_ELEMENTS.contains(&&*elem.name.local))
+}
+
+fn keeps_attribute(name: &str, value: &str, options: &SanitizeOptions) -> bool {
+    let name = name.to_ascii_lowercase();
+    if name.starts_with("on") {
+        return false;
+    }
+    if name == "style" {
+        return options.keep_style;
+    }
+    if URL_ATTRIBUTES.contains(&name.as_str()) {
+        return is_safe_url(value);
+    }
+    true
+}
+
+/// If the URL doesn't use the `javascript:`, `vbscript:` or `data:` scheme, or is a `data:image/*`
+/// URL.
+///
+/// The scheme is read like browsers do, which ignore leading spaces and control characters,
+/// tabs and line breaks anywhere and case, so `" Java\tScript:"` is still `javascript:`.
+fn is_safe_url(url: &str) -> bool {
+    let normalized: String = url
+        .trim_start_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let Some((scheme, rest)) = normalized.split_once(':') else {
+        return true;
+    };
+    match scheme {
+        "javascript" | "vbscript" => false,
+        "data" => rest.trim_start().starts_with("image/"),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn sanitized(html: &str, options: &SanitizeOptions) -> String {
+        let doc = kuchikiki::parse_html().one(html);
+        let body = doc.select_first("body").unwrap();
+        sanitize(body.as_node(), options);
+        body.as_node()
+            .children()
+            .map(|child| child.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn code_is_removed() {
+        let html = "<div onclick=\"steal()\" style=\"color: red\" class=\"card\">\
+            <script>steal()</script><p OnMouseOver=\"steal()\">text</p>\
+            <iframe src=\"/x\"></iframe><object data=\"x\"></object><embed src=\"x\">\
+            <form action=\"/login\"><input name=\"password\"></form>\
+            <svg><script>steal()</script></svg></div>";
+        assert_eq!(
+            sanitized(html, &SanitizeOptions::default()),
+            "<div class=\"card\"><p>text</p><svg></svg></div>"
+        );
+        assert_eq!(
+            sanitized(html, &SanitizeOptions { keep_style: true }),
+            "<div style=\"color: red\" class=\"card\"><p>text</p><svg></svg></div>"
+        );
+    }
+
+    #[test]
+    fn unsafe_urls_are_removed() {
+        for url in [
+            "javascript:steal()",
+            "JAVASCRIPT:steal()",
+            "java\tscript:steal()",
+            "java\nscript:steal()",
+            " \u{1}javascript:steal()",
+            "data:text/html,<script>steal()</script>",
+            "DATA:text/html;base64,PHNjcmlwdD4=",
+            "vbscript:msgbox(1)",
+            "VBScript:msgbox(1)",
+        ] {
+            assert!(!is_safe_url(url), "{url:?}");
+        }
+        for url in [
+            "https://example.com/a?b=javascript:",
+            "/relative:path",
+            "page.html",
+            "#javascript:",
+            "mailto:someone@example.com",
+            "data:image/png;base64,iVBORw0KGgo=",
+            "DATA:IMAGE/GIF;base64,R0lGOD==",
+        ] {
+            assert!(is_safe_url(url), "{url:?}");
+        }
+
+        assert_eq!(
+            sanitized(
+                "<a href=\"java&#x09;script:steal()\">a</a>\
+                <img src=\"data:image/png;base64,iVBORw0KGgo=\" alt=\"x\">\
+                <a href=\"/fine\">b</a>",
+                &SanitizeOptions::default()
+            ),
+            "<a>a</a><img src=\"data:image/png;base64,iVBORw0KGgo=\" alt=\"x\"><a href=\"/fine\">b</a>"
+        );
+    }
+
+    #[test]
+    fn page_altering_elements_are_removed() {
+        // in the body, so they're not moved into the head
+        let html = "<p>text</p><base href=\"https://evil.example/\">\
+            <meta http-equiv=\"refresh\" content=\"0; url=https://evil.example/\">\
+            <link rel=\"stylesheet\" href=\"/evil.css\"><style>body { display: none }</style>";
+        assert_eq!(sanitized(html, &SanitizeOptions::default()), "<p>text</p>");
+    }
+
+    #[test]
+    fn svg_animations_are_removed() {
+        let html = "<svg><a><animate attributeName=\"href\" values=\"javascript:steal()\"/>\
+            <set attributeName=\"href\" to=\"javascript:steal()\"/>\
+            <text>a</text></a></svg>";
+        assert_eq!(
+            sanitized(html, &SanitizeOptions::default()),
+            "<svg><a><text>a</text></a></svg>"
+        );
+    }
+
+    #[test]
+    fn removed_elements_include_their_descendants() {
+        let doc = kuchikiki::parse_html().one("<form><label>name</label></form><p>text</p>");
+        assert!(is_removed(doc.select_first("label").unwrap().as_node()));
+        assert!(!is_removed(doc.select_first("p").unwrap().as_node()));
+    }
+}
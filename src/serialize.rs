@@ -0,0 +1,323 @@
+//! Serializing nodes as HTML, with some control over how the markup is written.
+
+use html5ever::QualName;
+use html5ever::serialize::{
+    AttrRef, HtmlSerializer, Serialize, SerializeOpts, Serializer, TraversalScope,
+};
+use kuchikiki::NodeRef;
+use std::io::{self, Write};
+
+/// How [`serialize_with`] and pretty printing write the markup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SerializeOptions {
+    /// In which order the attributes of each element are written.
+    pub attribute_order: AttributeOrder,
+    /// Which comments are written.
+    pub comments: Comments,
+    /// Color tag names, attribute names, attribute values and comments with ANSI escape codes,
+    /// for reading the markup in a terminal. Text keeps the default color.
+    pub color: bool,
+}
+
+/// The ANSI escape codes for the parts of the markup, see [`SerializeOptions::color`].
+pub(crate) mod palette {
+    pub const TAG: &[u8] = b"\x1b[34m";
+    pub const ATTRIBUTE: &[u8] = b"\x1b[36m";
+    pub const VALUE: &[u8] = b"\x1b[32m";
+    pub const COMMENT: &[u8] = b"\x1b[90m";
+    pub const DIM: &[u8] = b"\x1b[2m";
+    pub const RESET: &[u8] = b"\x1b[0m";
+}
+
+/// Which comments are written, see [`SerializeOptions::comments`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Comments {
+    #[default]
+    All,
+    /// Only conditional comments like `<!--[if IE]>`, which can contain markup.
+    Conditional,
+    None,
+}
+
+impl Comments {
+    /// If the comment with this text is to be written.
+    pub(crate) fn keeps(self, text: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Conditional => is_conditional(text),
+            Self::None => false,
+        }
+    }
+}
+
+/// If the comment is (part of) a conditional comment like `<!--[if IE]>…<![endif]-->`,
+/// including the `<!--[if !IE]>-->` and `<!--<![endif]-->` around markup for other browsers.
+fn is_conditional(text: &str) -> bool {
+    let text = text.trim();
+    text.starts_with("[if ") || text.starts_with("<![endif]") || text.ends_with("<![endif]")
+}
+
+/// In which order attributes are written, see [`SerializeOptions::attribute_order`].
+///
+/// Only the output is affected, the attributes of the elements themselves stay as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AttributeOrder {
+    /// As they are in the input.
+    #[default]
+    Source,
+    /// By name, and then by namespace for names like `xlink:href` and `href`.
+    Sorted,
+    /// Like [`AttributeOrder::Sorted`], except that `id` and `class` come first.
+    Smart,
+}
+
+/// Serializes the node and its descendants as HTML, like [`NodeRef::to_string`] but as
+/// configured by the options.
+pub fn serialize_with(node: &NodeRef, options: &SerializeOptions) -> String {
+    serialize_scope(node, TraversalScope::IncludeNode, options)
+}
+
+/// Like [`serialize_with`], but only serializes the children of the node.
+pub fn serialize_children_with(node: &NodeRef, options: &SerializeOptions) -> String {
+    serialize_scope(node, TraversalScope::ChildrenOnly(None), options)
+}
+
+fn serialize_scope(node: &NodeRef, scope: TraversalScope, options: &SerializeOptions) -> String {
+    let mut serializer = Configured {
+        options,
+        inner: HtmlSerializer::new(
+            Vec::new(),
+            SerializeOpts {
+                traversal_scope: scope.clone(),
+                ..Default::default()
+            },
+        ),
+    };
+    Serialize::serialize(node, &mut serializer, scope).unwrap();
+    String::from_utf8(serializer.inner.writer).unwrap()
+}
+
+/// Colors what was written since `start`, if anything.
+pub(crate) fn paint(writer: &mut Vec<u8>, start: usize, color: &[u8]) {
+    if writer.len() > start {
+        writer.splice(start..start, color.iter().copied());
+        writer.extend_from_slice(palette::RESET);
+    }
+}
+
+/// Like [`paint`], but for a whole string and only if coloring is enabled.
+pub(crate) fn painted(text: String, color: &[u8], options: &SerializeOptions) -> String {
+    if !options.color {
+        return text;
+    }
+    let mut painted = text.into_bytes();
+    paint(&mut painted, 0, color);
+    String::from_utf8(painted).unwrap()
+}
+
+/// How many columns the text takes up in a terminal, not counting ANSI escape codes,
+/// with each character counting as one.
+pub(crate) fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => escaped = true,
+            'm' if escaped => escaped = false,
+            _ if escaped => {}
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+/// Serializes the attribute like `name="value"`, escaped as html5ever does in start tags.
+pub(crate) fn serialize_attribute(elem: &QualName, attr: AttrRef<'_>) -> io::Result<Vec<u8>> {
+    let mut scratch = HtmlSerializer::new(Vec::new(), SerializeOpts::default());
+    scratch.start_elem(elem.clone(), std::iter::once(attr))?;
+    // strip `<name ` and `>`
+    let tag = scratch.writer;
+    let separator = tag.iter().position(|&b| b == b' ').unwrap_or(0);
+    Ok(tag[separator + 1..tag.len() - 1].to_vec())
+}
+
+/// Writes the start tag like html5ever, calling `separate` before each attribute and coloring
+/// it if enabled.
+pub(crate) fn write_start_tag(
+    writer: &mut Vec<u8>,
+    name: &QualName,
+    attrs: &[AttrRef<'_>],
+    options: &SerializeOptions,
+    mut separate: impl FnMut(&mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<()> {
+    let paint = |writer: &mut Vec<u8>, start, color| {
+        if options.color {
+            paint(writer, start, color);
+        }
+    };
+
+    let start = writer.len();
+    writer.extend_from_slice(b"<");
+    writer.extend_from_slice(name.local.as_bytes());
+    paint(writer, start, palette::TAG);
+    for &attr in attrs {
+        separate(writer)?;
+        let serialized = serialize_attribute(name, attr)?;
+        let (attr_name, value) = serialized.split_at(
+            serialized
+                .iter()
+                .position(|&b| b == b'=')
+                .unwrap_or(serialized.len()),
+        );
+        let start = writer.len();
+        writer.extend_from_slice(attr_name);
+        paint(writer, start, palette::ATTRIBUTE);
+        let start = writer.len();
+        writer.extend_from_slice(value);
+        paint(writer, start, palette::VALUE);
+    }
+    let start = writer.len();
+    writer.extend_from_slice(b">");
+    paint(writer, start, palette::TAG);
+    Ok(())
+}
+
+/// Orders the attributes as given.
+pub(crate) fn order_attributes(attrs: &mut [AttrRef<'_>], order: AttributeOrder) {
+    let rank = |name: &QualName| match (order, name.local.as_ref()) {
+        (AttributeOrder::Smart, "id") if name.ns.is_empty() => 0,
+        (AttributeOrder::Smart, "class") if name.ns.is_empty() => 1,
+        _ => 2,
+    };
+    match order {
+        AttributeOrder::Source => {}
+        AttributeOrder::Sorted | AttributeOrder::Smart => attrs
+            .sort_by(|(a, _), (b, _)| (rank(a), &a.local, &a.ns).cmp(&(rank(b), &b.local, &b.ns))),
+    }
+}
+
+/// An [`HtmlSerializer`] writing the markup as configured.
+struct Configured<'o> {
+    options: &'o SerializeOptions,
+    inner: HtmlSerializer<Vec<u8>>,
+}
+
+impl Configured<'_> {
+    fn paint(&mut self, start: usize, color: &[u8]) {
+        if self.options.color {
+            paint(&mut self.inner.writer, start, color);
+        }
+    }
+}
+
+impl Serializer for Configured<'_> {
+    fn start_elem<'a, AttrIter>(&mut self, name: QualName, attrs: AttrIter) -> io::Result<()>
+    where
+        AttrIter: Iterator<Item = AttrRef<'a>>,
+    {
+        let mut attrs: Vec<_> = attrs.collect();
+        order_attributes(&mut attrs, self.options.attribute_order);
+        let start = self.inner.writer.len();
+        self.inner.start_elem(name.clone(), attrs.iter().copied())?;
+        if self.options.color && self.inner.writer.len() > start {
+            self.inner.writer.truncate(start);
+            write_start_tag(
+                &mut self.inner.writer,
+                &name,
+                &attrs,
+                self.options,
+                |writer| writer.write_all(b" "),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn end_elem(&mut self, name: QualName) -> io::Result<()> {
+        let start = self.inner.writer.len();
+        self.inner.end_elem(name)?;
+        self.paint(start, palette::TAG);
+        Ok(())
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        self.inner.write_text(text)
+    }
+
+    fn write_comment(&mut self, text: &str) -> io::Result<()> {
+        if !self.options.comments.keeps(text) {
+            return Ok(());
+        }
+        let start = self.inner.writer.len();
+        self.inner.write_comment(text)?;
+        self.paint(start, palette::COMMENT);
+        Ok(())
+    }
+
+    fn write_doctype(&mut self, name: &str) -> io::Result<()> {
+        let start = self.inner.writer.len();
+        self.inner.write_doctype(name)?;
+        self.paint(start, palette::COMMENT);
+        Ok(())
+    }
+
+    fn write_processing_instruction(&mut self, target: &str, data: &str) -> io::Result<()> {
+        self.inner.write_processing_instruction(target, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    #[test]
+    fn attributes_are_ordered() {
+        let doc = kuchikiki::parse_html().one(
+            "<svg><a xlink:href=\"#x\" href=\"b &amp; c\" class=\"k\" data-b=\"\" id=\"i\"></a></svg>",
+        );
+        let link = doc.select_first("a").unwrap();
+        let order = |attribute_order| {
+            let options = SerializeOptions {
+                attribute_order,
+                ..SerializeOptions::default()
+            };
+            serialize_with(link.as_node(), &options)
+        };
+
+        let source = link.as_node().to_string();
+        assert_eq!(order(AttributeOrder::Source), source);
+        assert_eq!(
+            order(AttributeOrder::Sorted),
+            "<a class=\"k\" data-b=\"\" href=\"b &amp; c\" xlink:href=\"#x\" id=\"i\"></a>"
+        );
+        assert_eq!(
+            order(AttributeOrder::Smart),
+            "<a id=\"i\" class=\"k\" data-b=\"\" href=\"b &amp; c\" xlink:href=\"#x\"></a>"
+        );
+        // the element itself is left alone
+        assert_eq!(link.as_node().to_string(), source);
+    }
+
+    #[test]
+    fn comments_can_be_stripped() {
+        let doc = kuchikiki::parse_html().one(
+            "<div><!-- tracking --><!--[if IE]><p>old</p><![endif]-->\
+            <!--[if !IE]>--><p>new</p><!--<![endif]--></div>",
+        );
+        let div = doc.select_first("div").unwrap();
+        let with = |comments| {
+            let options = SerializeOptions {
+                comments,
+                ..SerializeOptions::default()
+            };
+            serialize_with(div.as_node(), &options)
+        };
+
+        assert_eq!(with(Comments::All), div.as_node().to_string());
+        assert_eq!(
+            with(Comments::Conditional),
+            "<div><!--[if IE]><p>old</p><![endif]--><!--[if !IE]>--><p>new</p><!--<![endif]--></div>"
+        );
+        assert_eq!(with(Comments::None), "<div><p>new</p></div>");
+    }
+}
@@ -0,0 +1,131 @@
+//! Ordering matches by their text or an attribute, see [`Query::sort_by`].
+//!
+//! [`Query::sort_by`]: crate::Query::sort_by
+
+use crate::Match;
+use std::cmp::Ordering;
+
+/// What to sort matches by, given like `text`, `@data-downloads:numeric:desc`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    /// The attribute whose value is the key, or `None` for the text.
+    attribute: Option<String>,
+    /// Compare the keys as numbers, with those that aren't any last.
+    numeric: bool,
+    descending: bool,
+}
+
+impl SortKey {
+    /// Parses `text` or `@name`, optionally followed by `:numeric` and `:desc`.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut source = raw.trim();
+        let (mut numeric, mut descending) = (false, false);
+        // attribute names can contain colons too, like `xlink:href`
+        while let Some((rest, modifier)) = source.rsplit_once(':') {
+            match modifier {
+                "numeric" => numeric = true,
+                "desc" => descending = true,
+                _ => break,
+            }
+            source = rest;
+        }
+
+        let attribute = match source {
+            "text" => None,
+            _ => match source.strip_prefix('@') {
+                Some(name) if !name.is_empty() => Some(name.to_ascii_lowercase()),
+                _ => {
+                    return Err(format!(
+                        "expected `text` or `@ATTR`, optionally followed by `:numeric` or `:desc`, \
+                        got `{raw}`"
+                    ));
+                }
+            },
+        };
+        Ok(Self {
+            attribute,
+            numeric,
+            descending,
+        })
+    }
+
+    /// The key of the match: its text with whitespace collapsed, or the attribute value,
+    /// which is empty if it's missing.
+    fn key(&self, matched: &Match) -> String {
+        let Some(attribute) = &self.attribute else {
+            let text = matched.text();
+            return text.split_whitespace().collect::<Vec<_>>().join(" ");
+        };
+        matched
+            .node()
+            .as_element()
+            .and_then(|elem| {
+                let attributes = elem.attributes.borrow();
+                attributes.get(attribute.as_str()).map(str::to_string)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Sorts the matches by their keys, keeping those with equal ones in order.
+    pub(crate) fn sort(&self, matches: Vec<Match>) -> Vec<Match> {
+        let mut keyed: Vec<_> = matches
+            .into_iter()
+            .map(|matched| (self.key(&matched), matched))
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| self.compare(a, b));
+        keyed.into_iter().map(|(_, matched)| matched).collect()
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        let ordering = if self.numeric {
+            match (parse_number(a), parse_number(b)) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                // not numeric sorts last, even when descending
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        } else {
+            a.cmp(b)
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+fn parse_number(key: &str) -> Option<f64> {
+    key.trim()
+        .parse()
+        .ok()
+        .filter(|number: &f64| !number.is_nan())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_parsed() {
+        let key = |attribute: Option<&str>, numeric, descending| SortKey {
+            attribute: attribute.map(str::to_string),
+            numeric,
+            descending,
+        };
+        assert_eq!(SortKey::parse("text"), Ok(key(None, false, false)));
+        assert_eq!(SortKey::parse("text:desc"), Ok(key(None, false, true)));
+        assert_eq!(
+            SortKey::parse("@data-downloads:numeric:desc"),
+            Ok(key(Some("data-downloads"), true, true))
+        );
+        assert_eq!(
+            SortKey::parse("@xlink:href:desc"),
+            Ok(key(Some("xlink:href"), false, true))
+        );
+        for invalid in ["", "@", "title", "text:reverse", ":desc"] {
+            assert!(SortKey::parse(invalid).is_err(), "{invalid:?}");
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Counting which elements a document is made of, see [`Query::stats`].
+//!
+//! [`Query::stats`]: crate::Query::stats
+
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{Node, NodeRef};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+/// How often an element occurs and what's in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagStats {
+    pub name: String,
+    /// How many elements with this name there are.
+    pub count: usize,
+    /// How many characters of text are directly inside them, not counting text consisting
+    /// only of whitespace.
+    pub text_length: usize,
+    /// How many attributes they have together.
+    pub attributes: usize,
+    /// How deeply the most deeply nested of them is, with 1 for the root element.
+    pub max_depth: usize,
+}
+
+impl TagStats {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "tag": self.name,
+            "count": self.count,
+            "text_length": self.text_length,
+            "attributes": self.attributes,
+            "max_depth": self.max_depth,
+        })
+    }
+}
+
+/// The statistics of all elements in the nodes, including themselves, sorted by how often they
+/// occur and then by name.
+///
+/// Elements in several of the nodes, like when one contains another, are only counted once.
+pub fn collect<'n>(nodes: impl IntoIterator<Item = &'n NodeRef>) -> Vec<TagStats> {
+    let mut seen = HashSet::new();
+    let mut by_name: HashMap<String, TagStats> = HashMap::new();
+    for node in nodes {
+        for elem in node.inclusive_descendants().elements() {
+            let node = elem.as_node();
+            if !seen.insert(std::ptr::from_ref::<Node>(node)) {
+                continue;
+            }
+
+            let name = elem.name.local.to_string();
+            let stats = by_name.entry(name.clone()).or_insert_with(|| TagStats {
+                name,
+                ..TagStats::default()
+            });
+            stats.count += 1;
+            stats.text_length += node
+                .children()
+                .text_nodes()
+                .map(|text| text.borrow().clone())
+                .filter(|text| !text.trim().is_empty())
+                .map(|text| text.chars().count())
+                .sum::<usize>();
+            stats.attributes += elem.attributes.borrow().map.len();
+            stats.max_depth = stats.max_depth.max(node.ancestors().elements().count() + 1);
+        }
+    }
+
+    let mut stats: Vec<_> = by_name.into_values().collect();
+    stats.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    stats
+}
+
+/// Writes the statistics as a table with a header and aligned columns.
+pub fn write_table(stats: &[TagStats], output: &mut dyn io::Write) -> io::Result<()> {
+    let header = ["tag", "count", "text", "attributes", "depth"];
+    let rows: Vec<[String; 5]> = stats
+        .iter()
+        .map(|stats| {
+            [
+                stats.name.clone(),
+                stats.count.to_string(),
+                stats.text_length.to_string(),
+                stats.attributes.to_string(),
+                stats.max_depth.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.map(String::from);
+    for row in std::iter::once(&header).chain(&rows) {
+        // the name is aligned left, the numbers right
+        let [name, numbers @ ..] = row;
+        write!(output, "{name:<width$}", width = widths[0])?;
+        for (cell, width) in numbers.iter().zip(&widths[1..]) {
+            write!(output, "  {cell:>width$}")?;
+        }
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    const FIXTURE: &str = "<!DOCTYPE html><html lang=\"en\"><head><title>Shop</title></head><body>\
+        <ul class=\"products\">\n  <li id=\"a\" class=\"product\"><a href=\"/a\">Apple</a></li>\n  \
+        <li id=\"b\"><a href=\"/b\">Banana</a> and <a href=\"/c\">Cherry</a></li>\n</ul>\
+        <p>Prices include tax.</p></body></html>";
+
+    fn stats(selector: &str) -> Vec<TagStats> {
+        let doc = kuchikiki::parse_html().one(FIXTURE);
+        let nodes: Vec<_> = doc
+            .select(selector)
+            .unwrap()
+            .map(|elem| elem.as_node().clone())
+            .collect();
+        collect(&nodes)
+    }
+
+    fn row(name: &str, count: usize, text: usize, attributes: usize, depth: usize) -> TagStats {
+        TagStats {
+            name: name.to_string(),
+            count,
+            text_length: text,
+            attributes,
+            max_depth: depth,
+        }
+    }
+
+    #[test]
+    fn elements_are_counted() {
+        assert_eq!(
+            stats("html"),
+            [
+                row("a", 3, 17, 3, 5),
+                row("li", 2, 5, 3, 4),
+                row("body", 1, 0, 0, 2),
+                row("head", 1, 0, 0, 2),
+                row("html", 1, 0, 1, 1),
+                row("p", 1, 19, 0, 3),
+                row("title", 1, 4, 0, 3),
+                row("ul", 1, 0, 1, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_matches_are_counted_once() {
+        assert_eq!(stats("ul, li, a"), stats("ul"));
+        assert_eq!(stats("li"), [row("a", 3, 17, 3, 5), row("li", 2, 5, 3, 4)]);
+    }
+
+    #[test]
+    fn table_is_aligned() {
+        let mut output = Vec::new();
+        write_table(&stats("li"), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tag  count  text  attributes  depth\n\
+             a        3    17           3      5\n\
+             li       2     5           3      4\n"
+        );
+    }
+}
@@ -0,0 +1,300 @@
+//! Turning HTML tables into rectangular grids of cell texts, like for CSV or JSON.
+
+use html5ever::local_name;
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{ElementData, NodeDataRef, NodeRef};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::io;
+
+/// The HTML spec caps these, so malicious spans can't blow up the grid.
+const MAX_COLSPAN: usize = 1000;
+const MAX_ROWSPAN: usize = 65534;
+
+/// All tables in the node, including itself, but not tables nested within those.
+pub fn tables(node: &NodeRef) -> Vec<NodeRef> {
+    node.inclusive_descendants()
+        .elements()
+        .filter(|elem| elem.name.local == local_name!("table"))
+        .map(|elem| elem.as_node().clone())
+        .filter(|table| {
+            // stop at the node itself, it could be inside a table as well
+            !table
+                .ancestors()
+                .take_while(|ancestor| ancestor != node)
+                .any(|ancestor| is_element(&ancestor, &local_name!("table")))
+        })
+        .collect()
+}
+
+/// The text of all cells in the table, row by row.
+///
+/// Rows from `<thead>` come first and those from `<tfoot>` last, wherever they are in the table.
+/// Cells spanning multiple columns or rows are repeated in each of them,
+/// and rows are padded with empty cells so all have the same length.
+pub fn grid(table: &NodeRef) -> Vec<Vec<String>> {
+    parse(table)
+        .into_iter()
+        .map(|row| {
+            row.cells
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect()
+        })
+        .collect()
+}
+
+/// The rows of the table as objects, keyed by the text of the header cells.
+///
+/// The header is the first row with `<th>` cells, or the first row if there is none.
+/// Rows before the header are skipped. Repeated header names get a `_2`, `_3`, … suffix,
+/// columns without any header cell are keyed by their 1-based index.
+/// Cells missing at the end of a row are `null`, all others are strings.
+pub fn records(table: &NodeRef) -> Vec<Value> {
+    let rows = parse(table);
+    let header = rows.iter().position(|row| row.is_header).unwrap_or(0);
+    let Some(header_row) = rows.get(header) else {
+        return Vec::new();
+    };
+
+    let mut used = HashSet::new();
+    let keys: Vec<_> = header_row
+        .cells
+        .iter()
+        .enumerate()
+        .map(|(i, cell)| {
+            let name = cell.clone().unwrap_or_else(|| (i + 1).to_string());
+            let mut key = name.clone();
+            let mut n = 1;
+            while !used.insert(key.clone()) {
+                n += 1;
+                key = format!("{name}_{n}");
+            }
+            key
+        })
+        .collect();
+
+    rows[header + 1..]
+        .iter()
+        .map(|row| {
+            let object: Map<_, _> = keys
+                .iter()
+                .cloned()
+                .zip(
+                    row.cells
+                        .iter()
+                        .map(|cell| cell.clone().map_or(Value::Null, Value::from)),
+                )
+                .collect();
+            Value::Object(object)
+        })
+        .collect()
+}
+
+struct Row {
+    /// `None` for cells padded in to make the grid rectangular.
+    cells: Vec<Option<String>>,
+    /// If any of the cells is a `<th>`.
+    is_header: bool,
+}
+
+fn parse(table: &NodeRef) -> Vec<Row> {
+    let mut grid = Vec::new();
+    // per column, the text of a cell spanning into the following rows and how many are left
+    let mut spanning: Vec<Option<(String, usize)>> = Vec::new();
+
+    for row in rows(table) {
+        let mut cells = Vec::new();
+        let mut is_header = false;
+
+        for cell in row.as_node().children().elements().filter(is_cell) {
+            is_header |= cell.name.local == local_name!("th");
+            fill_spanned(&mut cells, &mut spanning);
+
+            let text = crate::serialize_text(cell.as_node(), false);
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            let attrs = cell.attributes.borrow();
+            let colspan = span(attrs.get("colspan"), MAX_COLSPAN);
+            let rowspan = span(attrs.get("rowspan"), MAX_ROWSPAN);
+
+            for _ in 0..colspan {
+                if rowspan > 1 {
+                    if spanning.len() <= cells.len() {
+                        spanning.resize(cells.len() + 1, None);
+                    }
+                    spanning[cells.len()] = Some((text.clone(), rowspan - 1));
+                }
+                cells.push(Some(text.clone()));
+            }
+        }
+        fill_spanned(&mut cells, &mut spanning);
+
+        // cells spanning down from further right still need to be filled in
+        while spanning
+            .get(cells.len()..)
+            .is_some_and(|rest| rest.iter().any(Option::is_some))
+        {
+            cells.push(None);
+            fill_spanned(&mut cells, &mut spanning);
+        }
+        grid.push(Row { cells, is_header });
+    }
+
+    let width = grid.iter().map(|row| row.cells.len()).max().unwrap_or(0);
+    for row in &mut grid {
+        row.cells.resize(width, None);
+    }
+    grid
+}
+
+/// Continues the row with cells spanning down from previous rows, as long as there are any.
+fn fill_spanned(cells: &mut Vec<Option<String>>, spanning: &mut [Option<(String, usize)>]) {
+    while let Some(Some((text, left))) = spanning.get_mut(cells.len()) {
+        cells.push(Some(text.clone()));
+        *left -= 1;
+        if *left == 0 {
+            spanning[cells.len() - 1] = None;
+        }
+    }
+}
+
+/// Writes the grid as CSV, quoting only cells that need it.
+pub fn write_csv(grid: &[Vec<String>], output: &mut dyn io::Write) -> io::Result<()> {
+    for row in grid {
+        let line: Vec<_> = row.iter().map(|cell| csv_field(cell)).collect();
+        writeln!(output, "{}", line.join(","))?;
+    }
+    Ok(())
+}
+
+fn csv_field(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// All rows of the table, `<thead>` first and `<tfoot>` last.
+fn rows(table: &NodeRef) -> Vec<NodeDataRef<ElementData>> {
+    let (mut head, mut body, mut foot) = (Vec::new(), Vec::new(), Vec::new());
+    for child in table.children().elements() {
+        let section = match child.name.local {
+            local_name!("tr") => {
+                body.push(child);
+                continue;
+            }
+            local_name!("thead") => &mut head,
+            local_name!("tbody") => &mut body,
+            local_name!("tfoot") => &mut foot,
+            _ => continue,
+        };
+        section.extend(
+            child
+                .as_node()
+                .children()
+                .elements()
+                .filter(|row| row.name.local == local_name!("tr")),
+        );
+    }
+
+    head.extend(body);
+    head.extend(foot);
+    head
+}
+
+fn is_cell(elem: &NodeDataRef<ElementData>) -> bool {
+    elem.name.local == local_name!("td") || elem.name.local == local_name!("th")
+}
+
+fn is_element(node: &NodeRef, name: &html5ever::LocalName) -> bool {
+    node.as_element()
+        .is_some_and(|elem| elem.name.local == *name)
+}
+
+/// Parses a `colspan` or `rowspan`, falling back to 1 like browsers do.
+fn span(raw: Option<&str>, max: usize) -> usize {
+    raw.and_then(|raw| raw.trim().parse::<usize>().ok())
+        .filter(|&span| span > 0)
+        .map_or(1, |span| span.min(max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn grid_of(html: &str) -> Vec<Vec<String>> {
+        let doc = kuchikiki::parse_html().one(html);
+        grid(&tables(&doc)[0])
+    }
+
+    fn strings<const N: usize>(rows: &[[&str; N]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(ToString::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn sections_are_ordered() {
+        let html = "<table><tfoot><tr><td>foot</td></tr></tfoot>\
+            <tbody><tr><td>1</td><td> a\n  b </td></tr></tbody>\
+            <thead><tr><th>Name</th><th>Value</th></tr></thead></table>";
+        assert_eq!(
+            grid_of(html),
+            strings(&[["Name", "Value"], ["1", "a b"], ["foot", ""]])
+        );
+    }
+
+    #[test]
+    fn spans_are_filled_in() {
+        let html = "<table>\
+            <tr><td rowspan=\"2\">a</td><td colspan=\"2\">b</td><td rowspan=\"3\">c</td></tr>\
+            <tr><td>d</td><td>e</td></tr>\
+            <tr><td>f</td></tr></table>";
+        assert_eq!(
+            grid_of(html),
+            strings(&[
+                ["a", "b", "b", "c"],
+                ["a", "d", "e", "c"],
+                ["f", "", "", "c"]
+            ])
+        );
+    }
+
+    #[test]
+    fn nested_tables_are_separate() {
+        let doc = kuchikiki::parse_html()
+            .one("<div><table><tr><td><table><tr><td>x</td></tr></table></td></tr></table></div>");
+        assert_eq!(tables(&doc).len(), 1);
+        assert_eq!(grid(&tables(&doc)[0]), strings(&[["x"]]));
+    }
+
+    #[test]
+    fn records_are_keyed_by_header() {
+        let doc = kuchikiki::parse_html().one(
+            "<table><tr><td>caption</td></tr>\
+            <tr><th>Name</th><th>Price</th><th>Price</th><td></td></tr>\
+            <tr><td>Tea</td><td>2</td><td>3</td><td>x</td></tr><tr><td colspan=\"2\">-</td></tr></table>",
+        );
+        let found = Value::from(records(&tables(&doc)[0]));
+        assert_eq!(
+            found.to_string(),
+            "[{\"Name\":\"Tea\",\"Price\":\"2\",\"Price_2\":\"3\",\"\":\"x\"},\
+            {\"Name\":\"-\",\"Price\":\"-\",\"Price_2\":null,\"\":null}]"
+        );
+
+        let doc = kuchikiki::parse_html()
+            .one("<table><tr><td>a</td></tr><tr><td>1</td><td>2</td></tr></table>");
+        let found = Value::from(records(&tables(&doc)[0]));
+        assert_eq!(found.to_string(), "[{\"a\":\"1\",\"2\":\"2\"}]");
+    }
+
+    #[test]
+    fn csv_is_quoted() {
+        let mut output = Vec::new();
+        let grid = strings(&[["a,b", "say \"hi\"", "plain"]]);
+        write_csv(&grid, &mut output).unwrap();
+        assert_eq!(output, b"\"a,b\",\"say \"\"hi\"\"\",plain\n");
+    }
+}
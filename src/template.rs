@@ -0,0 +1,211 @@
+//! Templates like `{@href}\t{text}` that describe how to output each match.
+
+use kuchikiki::NodeRef;
+use std::fmt;
+
+/// A parsed template, expanded once for each match.
+///
+/// Placeholders are `{@name}` for the value of the attribute `name`, `{text}` for the
+/// [text](crate::serialize_text), `{html}` for the outer HTML and `{tag}` for the element name.
+/// `\t`, `\n`, `\\`, `\{` and `\}` are replaced by what they escape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Attribute(String),
+    Text,
+    Html,
+    Tag,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.char_indices();
+
+        while let Some((idx, c)) = chars.next() {
+            let error = |message: String| ParseError {
+                position: idx,
+                message,
+            };
+            match c {
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, 't')) => '\t',
+                        Some((_, 'n')) => '\n',
+                        Some((_, c @ ('\\' | '{' | '}'))) => c,
+                        Some((_, c)) => return Err(error(format!("unknown escape `\\{c}`"))),
+                        None => return Err(error("dangling `\\` at the end".to_string())),
+                    };
+                    literal.push(escaped);
+                }
+                '{' => {
+                    let rest = &source[idx + 1..];
+                    let Some(len) = rest.find('}') else {
+                        return Err(error("unclosed `{`".to_string()));
+                    };
+                    let placeholder = &rest[..len];
+                    let part = match placeholder {
+                        "text" => Part::Text,
+                        "html" => Part::Html,
+                        "tag" => Part::Tag,
+                        _ => match placeholder.strip_prefix('@') {
+                            Some(name) if !name.is_empty() => Part::Attribute(name.to_string()),
+                            _ => {
+                                return Err(error(format!(
+                                    "unknown placeholder `{{{placeholder}}}`"
+                                )));
+                            }
+                        },
+                    };
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                    // skip over the placeholder and the closing brace
+                    chars.nth(placeholder.chars().count());
+                }
+                '}' => {
+                    return Err(error(
+                        "unmatched `}`, use `\\}` for a literal one".to_string(),
+                    ));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Replaces all placeholders by what they stand for in the node.
+    ///
+    /// Attributes the node doesn't have are replaced by nothing.
+    pub fn expand(&self, node: &NodeRef, ignore_whitespace: bool) -> String {
+        let mut expanded = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => expanded.push_str(literal),
+                Part::Attribute(name) => {
+                    if let Some(elem) = node.as_element()
+                        && let Some(value) = elem.attributes.borrow().get(name.as_str())
+                    {
+                        expanded.push_str(value);
+                    }
+                }
+                Part::Text => expanded.push_str(&crate::serialize_text(node, ignore_whitespace)),
+                Part::Html => expanded.push_str(&node.to_string()),
+                Part::Tag => {
+                    if let Some(elem) = node.as_element() {
+                        expanded.push_str(&elem.name.local.to_lowercase());
+                    }
+                }
+            }
+        }
+        expanded
+    }
+
+    /// The first attribute used in the template that the node doesn't have, if any.
+    pub fn missing_attribute(&self, node: &NodeRef) -> Option<&str> {
+        self.parts.iter().find_map(|part| match part {
+            Part::Attribute(name) => {
+                let present = node
+                    .as_element()
+                    .is_some_and(|elem| elem.attributes.borrow().contains(name.as_str()));
+                (!present).then_some(name.as_str())
+            }
+            _ => None,
+        })
+    }
+}
+
+/// A template that couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// At which byte of the template the problem is, starting at 0.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn link() -> NodeRef {
+        kuchikiki::parse_html()
+            .one("<a href=\"/x\" title=\"X\">Link <b>text</b></a>")
+            .select_first("a")
+            .unwrap()
+            .as_node()
+            .clone()
+    }
+
+    fn expand(template: &str) -> String {
+        Template::parse(template).unwrap().expand(&link(), false)
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(expand("{@href}\\t{text}"), "/x\tLink text");
+        assert_eq!(expand("<{tag}> {@title}{@missing}!"), "<a> X!");
+        assert_eq!(
+            expand("{html}"),
+            "<a href=\"/x\" title=\"X\">Link <b>text</b></a>"
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(expand("a\\nb\\\\c \\{text\\}"), "a\nb\\c {text}");
+        assert_eq!(expand("ä{tag}ö"), "äaö");
+    }
+
+    #[test]
+    fn missing_attributes() {
+        let template = Template::parse("{@href} {@rel} {@title}").unwrap();
+        assert_eq!(template.missing_attribute(&link()), Some("rel"));
+        assert_eq!(
+            Template::parse("{@href}")
+                .unwrap()
+                .missing_attribute(&link()),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_templates() {
+        let error = |template| {
+            let err = Template::parse(template).unwrap_err();
+            (err.position, err.message)
+        };
+        assert_eq!(
+            error("{text} {link}"),
+            (7, "unknown placeholder `{link}`".to_string())
+        );
+        assert_eq!(error("{@}"), (0, "unknown placeholder `{@}`".to_string()));
+        assert_eq!(error("a {text"), (2, "unclosed `{`".to_string()));
+        assert_eq!(
+            error("}"),
+            (0, "unmatched `}`, use `\\}` for a literal one".to_string())
+        );
+        assert_eq!(error("\\x"), (0, "unknown escape `\\x`".to_string()));
+        assert_eq!(error("a\\"), (1, "dangling `\\` at the end".to_string()));
+    }
+}
@@ -0,0 +1,453 @@
+//! Extracting the text out of nodes, in several flavors.
+
+use html5ever::local_name;
+use kuchikiki::iter::NodeEdge;
+use kuchikiki::traits::NodeIterator;
+use kuchikiki::{NodeData, NodeRef};
+use url::Url;
+
+/// How [`extract`] turns nodes into text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Concatenate all text nodes as they are, without line breaks for block-level elements.
+    pub raw: bool,
+    /// Skip text nodes consisting only of whitespace.
+    ///
+    /// With `raw`, all other text nodes are then terminated by a newline.
+    pub ignore_whitespace: bool,
+    /// Collapse runs of whitespace to a single space and trim them at the start and end of
+    /// lines, like browsers do. Text in `<pre>`, `<code>` and `<textarea>` is kept as it is.
+    ///
+    /// Has no effect with `raw`.
+    pub collapse_whitespace: bool,
+    /// Also include the content of `<script>`, `<style>`, `<template>` and `<noscript>`
+    /// elements inside the node.
+    pub keep_scripts: bool,
+    /// Skip text that is hidden by the `hidden` attribute, `aria-hidden="true"` or an inline
+    /// `display: none` or `visibility: hidden` style on it or any ancestor.
+    ///
+    /// Stylesheets aren't looked at.
+    pub visible_only: bool,
+    /// Include the `alt` text of images where they are, with `{}` in this replaced by it,
+    /// like `[{}]`.
+    ///
+    /// This covers `<img>`, `<area>` and `<input type="image">`. Empty `alt` texts are skipped.
+    pub alt_text: Option<String>,
+    /// Follow each link with a footnote number like `[1]` and list their URLs at the end.
+    ///
+    /// Links to the same URL share a number. Links to anchors on the same page and
+    /// `javascript:` links get none. Has no effect with `raw`.
+    pub link_footnotes: bool,
+    /// What relative URLs in link footnotes are resolved against.
+    pub base: Option<Url>,
+}
+
+/// All text contained in the node.
+///
+/// Unless [raw](TextOptions::raw), block-level elements like `<p>` and `<li>` are put on lines
+/// of their own and `<br>` becomes a line break. Lines consisting only of whitespace, like
+/// indentation between such elements, are dropped.
+///
+/// The text of scripts and styles is skipped unless [kept](TextOptions::keep_scripts),
+/// but only inside the node. If the node is a script itself, its text is included.
+///
+/// The text of a comment is what's inside it, trimmed unless [raw](TextOptions::raw).
+/// Comments inside the node are skipped.
+pub fn extract(node: &NodeRef, options: &TextOptions) -> String {
+    if let Some(comment) = node.as_comment() {
+        let comment = comment.borrow();
+        return if options.raw {
+            comment.clone()
+        } else {
+            comment.trim().to_string()
+        };
+    }
+    if options.raw {
+        return extract_raw(node, options);
+    }
+
+    let collapse_whitespace = options.collapse_whitespace;
+    let mut raw = String::new();
+    let mut footnotes: Vec<String> = Vec::new();
+    // verbatim text mustn't be trimmed, even at the end of lines
+    let mut ends_verbatim = false;
+    let trim_end = |raw: &mut String, ends_verbatim: bool| {
+        if collapse_whitespace && !ends_verbatim {
+            raw.truncate(raw.trim_end_matches(' ').len());
+        }
+    };
+
+    for edge in node.traverse_inclusive() {
+        let (NodeEdge::Start(current) | NodeEdge::End(current)) = &edge;
+        match current.data() {
+            NodeData::Text(text) if matches!(edge, NodeEdge::Start(_)) => {
+                let text = text.borrow();
+                if (options.ignore_whitespace && text.trim().is_empty())
+                    || is_skipped(current, node, options)
+                {
+                    continue;
+                }
+                ends_verbatim = collapse_whitespace && is_verbatim(current);
+                if !collapse_whitespace || ends_verbatim {
+                    raw.push_str(&text);
+                    continue;
+                }
+
+                for c in text.chars() {
+                    if !c.is_whitespace() {
+                        raw.push(c);
+                    } else if !(raw.is_empty() || raw.ends_with([' ', '\n'])) {
+                        raw.push(' ');
+                    }
+                }
+            }
+            NodeData::Element(_)
+                if matches!(edge, NodeEdge::Start(_))
+                    && let Some(alt) = alt_text(current, node, options) =>
+            {
+                raw.push_str(&alt);
+                ends_verbatim = false;
+            }
+            NodeData::Element(_)
+                if matches!(edge, NodeEdge::End(_))
+                    && options.link_footnotes
+                    && let Some(url) = footnote_url(current, node, options) =>
+            {
+                let number = match footnotes.iter().position(|known| *known == url) {
+                    Some(idx) => idx + 1,
+                    None => {
+                        footnotes.push(url);
+                        footnotes.len()
+                    }
+                };
+                trim_end(&mut raw, ends_verbatim);
+                raw.push_str(&format!("[{number}]"));
+                ends_verbatim = false;
+            }
+            NodeData::Element(elem) if elem.name.local == local_name!("br") => {
+                if matches!(edge, NodeEdge::Start(_)) {
+                    trim_end(&mut raw, ends_verbatim);
+                    raw.push('\n');
+                }
+            }
+            NodeData::Element(elem) if is_block(&elem.name.local) => {
+                trim_end(&mut raw, ends_verbatim);
+                if !raw.is_empty() && !raw.ends_with('\n') {
+                    raw.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+    trim_end(&mut raw, ends_verbatim);
+
+    let lines: Vec<_> = raw.lines().filter(|line| !line.trim().is_empty()).collect();
+    let mut text = lines.join("\n");
+    if !footnotes.is_empty() {
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        let list: Vec<_> = footnotes
+            .iter()
+            .enumerate()
+            .map(|(idx, url)| format!("[{}] {url}", idx + 1))
+            .collect();
+        text.push_str(&list.join("\n"));
+    }
+    text
+}
+
+fn extract_raw(node: &NodeRef, options: &TextOptions) -> String {
+    let mut result = String::new();
+    for descendant in node.inclusive_descendants() {
+        let text = match descendant.as_text() {
+            Some(text) => text.borrow().clone(),
+            None => match alt_text(&descendant, node, options) {
+                Some(alt) => alt,
+                None => continue,
+            },
+        };
+        if options.ignore_whitespace && text.trim().is_empty() {
+            continue;
+        }
+        if is_skipped(&descendant, node, options) {
+            continue;
+        }
+
+        result.push_str(&text);
+
+        if options.ignore_whitespace {
+            result.push('\n');
+        }
+    }
+
+    result
+}
+
+/// The wrapped `alt` text of the node if it's an image and alt texts are to be included.
+fn alt_text(node: &NodeRef, root: &NodeRef, options: &TextOptions) -> Option<String> {
+    let wrapper = options.alt_text.as_ref()?;
+    let elem = node.as_element()?;
+    let attrs = elem.attributes.borrow();
+    let is_image = match elem.name.local {
+        local_name!("img") | local_name!("area") => true,
+        local_name!("input") => attrs
+            .get("type")
+            .is_some_and(|kind| kind.eq_ignore_ascii_case("image")),
+        _ => false,
+    };
+
+    let alt = attrs.get("alt").filter(|alt| !alt.trim().is_empty())?;
+    if !is_image || is_skipped(node, root, options) {
+        return None;
+    }
+    Some(wrapper.replace("{}", alt.trim()))
+}
+
+/// The resolved URL the node links to if it is a link that deserves a footnote.
+fn footnote_url(node: &NodeRef, root: &NodeRef, options: &TextOptions) -> Option<String> {
+    let elem = node.as_element()?;
+    if elem.name.local != local_name!("a") || is_skipped(node, root, options) {
+        return None;
+    }
+    let attrs = elem.attributes.borrow();
+    let href = attrs.get("href")?.trim();
+    let is_script = href
+        .get(..11)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("javascript:"));
+    if href.is_empty() || href.starts_with('#') || is_script {
+        return None;
+    }
+
+    let Some(base) = &options.base else {
+        return Some(href.to_string());
+    };
+    let url = crate::link::resolve(href, base);
+    // the link might have been resolved already, but still only point to an anchor
+    if let Ok(parsed) = Url::parse(&url)
+        && parsed.fragment().is_some()
+        && parsed[..url::Position::AfterQuery] == base[..url::Position::AfterQuery]
+    {
+        return None;
+    }
+    Some(url)
+}
+
+/// If the text node is to be left out of the text of `root`.
+fn is_skipped(text: &NodeRef, root: &NodeRef, options: &TextOptions) -> bool {
+    if options.visible_only && is_hidden(text) {
+        return true;
+    }
+    if options.keep_scripts {
+        return false;
+    }
+    text.parent().is_some_and(|parent| {
+        parent != *root
+            && parent.as_element().is_some_and(|elem| {
+                matches!(
+                    elem.name.local,
+                    local_name!("script")
+                        | local_name!("style")
+                        | local_name!("template")
+                        | local_name!("noscript")
+                )
+            })
+    })
+}
+
+/// If any ancestor of the node hides it, judging only by attributes and inline styles.
+fn is_hidden(node: &NodeRef) -> bool {
+    // `visibility: visible` on a descendant overrides `visibility: hidden`, unlike `display: none`
+    let mut made_visible = false;
+    for elem in node.ancestors().elements() {
+        let attrs = elem.attributes.borrow();
+        if attrs.contains("hidden")
+            || attrs
+                .get("aria-hidden")
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"))
+        {
+            return true;
+        }
+
+        let Some(style) = attrs.get("style") else {
+            continue;
+        };
+        let style = style
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if style.contains("display:none") {
+            return true;
+        }
+        if style.contains("visibility:visible") {
+            made_visible = true;
+        }
+        if !made_visible && style.contains("visibility:hidden") {
+            return true;
+        }
+    }
+    false
+}
+
+/// If the text node is inside an element whose whitespace matters.
+fn is_verbatim(node: &NodeRef) -> bool {
+    node.ancestors().elements().any(|elem| {
+        let name = &elem.name.local;
+        *name == local_name!("pre")
+            || *name == local_name!("code")
+            || *name == local_name!("textarea")
+    })
+}
+
+/// Elements that are rendered on lines of their own.
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "address"
+            | "article"
+            | "aside"
+            | "blockquote"
+            | "dd"
+            | "details"
+            | "div"
+            | "dl"
+            | "dt"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "form"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "header"
+            | "hr"
+            | "li"
+            | "main"
+            | "nav"
+            | "ol"
+            | "p"
+            | "pre"
+            | "section"
+            | "summary"
+            | "table"
+            | "tr"
+            | "ul"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn body() -> NodeRef {
+        kuchikiki::parse_html()
+            .one(
+                "<p>a</p><script>if (a < b && c > d) { alert(\"<p>\") }</script>\
+                <style>p > a { color: red }</style><noscript>No JS</noscript><p>b</p>",
+            )
+            .select_first("body")
+            .unwrap()
+            .as_node()
+            .clone()
+    }
+
+    #[test]
+    fn scripts_are_skipped() {
+        let options = TextOptions::default();
+        assert_eq!(extract(&body(), &options), "a\nb");
+        let raw = TextOptions {
+            raw: true,
+            ..TextOptions::default()
+        };
+        assert_eq!(extract(&body(), &raw), "ab");
+    }
+
+    #[test]
+    fn hidden_text_is_skipped() {
+        let doc = kuchikiki::parse_html().one(
+            "<p>a</p><div hidden><p>b</p></div><div aria-hidden=\"TRUE\">c</div>\
+            <div style=\"color: red; display : none\"><p style=\"display: block\">d</p></div>\
+            <div style=\"visibility:hidden\">e<p style=\"visibility: visible\">f</p></div>\
+            <div style=\"visibility: visible\"><p style=\"visibility: hidden\">g</p>h</div>\
+            <div aria-hidden=\"false\">i</div>",
+        );
+        let options = TextOptions {
+            raw: true,
+            visible_only: true,
+            ..TextOptions::default()
+        };
+        assert_eq!(extract(&doc, &options), "afhi");
+        let options = TextOptions {
+            visible_only: false,
+            ..options
+        };
+        assert_eq!(extract(&doc, &options), "abcdefghi");
+    }
+
+    #[test]
+    fn alt_texts_are_included() {
+        let doc = kuchikiki::parse_html().one(
+            "<p>See <img src=\"a.png\" alt=\" the diagram \"> here</p><img alt=\"\"><img>\
+            <p hidden><img alt=\"hidden\"></p><input type=\"IMAGE\" alt=\"Go\"><input alt=\"no\">",
+        );
+        let options = TextOptions {
+            alt_text: Some("[{}]".to_string()),
+            visible_only: true,
+            ..TextOptions::default()
+        };
+        assert_eq!(extract(&doc, &options), "See [the diagram] here\n[Go]");
+        let options = TextOptions {
+            raw: true,
+            alt_text: Some("<{}>".to_string()),
+            ..TextOptions::default()
+        };
+        assert_eq!(
+            extract(&doc, &options),
+            "See <the diagram> here<hidden><Go>"
+        );
+    }
+
+    #[test]
+    fn links_get_footnotes() {
+        let doc = kuchikiki::parse_html().one(
+            "<p>Read <a href=\"/docs\">the docs </a> or <a href=\"#top\">go up</a>.</p>\
+            <p><a href=\"JavaScript:void(0)\">Menu</a> <a href=\"https://example.org/docs\">Docs</a>\
+            <a href=\"other\">again</a></p>",
+        );
+        let options = TextOptions {
+            collapse_whitespace: true,
+            link_footnotes: true,
+            base: Some(Url::parse("https://example.org/guide/").unwrap()),
+            ..TextOptions::default()
+        };
+        assert_eq!(
+            extract(&doc, &options),
+            "Read the docs[1] or go up.\nMenu Docs[1]again[2]\n\n\
+            [1] https://example.org/docs\n[2] https://example.org/guide/other"
+        );
+    }
+
+    #[test]
+    fn scripts_can_be_kept() {
+        let options = TextOptions {
+            raw: true,
+            keep_scripts: true,
+            ..TextOptions::default()
+        };
+        assert_eq!(
+            extract(&body(), &options),
+            "aif (a < b && c > d) { alert(\"<p>\") }p > a { color: red }No JSb"
+        );
+
+        let script = body().select_first("script").unwrap();
+        assert_eq!(
+            extract(script.as_node(), &TextOptions::default()),
+            "if (a < b && c > d) { alert(\"<p>\") }"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Wrapping nodes in new elements like `<figure>`.
+
+use html5ever::{LocalName, QualName, namespace_url, ns};
+use kuchikiki::{Attribute, ExpandedName, NodeRef};
+
+/// An element to wrap each match in, given like `div class="card"`, see [`Query::wrap`].
+///
+/// [`Query::wrap`]: crate::Query::wrap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wrapper {
+    name: String,
+    attributes: Vec<(String, String)>,
+}
+
+impl Wrapper {
+    /// Parses the tag name followed by attributes like in a start tag, without the `<` and `>`.
+    ///
+    /// Values can be in double or single quotes, or in none if they don't contain whitespace.
+    /// Character references in them aren't decoded, they're taken as they are.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let (name, mut rest) = raw.split_once(is_space).unwrap_or((raw, ""));
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid_name {
+            return Err(format!(
+                "`{name}` is not a valid tag name, expected one like `div` or `my-card`"
+            ));
+        }
+
+        let mut attributes = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(is_space);
+            if rest.is_empty() {
+                break;
+            }
+            let end = rest
+                .find(|c: char| is_space(c) || c == '=')
+                .unwrap_or(rest.len());
+            let (attr_name, after) = rest.split_at(end);
+            if attr_name.is_empty() || attr_name.contains(['"', '\'', '<', '>', '/']) {
+                return Err(format!("`{attr_name}` is not a valid attribute name"));
+            }
+
+            let Some(after) = after.strip_prefix('=') else {
+                attributes.push((attr_name.to_ascii_lowercase(), String::new()));
+                rest = after;
+                continue;
+            };
+            let (value, after) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => after[1..]
+                    .split_once(quote)
+                    .ok_or_else(|| format!("the value of `{attr_name}` lacks a closing {quote}"))?,
+                _ => after.split_at(after.find(is_space).unwrap_or(after.len())),
+            };
+            attributes.push((attr_name.to_ascii_lowercase(), value.to_string()));
+            rest = after;
+        }
+
+        Ok(Self {
+            name: name.to_ascii_lowercase(),
+            attributes,
+        })
+    }
+
+    /// Creates a new, empty element as described.
+    pub(crate) fn element(&self) -> NodeRef {
+        let attributes = self.attributes.iter().map(|(name, value)| {
+            (
+                ExpandedName::new(ns!(), LocalName::from(name.as_str())),
+                Attribute {
+                    prefix: None,
+                    value: value.clone(),
+                },
+            )
+        });
+        NodeRef::new_element(
+            QualName::new(None, ns!(html), LocalName::from(self.name.as_str())),
+            attributes,
+        )
+    }
+}
+
+fn is_space(c: char) -> bool {
+    c.is_ascii_whitespace()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specs_are_parsed() {
+        let wrapper = Wrapper::parse("figure").unwrap();
+        assert_eq!(wrapper.element().to_string(), "<figure></figure>");
+
+        let wrapper =
+            Wrapper::parse(" DIV class=\"card wide\" data-x='a \"b\"' hidden id=main ").unwrap();
+        assert_eq!(
+            wrapper.element().to_string(),
+            "<div class=\"card wide\" data-x=\"a &quot;b&quot;\" hidden=\"\" id=\"main\"></div>"
+        );
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        for spec in [
+            "",
+            "<div>",
+            "1st",
+            "div.card",
+            "div class=\"card",
+            "div =x",
+            "div a\"b=c",
+        ] {
+            assert!(Wrapper::parse(spec).is_err(), "{spec:?}");
+        }
+    }
+}
@@ -0,0 +1,1471 @@
+//! A small evaluator for XPath 1.0 expressions over parsed documents.
+//!
+//! All axes, the node tests `node()`, `text()`, `comment()` and `processing-instruction()`,
+//! predicates and most functions of the core library are supported.
+//! Variables and namespaces are not, name tests ignore any prefix and case.
+
+use kuchikiki::{NodeData, NodeRef};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A parsed XPath expression, ready to be evaluated against any number of documents.
+#[derive(Debug, Clone)]
+pub struct XPath {
+    expr: Expr,
+}
+
+impl XPath {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.chars().count(),
+        };
+
+        let expr = parser.parse_expr()?;
+        if let Some(token) = parser.peek_spanned() {
+            return Err(parser.error(format!("unexpected `{}`", token.text)));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Evaluates the expression with the given document node as context, in document order.
+    ///
+    /// Selected attributes are returned as detached text nodes containing their value.
+    /// If the expression doesn't evaluate to a node set at all, like `count(//a)`,
+    /// its value is returned as a single text node.
+    pub fn select(&self, root: &NodeRef) -> Vec<NodeRef> {
+        let evaluator = Evaluator::new(root);
+        let context = Context {
+            node: &Node::Tree(root.clone()),
+            position: 1,
+            size: 1,
+        };
+
+        match evaluator.eval(&self.expr, &context) {
+            Value::Nodes(nodes) => nodes
+                .into_iter()
+                .map(|node| match node {
+                    Node::Tree(node) => node,
+                    Node::Attribute { value, .. } => NodeRef::new_text(value),
+                })
+                .collect(),
+            value => vec![NodeRef::new_text(value.string())],
+        }
+    }
+}
+
+/// An XPath expression that couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// At which character of the expression the problem is, starting at 0.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Slash,
+    DoubleSlash,
+    Dot,
+    DotDot,
+    At,
+    DoubleColon,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Pipe,
+    Plus,
+    Minus,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `*` used as operator, not as name test.
+    Multiply,
+    And,
+    Or,
+    Div,
+    Mod,
+    /// A name test like `a`, `*` or `svg:*`, an axis name or a function name.
+    Name(String),
+    Literal(String),
+    Number(f64),
+}
+
+impl Token {
+    /// If this token can end an operand, so the next `*` or name has to be an operator.
+    fn ends_operand(&self) -> bool {
+        matches!(
+            self,
+            Self::Dot
+                | Self::DotDot
+                | Self::RParen
+                | Self::RBracket
+                | Self::Name(_)
+                | Self::Literal(_)
+                | Self::Number(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    /// Character offset of the token in the expression.
+    start: usize,
+    /// What the token looked like in the expression.
+    text: String,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Spanned>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Spanned> = Vec::new();
+    let mut i = 0;
+
+    let error = |position, message: String| ParseError { position, message };
+    let is_name_start = |c: char| c.is_alphabetic() || c == '_';
+    let is_name_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.');
+    let read_while = |i: &mut usize, pred: &dyn Fn(char) -> bool| {
+        while chars.get(*i).is_some_and(|&c| pred(c)) {
+            *i += 1;
+        }
+    };
+
+    while let Some(&c) = chars.get(i) {
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let next = chars.get(i + 1).copied();
+        let operator_expected = tokens.last().is_some_and(|last| last.token.ends_operand());
+
+        let mut symbol = |token, len| {
+            i += len;
+            token
+        };
+        let token = match c {
+            '/' if next == Some('/') => symbol(Token::DoubleSlash, 2),
+            '/' => symbol(Token::Slash, 1),
+            '.' if next == Some('.') => symbol(Token::DotDot, 2),
+            '.' if !next.is_some_and(|c| c.is_ascii_digit()) => symbol(Token::Dot, 1),
+            '@' => symbol(Token::At, 1),
+            ':' if next == Some(':') => symbol(Token::DoubleColon, 2),
+            '(' => symbol(Token::LParen, 1),
+            ')' => symbol(Token::RParen, 1),
+            '[' => symbol(Token::LBracket, 1),
+            ']' => symbol(Token::RBracket, 1),
+            ',' => symbol(Token::Comma, 1),
+            '|' => symbol(Token::Pipe, 1),
+            '+' => symbol(Token::Plus, 1),
+            '-' => symbol(Token::Minus, 1),
+            '=' => symbol(Token::Eq, 1),
+            '!' if next == Some('=') => symbol(Token::Ne, 2),
+            '<' if next == Some('=') => symbol(Token::Le, 2),
+            '<' => symbol(Token::Lt, 1),
+            '>' if next == Some('=') => symbol(Token::Ge, 2),
+            '>' => symbol(Token::Gt, 1),
+            '*' if operator_expected => symbol(Token::Multiply, 1),
+            '*' => symbol(Token::Name("*".to_string()), 1),
+            '"' | '\'' => {
+                let Some(len) = chars[i + 1..].iter().position(|&end| end == c) else {
+                    return Err(error(start, "unterminated string literal".to_string()));
+                };
+                let literal = chars[i + 1..i + 1 + len].iter().collect();
+                i += len + 2;
+                Token::Literal(literal)
+            }
+            '0'..='9' | '.' => {
+                read_while(&mut i, &|c| c.is_ascii_digit());
+                if chars.get(i) == Some(&'.') {
+                    i += 1;
+                    read_while(&mut i, &|c| c.is_ascii_digit());
+                }
+                let number: String = chars[start..i].iter().collect();
+                Token::Number(number.parse().unwrap_or(f64::NAN))
+            }
+            c if is_name_start(c) => {
+                read_while(&mut i, &is_name_char);
+                // a prefix like in `svg:rect` or `svg:*`, but not an axis like in `child::a`
+                if chars.get(i) == Some(&':') && chars.get(i + 1) != Some(&':') {
+                    i += 1;
+                    match chars.get(i) {
+                        Some('*') => i += 1,
+                        Some(&c) if is_name_start(c) => read_while(&mut i, &is_name_char),
+                        _ => {
+                            return Err(error(i, "expected a name after the prefix".to_string()));
+                        }
+                    }
+                }
+
+                let name: String = chars[start..i].iter().collect();
+                if operator_expected {
+                    match name.as_str() {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "div" => Token::Div,
+                        "mod" => Token::Mod,
+                        _ => {
+                            return Err(error(
+                                start,
+                                format!("expected an operator, found `{name}`"),
+                            ));
+                        }
+                    }
+                } else {
+                    Token::Name(name)
+                }
+            }
+            c => return Err(error(start, format!("unexpected character `{c}`"))),
+        };
+
+        tokens.push(Spanned {
+            token,
+            start,
+            text: chars[start..i].iter().collect(),
+        });
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    Arithmetic(Arithmetic, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    /// A location path, starting at the document node if `absolute`.
+    Path {
+        absolute: bool,
+        steps: Vec<Step>,
+    },
+    /// A node set that is filtered by predicates and then continued by a path.
+    Filter {
+        primary: Box<Expr>,
+        predicates: Vec<Expr>,
+        steps: Vec<Step>,
+    },
+    Literal(String),
+    Number(f64),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    /// What the expression evaluates to, which is known before evaluating it.
+    fn ty(&self) -> Type {
+        match self {
+            Self::Or(..) | Self::And(..) | Self::Compare(..) => Type::Boolean,
+            Self::Arithmetic(..) | Self::Negate(_) | Self::Number(_) => Type::Number,
+            Self::Union(..) | Self::Path { .. } | Self::Filter { .. } => Type::Nodes,
+            Self::Literal(_) => Type::String,
+            Self::Call(function, _) => function.signature().1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Nodes,
+    Boolean,
+    Number,
+    String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+impl Step {
+    /// What `//` abbreviates.
+    fn descendant_or_self() -> Self {
+        Self {
+            axis: Axis::DescendantOrSelf,
+            test: NodeTest::Node,
+            predicates: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    /// `self`, which is a keyword in Rust.
+    Itself,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "ancestor" => Self::Ancestor,
+            "ancestor-or-self" => Self::AncestorOrSelf,
+            "attribute" => Self::Attribute,
+            "child" => Self::Child,
+            "descendant" => Self::Descendant,
+            "descendant-or-self" => Self::DescendantOrSelf,
+            "following" => Self::Following,
+            "following-sibling" => Self::FollowingSibling,
+            "parent" => Self::Parent,
+            "preceding" => Self::Preceding,
+            "preceding-sibling" => Self::PrecedingSibling,
+            "self" => Self::Itself,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NodeTest {
+    /// `*`, any node of the principal type of the axis.
+    Any,
+    /// A node of the principal type of the axis with this local name.
+    Name(String),
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction,
+}
+
+impl NodeTest {
+    fn from_node_type(name: &str) -> Option<Self> {
+        Some(match name {
+            "node" => Self::Node,
+            "text" => Self::Text,
+            "comment" => Self::Comment,
+            "processing-instruction" => Self::ProcessingInstruction,
+            _ => return None,
+        })
+    }
+
+    fn matches(&self, node: &Node, axis: Axis) -> bool {
+        let name = match (node, axis) {
+            (Node::Attribute { name, .. }, Axis::Attribute) => Some(name.as_str()),
+            (Node::Tree(node), _) if axis != Axis::Attribute => {
+                node.as_element().map(|elem| &*elem.name.local)
+            }
+            _ => None,
+        };
+
+        match self {
+            Self::Any => name.is_some(),
+            Self::Name(expected) => name.is_some_and(|name| name.eq_ignore_ascii_case(expected)),
+            Self::Node => true,
+            Self::Text => node.tree().is_some_and(|node| node.as_text().is_some()),
+            Self::Comment => node.tree().is_some_and(|node| node.as_comment().is_some()),
+            Self::ProcessingInstruction => node
+                .tree()
+                .is_some_and(|node| matches!(node.data(), NodeData::ProcessingInstruction(_))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Last,
+    Position,
+    Count,
+    String,
+    Concat,
+    Contains,
+    StartsWith,
+    SubstringBefore,
+    SubstringAfter,
+    StringLength,
+    NormalizeSpace,
+    Not,
+    True,
+    False,
+    Boolean,
+    Number,
+    Sum,
+    Name,
+    LocalName,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "last" => Self::Last,
+            "position" => Self::Position,
+            "count" => Self::Count,
+            "string" => Self::String,
+            "concat" => Self::Concat,
+            "contains" => Self::Contains,
+            "starts-with" => Self::StartsWith,
+            "substring-before" => Self::SubstringBefore,
+            "substring-after" => Self::SubstringAfter,
+            "string-length" => Self::StringLength,
+            "normalize-space" => Self::NormalizeSpace,
+            "not" => Self::Not,
+            "true" => Self::True,
+            "false" => Self::False,
+            "boolean" => Self::Boolean,
+            "number" => Self::Number,
+            "sum" => Self::Sum,
+            "name" => Self::Name,
+            "local-name" => Self::LocalName,
+            _ => return None,
+        })
+    }
+
+    /// How many arguments the function takes at least and at most, and what it returns.
+    fn signature(self) -> ((usize, usize), Type) {
+        match self {
+            Self::Last | Self::Position => ((0, 0), Type::Number),
+            Self::Count | Self::Sum => ((1, 1), Type::Number),
+            Self::String | Self::NormalizeSpace => ((0, 1), Type::String),
+            Self::Concat => ((2, usize::MAX), Type::String),
+            Self::Contains | Self::StartsWith => ((2, 2), Type::Boolean),
+            Self::SubstringBefore | Self::SubstringAfter => ((2, 2), Type::String),
+            Self::StringLength | Self::Number => ((0, 1), Type::Number),
+            Self::Not | Self::Boolean => ((1, 1), Type::Boolean),
+            Self::True | Self::False => ((0, 0), Type::Boolean),
+            Self::Name | Self::LocalName => ((0, 1), Type::String),
+        }
+    }
+
+    /// If all arguments have to be node sets.
+    fn takes_nodes(self) -> bool {
+        matches!(self, Self::Count | Self::Sum | Self::Name | Self::LocalName)
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    /// Length of the expression in characters, for errors at its end.
+    end: usize,
+}
+
+impl Parser {
+    fn peek_spanned(&self) -> Option<&Spanned> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, offset: usize) -> Option<&Token> {
+        self.tokens
+            .get(self.pos + offset)
+            .map(|spanned| &spanned.token)
+    }
+
+    /// Where the current token starts.
+    fn position(&self) -> usize {
+        self.peek_spanned()
+            .map_or(self.end, |spanned| spanned.start)
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            position: self.position(),
+            message,
+        }
+    }
+
+    /// An error that something else than the current token was expected.
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let found = match self.peek_spanned() {
+            Some(spanned) => format!("`{}`", spanned.text),
+            None => "end of expression".to_string(),
+        };
+        self.error(format!("expected {expected}, found {found}"))
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.peek() == Some(token);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, token: &Token, expected: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_equality()?;
+        while self.eat(&Token::And) {
+            left = Expr::And(Box::new(left), Box::new(self.parse_equality()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_relational()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Eq) => Comparison::Eq,
+                Some(Token::Ne) => Comparison::Ne,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Compare(op, Box::new(left), Box::new(self.parse_relational()?));
+        }
+    }
+
+    fn parse_relational(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Lt) => Comparison::Lt,
+                Some(Token::Le) => Comparison::Le,
+                Some(Token::Gt) => Comparison::Gt,
+                Some(Token::Ge) => Comparison::Ge,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Compare(op, Box::new(left), Box::new(self.parse_additive()?));
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => Arithmetic::Add,
+                Some(Token::Minus) => Arithmetic::Subtract,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(self.parse_multiplicative()?));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Multiply) => Arithmetic::Multiply,
+                Some(Token::Div) => Arithmetic::Divide,
+                Some(Token::Mod) => Arithmetic::Modulo,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat(&Token::Minus) {
+            Ok(Expr::Negate(Box::new(self.parse_unary()?)))
+        } else {
+            self.parse_union()
+        }
+    }
+
+    fn parse_union(&mut self) -> Result<Expr, ParseError> {
+        let start = self.position();
+        let mut left = self.parse_path()?;
+        if self.peek() == Some(&Token::Pipe) && left.ty() != Type::Nodes {
+            return Err(ParseError {
+                position: start,
+                message: "only node sets can be combined with `|`".to_string(),
+            });
+        }
+
+        while self.eat(&Token::Pipe) {
+            let start = self.position();
+            let right = self.parse_path()?;
+            if right.ty() != Type::Nodes {
+                return Err(ParseError {
+                    position: start,
+                    message: "only node sets can be combined with `|`".to_string(),
+                });
+            }
+            left = Expr::Union(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some(Token::Slash) => {
+                self.pos += 1;
+                let steps = if self.starts_step() {
+                    self.parse_relative_path()?
+                } else {
+                    Vec::new()
+                };
+                Ok(Expr::Path {
+                    absolute: true,
+                    steps,
+                })
+            }
+            Some(Token::DoubleSlash) => {
+                self.pos += 1;
+                let mut steps = vec![Step::descendant_or_self()];
+                steps.extend(self.parse_relative_path()?);
+                Ok(Expr::Path {
+                    absolute: true,
+                    steps,
+                })
+            }
+            _ if self.starts_step() => Ok(Expr::Path {
+                absolute: false,
+                steps: self.parse_relative_path()?,
+            }),
+            _ => self.parse_filter(),
+        }
+    }
+
+    fn parse_filter(&mut self) -> Result<Expr, ParseError> {
+        let start = self.position();
+        let primary = self.parse_primary()?;
+        let predicates = self.parse_predicates()?;
+        let steps = match self.peek() {
+            Some(Token::Slash | Token::DoubleSlash) => self.parse_continued_path()?,
+            _ => Vec::new(),
+        };
+
+        if predicates.is_empty() && steps.is_empty() {
+            return Ok(primary);
+        }
+        if primary.ty() != Type::Nodes {
+            return Err(ParseError {
+                position: start,
+                message: "only node sets can be filtered or continued by a path".to_string(),
+            });
+        }
+        Ok(Expr::Filter {
+            primary: Box::new(primary),
+            predicates,
+            steps,
+        })
+    }
+
+    /// If the current token starts a location step, as opposed to e.g. a function call.
+    fn starts_step(&self) -> bool {
+        match self.peek() {
+            Some(Token::Dot | Token::DotDot | Token::At) => true,
+            Some(Token::Name(name)) => match self.peek_nth(1) {
+                Some(Token::LParen) => NodeTest::from_node_type(name).is_some(),
+                _ => true,
+            },
+            _ => false,
+        }
+    }
+
+    fn parse_relative_path(&mut self) -> Result<Vec<Step>, ParseError> {
+        let mut steps = vec![self.parse_step()?];
+        steps.extend(self.parse_continued_path()?);
+        Ok(steps)
+    }
+
+    /// Parses any further steps, each introduced by `/` or `//`.
+    fn parse_continued_path(&mut self) -> Result<Vec<Step>, ParseError> {
+        let mut steps = Vec::new();
+        loop {
+            if self.eat(&Token::DoubleSlash) {
+                steps.push(Step::descendant_or_self());
+            } else if !self.eat(&Token::Slash) {
+                return Ok(steps);
+            }
+            steps.push(self.parse_step()?);
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, ParseError> {
+        let abbreviated = match self.peek() {
+            Some(Token::Dot) => Some(Axis::Itself),
+            Some(Token::DotDot) => Some(Axis::Parent),
+            _ => None,
+        };
+        if let Some(axis) = abbreviated {
+            self.pos += 1;
+            return Ok(Step {
+                axis,
+                test: NodeTest::Node,
+                predicates: Vec::new(),
+            });
+        }
+
+        let axis = if self.eat(&Token::At) {
+            Axis::Attribute
+        } else if let Some(Token::Name(name)) = self.peek()
+            && self.peek_nth(1) == Some(&Token::DoubleColon)
+        {
+            let Some(axis) = Axis::from_name(name) else {
+                return Err(self.error(format!("unknown axis `{name}`")));
+            };
+            self.pos += 2;
+            axis
+        } else {
+            Axis::Child
+        };
+
+        let test = self.parse_node_test()?;
+        let predicates = self.parse_predicates()?;
+        Ok(Step {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    fn parse_node_test(&mut self) -> Result<NodeTest, ParseError> {
+        let Some(Token::Name(name)) = self.peek() else {
+            return Err(self.unexpected("a node test"));
+        };
+
+        if self.peek_nth(1) == Some(&Token::LParen) {
+            let Some(test) = NodeTest::from_node_type(name) else {
+                return Err(self.error(format!("unknown node type `{name}()`")));
+            };
+            self.pos += 2;
+            self.expect(&Token::RParen, "`)`")?;
+            return Ok(test);
+        }
+
+        let local = name.rsplit(':').next().unwrap_or(name);
+        let test = if local == "*" {
+            NodeTest::Any
+        } else {
+            NodeTest::Name(local.to_string())
+        };
+        self.pos += 1;
+        Ok(test)
+    }
+
+    fn parse_predicates(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut predicates = Vec::new();
+        while self.eat(&Token::LBracket) {
+            predicates.push(self.parse_expr()?);
+            self.expect(&Token::RBracket, "`]`")?;
+        }
+        Ok(predicates)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.position();
+        let expr = match self.peek() {
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RParen, "`)`")?;
+                return Ok(expr);
+            }
+            Some(Token::Literal(literal)) => Expr::Literal(literal.clone()),
+            Some(Token::Number(number)) => Expr::Number(*number),
+            Some(Token::Name(name)) if self.peek_nth(1) == Some(&Token::LParen) => {
+                let Some(function) = Function::from_name(name) else {
+                    return Err(self.error(format!("unknown function `{name}()`")));
+                };
+                let name = name.clone();
+                self.pos += 2;
+                return self.parse_call(function, &name, start);
+            }
+            _ => return Err(self.unexpected("an expression")),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+
+    /// Parses the arguments of a function call, after the opening parenthesis.
+    fn parse_call(
+        &mut self,
+        function: Function,
+        name: &str,
+        start: usize,
+    ) -> Result<Expr, ParseError> {
+        let mut args = Vec::new();
+        if !self.eat(&Token::RParen) {
+            loop {
+                let arg_start = self.position();
+                let arg = self.parse_expr()?;
+                if function.takes_nodes() && arg.ty() != Type::Nodes {
+                    return Err(ParseError {
+                        position: arg_start,
+                        message: format!("`{name}()` expects a node set"),
+                    });
+                }
+                args.push(arg);
+
+                if !self.eat(&Token::Comma) {
+                    self.expect(&Token::RParen, "`,` or `)`")?;
+                    break;
+                }
+            }
+        }
+
+        let ((min, max), _) = function.signature();
+        if args.len() < min || args.len() > max {
+            let expected = match (min, max) {
+                (min, max) if min == max => format!("{min}"),
+                (min, usize::MAX) => format!("at least {min}"),
+                (min, max) => format!("{min} to {max}"),
+            };
+            return Err(ParseError {
+                position: start,
+                message: format!(
+                    "`{name}()` takes {expected} arguments, but {} were given",
+                    args.len()
+                ),
+            });
+        }
+
+        Ok(Expr::Call(function, args))
+    }
+}
+
+/// A node as XPath sees it. Unlike in the tree, attributes are nodes too.
+#[derive(Debug, Clone)]
+enum Node {
+    Tree(NodeRef),
+    Attribute {
+        owner: NodeRef,
+        /// Position among the attributes of the owner.
+        index: usize,
+        name: String,
+        value: String,
+    },
+}
+
+impl Node {
+    fn tree(&self) -> Option<&NodeRef> {
+        match self {
+            Self::Tree(node) => Some(node),
+            Self::Attribute { .. } => None,
+        }
+    }
+
+    fn string_value(&self) -> String {
+        let node = match self {
+            Self::Tree(node) => node,
+            Self::Attribute { value, .. } => return value.clone(),
+        };
+
+        match node.data() {
+            NodeData::Text(text) | NodeData::Comment(text) => text.borrow().clone(),
+            NodeData::ProcessingInstruction(contents) => contents.borrow().1.clone(),
+            NodeData::Doctype(_) => String::new(),
+            NodeData::Element(_) | NodeData::Document(_) | NodeData::DocumentFragment => {
+                node.text_contents()
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Tree(node) => node
+                .as_element()
+                .map(|elem| elem.name.local.to_string())
+                .unwrap_or_default(),
+            Self::Attribute { name, .. } => name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    /// Always in document order and without duplicates.
+    Nodes(Vec<Node>),
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    fn boolean(&self) -> bool {
+        match self {
+            Self::Nodes(nodes) => !nodes.is_empty(),
+            Self::Boolean(boolean) => *boolean,
+            Self::Number(number) => *number != 0.0 && !number.is_nan(),
+            Self::String(string) => !string.is_empty(),
+        }
+    }
+
+    fn number(&self) -> f64 {
+        match self {
+            Self::Boolean(boolean) => f64::from(u8::from(*boolean)),
+            Self::Number(number) => *number,
+            Self::Nodes(_) | Self::String(_) => parse_number(&self.string()),
+        }
+    }
+
+    fn string(&self) -> String {
+        match self {
+            Self::Nodes(nodes) => nodes.first().map(Node::string_value).unwrap_or_default(),
+            Self::Boolean(boolean) => boolean.to_string(),
+            Self::Number(number) => format_number(*number),
+            Self::String(string) => string.clone(),
+        }
+    }
+
+    fn into_nodes(self) -> Vec<Node> {
+        match self {
+            Self::Nodes(nodes) => nodes,
+            // the parser makes sure only node sets end up here
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn is_xml_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+/// Converts a string to a number like XPath's `number()`, without exponents or infinities.
+fn parse_number(raw: &str) -> f64 {
+    let raw = raw.trim_matches(is_xml_space);
+    let digits = raw.strip_prefix('-').unwrap_or(raw);
+    let valid = digits.chars().any(|c| c.is_ascii_digit())
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.matches('.').count() <= 1;
+
+    if valid {
+        raw.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+fn format_number(number: f64) -> String {
+    if number.is_nan() {
+        "NaN".to_string()
+    } else if number.is_infinite() {
+        if number > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }
+        .to_string()
+    } else if number == 0.0 {
+        // also for -0
+        "0".to_string()
+    } else {
+        number.to_string()
+    }
+}
+
+struct Context<'a> {
+    node: &'a Node,
+    /// 1-based position of the node in the node set currently being filtered.
+    position: usize,
+    size: usize,
+}
+
+struct Evaluator {
+    root: NodeRef,
+    /// Position of each node of the tree in document order.
+    order: HashMap<*const kuchikiki::Node, usize>,
+}
+
+impl Evaluator {
+    fn new(root: &NodeRef) -> Self {
+        let order = root
+            .inclusive_descendants()
+            .enumerate()
+            .map(|(i, node)| (std::ptr::from_ref::<kuchikiki::Node>(&node), i))
+            .collect();
+        Self {
+            root: root.clone(),
+            order,
+        }
+    }
+
+    /// Sorts the nodes in document order and removes duplicates.
+    fn normalize(&self, mut nodes: Vec<Node>) -> Vec<Node> {
+        let key = |node: &Node| {
+            let (tree, attribute) = match node {
+                Node::Tree(node) => (node, 0),
+                Node::Attribute { owner, index, .. } => (owner, index + 1),
+            };
+            let ptr = std::ptr::from_ref::<kuchikiki::Node>(tree);
+            (
+                self.order.get(&ptr).copied().unwrap_or(usize::MAX),
+                attribute,
+            )
+        };
+        nodes.sort_by_key(key);
+        nodes.dedup_by_key(|node| key(node));
+        nodes
+    }
+
+    fn eval(&self, expr: &Expr, context: &Context) -> Value {
+        match expr {
+            Expr::Or(left, right) => Value::Boolean(
+                self.eval(left, context).boolean() || self.eval(right, context).boolean(),
+            ),
+            Expr::And(left, right) => Value::Boolean(
+                self.eval(left, context).boolean() && self.eval(right, context).boolean(),
+            ),
+            Expr::Compare(op, left, right) => Value::Boolean(compare(
+                *op,
+                &self.eval(left, context),
+                &self.eval(right, context),
+            )),
+            Expr::Arithmetic(op, left, right) => {
+                let left = self.eval(left, context).number();
+                let right = self.eval(right, context).number();
+                Value::Number(match op {
+                    Arithmetic::Add => left + right,
+                    Arithmetic::Subtract => left - right,
+                    Arithmetic::Multiply => left * right,
+                    Arithmetic::Divide => left / right,
+                    Arithmetic::Modulo => left % right,
+                })
+            }
+            Expr::Negate(operand) => Value::Number(-self.eval(operand, context).number()),
+            Expr::Union(left, right) => {
+                let mut nodes = self.eval(left, context).into_nodes();
+                nodes.extend(self.eval(right, context).into_nodes());
+                Value::Nodes(self.normalize(nodes))
+            }
+            Expr::Path { absolute, steps } => {
+                let start = if *absolute {
+                    Node::Tree(self.root.clone())
+                } else {
+                    context.node.clone()
+                };
+                Value::Nodes(self.apply_steps(vec![start], steps))
+            }
+            Expr::Filter {
+                primary,
+                predicates,
+                steps,
+            } => {
+                let nodes = self.eval(primary, context).into_nodes();
+                let nodes = self.filter(nodes, predicates);
+                Value::Nodes(self.apply_steps(nodes, steps))
+            }
+            Expr::Literal(literal) => Value::String(literal.clone()),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Call(function, args) => self.call(*function, args, context),
+        }
+    }
+
+    fn apply_steps(&self, mut nodes: Vec<Node>, steps: &[Step]) -> Vec<Node> {
+        for step in steps {
+            let mut next = Vec::new();
+            for node in &nodes {
+                let candidates = axis(node, step.axis)
+                    .into_iter()
+                    .filter(|candidate| step.test.matches(candidate, step.axis))
+                    .collect();
+                next.extend(self.filter(candidates, &step.predicates));
+            }
+            nodes = self.normalize(next);
+        }
+        nodes
+    }
+
+    /// Keeps only the nodes for which all predicates hold, one predicate after another.
+    fn filter(&self, mut nodes: Vec<Node>, predicates: &[Expr]) -> Vec<Node> {
+        for predicate in predicates {
+            let size = nodes.len();
+            nodes = nodes
+                .into_iter()
+                .enumerate()
+                .filter(|(i, node)| {
+                    let context = Context {
+                        node,
+                        position: i + 1,
+                        size,
+                    };
+                    match self.eval(predicate, &context) {
+                        // `[2]` is short for `[position() = 2]`
+                        Value::Number(number) => number == (i + 1) as f64,
+                        value => value.boolean(),
+                    }
+                })
+                .map(|(_, node)| node)
+                .collect();
+        }
+        nodes
+    }
+
+    fn call(&self, function: Function, args: &[Expr], context: &Context) -> Value {
+        let arg = |i: usize| self.eval(&args[i], context);
+        let string_arg = |i: usize| {
+            args.get(i)
+                .map_or_else(|| context.node.string_value(), |_| arg(i).string())
+        };
+        let first_node = || -> Option<Node> {
+            match args.first() {
+                Some(_) => arg(0).into_nodes().into_iter().next(),
+                None => Some(context.node.clone()),
+            }
+        };
+
+        match function {
+            Function::Last => Value::Number(context.size as f64),
+            Function::Position => Value::Number(context.position as f64),
+            Function::Count => Value::Number(arg(0).into_nodes().len() as f64),
+            Function::String => Value::String(string_arg(0)),
+            Function::Concat => Value::String((0..args.len()).map(|i| arg(i).string()).collect()),
+            Function::Contains => Value::Boolean(string_arg(0).contains(&string_arg(1))),
+            Function::StartsWith => Value::Boolean(string_arg(0).starts_with(&string_arg(1))),
+            Function::SubstringBefore => {
+                let (haystack, needle) = (string_arg(0), string_arg(1));
+                let before = haystack.find(&needle).map(|idx| &haystack[..idx]);
+                Value::String(before.unwrap_or_default().to_string())
+            }
+            Function::SubstringAfter => {
+                let (haystack, needle) = (string_arg(0), string_arg(1));
+                let after = haystack
+                    .find(&needle)
+                    .map(|idx| &haystack[idx + needle.len()..]);
+                Value::String(after.unwrap_or_default().to_string())
+            }
+            Function::StringLength => Value::Number(string_arg(0).chars().count() as f64),
+            Function::NormalizeSpace => {
+                let string = string_arg(0);
+                let words: Vec<_> = string
+                    .split(is_xml_space)
+                    .filter(|word| !word.is_empty())
+                    .collect();
+                Value::String(words.join(" "))
+            }
+            Function::Not => Value::Boolean(!arg(0).boolean()),
+            Function::True => Value::Boolean(true),
+            Function::False => Value::Boolean(false),
+            Function::Boolean => Value::Boolean(arg(0).boolean()),
+            Function::Number => Value::Number(match args.first() {
+                Some(_) => arg(0).number(),
+                None => parse_number(&context.node.string_value()),
+            }),
+            Function::Sum => Value::Number(
+                arg(0)
+                    .into_nodes()
+                    .iter()
+                    .map(|node| parse_number(&node.string_value()))
+                    .sum(),
+            ),
+            Function::Name | Function::LocalName => {
+                Value::String(first_node().map(|node| node.name()).unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// All nodes on the axis starting at the node, in the order of the axis.
+///
+/// Reverse axes like `ancestor` start at the node closest to the given one.
+fn axis(node: &Node, axis: Axis) -> Vec<Node> {
+    let tree = |nodes: &mut dyn Iterator<Item = NodeRef>| nodes.map(Node::Tree).collect();
+
+    let node_ref = match node {
+        Node::Tree(node_ref) => node_ref,
+        Node::Attribute { owner, .. } => {
+            return match axis {
+                Axis::Parent => vec![Node::Tree(owner.clone())],
+                Axis::Ancestor => tree(&mut owner.inclusive_ancestors()),
+                Axis::AncestorOrSelf => {
+                    let mut nodes = vec![node.clone()];
+                    nodes.extend(owner.inclusive_ancestors().map(Node::Tree));
+                    nodes
+                }
+                Axis::Itself => vec![node.clone()],
+                Axis::Following => {
+                    let mut nodes: Vec<_> = owner.descendants().map(Node::Tree).collect();
+                    nodes.extend(following(owner));
+                    nodes
+                }
+                Axis::Preceding => preceding(owner),
+                _ => Vec::new(),
+            };
+        }
+    };
+
+    match axis {
+        Axis::Ancestor => tree(&mut node_ref.ancestors()),
+        Axis::AncestorOrSelf => tree(&mut node_ref.inclusive_ancestors()),
+        Axis::Attribute => attributes(node_ref),
+        Axis::Child => tree(&mut node_ref.children()),
+        Axis::Descendant => tree(&mut node_ref.descendants()),
+        Axis::DescendantOrSelf => tree(&mut node_ref.inclusive_descendants()),
+        Axis::Following => following(node_ref),
+        Axis::FollowingSibling => tree(&mut node_ref.following_siblings()),
+        Axis::Parent => tree(&mut node_ref.parent().into_iter()),
+        Axis::Preceding => preceding(node_ref),
+        Axis::PrecedingSibling => tree(&mut node_ref.preceding_siblings()),
+        Axis::Itself => vec![node.clone()],
+    }
+}
+
+fn attributes(node: &NodeRef) -> Vec<Node> {
+    let Some(elem) = node.as_element() else {
+        return Vec::new();
+    };
+
+    elem.attributes
+        .borrow()
+        .map
+        .iter()
+        .enumerate()
+        .map(|(index, (name, attr))| Node::Attribute {
+            owner: node.clone(),
+            index,
+            name: name.local.to_string(),
+            value: attr.value.clone(),
+        })
+        .collect()
+}
+
+/// All nodes after the node in document order, except its descendants.
+fn following(node: &NodeRef) -> Vec<Node> {
+    node.inclusive_ancestors()
+        .flat_map(|ancestor| ancestor.following_siblings())
+        .flat_map(|sibling| sibling.inclusive_descendants())
+        .map(Node::Tree)
+        .collect()
+}
+
+/// All nodes before the node in reverse document order, except its ancestors.
+fn preceding(node: &NodeRef) -> Vec<Node> {
+    node.inclusive_ancestors()
+        .flat_map(|ancestor| ancestor.preceding_siblings())
+        .flat_map(|sibling| {
+            let descendants: Vec<_> = sibling.inclusive_descendants().collect();
+            descendants.into_iter().rev()
+        })
+        .map(Node::Tree)
+        .collect()
+}
+
+fn compare(op: Comparison, left: &Value, right: &Value) -> bool {
+    let string = |node: &Node| Value::String(node.string_value());
+
+    match (left, right) {
+        (Value::Nodes(left), Value::Nodes(right)) => {
+            let right: Vec<_> = right.iter().map(string).collect();
+            left.iter().any(|left| {
+                right
+                    .iter()
+                    .any(|right| compare_atomic(op, &string(left), right))
+            })
+        }
+        (Value::Nodes(_), Value::Boolean(_)) | (Value::Boolean(_), Value::Nodes(_)) => {
+            compare_atomic(
+                op,
+                &Value::Boolean(left.boolean()),
+                &Value::Boolean(right.boolean()),
+            )
+        }
+        (Value::Nodes(left), right) => left
+            .iter()
+            .any(|left| compare_atomic(op, &string(left), right)),
+        (left, Value::Nodes(right)) => right
+            .iter()
+            .any(|right| compare_atomic(op, left, &string(right))),
+        (left, right) => compare_atomic(op, left, right),
+    }
+}
+
+/// Compares two values that are not node sets.
+fn compare_atomic(op: Comparison, left: &Value, right: &Value) -> bool {
+    match op {
+        Comparison::Eq | Comparison::Ne => {
+            let equal = if matches!(left, Value::Boolean(_)) || matches!(right, Value::Boolean(_)) {
+                left.boolean() == right.boolean()
+            } else if matches!(left, Value::Number(_)) || matches!(right, Value::Number(_)) {
+                left.number() == right.number()
+            } else {
+                left.string() == right.string()
+            };
+            equal == (op == Comparison::Eq)
+        }
+        Comparison::Lt => left.number() < right.number(),
+        Comparison::Le => left.number() <= right.number(),
+        Comparison::Gt => left.number() > right.number(),
+        Comparison::Ge => left.number() >= right.number(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    const HTML: &str = "<table>\
+        <tr><th>Price</th><td>5</td><td class=\"sale\">4</td></tr>\
+        <tr><th>Size</th><td>L</td></tr>\
+        </table><a href=\"/a\" title=\"A\">one</a><!--note-->";
+
+    fn select(expression: &str) -> Vec<String> {
+        let root = kuchikiki::parse_html().one(HTML);
+        XPath::parse(expression)
+            .unwrap()
+            .select(&root)
+            .iter()
+            .map(NodeRef::to_string)
+            .collect()
+    }
+
+    fn error(expression: &str) -> (usize, String) {
+        let err = XPath::parse(expression).unwrap_err();
+        (err.position, err.message)
+    }
+
+    #[test]
+    fn paths_and_axes() {
+        assert_eq!(select("//th"), ["<th>Price</th>", "<th>Size</th>"]);
+        assert_eq!(select("/html/body/a/text()"), ["one"]);
+        assert_eq!(select("//td[@class]/.."), select("//tr[1]"));
+        assert_eq!(
+            select("//th[contains(., 'Price')]/following-sibling::td"),
+            ["<td>5</td>", "<td class=\"sale\">4</td>"]
+        );
+        assert_eq!(
+            select("//td[. = 'L']/preceding-sibling::*"),
+            ["<th>Size</th>"]
+        );
+        assert_eq!(select("//a/ancestor::*[last()]").len(), 1);
+        assert_eq!(select("//comment()"), ["<!--note-->"]);
+        assert_eq!(select("(//TH)[2]/child::text()"), ["Size"]);
+    }
+
+    #[test]
+    fn attributes_become_text() {
+        assert_eq!(select("//a/@*"), ["/a", "A"]);
+        assert_eq!(select("//@class"), ["sale"]);
+        assert_eq!(select("name(//a/@title)"), ["title"]);
+    }
+
+    #[test]
+    fn predicates_and_positions() {
+        assert_eq!(select("//td[2]"), ["<td class=\"sale\">4</td>"]);
+        assert_eq!(select("(//td)[last()]"), ["<td>L</td>"]);
+        assert_eq!(select("//tr[count(td) > 1]/th/text()"), ["Price"]);
+        assert_eq!(select("//td[position() != 1 or . = 'L']").len(), 2);
+        assert_eq!(select("//td[not(@class)][1]"), ["<td>5</td>", "<td>L</td>"]);
+    }
+
+    #[test]
+    fn non_node_values() {
+        assert_eq!(select("count(//td)"), ["3"]);
+        assert_eq!(select("sum(//td[@class]) div 2"), ["2"]);
+        assert_eq!(select("1 div 0"), ["Infinity"]);
+        assert_eq!(
+            select("concat(name(//a), '-', normalize-space('  x  y '))"),
+            ["a-x y"]
+        );
+        assert_eq!(select("//td = 4 and //td = 'L'"), ["true"]);
+        assert_eq!(select("substring-after(//a/@href, '/')"), ["a"]);
+    }
+
+    #[test]
+    fn union_is_in_document_order() {
+        assert_eq!(
+            select("//a | //th[1] | //th"),
+            [
+                "<th>Price</th>",
+                "<th>Size</th>",
+                "<a href=\"/a\" title=\"A\">one</a>"
+            ]
+        );
+    }
+
+    #[test]
+    fn operators_and_names_are_told_apart() {
+        assert_eq!(select("//*[name() = 'td'][2 * 1]").len(), 1);
+        assert_eq!(select("count(//div) = 0"), ["true"]);
+        assert_eq!(select("7 mod 4 - -1"), ["4"]);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(
+            error("//a["),
+            (
+                4,
+                "expected an expression, found end of expression".to_string()
+            )
+        );
+        assert_eq!(
+            error("//a[1"),
+            (5, "expected `]`, found end of expression".to_string())
+        );
+        assert_eq!(error("//a]"), (3, "unexpected `]`".to_string()));
+        assert_eq!(
+            error("child::a/sibling::b"),
+            (9, "unknown axis `sibling`".to_string())
+        );
+        assert_eq!(
+            error("//a[frob()]"),
+            (4, "unknown function `frob()`".to_string())
+        );
+        assert_eq!(
+            error("contains('a')"),
+            (
+                0,
+                "`contains()` takes 2 arguments, but 1 were given".to_string()
+            )
+        );
+        assert_eq!(
+            error("count('a')"),
+            (6, "`count()` expects a node set".to_string())
+        );
+        assert_eq!(error("//a 'b'"), (4, "unexpected `'b'`".to_string()));
+        assert_eq!(
+            error("'open"),
+            (0, "unterminated string literal".to_string())
+        );
+        assert_eq!(error("//a/#"), (4, "unexpected character `#`".to_string()));
+    }
+}
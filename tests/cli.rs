@@ -1,5 +1,7 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
 
 macro_rules! cmd_success_tests {
     ($($name:ident: $value:expr,)*) => {
@@ -35,4 +37,1855 @@ cmd_success_tests!(
         ["#my-id", "--remove-nodes", "a"],
         "<div id=\"my-id\"></div>\n",
     ),
+    remove_several_children: (
+        "<article><h1>Title</h1><script>a()</script><p>Text</p><script>b()</script></article>",
+        ["article", "--remove-nodes", "script"],
+        "<article><h1>Title</h1><p>Text</p></article>\n",
+    ),
+    remove_match_itself: (
+        "<article class=\"ad\">Buy</article><article>Read</article>",
+        ["article", "--remove-nodes", ".ad", "--text"],
+        "Read\n",
+    ),
+    remove_match_inside_removed_node: (
+        "<div class=\"ad\"><p>Buy</p></div><p>Read</p>",
+        ["p", "--remove-nodes", ".ad", "--text"],
+        "Read\n",
+    ),
+    remove_first_child_of_earlier_match: (
+        "<p><i></i></p><p>z</p>",
+        ["p", "-r", "i"],
+        "<p></p>\n<p>z</p>\n",
+    ),
+    remove_first_child_of_earlier_match_count: (
+        "<p><i></i></p><p>z</p>",
+        ["p", "-r", "i", "-c"],
+        "2\n",
+    ),
+    remove_nested_children: (
+        "<article><aside>a<aside>b</aside></aside><p>Text</p></article>",
+        ["article", "--remove-nodes", "aside"],
+        "<article><p>Text</p></article>\n",
+    ),
+    remove_every_occurrence: (
+        "<body><script>1</script><p>a</p><script>2</script><style>x</style><script>3</script><p>b</p><script>4</script><style>y</style><script>5</script></body>",
+        ["body", "-r", "script", "-r", "style"],
+        "<body><p>a</p><p>b</p></body>\n",
+    ),
+    remove_repeated_nested_occurrences: (
+        "<div id=\"root\"><span><b>1</b><i><b>2</b></i></span><p><b>3<b>4</b></b></p><b>5</b></div>",
+        ["#root", "--remove-nodes", "b"],
+        "<div id=\"root\"><span><i></i></span><p></p></div>\n",
+    ),
+    remove_repeated_nested_occurrences_in_sibling_matches: (
+        "<div><b>1<b>2</b></b><i><b>3</b></i></div><div><b>4</b>z</div>",
+        ["div", "--remove-nodes", "b"],
+        "<div><i></i></div>\n<div>z</div>\n",
+    ),
+    json_object: (
+        "<html><head></head><body><a href=\"/foo\" title=\"x\">Hello</a></body></html>",
+        ["a", "--json"],
+        "[{\"tag\":\"a\",\"attributes\":{\"href\":\"/foo\",\"title\":\"x\"},\"text\":\"Hello\",\"html\":\"<a href=\\\"/foo\\\" title=\\\"x\\\">Hello</a>\"}]\n",
+    ),
+    json_no_matches: (
+        "<html><head></head><body></body></html>",
+        ["a", "--json", "--allow-empty"],
+        "[]\n",
+    ),
+    json_only_requested_attributes: (
+        "<html><head></head><body><a href=\"/foo\" title=\"x\">Hello</a><a>Hi</a></body></html>",
+        ["a", "--json", "--attributes", "href"],
+        "[{\"tag\":\"a\",\"attributes\":{\"href\":\"/foo\"},\"text\":\"Hello\",\"html\":\"<a href=\\\"/foo\\\" title=\\\"x\\\">Hello</a>\"},{\"tag\":\"a\",\"attributes\":{},\"text\":\"Hi\",\"html\":\"<a>Hi</a>\"}]\n",
+    ),
+    json_escapes_quotes_and_non_ascii: (
+        "<html><head></head><body><span title='say \"hï\" \\ ✓'>ü</span></body></html>",
+        ["span", "--json", "--attributes", "title"],
+        "[{\"tag\":\"span\",\"attributes\":{\"title\":\"say \\\"hï\\\" \\\\ ✓\"},\"text\":\"ü\",\"html\":\"<span title=\\\"say &quot;hï&quot; \\\\ ✓\\\">ü</span>\"}]\n",
+    ),
+    json_ignore_whitespace: (
+        "<html><head></head><body><div>\n  <p>a</p>\n  <p>b</p>\n</div></body></html>",
+        ["div", "--json", "--ignore-whitespace", "--attributes", "id"],
+        "[{\"tag\":\"div\",\"attributes\":{},\"text\":\"a\\nb\",\"html\":\"<div>\\n  <p>a</p>\\n  <p>b</p>\\n</div>\"}]\n",
+    ),
+    count_matches: (
+        "<html><head></head><body><table><tr><td>a</td></tr><tr><td>b\nc</td></tr></table></body></html>",
+        ["tr", "--count"],
+        "2\n",
+    ),
+    count_skips_removed_nodes: (
+        "<html><head></head><body><div><p>a</p><div class=\"ad\"><p>b</p></div></div></body></html>",
+        ["div", "-c", "--remove-nodes", ".ad"],
+        "1\n",
+    ),
+    limit_matches: (
+        "<ul><li>1</li><li>2</li><li>3</li></ul>",
+        ["li", "--limit", "2", "--text"],
+        "1\n2\n",
+    ),
+    limit_above_match_count: (
+        "<ul><li>1</li><li>2</li></ul>",
+        ["li", "--limit", "5", "--text"],
+        "1\n2\n",
+    ),
+    first_match: (
+        "<ul><li>1</li><li>2</li><li>3</li></ul>",
+        ["li", "--first", "--text"],
+        "1\n",
+    ),
+    limit_is_applied_to_count: (
+        "<ul><li>1</li><li>2</li><li>3</li></ul>",
+        ["li", "--limit", "2", "--count"],
+        "2\n",
+    ),
+    nth_match: (
+        "<table><tr><td>1</td><td>2</td><td>3</td></tr></table>",
+        ["td", "--nth", "3", "--text"],
+        "3\n",
+    ),
+    nth_first_match: (
+        "<table><tr><td>1</td><td>2</td><td>3</td></tr></table>",
+        ["td", "--nth", "1", "--text"],
+        "1\n",
+    ),
+    nth_last_match: (
+        "<ul><li>1</li><li>2</li><li>3</li></ul>",
+        ["li", "--nth", "-1", "--text"],
+        "3\n",
+    ),
+    nth_from_end: (
+        "<ul><li>1</li><li>2</li><li>3</li></ul>",
+        ["li", "--nth=-3", "--text"],
+        "1\n",
+    ),
+    ndjson_one_object_per_line: (
+        "<html><head></head><body><a href=\"/a\">A</a><a href=\"/b\">B</a></body></html>",
+        ["a", "--ndjson"],
+        "{\"tag\":\"a\",\"attributes\":{\"href\":\"/a\"},\"text\":\"A\",\"html\":\"<a href=\\\"/a\\\">A</a>\"}\n{\"tag\":\"a\",\"attributes\":{\"href\":\"/b\"},\"text\":\"B\",\"html\":\"<a href=\\\"/b\\\">B</a>\"}\n",
+    ),
+    ndjson_attributes_and_empty_matches: (
+        "<html><head></head><body><a href=\"/a\" id=\"x\">A</a><a>B</a></body></html>",
+        ["a", "--ndjson", "--attributes", "href"],
+        "{\"attributes\":{\"href\":\"/a\"}}\n{\"attributes\":{}}\n",
+    ),
+    contains_with_remove_nodes: (
+        "<html><head></head><body><a href=\"/a\">Docs</a><p><a href=\"/b\">Download <span>now</span></a></p></body></html>",
+        ["p > a:contains(\"Download\")", "--remove-nodes", "span:icontains(NOW)"],
+        "<a href=\"/b\">Download </a>\n",
+    ),
+    text_matches_with_limit: (
+        "<html><head></head><body><ul><li>notes</li><li>v1.2</li><li>v1.3</li></ul></body></html>",
+        ["li", "--text-matches", "^v\\d+\\.\\d+", "--limit", "1", "--text"],
+        "v1.2\n",
+    ),
+    text_not_matches_with_attributes: (
+        "<html><head></head><body><a href=\"/a\">Ad</a><a href=\"/b\">Blog</a></body></html>",
+        ["a", "--text-not-matches", "^Ad$", "--attributes", "href"],
+        "/b\n",
+    ),
+    attr_matches_combined: (
+        "<html><head></head><body><a href=\"https://github.com/a\" rel=\"me\">1</a><a href=\"https://github.com/b\">2</a><a>3</a></body></html>",
+        ["a", "--attr-matches", "href=^https://github\\.com/", "--attr-matches", "rel=me", "--text"],
+        "1\n",
+    ),
+    invert_removes_matches: (
+        "<html><head><script>x()</script></head><body><nav>menu</nav><p>Text</p><footer>f</footer></body></html>",
+        ["-v", "nav, footer, script"],
+        "<html><head></head><body><p>Text</p></body></html>\n",
+    ),
+    invert_text: (
+        "<html><head></head><body><nav>menu</nav><p>Text</p></body></html>",
+        ["--invert", "nav", "--text"],
+        "Text\n",
+    ),
+    inner_html: (
+        "<html><head></head><body><div class=\"content\">Hi <!--note--><b>there</b></div><div class=\"content\"></div></body></html>",
+        ["div.content", "--inner"],
+        "Hi <!--note--><b>there</b>\n\n",
+    ),
+    tag_names_with_limit: (
+        "<html><head></head><body><div id=\"main\"><p>a</p><ul><li>b</li></ul></div></body></html>",
+        ["#main *", "--tag-names", "--limit", "2"],
+        "p\nul\n",
+    ),
+    classes: (
+        "<div class=\"card  wide\"><p class=\"\n  note\tsmall\n\">x</p><p>y</p></div>",
+        ["div, p", "--classes"],
+        "card\nwide\nnote\nsmall\n",
+    ),
+    classes_unique_with_limit: (
+        "<p class=\"a b\"></p><p class=\"b c\"></p><p class=\"d\"></p>",
+        ["p", "--classes", "--unique", "--limit", "2"],
+        "a\nb\nc\n",
+    ),
+    stats: (
+        "<ul><li class=\"a\">one</li><li>two</li></ul>",
+        ["ul", "--stats"],
+        "tag  count  text  attributes  depth\nli       2     6           1      4\nul       1     0           0      3\n",
+    ),
+    stats_json: (
+        "<p>text</p>",
+        ["p", "--stats", "--json"],
+        "[{\"tag\":\"p\",\"count\":1,\"text_length\":4,\"attributes\":0,\"max_depth\":3}]\n",
+    ),
+    outline: (
+        "<nav id=\"top\"><ul class=\"nav\"><li>a</li><li>b</li><li>c</li></ul></nav><p>text</p>",
+        ["body", "--outline"],
+        "body\n  nav#top > ul.nav > li ×3\n    \"a\"\n  p\n    \"text\"\n",
+    ),
+    outline_max_depth: (
+        "<main><section><h2>a</h2></section></main>",
+        ["main", "--outline", "--max-depth", "2"],
+        "main > section …\n",
+    ),
+    fields: (
+        "<div class=\"listing\"><h2 class=\"title\">Flat, \"cosy\"</h2><span class=\"price\">900</span>\
+        <a href=\"/1\">a</a><a href=\"/2\">b</a></div><div class=\"listing\"><h2 class=\"title\">Loft</h2></div>",
+        [
+            "div.listing", "--field", "title=.title", "--field", "price=.price",
+            "--field", "url=a@href", "--base", "https://example.com/",
+        ],
+        "title,price,url\n\"Flat, \"\"cosy\"\"\",900,https://example.com/1\nLoft,,\n",
+    ),
+    fields_all_json: (
+        "<div><a href=\"/1\">a</a><a href=\"/2\">b</a></div>",
+        ["div", "--field", "urls=a@href", "--field", "links=a", "--field-all", "--json"],
+        "[{\"urls\":\"/1, /2\",\"links\":\"a, b\"}]\n",
+    ),
+    fields_all_separator: (
+        "<div><a>a</a><a>b</a></div>",
+        ["div", "--field", "links=a", "--field-all= | "],
+        "links\na | b\n",
+    ),
+    sort_by_numeric_attribute: (
+        "<a class=\"package\" data-downloads=\"900\">b</a><a class=\"package\" data-downloads=\"12000\">a</a>\
+        <a class=\"package\">c</a><a class=\"package\" data-downloads=\"900\">d</a>",
+        ["a.package", "--sort-by", "@data-downloads:numeric:desc", "--text"],
+        "a\nb\nd\nc\n",
+    ),
+    reverse_limit: (
+        "<ul><li id=\"a\">1</li><li id=\"b\">2</li><li id=\"c\">3</li><li id=\"d\">4</li></ul>",
+        ["li", "--reverse", "--limit", "3", "--attributes", "id"],
+        "d\nc\nb\n",
+    ),
+    reverse_fields: (
+        "<li id=\"a\"><b>1</b></li><li id=\"b\"><b>2</b></li>",
+        ["li", "--reverse", "--field", "id=@id", "--field", "text=b"],
+        "id,text\nb,2\na,1\n",
+    ),
+    outermost_count: (
+        "<div><div><div>x</div></div></div><p><div>y</div></p>",
+        ["div", "--outermost", "--count"],
+        "2\n",
+    ),
+    outermost_inner: (
+        "<div><div><div>x</div></div></div><p><div>y</div></p>",
+        ["div", "--outermost", "--inner"],
+        "<div><div>x</div></div>\ny\n",
+    ),
+    innermost: (
+        "<section data-widget=\"a\"><div data-widget=\"b\"><i data-widget=\"c\"></i></div>\
+        <p data-widget=\"d\"></p></section>",
+        ["[data-widget]", "--innermost", "--attributes", "data-widget"],
+        "c\nd\n",
+    ),
+    css_path_with_attributes: (
+        "<html><head></head><body><ul><li><a href=\"/a\">A</a></li><li><a href=\"/b\">B</a></li></ul></body></html>",
+        ["a", "--css-path", "--attributes", "href"],
+        "html > body > ul > li:nth-child(1) > a\n/a\nhtml > body > ul > li:nth-child(2) > a\n/b\n",
+    ),
+    with_position: (
+        "<!DOCTYPE html>\n<html><body>\n<p>a</p>\n  <p class=\"x\">b</p></body></html>\n",
+        ["p", "--with-position"],
+        "3:1:<p>a</p>\n4:3:<p class=\"x\">b</p>\n",
+    ),
+    format_template: (
+        "<html><head></head><body><a href=\"/a\">First</a><a>Second</a></body></html>",
+        ["a", "--format", "{@href}\\t{text}"],
+        "/a\tFirst\n\tSecond\n",
+    ),
+    links_resolved_against_base: (
+        "<html><head><link href=\"/feed\"></head><body><p><a href=\"/a\">A\n  link</a><a href=\"b\">B</a></p></body></html>",
+        ["--links", "--base", "https://example.org/"],
+        "https://example.org/a\tA link\nhttps://example.org/b\tB\n",
+    ),
+    links_all_scoped_by_selector: (
+        "<html><head><link href=\"/feed\"></head><body><a href=\"/a\">A</a></body></html>",
+        ["head", "--links-all"],
+        "/feed\t\n",
+    ),
+    unique_attributes_with_limit: (
+        "<html><head></head><body><a href=\"/1\">1</a><a href=\"/1\">1</a><a href=\"/2\">2</a><a href=\"/3\">3</a></body></html>",
+        ["a", "--attributes", "href", "--unique", "--limit", "2"],
+        "/1\n/2\n",
+    ),
+    unique_text: (
+        "<html><head></head><body><p>a</p><p>b</p><p>a</p></body></html>",
+        ["p", "--text", "--unique"],
+        "a\nb\n",
+    ),
+    unique_links: (
+        "<html><head></head><body><a href=\"?page=2\">Next</a><p>…</p><a href=\"?page=2\">Next</a></body></html>",
+        ["--links", "--unique"],
+        "?page=2\tNext\n",
+    ),
+    table_csv: (
+        "<html><head></head><body><table class=\"prices\"><tbody><tr><td>Tea, green</td><td rowspan=\"2\">2</td></tr><tr><td>Coffee</td></tr></tbody><thead><tr><th>Item</th><th>Price</th></tr></thead></table></body></html>",
+        ["table.prices", "--table-csv"],
+        "Item,Price\n\"Tea, green\",2\nCoffee,2\n",
+    ),
+    table_json: (
+        "<html><head></head><body><table><thead><tr><th>Item</th><th>Price</th></tr></thead><tbody><tr><td>Tea</td><td>2.50</td></tr><tr><td>Coffee</td></tr></tbody></table></body></html>",
+        ["table", "--table-json"],
+        "[{\"Item\":\"Tea\",\"Price\":\"2.50\"},{\"Item\":\"Coffee\",\"Price\":null}]\n",
+    ),
+    forms_resolve_action: (
+        "<html><head></head><body><form action=\"/login\" method=\"post\"><input type=\"hidden\" name=\"token\" value=\"t\"><input type=\"submit\"></form></body></html>",
+        ["form", "--forms", "--base", "https://example.org/"],
+        "{\"action\":\"https://example.org/login\",\"method\":\"post\",\"fields\":[{\"name\":\"token\",\"type\":\"hidden\",\"value\":\"t\",\"required\":false,\"disabled\":false}]}\n",
+    ),
+    meta_opengraph: (
+        "<html><head><title>Hi</title><meta property=\"og:title\" content=\"Hello\"><link rel=\"canonical\" href=\"/hi\"></head><body></body></html>",
+        ["--meta", "--base", "https://example.org/"],
+        "title\tHi\nog:title\tHello\ncanonical\thttps://example.org/hi\n",
+    ),
+    markdown_article: (
+        "<html><head></head><body><article><h2>Title</h2><p>Some <em>text</em>.</p><ul><li>one</li><li>two</li></ul></article></body></html>",
+        ["article", "--markdown"],
+        "## Title\n\nSome *text*.\n\n- one\n- two\n",
+    ),
+    text_breaks_lines_at_blocks: (
+        "<html><head></head><body><p>one</p><p>two<br>three</p></body></html>",
+        ["body", "--text"],
+        "one\ntwo\nthree\n",
+    ),
+    text_raw_concatenates: (
+        "<html><head></head><body><p>one</p><p>two<br>three</p></body></html>",
+        ["body", "--text", "--text-raw"],
+        "onetwothree\n",
+    ),
+    text_raw_ignore_whitespace: (
+        "<html><head></head><body><div>\n  <p>a</p>\n  <p>b</p>\n</div></body></html>",
+        ["div", "--text", "--text-raw", "--ignore-whitespace"],
+        "a\nb\n\n",
+    ),
+    collapse_whitespace: (
+        "<html><head></head><body><article>\n    <h1>\n      Title\n    </h1>\n    <p>Some\n      text</p>\n  </article></body></html>",
+        ["article", "--text", "--collapse-whitespace"],
+        "Title\nSome text\n",
+    ),
+    separator_between_matches: (
+        "<html><head></head><body><a href=\"/1\">1</a><a href=\"/2\">2</a></body></html>",
+        ["a", "--attributes", "href", "--separator", "\\0"],
+        "/1\0/2",
+    ),
+    empty_separator_joins: (
+        "<html><head></head><body><li>a</li><li>b</li></body></html>",
+        ["li", "--separator", ""],
+        "<li>a</li><li>b</li>",
+    ),
+    text_skips_scripts: (
+        "<html><head><style>p { color: red }</style></head><body><p>Hi</p><script>if (1 < 2) { document.write('<p>x</p>') }</script></body></html>",
+        [":root", "--text"],
+        "Hi\n",
+    ),
+    keep_scripts_text: (
+        "<html><head></head><body><p>Hi</p><script>a < b</script></body></html>",
+        ["body", "--text", "--keep-scripts-text"],
+        "Hi\na < b\n",
+    ),
+    visible_only_text: (
+        "<html><head></head><body><div id=\"cookies\" style=\"display:none\">Accept cookies</div><p>Content<span class=\"sr-only\" aria-hidden=\"true\"> again</span></p></body></html>",
+        ["body", "--text", "--visible-only"],
+        "Content\n",
+    ),
+    alt_text: (
+        "<html><head></head><body><p>Logo: <img src=\"logo.png\" alt=\"ACME\"><img src=\"spacer.gif\" alt=\"\"></p></body></html>",
+        ["p", "--text", "--alt-text"],
+        "Logo: [ACME]\n",
+    ),
+    alt_text_format: (
+        "<html><head></head><body><p><img alt=\"ACME\"> Inc.</p></body></html>",
+        ["p", "--text", "--alt-text", "--alt-text-format", "({})"],
+        "(ACME) Inc.\n",
+    ),
+    link_footnotes: (
+        "<html><head><base href=\"https://example.org/blog/\"></head><body><article><p>See <a href=\"post\">this post</a> and <a href=\"#comments\">the comments</a>.</p><p><a href=\"https://example.org/blog/post\">Again</a></p></article></body></html>",
+        ["article", "--text", "--detect-base", "--link-footnotes"],
+        "See this post[1] and the comments.\nAgain[1]\n\n[1] https://example.org/blog/post\n",
+    ),
+    attributes_with_names: (
+        "<html><head></head><body><img src=\"a.png\" alt=\"A\"><img src=\"b.png\"></body></html>",
+        ["img", "-a", "src", "-a", "alt", "--attributes-with-names"],
+        "src=a.png\nalt=A\nsrc=b.png\n",
+    ),
+    attributes_tsv: (
+        "<html><head></head><body><img src=\"a.png\" alt=\"A\"></body></html>",
+        ["img", "-a", "alt", "--attributes-with-names", "--tsv"],
+        "alt\tA\n",
+    ),
+    attribute_wildcard: (
+        "<html><head></head><body><div class=\"widget\" data-id=\"7\" data-state=\"open\"></div></body></html>",
+        ["div.widget", "-a", "data-*"],
+        "data-id=7\ndata-state=open\n",
+    ),
+    json_attribute_wildcard: (
+        "<html><head></head><body><div class=\"widget\" data-id=\"7\"></div></body></html>",
+        ["div", "--ndjson", "-a", "data-*"],
+        "{\"attributes\":{\"data-id\":\"7\"}}\n",
+    ),
+    attribute_missing: (
+        "<html><head></head><body><a href=\"/a\" title=\"A\">A</a><a href=\"/b\">B</a></body></html>",
+        ["a", "-a", "href", "-a", "title", "--attribute-missing", "", "--separator", "\\t"],
+        "/a\nA\t/b\n",
+    ),
+    attribute_fallback_text: (
+        "<html><head></head><body><nav><a href=\"/a\">A</a><span>Label</span><a href=\"/b\">B</a></nav></body></html>",
+        ["nav > *", "-a", "href", "--attribute-fallback-text"],
+        "/a\nLabel\n/b\n",
+    ),
+    rewrite_url_attributes: (
+        "<html><head></head><body><div><img src=\"/logo.png\" data-src=\"/big.png\"><script src=\"app.js\"></script></div></body></html>",
+        ["div", "--base", "https://example.org/blog/", "--rewrite-attrs", "data-src"],
+        "<div><img src=\"https://example.org/logo.png\" data-src=\"https://example.org/big.png\"><script src=\"https://example.org/blog/app.js\"></script></div>\n",
+    ),
+    base_with_path: (
+        "<html><head></head><body><a href=\"page2.html\">Next</a><img src=\"../img/a.png\"></body></html>",
+        ["a, img", "--base", "https://example.com/docs/intro.html"],
+        "<a href=\"https://example.com/docs/page2.html\">Next</a>\n<img src=\"https://example.com/img/a.png\">\n",
+    ),
+    rewrite_keeps_pseudo_links: (
+        "<html><head></head><body><p><a href=\"mailto:me@example.com\">Mail</a><a href=\"#top\">Up</a><a href=\"/x\">X</a></p></body></html>",
+        ["p", "--base", "https://example.org/"],
+        "<p><a href=\"mailto:me@example.com\">Mail</a><a href=\"#top\">Up</a><a href=\"https://example.org/x\">X</a></p>\n",
+    ),
+    rewrite_fragments: (
+        "<html><head></head><body><a href=\"#top\">Up</a></body></html>",
+        ["a", "--base", "https://example.org/page", "--rewrite-fragments"],
+        "<a href=\"https://example.org/page#top\">Up</a>\n",
+    ),
+    rewrite_css: (
+        "<html><head></head><body><div style=\"background: url(/img/hero.jpg)\"><style>p { background: url('dot.png') }</style></div></body></html>",
+        ["div", "--base", "https://example.org/blog/", "--rewrite-css"],
+        "<div style=\"background: url(https://example.org/img/hero.jpg)\"><style>p { background: url('https://example.org/blog/dot.png') }</style></div>\n",
+    ),
+    rewrite_meta_refresh: (
+        "<html><head><meta http-equiv=\"refresh\" content=\"0; url=/next\"></head><body></body></html>",
+        ["meta", "--base", "https://example.org/a/"],
+        "<meta http-equiv=\"refresh\" content=\"0; url=https://example.org/next\">\n",
+    ),
+    relativize: (
+        "<html><head></head><body><p><a href=\"https://example.com/docs/a/b.html\">B</a><img src=\"https://example.com/img/x.png\"><a href=\"https://other.org/\">O</a></p></body></html>",
+        ["p", "--relativize", "https://example.com/docs/"],
+        "<p><a href=\"a/b.html\">B</a><img src=\"../img/x.png\"><a href=\"https://other.org/\">O</a></p>\n",
+    ),
+    relative_detected_base: (
+        "<html><head><base href=\"/en/\"></head><body><a href=\"about\">About</a></body></html>",
+        ["a", "--detect-base", "--prefer-detected-base", "--base", "https://example.org/docs/intro.html"],
+        "<a href=\"https://example.org/en/about\">About</a>\n",
+    ),
+    explicit_base_wins: (
+        "<html><head><base href=\"https://bogus.example/\"></head><body><a href=\"about\">About</a></body></html>",
+        ["a", "--detect-base", "--base", "https://example.org/"],
+        "<a href=\"https://example.org/about\">About</a>\n",
+    ),
+    schemeless_base: (
+        "<html><head></head><body><a href=\"x\">x</a></body></html>",
+        ["a", "--base", "example.com"],
+        "<a href=\"https://example.com/x\">x</a>\n",
+    ),
+    schemeless_base_with_path: (
+        "<html><head></head><body><a href=\"x\">x</a></body></html>",
+        ["a", "--base", "example.com/docs/"],
+        "<a href=\"https://example.com/docs/x\">x</a>\n",
+    ),
+    schemeless_base_with_port: (
+        "<html><head></head><body><a href=\"x\">x</a></body></html>",
+        ["a", "--base", "localhost:8080"],
+        "<a href=\"https://localhost:8080/x\">x</a>\n",
+    ),
+    schemeless_ipv6_base: (
+        "<html><head></head><body><a href=\"x\">x</a></body></html>",
+        ["a", "--base", "[::1]:8080/app/"],
+        "<a href=\"https://[::1]:8080/app/x\">x</a>\n",
+    ),
+    normalized_urls: (
+        "<html><head></head><body><a href=\"../a b/./c.html\">x</a></body></html>",
+        ["a", "--base", "https://example.org/docs/intro.html"],
+        "<a href=\"https://example.org/a%20b/c.html\">x</a>\n",
+    ),
+    no_url_normalize: (
+        "<html><head></head><body><a href=\"../a b/./c.html\">x</a></body></html>",
+        ["a", "--base", "https://example.org/docs/intro.html", "--no-url-normalize"],
+        "<a href=\"https://example.org/docs/../a b/./c.html\">x</a>\n",
+    ),
+    xml_feed: (
+        "<?xml version=\"1.0\"?><rss xmlns:atom=\"http://www.w3.org/2005/Atom\"><channel>\
+        <atom:link href=\"https://example.org/feed\"/><title>Feed</title>\
+        <item><title>First</title><link>https://example.org/1</link></item>\
+        <item><title>Second</title><link>https://example.org/2</link></item></channel></rss>",
+        ["--xml", "item > link", "--text"],
+        "https://example.org/1\nhttps://example.org/2\n",
+    ),
+    xml_namespaced_elements: (
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:m=\"urn:media\">\
+        <m:thumbnail url=\"a.png\"/><Title>Case</Title><title>lower</title></feed>",
+        ["--xml", "thumbnail, Title", "--attributes", "url", "--attribute-fallback-text"],
+        "a.png\nCase\n",
+    ),
+    fragment_top_level_nodes: (
+        "<li>a</li>\n<li>b</li>",
+        ["--fragment"],
+        "<li>a</li>\n<li>b</li>\n",
+    ),
+    fragment_chained: (
+        "<ul><li>a</li><li>b</li></ul>",
+        ["--fragment", "li", "--text"],
+        "a\nb\n",
+    ),
+    fragment_context: (
+        "<td>a</td><td>b</td>",
+        ["--fragment", "--fragment-context", "TR", ":root:last-child"],
+        "<td>b</td>\n",
+    ),
+    bare_root: (
+        "<title>T</title><ul><li>a</li></ul>",
+        ["--bare"],
+        "<title>T</title><ul><li>a</li></ul>\n",
+    ),
+    bare_without_head: (
+        "<html><head></head><body><p>a</p> <p>b</p></body></html>",
+        [":root", "--bare"],
+        "<p>a</p> <p>b</p>\n",
+    ),
+    bare_keeps_attributed_wrapper: (
+        "<html lang=\"en\"><body><p>a</p></body></html>",
+        ["--bare"],
+        "<html lang=\"en\"><head></head><body><p>a</p></body></html>\n",
+    ),
+    bare_only_affects_root: (
+        "<p>a</p>",
+        ["body", "--bare"],
+        "<body><p>a</p></body>\n",
+    ),
+    bare_inverted: (
+        "<!DOCTYPE html><p>x</p><div>y</div>",
+        ["p", "-v", "--bare"],
+        "<div>y</div>\n",
+    ),
+    bare_with_set_attr: (
+        "<p>x</p>",
+        ["p", "--set-attr", "a=b", "--bare"],
+        "<p a=\"b\">x</p>\n",
+    ),
+    bare_with_insert_after: (
+        "<p>x</p><div>y</div>",
+        ["p", "--insert-after", "<hr>", "--bare"],
+        "<p>x</p><hr><div>y</div>\n",
+    ),
+    doctype_kept_for_root: (
+        "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \"http://www.w3.org/TR/html4/strict.dtd\"><p>a</p>",
+        [":root"],
+        "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \"http://www.w3.org/TR/html4/strict.dtd\">\n\
+        <html><head></head><body><p>a</p></body></html>\n",
+    ),
+    doctype_kept_when_inverting: (
+        "<!DOCTYPE html SYSTEM \"about:legacy-compat\"><p>a</p><b>b</b>",
+        ["--invert", "b"],
+        "<!DOCTYPE html SYSTEM \"about:legacy-compat\"><html><head></head><body><p>a</p></body></html>\n",
+    ),
+    doctype_for_every_match: (
+        "<!DOCTYPE html><p>a</p><p>b</p>",
+        ["p", "--document"],
+        "<!DOCTYPE html>\n<p>a</p>\n<!DOCTYPE html>\n<p>b</p>\n",
+    ),
+    pretty_indent: (
+        "<ul><li>a</li></ul>",
+        ["ul", "--pretty", "--indent", "4"],
+        "\n<ul>\n    <li>a\n    </li>\n</ul>\n",
+    ),
+    pretty_indent_tabs: (
+        "<ul><li>a</li></ul>",
+        ["ul", "--pretty", "--indent-tabs"],
+        "\n<ul>\n\t<li>a\n\t</li>\n</ul>\n",
+    ),
+    pretty_wrap_attributes: (
+        "<a href=\"/x\" class=\"button primary\">x</a>",
+        ["a", "--pretty", "--wrap-attributes", "20"],
+        "<a\n  href=\"/x\"\n  class=\"button primary\">x</a>\n",
+    ),
+    pretty_max_width: (
+        "<p>one two three <em>four</em> five</p>",
+        ["p", "--pretty", "--max-width", "12"],
+        "\n<p>one two\n  three <em>four</em>\n  five\n</p>\n",
+    ),
+    sort_attributes: (
+        "<a id=\"x\" href=\"/a\" class=\"b\">x</a>",
+        ["a", "--sort-attributes"],
+        "<a class=\"b\" href=\"/a\" id=\"x\">x</a>\n",
+    ),
+    sort_attributes_smart_pretty: (
+        "<div><a title=\"t\" href=\"/a\" class=\"b\" id=\"x\">x</a></div>",
+        ["div", "--sort-attributes=smart", "--pretty"],
+        "\n<div><a id=\"x\" class=\"b\" href=\"/a\" title=\"t\">x</a>\n</div>\n",
+    ),
+    strip_comments: (
+        "<div><!-- note --><p>x</p><!--[if IE]>old<![endif]--></div>",
+        ["div", "--strip-comments"],
+        "<div><p>x</p></div>\n",
+    ),
+    strip_comments_keep_conditional_pretty: (
+        "<div><!-- note --><p>x</p><!--[if IE]>old<![endif]--></div>",
+        ["div", "--strip-comments", "--keep-conditional-comments", "--pretty"],
+        "\n<div>\n  <p>x\n  </p>\n  <!--[if IE]>old<![endif]-->\n</div>\n",
+    ),
+    strip_comments_keeps_text: (
+        "<p>a<!-- b -->c</p>",
+        ["p", "--strip-comments", "--text"],
+        "ac\n",
+    ),
+    remove_comments: (
+        "<div><!-- note --><p>x<!--[if IE]>old<![endif]--></p></div>",
+        ["div", "--remove-comments", "--json"],
+        "[{\"tag\":\"div\",\"attributes\":{},\"text\":\"x\",\"html\":\"<div><p>x</p></div>\"}]\n",
+    ),
+    remove_comments_keep_conditional: (
+        "<div><!-- note --><p>x<!--[if IE]>old<![endif]--></p></div>",
+        ["p", "--remove-comments", "--keep-conditional-comments"],
+        "<p>x<!--[if IE]>old<![endif]--></p>\n",
+    ),
+    remove_comments_several_matches: (
+        "<p><!--c--></p><p>z</p>",
+        ["p", "--remove-comments"],
+        "<p></p>\n<p>z</p>\n",
+    ),
+    comments: (
+        "<!-- build 42 --><p>a<!-- ab: b --></p>",
+        ["--comments"],
+        "build 42\nab: b\n",
+    ),
+    comments_scoped_and_filtered: (
+        "<!-- build 42 --><p>a<!-- ab: b --><!-- ab: c --><!-- x --></p>",
+        ["p", "--comments", "--text-matches", "^ab", "--separator", ",", "--comments-html"],
+        "<!-- ab: b -->,<!-- ab: c -->",
+    ),
+    comments_limit: (
+        "<!-- a --><!-- b --><!-- c -->",
+        ["--comments", "--limit", "2"],
+        "a\nb\n",
+    ),
+    minify: (
+        "<ul>\n  <li class=\"a\"><b>x</b> <i>y</i></li>\n  <!-- c -->\n  <li title=\"t t\">z</li>\n</ul>",
+        ["ul", "--minify"],
+        "<ul><li class=a><b>x</b> <i>y</i></li><li title=\"t t\">z</li></ul>\n",
+    ),
+    minify_document: (
+        "<!DOCTYPE html>\n<html>\n<body>\n  <p>x</p>\n</body>\n</html>",
+        [":root", "--minify"],
+        "<!DOCTYPE html>\n<html><head></head><body><p>x</p></body></html>\n",
+    ),
+    color_always: (
+        "<p class=\"a\">x<!-- c --></p>",
+        ["p", "--color=always"],
+        "\x1b[34m<p\x1b[0m \x1b[36mclass\x1b[0m\x1b[32m=\"a\"\x1b[0m\x1b[34m>\x1b[0mx\x1b[90m<!-- c -->\x1b[0m\x1b[34m</p>\x1b[0m\n",
+    ),
+    color_always_keeps_values_apart: (
+        "<a title=\"a<b>c\">x</a>",
+        ["a", "--color=always", "--pretty"],
+        "\x1b[34m<a\x1b[0m \x1b[36mtitle\x1b[0m\x1b[32m=\"a<b>c\"\x1b[0m\x1b[34m>\x1b[0mx\x1b[34m</a>\x1b[0m\n",
+    ),
+    color_never: (
+        "<p class=\"a\">x</p>",
+        ["p", "--color=never"],
+        "<p class=\"a\">x</p>\n",
+    ),
+    color_auto_not_a_terminal: (
+        "<p class=\"a\">x</p>",
+        ["p"],
+        "<p class=\"a\">x</p>\n",
+    ),
+    no_newline_attribute: (
+        "<a href=\"data:text/plain,hi\">x</a>",
+        ["a", "-a", "href", "-j"],
+        "data:text/plain,hi",
+    ),
+    no_newline_joins_matches: (
+        "<p>one</p><p>two</p>",
+        ["p", "--text", "--no-newline"],
+        "onetwo",
+    ),
+    no_newline_html: (
+        "<p>one</p><p>two</p>",
+        ["p", "-j"],
+        "<p>one</p><p>two</p>",
+    ),
+    no_newline_separator: (
+        "<p>one</p><p>two</p>",
+        ["p", "--text", "-j", "--separator", ","],
+        "one,two",
+    ),
+    append_to_stdout: (
+        "<p>one</p>",
+        ["p", "--text", "--append", "-o", "-"],
+        "one\n",
+    ),
+    set_attr_outputs_document: (
+        "<p><img src=\"a.png\"><img src=\"b.png\" loading=\"eager\"></p>",
+        ["img", "--set-attr", "loading=lazy"],
+        "<html><head></head><body><p><img src=\"a.png\" loading=\"lazy\"><img src=\"b.png\" loading=\"lazy\"></p></body></html>\n",
+    ),
+    set_attr_keeps_equals_and_empty_values: (
+        "<a href=\"/x\">x</a>",
+        ["a", "--set-attr", "href=/q?a=b=c", "--set-attr", "data-x=", "--set-attr", "href=/q?a=b=c&d"],
+        "<html><head></head><body><a href=\"/q?a=b=c&amp;d\" data-x=\"\">x</a></body></html>\n",
+    ),
+    set_attr_with_attributes_outputs_matches: (
+        "<a href=\"https://example.com\">x</a><a href=\"/local\">y</a>",
+        ["a[href^=http]", "--set-attr", "target=_blank", "-a", "target"],
+        "_blank\n",
+    ),
+    strip_attrs: (
+        "<article class=\"a\" style=\"s\"><img src=\"x.png\" data-lazy=\"1\" onload=\"f()\"></article>",
+        ["article", "--strip-attrs", "class,style,on*,data-*"],
+        "<article><img src=\"x.png\"></article>\n",
+    ),
+    strip_attrs_pretty: (
+        "<div id=\"x\"><p class=\"a\">text</p></div>",
+        ["div", "--strip-attrs", "class", "--strip-attrs", "id", "--pretty"],
+        "\n<div>\n  <p>text\n  </p>\n</div>\n",
+    ),
+    sanitize: (
+        "<article onclick=\"f()\" style=\"color: red\"><a href=\" JavaScript:f()\">a</a>\
+        <img src=\"data:image/gif;base64,R0lGOD==\"><iframe src=\"/ad\"></iframe></article>",
+        ["article", "--sanitize"],
+        "<article><a>a</a><img src=\"data:image/gif;base64,R0lGOD==\"></article>\n",
+    ),
+    sanitize_keep_style: (
+        "<p style=\"color: red\" onclick=\"f()\">x</p>",
+        ["p", "--sanitize", "--sanitize-keep-style"],
+        "<p style=\"color: red\">x</p>\n",
+    ),
+    sanitize_several_matches: (
+        "<div><script>x</script></div><div>z</div>",
+        ["div", "--sanitize"],
+        "<div></div>\n<div>z</div>\n",
+    ),
+    unwrap: (
+        "<div><center><font color=\"red\">a</font> <span class=\"highlight\">b</span></center></div>",
+        [":root", "--unwrap", "span.highlight, font", "--unwrap", "center", "--bare"],
+        "<div>a b</div>\n",
+    ),
+    unwrap_first_child_of_earlier_match: (
+        "<p><b>a</b></p><p>z</p>",
+        ["p", "--unwrap", "b"],
+        "<p>a</p>\n<p>z</p>\n",
+    ),
+    wrap: (
+        "<img src=\"a.png\"><img src=\"b.png\">",
+        ["img", "--wrap", "figure"],
+        "<figure><img src=\"a.png\"></figure>\n<figure><img src=\"b.png\"></figure>\n",
+    ),
+    wrap_inner_with_attributes: (
+        "<section><h2>Title</h2><p>text</p></section>",
+        ["section", "--inner", "--wrap", "div class=\"card\" data-kind=note"],
+        "<div class=\"card\" data-kind=\"note\"><h2>Title</h2><p>text</p></div>\n",
+    ),
+    replace_text: (
+        "<p title=\"me@example.org\">Mail me@example.org<script>'me@example.org'</script></p>",
+        ["p", "--replace-text", r"(\w+)@\S+=$1@…", "--replace-text", "Mail=Write"],
+        "<p title=\"me@example.org\">Write me@…<script>'me@example.org'</script></p>\n",
+    ),
+    insert_after_headings: (
+        "<h2 id=\"intro\">Intro</h2><p>text</p>",
+        ["h2", "--insert-after", "<a class=\"anchor\" href=\"#{@id}\">#</a>"],
+        "<html><head></head><body><h2 id=\"intro\">Intro</h2><a class=\"anchor\" href=\"#intro\">#</a><p>text</p></body></html>\n",
+    ),
+    insert_before_pretty: (
+        "<div><p>a</p></div>",
+        ["p", "--insert-before", "<hr><span>note</span>", "--pretty"],
+        "\n<html>\n  <head>\n  </head>\n  <body>\n    <div>\n      <hr>\n      <span>\n        note</span>\n      <p>a\n      </p>\n    </div>\n  </body>\n</html>\n",
+    ),
+    ndjson_text: (
+        "<html><head></head><body><p>one</p><p></p></body></html>",
+        ["p", "--ndjson", "--text"],
+        "{\"text\":\"one\"}\n{\"text\":\"\"}\n",
+    ),
 );
+
+/// Creates a temporary directory containing the given files.
+fn dir_with(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, content) in files {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn multiple_inputs_have_headers() {
+    let dir = dir_with(&[("a.html", "<h1>A</h1>"), ("b.html", "<h1>B</h1><h1>C</h1>")]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["h1", "a.html", "b.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "==> a.html <==\n<h1>A</h1>\n\n==> b.html <==\n<h1>B</h1>\n<h1>C</h1>\n",
+        ));
+}
+
+#[test]
+fn multiple_inputs_without_headers() {
+    let dir = dir_with(&[("a.html", "<h1>A</h1>"), ("b.html", "<h1>B</h1>")]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["--no-filename", "--text", "h1", "a.html", "-f", "b.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("B\nA\n"));
+}
+
+#[test]
+fn single_input_has_no_header() {
+    let dir = dir_with(&[("a.html", "<h1>A</h1>")]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["h1", "a.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("<h1>A</h1>\n"));
+}
+
+#[test]
+fn missing_input_continues_with_others() {
+    let dir = dir_with(&[("a.html", "<h1>A</h1>"), ("c.html", "<h1>C</h1>")]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["h1", "a.html", "missing.html", "c.html"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::diff(
+            "==> a.html <==\n<h1>A</h1>\n\n==> c.html <==\n<h1>C</h1>\n",
+        ))
+        .stderr(predicate::str::diff(
+            "hq: cannot open 'missing.html': No such file or directory\n",
+        ));
+}
+
+#[test]
+fn glob_inputs_are_sorted() {
+    let dir = dir_with(&[
+        ("docs/b.html", "<h1>B</h1>"),
+        ("docs/a/c.html", "<h1>C</h1>"),
+        ("docs/a/skip.txt", "<h1>no</h1>"),
+        ("other.html", "<h1>no</h1>"),
+    ]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["--glob", "docs/**/*.html", "--text", "h1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "==> docs/a/c.html <==\nC\n\n==> docs/b.html <==\nB\n",
+        ));
+}
+
+#[test]
+fn invalid_glob_is_usage_error() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--glob", "docs/[", "h1"])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn no_matches_exits_1() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .arg(".does-not-exist")
+        .write_stdin("<div class=\"other\"></div>")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn empty_match_exits_0() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args([".empty", "--text"])
+        .write_stdin("<div class=\"empty\"></div>")
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("\n"));
+}
+
+#[test]
+fn allow_empty_exits_0() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args([".does-not-exist", "--allow-empty"])
+        .write_stdin("<div class=\"other\"></div>")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn invalid_selector_is_reported() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .arg("div[")
+        .write_stdin("<div></div>")
+        .assert()
+        .code(3)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::diff(
+            "hq: invalid CSS selector `div[` given as selector\n",
+        ));
+}
+
+#[test]
+fn invalid_remove_nodes_selector_is_reported() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["div", "-r", "script", "-r", "p >> a"])
+        .write_stdin("<div></div>")
+        .assert()
+        .code(3)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::diff(
+            "hq: invalid CSS selector `p >> a` given to --remove-nodes\n",
+        ));
+}
+
+#[test]
+fn invalid_selector_is_reported_before_reading_inputs() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["div[", "missing.html"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("missing.html").not());
+}
+
+#[test]
+fn text_matches_nothing_exits_1() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["li", "--text-matches", "^v"])
+        .write_stdin("<li>notes</li>")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn invalid_text_regex_is_rejected() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["li", "--text-matches", "v("])
+        .write_stdin("<li>v1</li>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("unclosed group"));
+}
+
+#[test]
+fn malformed_attr_matches_is_rejected() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "--attr-matches", "href"])
+        .write_stdin("<a href=\"/\"></a>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("expected `name=REGEX`"));
+}
+
+#[test]
+fn invert_of_root_is_empty() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["-v", "html"])
+        .write_stdin("<!DOCTYPE html><p>a</p>")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn tag_names_conflict_with_text() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--tag-names", "--text"])
+        .write_stdin("<p></p>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn format_strict_fails_on_missing_attribute() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "--format", "{@href}", "--format-strict"])
+        .write_stdin("<a href=\"/a\">A</a><a>B</a>")
+        .assert()
+        .code(2)
+        .stdout(predicate::str::diff("/a\n"))
+        .stderr(predicate::str::diff(
+            "hq: -: a match has no attribute `href` used in the template\n",
+        ));
+}
+
+#[test]
+fn unknown_format_placeholder_is_rejected() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "--format", "{link}"])
+        .write_stdin("<a></a>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("unknown placeholder `{link}`"));
+}
+
+#[test]
+fn json_ld_warns_about_invalid_blocks() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--json-ld", "--json"])
+        .write_stdin(
+            "<script>track()</script><script type=\"application/ld+json\"><!--{\"@type\": \"Article\"}--></script>\
+            <script type=\"application/ld+json\">{</script>",
+        )
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("[{\"@type\":\"Article\"}]\n"))
+        .stderr(predicate::str::starts_with(
+            "hq: warning: JSON-LD block is not valid JSON: ",
+        ));
+}
+
+#[test]
+fn verbose_warns_about_malformed_srcset() {
+    let run = |verbose: bool| {
+        let mut command = Command::cargo_bin("hq").unwrap();
+        command.args(["img", "--base", "https://example.org/"]);
+        if verbose {
+            command.arg("--verbose");
+        }
+        command
+            .write_stdin("<img srcset=\"a.png large\">")
+            .assert()
+            .success()
+            .stdout(predicate::str::diff("<img srcset=\"a.png large\">\n"))
+    };
+    run(false).stderr(predicate::str::is_empty());
+    run(true).stderr(predicate::str::diff(
+        "hq: warning: leaving malformed srcset `a.png large` as it is\n",
+    ));
+}
+
+#[test]
+fn invalid_base_is_an_error() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "--base", "https://exa mple.org/"])
+        .write_stdin("<a href=\"/x\">x</a>")
+        .assert()
+        .code(2)
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn schemeless_base_is_noted() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "--base", "example.com"])
+        .write_stdin("<a href=\"/x\">x</a>")
+        .assert()
+        .success()
+        .stderr(predicate::str::diff(
+            "hq: note: assuming `https://example.com/` for the base `example.com`\n",
+        ));
+}
+
+#[test]
+fn windows_1252_input_is_decoded() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text"])
+        .write_stdin(
+            b"<meta charset=\"windows-1252\"><p>Gr\xfc\xdfe aus dem Caf\xe9</p>".as_slice(),
+        )
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Grüße aus dem Café\n"));
+}
+
+#[test]
+fn input_encoding_overrides_detection() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text", "--input-encoding", "latin1"])
+        .write_stdin(b"<meta charset=\"utf-8\"><p>Caf\xe9</p>".as_slice())
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Café\n"));
+}
+
+#[test]
+fn unknown_input_encoding_is_rejected() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--input-encoding", "latin-9000"])
+        .write_stdin("<p>x</p>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "common ones are utf-8, windows-1252",
+        ));
+}
+
+#[test]
+fn strict_fails_on_malformed_input() {
+    let input = b"<p>Caf\xe9</p>".as_slice();
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Caf\u{fffd}\n"));
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text", "--strict"])
+        .write_stdin(input)
+        .assert()
+        .code(2)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("invalid UTF-8 at byte offset 6"));
+}
+
+#[test]
+fn output_encoding_transcodes() {
+    let html = "<p title=\"→\">Café → Bar</p>";
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--output-encoding", "windows-1252"])
+        .write_stdin(html)
+        .assert()
+        .success()
+        .stdout(predicate::eq(
+            b"<p title=\"&#8594;\">Caf\xe9 &#8594; Bar</p>\n".as_slice(),
+        ));
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text", "--output-encoding", "windows-1252"])
+        .write_stdin(html)
+        .assert()
+        .success()
+        .stdout(predicate::eq(b"Caf\xe9 ? Bar\n".as_slice()));
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args([
+            "p",
+            "--text",
+            "--output-encoding",
+            "windows-1252",
+            "--strict",
+        ])
+        .write_stdin(html)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "'→' can't be represented in windows-1252",
+        ));
+}
+
+#[test]
+#[cfg(feature = "decompress")]
+fn compressed_input_is_decompressed() {
+    // `<p>Hello</p>` compressed with `gzip -n`
+    let gzip = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03\xb3)\xb0\xf3H\xcd\xc9\xc9\xb7\xd1/\xb0\x03\x00\xe7y_P\x0c\x00\x00\x00";
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text"])
+        .write_stdin(gzip.as_slice())
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Hello\n"));
+
+    // the name doesn't matter, only the content
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("page.html");
+    fs::write(&path, gzip).unwrap();
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Hello\n"));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text"])
+        .write_stdin(&gzip[..20])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "cannot read '-': decompressing gzip failed",
+        ));
+}
+
+#[test]
+fn print0_ends_values_with_nul() {
+    let html = "<a href=\"/a\">one\ntwo</a><a href=\"/b\">three</a><a href=\"/a\">x</a>";
+    for (args, expected) in [
+        (&["a", "-a", "href", "-0"][..], "/a\0/b\0/a\0"),
+        (&["a", "--text", "--print0"], "one\ntwo\0three\0x\0"),
+        (&["a", "--format", "{@href}", "-0"], "/a\0/b\0/a\0"),
+    ] {
+        Command::cargo_bin("hq")
+            .unwrap()
+            .args(args)
+            .write_stdin(html)
+            .assert()
+            .success()
+            .stdout(predicate::str::diff(expected));
+    }
+}
+
+#[test]
+fn print0_conflicts_with_pretty() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "-0", "--pretty"])
+        .write_stdin("<a></a>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn output_pattern_writes_each_match_to_a_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let pattern = dir.path().join("out/{tag}-{id}.html");
+    let html = "<section id=\"intro\">a</section><section id=\"intro\">b</section>\
+        <section>c</section><section id=\"../up\">d</section>";
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["section", "--output-pattern", pattern.to_str().unwrap()])
+        .write_stdin(html)
+        .assert()
+        .success()
+        .stdout("");
+
+    let mut files: Vec<_> = fs::read_dir(dir.path().join("out"))
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read_to_string(&path).unwrap())
+        })
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            ("section-3.html", "<section>c</section>\n"),
+            ("section-_up.html", "<section id=\"../up\">d</section>\n"),
+            (
+                "section-intro-2.html",
+                "<section id=\"intro\">b</section>\n"
+            ),
+            ("section-intro.html", "<section id=\"intro\">a</section>\n"),
+        ]
+        .map(|(name, content)| (name.to_string(), content.to_string()))
+    );
+}
+
+#[test]
+fn output_pattern_refuses_to_overwrite() {
+    let dir = tempfile::tempdir().unwrap();
+    let existing = dir.path().join("1.txt");
+    fs::write(&existing, "old").unwrap();
+    let pattern = dir.path().join("{n}.txt");
+    let write = |force: bool| {
+        let mut command = Command::cargo_bin("hq").unwrap();
+        command
+            .args(["p", "--text", "--output-pattern", pattern.to_str().unwrap()])
+            .write_stdin("<p>new</p>");
+        if force {
+            command.arg("--force");
+        }
+        command.assert()
+    };
+
+    write(false).code(2).stderr(predicate::str::contains(
+        "file exists, use --force to overwrite it",
+    ));
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "old");
+    write(true).success();
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "new\n");
+}
+
+#[test]
+fn in_place_replaces_input_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b, c) = (
+        dir.path().join("a.html"),
+        dir.path().join("b.html"),
+        dir.path().join("c.html"),
+    );
+    fs::write(&a, "<p>a</p><script>x()</script>").unwrap();
+    fs::write(&b, "<div><script>y()</script>b</div>").unwrap();
+    fs::write(&c, "<span>c</span>").unwrap();
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p, div", "--in-place=.bak", "--remove-nodes", "script"])
+        .args([&a, &b, &c])
+        .assert()
+        .success()
+        .stdout("");
+
+    // everything else in them is kept
+    assert_eq!(
+        fs::read_to_string(&a).unwrap(),
+        "<html><head></head><body><p>a</p></body></html>\n",
+    );
+    assert_eq!(
+        fs::read_to_string(&b).unwrap(),
+        "<html><head></head><body><div>b</div></body></html>\n",
+    );
+    // no matches, so nothing to replace it by
+    assert_eq!(fs::read_to_string(&c).unwrap(), "<span>c</span>");
+    let backup = fs::read_to_string(dir.path().join("a.html.bak")).unwrap();
+    assert_eq!(backup, "<p>a</p><script>x()</script>");
+    assert!(!dir.path().join("c.html.bak").exists());
+}
+
+#[test]
+fn in_place_takes_first_positional_as_input() {
+    let dir = dir_with(&[]);
+    let original = "<p>a</p><script>x()</script>";
+    for args in [
+        &["-I", "-r", "script", "file.html"][..],
+        &["-I=.bak", "--remove-nodes", "script", "file.html"],
+    ] {
+        fs::write(dir.path().join("file.html"), original).unwrap();
+        Command::cargo_bin("hq")
+            .unwrap()
+            .current_dir(dir.path())
+            .args(args)
+            .assert()
+            .success()
+            .stdout("");
+        let replaced = fs::read_to_string(dir.path().join("file.html")).unwrap();
+        assert_eq!(
+            replaced,
+            "<html><head></head><body><p>a</p></body></html>\n"
+        );
+    }
+    let backup = fs::read_to_string(dir.path().join("file.html.bak")).unwrap();
+    assert_eq!(backup, original);
+}
+
+#[test]
+fn in_place_leaves_files_alone_on_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.html");
+    fs::write(&path, "<a>x</a>").unwrap();
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "-I", "--nth", "3"])
+        .arg(&path)
+        .assert()
+        .code(2);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "<a>x</a>");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn in_place_refuses_partial_output() {
+    let dir = dir_with(&[("a.html", "<a>x</a>")]);
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["a", "-I", "--text", "a.html"])
+        .assert()
+        .code(2)
+        .stderr("hq: --in-place can only write back whole documents as HTML\n");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.html")).unwrap(),
+        "<a>x</a>"
+    );
+}
+
+#[test]
+fn in_place_refuses_stdin() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--in-place"])
+        .write_stdin("<p></p>")
+        .assert()
+        .code(2)
+        .stderr("hq: --in-place can only replace files, not stdin\n");
+}
+
+#[test]
+fn append_keeps_earlier_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.txt");
+    for (html, append) in [
+        ("<p>one</p>", false),
+        ("<p>two</p>", true),
+        ("<p>three</p>", true),
+    ] {
+        let mut command = Command::cargo_bin("hq").unwrap();
+        command
+            .args(["p", "--text", "-o"])
+            .arg(&path)
+            .write_stdin(html);
+        if append {
+            command.arg("--append");
+        }
+        command.assert().success();
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree\n");
+
+    // without it, the file is overwritten
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--text", "-o"])
+        .arg(&path)
+        .write_stdin("<p>four</p>")
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "four\n");
+}
+
+#[test]
+fn wrap_rejects_invalid_tag_names() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["img", "--wrap", "<figure>"])
+        .write_stdin("<img>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("not a valid tag name"));
+}
+
+#[test]
+fn replace_text_reports_count_with_verbose() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--replace-text", "a=b", "--verbose"])
+        .write_stdin("<p>a a</p><p>a <i>a</i></p>")
+        .assert()
+        .success()
+        .stdout("<p>b b</p>\n<p>b <i>b</i></p>\n")
+        .stderr("hq: made 4 text replacements\n");
+}
+
+#[test]
+fn check_exits_1_for_errors() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .arg("--check")
+        .write_stdin("<!DOCTYPE html>\n<p>a</div>\n<li id=\"x\">b</li>")
+        .assert()
+        .code(1)
+        .stdout("2: error: unexpected </div>\n3:1: warning: <li> outside of a list\n");
+}
+
+#[test]
+fn check_exits_0_for_warnings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("page.html");
+    fs::write(&path, "<!DOCTYPE html><p id=a></p><p id=a></p>").unwrap();
+    Command::cargo_bin("hq")
+        .unwrap()
+        .arg("--check")
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("1:28: warning: duplicate id `a`, first used at 1:16\n");
+}
+
+#[test]
+fn several_selectors_match_in_document_order() {
+    let dir = dir_with(&[(
+        "page.html",
+        "<head><meta name=\"description\" content=\"x\"></head>\
+        <h2 class=\"a\">2</h2><h1 class=\"a\">1</h1>",
+    )]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["h1", "h2", ".a", "meta[name=description]", "page.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "<meta name=\"description\" content=\"x\">\n\
+            <h2 class=\"a\">2</h2>\n<h1 class=\"a\">1</h1>\n",
+        ));
+}
+
+#[test]
+fn several_selectors_before_missing_input() {
+    let dir = dir_with(&[]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["h1", "h2", "missing.html"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("missing.html"));
+}
+
+#[test]
+fn invalid_one_of_several_selectors() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["h1", "h2[", "p"])
+        .write_stdin("<h1>a</h1>")
+        .assert()
+        .code(3)
+        .stderr("hq: invalid CSS selector `h2[` given as argument 2\n");
+}
+
+#[test]
+fn expressions_select_within_each_other() {
+    let dir = dir_with(&[(
+        "page.html",
+        "<a class=\"title\">ad</a><div class=\"results\"><a class=\"title\">1</a>\
+        <div class=\"results\"><a class=\"title\">2</a></div></div>",
+    )]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["-e", "div.results", "-e", "a.title", "--text", "page.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("1\n2\n"));
+}
+
+#[test]
+fn invalid_expression() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["-e", "div", "-e", "a["])
+        .write_stdin("<div></div>")
+        .assert()
+        .code(3)
+        .stderr("hq: invalid CSS selector `a[` given to -e\n");
+}
+
+#[test]
+fn then_groups_matches() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["li.result", "--then", "a", "-a", "href"])
+        .write_stdin(
+            "<ul><li class=\"result\"><a href=\"/a\">a</a><a href=\"/b\">b</a></li>\
+            <li class=\"result\">none</li><li class=\"result\"><a href=\"/c\">c</a></li></ul>",
+        )
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("/a\n/b\n\n\n/c\n"));
+}
+
+#[test]
+fn then_without_any_matches() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["li", "--then", "a", "--separator=---\\n"])
+        .write_stdin("<ul><li>a</li><li>b</li></ul>")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::diff("---\n"));
+}
+
+#[test]
+fn innermost_conflicts_with_outermost() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["div", "--innermost", "--outermost"])
+        .write_stdin("<div></div>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn quiet_exits_0_at_first_match() {
+    let dir = dir_with(&[(
+        "page.html",
+        "<meta name=\"robots\" content=\"noindex, nofollow\"><p>text</p>",
+    )]);
+
+    // the missing input after the match isn't read anymore
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args([
+            "-q",
+            "meta[name=robots][content*=noindex]",
+            "page.html",
+            "missing.html",
+        ])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("");
+}
+
+#[test]
+fn quiet_exits_1_without_matches() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--quiet", "p", "--remove-nodes", "p.ad"])
+        .write_stdin("<p class=\"ad\">text</p>")
+        .assert()
+        .code(1)
+        .stdout("");
+}
+
+#[test]
+fn missing_attributes_exit_1() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "-a", "href", "--attribute-missing", "-"])
+        .write_stdin("<a></a><a></a>")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::diff("-\n-\n"));
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["a", "-a", "href", "-q"])
+        .write_stdin("<a></a>")
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn count_zero_exits_1() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["tr", "--count"])
+        .write_stdin("<p></p>")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::diff("0\n"));
+}
+
+#[test]
+fn minify_conflicts_with_pretty() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--minify", "--pretty"])
+        .write_stdin("<p></p>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn count_conflicts_with_text() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["tr", "--count", "--text"])
+        .write_stdin("<p></p>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn limit_zero_is_rejected() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["li", "--limit", "0"])
+        .write_stdin("<li>1</li>")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn nth_out_of_range_fails() {
+    for nth in ["4", "-4"] {
+        Command::cargo_bin("hq")
+            .unwrap()
+            .args(["li", "--nth", nth])
+            .write_stdin("<ul><li>1</li><li>2</li><li>3</li></ul>")
+            .assert()
+            .code(2)
+            .stdout(predicate::str::is_empty())
+            .stderr(predicate::str::contains(
+                "out of range, only 3 nodes matched",
+            ));
+    }
+}
+
+#[test]
+fn nth_zero_is_rejected() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["li", "--nth", "0"])
+        .write_stdin("<li>1</li>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("indices start at 1"));
+}
+
+#[test]
+fn removing_only_match_exits_1() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["article", "--remove-nodes", "article"])
+        .write_stdin("<article>Buy</article>")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn unopenable_input_is_reported() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["-f", "does/not/exist.html", "h1"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::diff(
+            "hq: cannot open 'does/not/exist.html': No such file or directory\n",
+        ));
+}
+
+#[test]
+fn missing_path_like_input_is_reported() {
+    let dir = dir_with(&[("a.html", "<p>a</p>")]);
+    for (args, path) in [
+        (&["p", "notes/typo"][..], "notes/typo"),
+        (&["p", "--", "README"], "README"),
+        (&["p", "h1", "--", "a.html", "README"], "README"),
+    ] {
+        Command::cargo_bin("hq")
+            .unwrap()
+            .current_dir(dir.path())
+            .args(args)
+            .assert()
+            .code(2)
+            .stderr(predicate::str::diff(format!(
+                "hq: cannot open '{path}': No such file or directory\n"
+            )));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_input_is_reported() {
+    let dir = dir_with(&[("docs/a.html", "")]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["h1", "docs"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::diff(
+            "hq: cannot read 'docs': Is a directory\n",
+        ));
+}
+
+#[test]
+fn uncreatable_output_is_reported() {
+    let dir = dir_with(&[]);
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["-o", "missing/out.html", "h1"])
+        .write_stdin("<h1>A</h1>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::diff(
+            "hq: cannot create 'missing/out.html': No such file or directory\n",
+        ));
+}
+
+#[test]
+fn broken_pipe_exits_quietly() {
+    let dir = dir_with(&[("big.html", &"<p>some paragraph text</p>".repeat(20_000))]);
+
+    for mode in [None, Some("--text"), Some("--ndjson"), Some("--json")] {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("hq"))
+            .current_dir(dir.path())
+            .args(["p", "big.html"])
+            .args(mode)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        // just like `head` would after having read enough
+        drop(child.stdout.take());
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{mode:?}: {:?}", output.status);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "", "{mode:?}");
+    }
+}
+
+#[test]
+fn xpath_selects_attributes() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--xpath", "//a[contains(., 'B')]/@href"])
+        .write_stdin("<a href=\"/a\">A</a><a href=\"/b\">B</a>")
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("/b\n"));
+}
+
+#[test]
+fn xpath_takes_first_positional_as_input() {
+    let dir = dir_with(&[("a.html", "<p>one</p><p>two</p>")]);
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(dir.path())
+        .args(["-x", "//p[last()]", "--text", "a.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("two\n"));
+}
+
+#[test]
+fn invalid_xpath_is_reported() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--xpath", "//p[@"])
+        .write_stdin("<p></p>")
+        .assert()
+        .code(3)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::diff(
+            "hq: invalid XPath expression: expected a node test, found end of expression at position 6\n  //p[@\n       ^\n",
+        ));
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "fetch")]
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+/// Serves exactly one HTTP request with the given status line and body.
+///
+/// Returns the URL to request and a handle resolving to the raw request head that was received.
+fn serve_once(status: &'static str, body: &'static str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/page.html", listener.local_addr().unwrap());
+
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let mut head = String::new();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            head.push_str(&line);
+        }
+
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        head
+    });
+
+    (url, handle)
+}
+
+#[test]
+fn fetch_url_uses_response_url_as_base() {
+    let (url, server) = serve_once(
+        "200 OK",
+        "<html><head></head><body><a href=\"/foo\">Hello</a></body></html>",
+    );
+    let base = url.trim_end_matches("/page.html").to_string();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["-f", &url, "a"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(format!(
+            "<a href=\"{base}/foo\">Hello</a>\n"
+        )));
+    server.join().unwrap();
+}
+
+#[test]
+fn fetch_explicit_base_wins() {
+    let (url, server) = serve_once(
+        "200 OK",
+        "<html><head></head><body><a href=\"/foo\">Hello</a></body></html>",
+    );
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["-f", &url, "--base", "https://example.org", "a"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "<a href=\"https://example.org/foo\">Hello</a>\n",
+        ));
+    server.join().unwrap();
+}
+
+#[test]
+fn fetch_non_200_fails() {
+    let (url, server) = serve_once("404 Not Found", "<html><body><a>not this</a></body></html>");
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["-f", &url, "a"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("404"));
+    server.join().unwrap();
+}
+
+#[test]
+fn fetch_sends_headers_and_user_agent() {
+    let (url, server) = serve_once("200 OK", "<html><body><p>hi</p></body></html>");
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["-f", &url, "--text", "p"])
+        .args(["--header", "Cookie: session=abc", "-H", "X-Test:1"])
+        .args(["--user-agent", "hq-test/1.0"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("hi\n"));
+
+    let head = server.join().unwrap().to_lowercase();
+    assert!(head.contains("cookie: session=abc\r\n"), "{head}");
+    assert!(head.contains("x-test: 1\r\n"), "{head}");
+    assert!(head.contains("user-agent: hq-test/1.0\r\n"), "{head}");
+}
+
+#[test]
+fn malformed_header_is_usage_error() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--header", "no colon here", "p"])
+        .write_stdin("<p>hi</p>")
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("Name: value"));
+}